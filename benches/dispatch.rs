@@ -0,0 +1,1314 @@
+// dispatch.rs
+// Compares decoding opcodes through the flat decode table with the phf map lookup it replaced
+// Run with `cargo bench --bench dispatch`
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use phf::phf_map;
+
+use rust_nes::cpu::instruction::{self, AddressingMode, Mnemonic};
+
+/// How many times each benchmark decodes all 256 opcodes
+const ROUNDS: u32 = 200_000;
+
+/// An entry of the old map; `Instruction` can't be built outside the crate, so this holds the same fields
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone)]
+struct Entry {
+    mnemonic: Mnemonic,
+    mode: AddressingMode,
+    time: u8,
+}
+
+/// The map the CPU decoded opcodes through before the flat table
+static MAP: phf::Map<u8, Entry> = phf_map! {
+    // ADC
+    0x69u8 => Entry {
+        mnemonic: Mnemonic::ADC,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    },
+    0x65u8 => Entry {
+        mnemonic: Mnemonic::ADC,
+        mode: AddressingMode::Zero,
+        time: 3,
+    },
+    0x75u8 => Entry {
+        mnemonic: Mnemonic::ADC,
+        mode: AddressingMode::ZeroX,
+        time: 4,
+    },
+    0x6du8 => Entry {
+        mnemonic: Mnemonic::ADC,
+        mode: AddressingMode::Absolute,
+        time: 4,
+    },
+    0x7du8 => Entry {
+        mnemonic: Mnemonic::ADC,
+        mode: AddressingMode::AbsoluteX,
+        time: 4,
+    },
+    0x79u8 => Entry {
+        mnemonic: Mnemonic::ADC,
+        mode: AddressingMode::AbsoluteY,
+        time: 4,
+    },
+    0x61u8 => Entry {
+        mnemonic: Mnemonic::ADC,
+        mode: AddressingMode::IndirectX,
+        time: 6,
+    },
+    0x71u8 => Entry {
+        mnemonic: Mnemonic::ADC,
+        mode: AddressingMode::IndirectY,
+        time: 5,
+    },
+
+    // AND
+    0x29u8 => Entry {
+        mnemonic: Mnemonic::AND,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    },
+    0x25u8 => Entry {
+        mnemonic: Mnemonic::AND,
+        mode: AddressingMode::Zero,
+        time: 3,
+    },
+    0x35u8 => Entry {
+        mnemonic: Mnemonic::AND,
+        mode: AddressingMode::ZeroX,
+        time: 4,
+    },
+    0x2du8 => Entry {
+        mnemonic: Mnemonic::AND,
+        mode: AddressingMode::Absolute,
+        time: 4,
+    },
+    0x3du8 => Entry {
+        mnemonic: Mnemonic::AND,
+        mode: AddressingMode::AbsoluteX,
+        time: 4,
+    },
+    0x39u8 => Entry {
+        mnemonic: Mnemonic::AND,
+        mode: AddressingMode::AbsoluteY,
+        time: 4,
+    },
+    0x21u8 => Entry {
+        mnemonic: Mnemonic::AND,
+        mode: AddressingMode::IndirectX,
+        time: 6,
+    },
+    0x31u8 => Entry {
+        mnemonic: Mnemonic::AND,
+        mode: AddressingMode::IndirectY,
+        time: 5,
+    },
+
+    // ASL
+    0x0au8 => Entry {
+        mnemonic: Mnemonic::ASL,
+        mode: AddressingMode::Accumulator,
+        time: 2,
+    },
+    0x06u8 => Entry {
+        mnemonic: Mnemonic::ASL,
+        mode: AddressingMode::Zero,
+        time: 5,
+    },
+    0x16u8 => Entry {
+        mnemonic: Mnemonic::ASL,
+        mode: AddressingMode::ZeroX,
+        time: 6,
+    },
+    0x0eu8 => Entry {
+        mnemonic: Mnemonic::ASL,
+        mode: AddressingMode::Absolute,
+        time: 6,
+    },
+    0x1eu8 => Entry {
+        mnemonic: Mnemonic::ASL,
+        mode: AddressingMode::AbsoluteX,
+        time: 7,
+    },
+
+    // BIT
+    0x24u8 => Entry {
+        mnemonic: Mnemonic::BIT,
+        mode: AddressingMode::Zero,
+        time: 3,
+    },
+    0x2cu8 => Entry {
+        mnemonic: Mnemonic::BIT,
+        mode: AddressingMode::Absolute,
+        time: 4,
+    },
+
+    /*
+    
+    Branching instructions
+    The cycles (time) listed here are for a branch not taken;
+    * a branch  not taken requires 2 cycles
+    * a branch taken adds 1 cycle
+    * if a page boundary is crossed, another cycle is added
+    The extra cycles are added by the CPU when the branch is executed
+
+    */
+    0x10u8 => Entry {
+        mnemonic: Mnemonic::BPL,
+        mode: AddressingMode::Relative,
+        time: 2,
+    },
+    0x30u8 => Entry {
+        mnemonic: Mnemonic::BMI,
+        mode: AddressingMode::Relative,
+        time: 2,
+    },
+    0x50u8 => Entry {
+        mnemonic: Mnemonic::BVC,
+        mode: AddressingMode::Relative,
+        time: 2,
+    },
+    0x70u8 => Entry {
+        mnemonic: Mnemonic::BVS,
+        mode: AddressingMode::Relative,
+        time: 2,
+    },
+    0x90u8 => Entry {
+        mnemonic: Mnemonic::BCC,
+        mode: AddressingMode::Relative,
+        time: 2,
+    },
+    0xb0u8 => Entry {
+        mnemonic: Mnemonic::BCS,
+        mode: AddressingMode::Relative,
+        time: 2,
+    },
+    0xd0u8 => Entry {
+        mnemonic: Mnemonic::BNE,
+        mode: AddressingMode::Relative,
+        time: 2,
+    },
+    0xf0u8 => Entry {
+        mnemonic: Mnemonic::BEQ,
+        mode: AddressingMode::Relative,
+        time: 2,
+    },
+
+    // BRK
+    0x00u8 => Entry {
+        mnemonic: Mnemonic::BRK,
+        mode: AddressingMode::Implied,
+        time: 7,
+    },
+    
+    // CMP
+    0xc9u8 => Entry {
+        mnemonic: Mnemonic::CMP,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    },
+    0xc5u8 => Entry {
+        mnemonic: Mnemonic::CMP,
+        mode: AddressingMode::Zero,
+        time: 3,
+    },
+    0xd5u8 => Entry {
+        mnemonic: Mnemonic::CMP,
+        mode: AddressingMode::ZeroX,
+        time: 4,
+    },
+    0xcdu8 => Entry {
+        mnemonic: Mnemonic::CMP,
+        mode: AddressingMode::Absolute,
+        time: 4,
+    },
+    0xddu8 => Entry {
+        mnemonic: Mnemonic::CMP,
+        mode: AddressingMode::AbsoluteX,
+        time: 4,
+    },
+    0xd9u8 => Entry {
+        mnemonic: Mnemonic::CMP,
+        mode: AddressingMode::AbsoluteY,
+        time: 4,
+    },
+    0xc1u8 => Entry {
+        mnemonic: Mnemonic::CMP,
+        mode: AddressingMode::IndirectX,
+        time: 6,
+    },
+    0xd1u8 => Entry {
+        mnemonic: Mnemonic::CMP,
+        mode: AddressingMode::IndirectY,
+        time: 5,
+    },
+
+    // CPX
+    0xe0u8 => Entry {
+        mnemonic: Mnemonic::CPX,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    },
+    0xe4u8 => Entry {
+        mnemonic: Mnemonic::CPX,
+        mode: AddressingMode::Zero,
+        time: 3,
+    },
+    0xecu8 => Entry {
+        mnemonic: Mnemonic::CPX,
+        mode: AddressingMode::Absolute,
+        time: 4,
+    },
+
+    // CPY
+    0xc0u8 => Entry {
+        mnemonic: Mnemonic::CPY,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    },
+    0xc4u8 => Entry {
+        mnemonic: Mnemonic::CPY,
+        mode: AddressingMode::Zero,
+        time: 3,
+    },
+    0xccu8 => Entry {
+        mnemonic: Mnemonic::CPY,
+        mode: AddressingMode::Absolute,
+        time: 4,
+    },
+
+    // DEC
+    0xc6u8 => Entry {
+        mnemonic: Mnemonic::DEC,
+        mode: AddressingMode::Zero,
+        time: 5,
+    },
+    0xd6u8 => Entry {
+        mnemonic: Mnemonic::DEC,
+        mode: AddressingMode::ZeroX,
+        time: 6,
+    },
+    0xceu8 => Entry {
+        mnemonic: Mnemonic::DEC,
+        mode: AddressingMode::Absolute,
+        time: 6,
+    },
+    0xdeu8 => Entry {
+        mnemonic: Mnemonic::DEC,
+        mode: AddressingMode::AbsoluteX,
+        time: 7,
+    },
+
+    // EOR
+    0x49u8 => Entry {
+        mnemonic: Mnemonic::EOR,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    },
+    0x45u8 => Entry {
+        mnemonic: Mnemonic::EOR,
+        mode: AddressingMode::Zero,
+        time: 3,
+    },
+    0x55u8 => Entry {
+        mnemonic: Mnemonic::EOR,
+        mode: AddressingMode::ZeroX,
+        time: 4,
+    },
+    0x4du8 => Entry {
+        mnemonic: Mnemonic::EOR,
+        mode: AddressingMode::Absolute,
+        time: 4,
+    },
+    0x5du8 => Entry {
+        mnemonic: Mnemonic::EOR,
+        mode: AddressingMode::AbsoluteX,
+        time: 4,
+    },
+    0x59u8 => Entry {
+        mnemonic: Mnemonic::EOR,
+        mode: AddressingMode::AbsoluteY,
+        time: 4,
+    },
+    0x41u8 => Entry {
+        mnemonic: Mnemonic::EOR,
+        mode: AddressingMode::IndirectX,
+        time: 6,
+    },
+    0x51u8 => Entry {
+        mnemonic: Mnemonic::EOR,
+        mode: AddressingMode::IndirectY,
+        time: 5,
+    },
+
+    // Flag instructions
+    // All of these require two cycles
+    0x18u8 => Entry {
+        mnemonic: Mnemonic::CLC,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+    0x38u8 => Entry {
+        mnemonic: Mnemonic::SEC,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+    0x58u8 => Entry {
+        mnemonic: Mnemonic::CLI,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+    0x78u8 => Entry {
+        mnemonic: Mnemonic::SEI,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+    0xb8u8 => Entry {
+        mnemonic: Mnemonic::CLV,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+    0xd8u8 => Entry {
+        mnemonic: Mnemonic::CLD,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+    0xf8u8 => Entry {
+        mnemonic: Mnemonic::SED,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+
+    // INC
+    0xe6u8 => Entry {
+        mnemonic: Mnemonic::INC,
+        mode: AddressingMode::Zero,
+        time: 5,
+    },
+    0xf6u8 => Entry {
+        mnemonic: Mnemonic::INC,
+        mode: AddressingMode::ZeroX,
+        time: 6,
+    },
+    0xeeu8 => Entry {
+        mnemonic: Mnemonic::INC,
+        mode: AddressingMode::Absolute,
+        time: 6,
+    },
+    0xfeu8 => Entry {
+        mnemonic: Mnemonic::INC,
+        mode: AddressingMode::AbsoluteX,
+        time: 7,
+    },
+
+    // JMP
+    0x4cu8 => Entry {
+        mnemonic: Mnemonic::JMP,
+        mode: AddressingMode::Absolute,
+        time: 3,
+    },
+    0x6cu8 => Entry {
+        mnemonic: Mnemonic::JMP,
+        mode: AddressingMode::Indirect,
+        time: 5,
+    },
+
+    // JSR
+    0x20u8 => Entry {
+        mnemonic: Mnemonic::JSR,
+        mode: AddressingMode::Absolute,
+        time: 6,
+    },
+
+    // LDA
+    0xa9u8 => Entry {
+        mnemonic: Mnemonic::LDA,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    },
+    0xa5u8 => Entry {
+        mnemonic: Mnemonic::LDA,
+        mode: AddressingMode::Zero,
+        time: 3,
+    },
+    0xb5u8 => Entry {
+        mnemonic: Mnemonic::LDA,
+        mode: AddressingMode::ZeroX,
+        time: 4,
+    },
+    0xadu8 => Entry {
+        mnemonic: Mnemonic::LDA,
+        mode: AddressingMode::Absolute,
+        time: 4,
+    },
+    0xbdu8 => Entry {
+        mnemonic: Mnemonic::LDA,
+        mode: AddressingMode::AbsoluteX,
+        time: 4,
+    },
+    0xb9u8 => Entry {
+        mnemonic: Mnemonic::LDA,
+        mode: AddressingMode::AbsoluteY,
+        time: 4,
+    },
+    0xa1u8 => Entry {
+        mnemonic: Mnemonic::LDA,
+        mode: AddressingMode::IndirectX,
+        time: 6,
+    },
+    0xb1u8 => Entry {
+        mnemonic: Mnemonic::LDA,
+        mode: AddressingMode::IndirectY,
+        time: 5,
+    },
+
+    // LDX
+    0xa2u8 => Entry {
+        mnemonic: Mnemonic::LDX,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    },
+    0xa6u8 => Entry {
+        mnemonic: Mnemonic::LDX,
+        mode: AddressingMode::Zero,
+        time: 3,
+    },
+    0xb6u8 => Entry {
+        mnemonic: Mnemonic::LDX,
+        mode: AddressingMode::ZeroY,
+        time: 4,
+    },
+    0xaeu8 => Entry {
+        mnemonic: Mnemonic::LDX,
+        mode: AddressingMode::Absolute,
+        time: 4,
+    },
+    0xbeu8 => Entry {
+        mnemonic: Mnemonic::LDX,
+        mode: AddressingMode::AbsoluteY,
+        time: 4,
+    },
+
+    // LDY
+    0xa0u8 => Entry {
+        mnemonic: Mnemonic::LDY,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    },
+    0xa4u8 => Entry {
+        mnemonic: Mnemonic::LDY,
+        mode: AddressingMode::Zero,
+        time: 3,
+    },
+    0xb4u8 => Entry {
+        mnemonic: Mnemonic::LDY,
+        mode: AddressingMode::ZeroX,
+        time: 4,
+    },
+    0xacu8 => Entry {
+        mnemonic: Mnemonic::LDY,
+        mode: AddressingMode::Absolute,
+        time: 4,
+    },
+    0xbcu8 => Entry {
+        mnemonic: Mnemonic::LDY,
+        mode: AddressingMode::AbsoluteX,
+        time: 4,
+    },
+
+    // LSR
+    0x4au8 => Entry {
+        mnemonic: Mnemonic::LSR,
+        mode: AddressingMode::Accumulator,
+        time: 2,
+    },
+    0x46u8 => Entry {
+        mnemonic: Mnemonic::LSR,
+        mode: AddressingMode::Zero,
+        time: 5,
+    },
+    0x56u8 => Entry {
+        mnemonic: Mnemonic::LSR,
+        mode: AddressingMode::ZeroX,
+        time: 6,
+    },
+    0x4eu8 => Entry {
+        mnemonic: Mnemonic::LSR,
+        mode: AddressingMode::Absolute,
+        time: 6,
+    },
+    0x5eu8 => Entry {
+        mnemonic: Mnemonic::LSR,
+        mode: AddressingMode::AbsoluteX,
+        time: 7,
+    },
+
+    // NOP
+    0xeau8 => Entry {
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+    0x80u8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    },
+    0xdau8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+    0xfau8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+    0x1au8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+    0x3au8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+    0x5au8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+    0x7au8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+    0x82u8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    },
+    0x89u8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    },
+    0xc2u8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    },
+    0xe2u8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    },
+    0x04u8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Zero,
+        time: 3,
+    },
+    0x44u8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Zero,
+        time: 3,
+    },
+    0x64u8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Zero,
+        time: 3,
+    },
+    0x14u8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::ZeroX,
+        time: 4,
+    },
+    0x34u8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::ZeroX,
+        time: 4,
+    },
+    0x54u8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::ZeroX,
+        time: 4,
+    },
+    0x74u8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::ZeroX,
+        time: 4,
+    },
+    0xd4u8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::ZeroX,
+        time: 4,
+    },
+    0xf4u8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::ZeroX,
+        time: 4,
+    },
+    0x0cu8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Absolute,
+        time: 4,
+    },
+    0x1cu8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::AbsoluteX,
+        time: 4,
+    },
+    0x3cu8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::AbsoluteX,
+        time: 4,
+    },
+    0x5cu8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::AbsoluteX,
+        time: 4,
+    },
+    0x7cu8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::AbsoluteX,
+        time: 4,
+    },
+    0xdcu8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::AbsoluteX,
+        time: 4,
+    },
+    0xfcu8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::AbsoluteX,
+        time: 4,
+    },
+
+    // ORA
+    0x09u8 => Entry {
+        mnemonic: Mnemonic::ORA,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    },
+    0x05u8 => Entry {
+        mnemonic: Mnemonic::ORA,
+        mode: AddressingMode::Zero,
+        time: 3,
+    },
+    0x15u8 => Entry {
+        mnemonic: Mnemonic::ORA,
+        mode: AddressingMode::ZeroX,
+        time: 4,
+    },
+    0x0du8 => Entry {
+        mnemonic: Mnemonic::ORA,
+        mode: AddressingMode::Absolute,
+        time: 4,
+    },
+    0x1du8 => Entry {
+        mnemonic: Mnemonic::ORA,
+        mode: AddressingMode::AbsoluteX,
+        time: 4,
+    },
+    0x19u8 => Entry {
+        mnemonic: Mnemonic::ORA,
+        mode: AddressingMode::AbsoluteY,
+        time: 4,
+    },
+    0x01u8 => Entry {
+        mnemonic: Mnemonic::ORA,
+        mode: AddressingMode::IndirectX,
+        time: 6,
+    },
+    0x11u8 => Entry {
+        mnemonic: Mnemonic::ORA,
+        mode: AddressingMode::IndirectY,
+        time: 5,
+    },
+
+    // Register instructions
+    // These require two cycles
+    0xaau8 => Entry {
+        mnemonic: Mnemonic::TAX,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+    0x8au8 => Entry {
+        mnemonic: Mnemonic::TXA,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+    0xcau8 => Entry {
+        mnemonic: Mnemonic::DEX,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+    0xe8u8 => Entry {
+        mnemonic: Mnemonic::INX,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+    0xa8u8 => Entry {
+        mnemonic: Mnemonic::TAY,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+    0x98u8 => Entry {
+        mnemonic: Mnemonic::TYA,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+    0x88u8 => Entry {
+        mnemonic: Mnemonic::DEY,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+    0xc8u8 => Entry {
+        mnemonic: Mnemonic::INY,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+
+    // ROL
+    0x2au8 => Entry {
+        mnemonic: Mnemonic::ROL,
+        mode: AddressingMode::Accumulator,
+        time: 2,
+    },
+    0x26u8 => Entry {
+        mnemonic: Mnemonic::ROL,
+        mode: AddressingMode::Zero,
+        time: 5,
+    },
+    0x36u8 => Entry {
+        mnemonic: Mnemonic::ROL,
+        mode: AddressingMode::ZeroX,
+        time: 6,
+    },
+    0x2eu8 => Entry {
+        mnemonic: Mnemonic::ROL,
+        mode: AddressingMode::Absolute,
+        time: 6,
+    },
+    0x3eu8 => Entry {
+        mnemonic: Mnemonic::ROL,
+        mode: AddressingMode::AbsoluteX,
+        time: 7,
+    },
+
+    // ROR
+    0x6au8 => Entry {
+        mnemonic: Mnemonic::ROR,
+        mode: AddressingMode::Accumulator,
+        time: 2,
+    },
+    0x66u8 => Entry {
+        mnemonic: Mnemonic::ROR,
+        mode: AddressingMode::Zero,
+        time: 5,
+    },
+    0x76u8 => Entry {
+        mnemonic: Mnemonic::ROR,
+        mode: AddressingMode::ZeroX,
+        time: 6,
+    },
+    0x6eu8 => Entry {
+        mnemonic: Mnemonic::ROR,
+        mode: AddressingMode::Absolute,
+        time: 6,
+    },
+    0x7eu8 => Entry {
+        mnemonic: Mnemonic::ROR,
+        mode: AddressingMode::AbsoluteX,
+        time: 7,
+    },
+
+    // RTI
+    0x40u8 => Entry {
+        mnemonic: Mnemonic::RTI,
+        mode: AddressingMode::Implied,
+        time: 6,
+    },
+
+    // RTS
+    0x60u8 => Entry {
+        mnemonic: Mnemonic::RTS,
+        mode: AddressingMode::Implied,
+        time: 6,
+    },
+
+    // SBC
+    0xe9u8 => Entry {
+        mnemonic: Mnemonic::SBC,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    },
+    0xebu8 => Entry {  // Unofficial
+        mnemonic: Mnemonic::SBC,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    },
+    0xe5u8 => Entry {
+        mnemonic: Mnemonic::SBC,
+        mode: AddressingMode::Zero,
+        time: 3,
+    },
+    0xf5u8 => Entry {
+        mnemonic: Mnemonic::SBC,
+        mode: AddressingMode::ZeroX,
+        time: 4,
+    },
+    0xedu8 => Entry {
+        mnemonic: Mnemonic::SBC,
+        mode: AddressingMode::Absolute,
+        time: 4,
+    },
+    0xfdu8 => Entry {
+        mnemonic: Mnemonic::SBC,
+        mode: AddressingMode::AbsoluteX,
+        time: 4,
+    },
+    0xf9u8 => Entry {
+        mnemonic: Mnemonic::SBC,
+        mode: AddressingMode::AbsoluteY,
+        time: 4,
+    },
+    0xe1u8 => Entry {
+        mnemonic: Mnemonic::SBC,
+        mode: AddressingMode::IndirectX,
+        time: 6,
+    },
+    0xf1u8 => Entry {
+        mnemonic: Mnemonic::SBC,
+        mode: AddressingMode::IndirectY,
+        time: 5,
+    },
+
+    // STA
+    0x85u8 => Entry {
+        mnemonic: Mnemonic::STA,
+        mode: AddressingMode::Zero,
+        time: 3,
+    },
+    0x95u8 => Entry {
+        mnemonic: Mnemonic::STA,
+        mode: AddressingMode::ZeroX,
+        time: 4,
+    },
+    0x8du8 => Entry {
+        mnemonic: Mnemonic::STA,
+        mode: AddressingMode::Absolute,
+        time: 4,
+    },
+    0x9du8 => Entry {
+        mnemonic: Mnemonic::STA,
+        mode: AddressingMode::AbsoluteX,
+        time: 5,
+    },
+    0x99u8 => Entry {
+        mnemonic: Mnemonic::STA,
+        mode: AddressingMode::AbsoluteY,
+        time: 5,
+    },
+    0x81u8 => Entry {
+        mnemonic: Mnemonic::STA,
+        mode: AddressingMode::IndirectX,
+        time: 6,
+    },
+    0x91u8 => Entry {
+        mnemonic: Mnemonic::STA,
+        mode: AddressingMode::IndirectY,
+        time: 6,
+    },
+
+    // Stack instructions
+    0x9au8 => Entry {
+        mnemonic: Mnemonic::TXS,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+    0xbau8 => Entry {
+        mnemonic: Mnemonic::TSX,
+        mode: AddressingMode::Implied,
+        time: 2,
+    },
+    0x48u8 => Entry {
+        mnemonic: Mnemonic::PHA,
+        mode: AddressingMode::Implied,
+        time: 3,
+    },
+    0x68u8 => Entry {
+        mnemonic: Mnemonic::PLA,
+        mode: AddressingMode::Implied,
+        time: 4,
+    },
+    0x08u8 => Entry {
+        mnemonic: Mnemonic::PHP,
+        mode: AddressingMode::Implied,
+        time: 3,
+    },
+    0x28u8 => Entry {
+        mnemonic: Mnemonic::PLP,
+        mode: AddressingMode::Implied,
+        time: 4,
+    },
+
+    // STX
+    0x86u8 => Entry {
+        mnemonic: Mnemonic::STX,
+        mode: AddressingMode::Zero,
+        time: 3,
+    },
+    0x96u8 => Entry {
+        mnemonic: Mnemonic::STX,
+        mode: AddressingMode::ZeroY,
+        time: 4,
+    },
+    0x8eu8 => Entry {
+        mnemonic: Mnemonic::STX,
+        mode: AddressingMode::Absolute,
+        time: 4,
+    },
+
+    // STY
+    0x84u8 => Entry {
+        mnemonic: Mnemonic::STY,
+        mode: AddressingMode::Zero,
+        time: 3,
+    },
+    0x94u8 => Entry {
+        mnemonic: Mnemonic::STY,
+        mode: AddressingMode::ZeroX,
+        time: 4,
+    },
+    0x8cu8 => Entry {
+        mnemonic: Mnemonic::STY,
+        mode: AddressingMode::Absolute,
+        time: 4,
+    },
+
+    // Unofficial opcodes
+    0x8bu8 => Entry {
+        mnemonic: Mnemonic::XAA,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    },
+    0xabu8 => Entry {
+        mnemonic: Mnemonic::LAX,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    },
+
+    // LAX
+    0xa7u8 => Entry {
+        mnemonic: Mnemonic::LAX,
+        mode: AddressingMode::Zero,
+        time: 3,
+    },
+    0xb7u8 => Entry {
+        mnemonic: Mnemonic::LAX,
+        mode: AddressingMode::ZeroY,
+        time: 4,
+    },
+    0xafu8 => Entry {
+        mnemonic: Mnemonic::LAX,
+        mode: AddressingMode::Absolute,
+        time: 4,
+    },
+    0xbfu8 => Entry {
+        mnemonic: Mnemonic::LAX,
+        mode: AddressingMode::AbsoluteY,
+        time: 4,
+    },
+    0xa3u8 => Entry {
+        mnemonic: Mnemonic::LAX,
+        mode: AddressingMode::IndirectX,
+        time: 6,
+    },
+    0xb3u8 => Entry {
+        mnemonic: Mnemonic::LAX,
+        mode: AddressingMode::IndirectY,
+        time: 5,
+    },
+
+    // SAX
+    0x87u8 => Entry {
+        mnemonic: Mnemonic::SAX,
+        mode: AddressingMode::Zero,
+        time: 3,
+    },
+    0x97u8 => Entry {
+        mnemonic: Mnemonic::SAX,
+        mode: AddressingMode::ZeroY,
+        time: 4,
+    },
+    0x8fu8 => Entry {
+        mnemonic: Mnemonic::SAX,
+        mode: AddressingMode::Absolute,
+        time: 4,
+    },
+    0x83u8 => Entry {
+        mnemonic: Mnemonic::SAX,
+        mode: AddressingMode::IndirectX,
+        time: 6,
+    },
+
+    // DCP
+    0xc7u8 => Entry {
+        mnemonic: Mnemonic::DCP,
+        mode: AddressingMode::Zero,
+        time: 5,
+    },
+    0xd7u8 => Entry {
+        mnemonic: Mnemonic::DCP,
+        mode: AddressingMode::ZeroX,
+        time: 6,
+    },
+    0xcfu8 => Entry {
+        mnemonic: Mnemonic::DCP,
+        mode: AddressingMode::Absolute,
+        time: 6,
+    },
+    0xdfu8 => Entry {
+        mnemonic: Mnemonic::DCP,
+        mode: AddressingMode::AbsoluteX,
+        time: 7,
+    },
+    0xdbu8 => Entry {
+        mnemonic: Mnemonic::DCP,
+        mode: AddressingMode::AbsoluteY,
+        time: 7,
+    },
+    0xc3u8 => Entry {
+        mnemonic: Mnemonic::DCP,
+        mode: AddressingMode::IndirectX,
+        time: 8,
+    },
+    0xd3u8 => Entry {
+        mnemonic: Mnemonic::DCP,
+        mode: AddressingMode::IndirectY,
+        time: 8,
+    },
+
+    // ISC
+    0xe7u8 => Entry {
+        mnemonic: Mnemonic::ISC,
+        mode: AddressingMode::Zero,
+        time: 5,
+    },
+    0xf7u8 => Entry {
+        mnemonic: Mnemonic::ISC,
+        mode: AddressingMode::ZeroX,
+        time: 6,
+    },
+    0xefu8 => Entry {
+        mnemonic: Mnemonic::ISC,
+        mode: AddressingMode::Absolute,
+        time: 6,
+    },
+    0xffu8 => Entry {
+        mnemonic: Mnemonic::ISC,
+        mode: AddressingMode::AbsoluteX,
+        time: 7,
+    },
+    0xfbu8 => Entry {
+        mnemonic: Mnemonic::ISC,
+        mode: AddressingMode::AbsoluteY,
+        time: 7,
+    },
+    0xe3u8 => Entry {
+        mnemonic: Mnemonic::ISC,
+        mode: AddressingMode::IndirectX,
+        time: 8,
+    },
+    0xf3u8 => Entry {
+        mnemonic: Mnemonic::ISC,
+        mode: AddressingMode::IndirectY,
+        time: 8,
+    },
+
+    // SLO
+    0x07u8 => Entry {
+        mnemonic: Mnemonic::SLO,
+        mode: AddressingMode::Zero,
+        time: 5,
+    },
+    0x17u8 => Entry {
+        mnemonic: Mnemonic::SLO,
+        mode: AddressingMode::ZeroX,
+        time: 6,
+    },
+    0x0fu8 => Entry {
+        mnemonic: Mnemonic::SLO,
+        mode: AddressingMode::Absolute,
+        time: 6,
+    },
+    0x1fu8 => Entry {
+        mnemonic: Mnemonic::SLO,
+        mode: AddressingMode::AbsoluteX,
+        time: 7,
+    },
+    0x1bu8 => Entry {
+        mnemonic: Mnemonic::SLO,
+        mode: AddressingMode::AbsoluteY,
+        time: 7,
+    },
+    0x03u8 => Entry {
+        mnemonic: Mnemonic::SLO,
+        mode: AddressingMode::IndirectX,
+        time: 8,
+    },
+    0x13u8 => Entry {
+        mnemonic: Mnemonic::SLO,
+        mode: AddressingMode::IndirectY,
+        time: 8,
+    },
+
+    // RLA
+    0x27u8 => Entry {
+        mnemonic: Mnemonic::RLA,
+        mode: AddressingMode::Zero,
+        time: 5,
+    },
+    0x37u8 => Entry {
+        mnemonic: Mnemonic::RLA,
+        mode: AddressingMode::ZeroX,
+        time: 6,
+    },
+    0x2fu8 => Entry {
+        mnemonic: Mnemonic::RLA,
+        mode: AddressingMode::Absolute,
+        time: 6,
+    },
+    0x3fu8 => Entry {
+        mnemonic: Mnemonic::RLA,
+        mode: AddressingMode::AbsoluteX,
+        time: 7,
+    },
+    0x3bu8 => Entry {
+        mnemonic: Mnemonic::RLA,
+        mode: AddressingMode::AbsoluteY,
+        time: 7,
+    },
+    0x23u8 => Entry {
+        mnemonic: Mnemonic::RLA,
+        mode: AddressingMode::IndirectX,
+        time: 8,
+    },
+    0x33u8 => Entry {
+        mnemonic: Mnemonic::RLA,
+        mode: AddressingMode::IndirectY,
+        time: 8,
+    },
+
+    // SRE
+    0x47u8 => Entry {
+        mnemonic: Mnemonic::SRE,
+        mode: AddressingMode::Zero,
+        time: 5,
+    },
+    0x57u8 => Entry {
+        mnemonic: Mnemonic::SRE,
+        mode: AddressingMode::ZeroX,
+        time: 6,
+    },
+    0x4fu8 => Entry {
+        mnemonic: Mnemonic::SRE,
+        mode: AddressingMode::Absolute,
+        time: 6,
+    },
+    0x5fu8 => Entry {
+        mnemonic: Mnemonic::SRE,
+        mode: AddressingMode::AbsoluteX,
+        time: 7,
+    },
+    0x5bu8 => Entry {
+        mnemonic: Mnemonic::SRE,
+        mode: AddressingMode::AbsoluteY,
+        time: 7,
+    },
+    0x43u8 => Entry {
+        mnemonic: Mnemonic::SRE,
+        mode: AddressingMode::IndirectX,
+        time: 8,
+    },
+    0x53u8 => Entry {
+        mnemonic: Mnemonic::SRE,
+        mode: AddressingMode::IndirectY,
+        time: 8,
+    },
+
+    // RRA
+    0x67u8 => Entry {
+        mnemonic: Mnemonic::RRA,
+        mode: AddressingMode::Zero,
+        time: 5,
+    },
+    0x77u8 => Entry {
+        mnemonic: Mnemonic::RRA,
+        mode: AddressingMode::ZeroX,
+        time: 6,
+    },
+    0x6fu8 => Entry {
+        mnemonic: Mnemonic::RRA,
+        mode: AddressingMode::Absolute,
+        time: 6,
+    },
+    0x7fu8 => Entry {
+        mnemonic: Mnemonic::RRA,
+        mode: AddressingMode::AbsoluteX,
+        time: 7,
+    },
+    0x7bu8 => Entry {
+        mnemonic: Mnemonic::RRA,
+        mode: AddressingMode::AbsoluteY,
+        time: 7,
+    },
+    0x63u8 => Entry {
+        mnemonic: Mnemonic::RRA,
+        mode: AddressingMode::IndirectX,
+        time: 8,
+    },
+    0x73u8 => Entry {
+        mnemonic: Mnemonic::RRA,
+        mode: AddressingMode::IndirectY,
+        time: 8,
+    }
+};
+
+/// Decodes every opcode `ROUNDS` times with `decode`, returning the time taken and the total of the cycle counts (so the work can't be optimized away)
+fn time_decoding(decode: impl Fn(u8) -> Option<u8>) -> (Duration, u64) {
+    let start = Instant::now();
+    let mut total = 0;
+    for _ in 0..ROUNDS {
+        for opcode in 0..=255u8 {
+            if let Some(time) = decode(black_box(opcode)) {
+                total += time as u64;
+            }
+        }
+    }
+    (start.elapsed(), total)
+}
+
+fn main() {
+    // the CPU used to check the map for the opcode, then index it
+    let (map_time, map_total) = time_decoding(|opcode| {
+        if MAP.contains_key(&opcode) {
+            Some(MAP[&opcode].time)
+        } else {
+            None
+        }
+    });
+
+    let (table_time, table_total) = time_decoding(|opcode| instruction::decode(opcode).map(|i| i.time));
+    assert_eq!(map_total, table_total);
+
+    let decodes = ROUNDS as f64 * 256.0;
+    println!("map lookup:   {:>8.2} ns/decode", map_time.as_nanos() as f64 / decodes);
+    println!("decode table: {:>8.2} ns/decode", table_time.as_nanos() as f64 / decodes);
+    println!("speedup:      {:>8.2}x", map_time.as_secs_f64() / table_time.as_secs_f64());
+}
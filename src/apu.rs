@@ -1,6 +1,541 @@
 // apu.rs
 // Implements the Audio Processing Unit
 
-mod apu {
-    // todo: everything
+mod dmc;
+mod filter;
+mod noise;
+mod pulse;
+mod triangle;
+mod units;
+
+use std::collections::VecDeque;
+
+use crate::nes::Region;
+use crate::state::{SaveState, StateReader, StateWriter};
+use dmc::Dmc;
+use filter::{HighPass, LowPass};
+use noise::Noise;
+use pulse::Pulse;
+use triangle::Triangle;
+
+/// The CPU cycles on which the steps of the frame sequencer happen; the fourth step is only used in 4-step mode
+const FRAME_STEP_1: u32 = 7457;
+const FRAME_STEP_2: u32 = 14913;
+const FRAME_STEP_3: u32 = 22371;
+const FRAME_STEP_4: u32 = 29829;
+/// The final step in 5-step mode
+const FRAME_STEP_5: u32 = 37281;
+
+/// The length of a frame sequence in 4-step mode, in CPU cycles
+const FRAME_PERIOD_4_STEP: u32 = 29830;
+/// The length of a frame sequence in 5-step mode, in CPU cycles
+const FRAME_PERIOD_5_STEP: u32 = 37282;
+
+/// The sample rate used until the frontend asks for another
+const DEFAULT_SAMPLE_RATE: u32 = 44100;
+/// The cutoff of the high-pass filter in the output circuitry, in Hz
+const HIGH_PASS_CUTOFF: f32 = 90.0;
+/// The cutoff of the low-pass filter in the output circuitry, in Hz
+const LOW_PASS_CUTOFF: f32 = 14000.0;
+
+/// The source of an IRQ the APU raised, as reported to the hook set with `set_irq_hook`
+#[derive(PartialEq, Eq)]
+#[derive(Debug, Copy, Clone)]
+pub enum ApuIrqKind {
+    /// The frame counter reached the end of a 4-step sequence
+    Frame,
+    /// The DMC finished a sample that doesn't loop
+    Dmc,
+}
+
+/// The Audio Processing Unit.
+/// The APU's channels are controlled through registers at `0x4000 - 0x4017`:
+/// * `0x4000 - 0x4003`: pulse 1
+/// * `0x4004 - 0x4007`: pulse 2
+/// * `0x4008 - 0x400B`: triangle
+/// * `0x400C - 0x400F`: noise
+/// * `0x4010 - 0x4013`: DMC
+/// * `0x4015`: channel enables (write) and status (read)
+/// * `0x4017`: frame counter
+///
+/// Each channel is driven by a timer, while a frame sequencer clocks the envelopes (every quarter frame) and the length counters and sweep units (every half frame).
+pub struct APU {
+    pulse_1: Pulse,
+    pulse_2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+
+    // CPU cycles since the start of the frame sequence
+    frame_cycle: u32,
+    // the sequencer is either in 4-step mode (false), which can raise an IRQ, or 5-step mode (true), which can't
+    five_step_mode: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    // the pulse timers only run on every other CPU cycle
+    even_cycle: bool,
+
+    // the output is averaged over the CPU cycles in each output sample, then filtered
+    sample_rate: u32,
+    cpu_clock_rate: u32,
+    cycles_per_sample: f64,
+    sample_clock: f64,
+    sample_sum: f32,
+    sample_count: u32,
+    high_pass: HighPass,
+    low_pass: LowPass,
+    // samples that haven't been drained by the frontend yet
+    samples: VecDeque<f32>,
+
+    // called whenever the frame counter or DMC raises its IRQ, for debugging
+    irq_hook: Option<Box<dyn FnMut(ApuIrqKind)>>,
+}
+
+impl Default for APU {
+    #[inline]
+    fn default() -> APU {
+        APU::new()
+    }
+}
+
+impl APU {
+    /// Creates an APU with all channels silenced
+    pub fn new() -> APU {
+        APU {
+            pulse_1: Pulse::new(true),
+            pulse_2: Pulse::new(false),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            frame_cycle: 0,
+            five_step_mode: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            even_cycle: true,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            cpu_clock_rate: Region::Ntsc.cpu_clock_rate(),
+            cycles_per_sample: Region::Ntsc.cpu_clock_rate() as f64 / DEFAULT_SAMPLE_RATE as f64,
+            sample_clock: 0.0,
+            sample_sum: 0.0,
+            sample_count: 0,
+            high_pass: HighPass::new(HIGH_PASS_CUTOFF, DEFAULT_SAMPLE_RATE as f32),
+            low_pass: LowPass::new(LOW_PASS_CUTOFF, DEFAULT_SAMPLE_RATE as f32),
+            samples: VecDeque::new(),
+            irq_hook: None,
+        }
+    }
+
+    /// Calls `hook` each time the frame counter or DMC raises its IRQ, replacing any previous hook
+    /// This is purely for observation, such as diagnosing music drivers with tricky IRQ timing; the hook is called when an IRQ is raised, whether or not the CPU goes on to take it.
+    pub fn set_irq_hook(&mut self, hook: impl FnMut(ApuIrqKind) + 'static) {
+        self.irq_hook = Some(Box::new(hook));
+    }
+
+    /// Stops reporting IRQs
+    pub fn clear_irq_hook(&mut self) {
+        self.irq_hook = None;
+    }
+
+    /// Reports a newly raised IRQ to the hook, if there is one
+    fn notify_irq(&mut self, kind: ApuIrqKind) {
+        if let Some(hook) = &mut self.irq_hook {
+            hook(kind);
+        }
+    }
+
+    /// Sets the rate, in Hz, at which samples are produced for `drain_samples`
+    pub fn set_sample_rate(&mut self, hz: u32) {
+        let hz = hz.max(1);
+        self.sample_rate = hz;
+        self.cycles_per_sample = self.cpu_clock_rate as f64 / hz as f64;
+        self.high_pass = HighPass::new(HIGH_PASS_CUTOFF, hz as f32);
+        self.low_pass = LowPass::new(LOW_PASS_CUTOFF, hz as f32);
+        self.samples.clear();
+    }
+
+    /// Sets the rate, in Hz, of the CPU clock that drives the APU, so samples are produced at the right rate for the console's region
+    pub fn set_cpu_clock_rate(&mut self, hz: u32) {
+        self.cpu_clock_rate = hz;
+        self.cycles_per_sample = hz as f64 / self.sample_rate as f64;
+    }
+
+    /// Moves as many generated samples as fit into `out`, returning how many were written
+    /// The samples are at the rate given to `set_sample_rate` (44.1kHz by default), roughly centred on zero.
+    pub fn drain_samples(&mut self, out: &mut [f32]) -> usize {
+        let count = out.len().min(self.samples.len());
+        for (slot, sample) in out.iter_mut().zip(self.samples.drain(..count)) {
+            *slot = sample;
+        }
+        count
+    }
+
+    /// Accumulates this cycle's output, producing a filtered sample once enough cycles have passed
+    fn generate_sample(&mut self) {
+        self.sample_sum += self.output_sample();
+        self.sample_count += 1;
+
+        self.sample_clock += 1.0;
+        if self.sample_clock >= self.cycles_per_sample {
+            self.sample_clock -= self.cycles_per_sample;
+
+            let average = self.sample_sum / self.sample_count as f32;
+            self.sample_sum = 0.0;
+            self.sample_count = 0;
+            let sample = self.low_pass.process(self.high_pass.process(average));
+
+            // if the frontend stops draining, keep only the most recent second
+            if self.samples.len() >= self.sample_rate as usize {
+                self.samples.pop_front();
+            }
+            self.samples.push_back(sample);
+        }
+    }
+
+    /// Writes one of the APU's registers
+    pub fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x4000..=0x4003 => self.pulse_1.write_register(address & 0x03, value),
+            0x4004..=0x4007 => self.pulse_2.write_register(address & 0x03, value),
+            0x4008..=0x400b => self.triangle.write_register(address & 0x03, value),
+            0x400c..=0x400f => self.noise.write_register(address & 0x03, value),
+            0x4010..=0x4013 => self.dmc.write_register(address & 0x03, value),
+            0x4015 => {
+                self.pulse_1.length.set_enabled((value & 0x01) != 0);
+                self.pulse_2.length.set_enabled((value & 0x02) != 0);
+                self.triangle.length.set_enabled((value & 0x04) != 0);
+                self.noise.length.set_enabled((value & 0x08) != 0);
+                self.dmc.set_enabled((value & 0x10) != 0);
+            },
+            0x4017 => {
+                self.five_step_mode = (value & 0x80) != 0;
+                self.irq_inhibit = (value & 0x40) != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+
+                // writing resets the sequence, and entering 5-step mode clocks everything immediately
+                self.frame_cycle = 0;
+                if self.five_step_mode {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+            },
+            _ => {},
+        }
+    }
+
+    /// Advances the APU by one CPU cycle
+    pub fn clock(&mut self) {
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        if self.even_cycle {
+            self.pulse_1.clock_timer();
+            self.pulse_2.clock_timer();
+        }
+        self.even_cycle = !self.even_cycle;
+
+        self.clock_frame_sequencer();
+        self.generate_sample();
+    }
+
+    /// Reads the status register at `0x4015`
+    /// The low five bits report whether each channel is still playing, and the top two report the frame and DMC interrupts; reading clears the frame interrupt.
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+        self.frame_irq = false;
+        status
+    }
+
+    /// Returns the value of the status register, without clearing the frame interrupt
+    pub fn peek_status(&self) -> u8 {
+        (!self.pulse_1.length.is_zero() as u8)
+            | (!self.pulse_2.length.is_zero() as u8) << 1
+            | (!self.triangle.length.is_zero() as u8) << 2
+            | (!self.noise.length.is_zero() as u8) << 3
+            | (self.dmc.is_active() as u8) << 4
+            | (self.frame_irq as u8) << 6
+            | (self.dmc.irq as u8) << 7
+    }
+
+    /// Returns whether the APU is asserting the CPU's IRQ line, from either the frame counter or the DMC
+    pub fn irq_pending(&self) -> bool {
+        self.frame_irq || self.dmc.irq
+    }
+
+    /// Advances the frame sequencer, which clocks the envelopes and linear counter on every step (a quarter frame), and the length counters and sweep units on every other step (a half frame)
+    /// In 4-step mode, the IRQ flag is raised over the last three cycles of the sequence unless inhibited.
+    fn clock_frame_sequencer(&mut self) {
+        self.frame_cycle += 1;
+        match self.frame_cycle {
+            FRAME_STEP_1 | FRAME_STEP_3 => self.clock_quarter_frame(),
+            FRAME_STEP_2 => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            },
+            _ => {},
+        }
+
+        if self.five_step_mode {
+            if self.frame_cycle == FRAME_STEP_5 {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            } else if self.frame_cycle == FRAME_PERIOD_5_STEP {
+                self.frame_cycle = 0;
+            }
+        } else {
+            if self.frame_cycle == FRAME_STEP_4 {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            if self.frame_cycle >= FRAME_STEP_4 - 1 && !self.irq_inhibit {
+                if !self.frame_irq {
+                    self.notify_irq(ApuIrqKind::Frame);
+                }
+                self.frame_irq = true;
+            }
+            if self.frame_cycle == FRAME_PERIOD_4_STEP {
+                self.frame_cycle = 0;
+            }
+        }
+    }
+
+    /// Clocks the envelopes and the triangle's linear counter
+    fn clock_quarter_frame(&mut self) {
+        self.pulse_1.clock_quarter_frame();
+        self.pulse_2.clock_quarter_frame();
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+
+    /// Clocks the length counters and sweep units
+    fn clock_half_frame(&mut self) {
+        self.pulse_1.clock_half_frame();
+        self.pulse_2.clock_half_frame();
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
+
+    /// Returns the address of the sample byte the DMC needs next, if its buffer is empty
+    pub fn dmc_fetch_address(&self) -> Option<u16> {
+        self.dmc.fetch_address()
+    }
+
+    /// Gives the DMC the sample byte it requested through `dmc_fetch_address`
+    pub fn dmc_fill_buffer(&mut self, value: u8) {
+        let raised = self.dmc.irq;
+        self.dmc.fill_buffer(value);
+        if self.dmc.irq && !raised {
+            self.notify_irq(ApuIrqKind::Dmc);
+        }
+    }
+
+    /// Returns the current output of the APU, from 0.0 to 1.0
+    /// The channels are mixed with the nonlinear formula that approximates the DACs on the real chip.
+    pub fn output_sample(&mut self) -> f32 {
+        let pulses = (self.pulse_1.output() + self.pulse_2.output()) as f32;
+        let pulse_out = if pulses == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulses + 100.0)
+        };
+
+        // the triangle, noise, and DMC share a DAC
+        let tnd = self.triangle.output() as f32 / 8227.0
+            + self.noise.output() as f32 / 12241.0
+            + self.dmc.output() as f32 / 22638.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+
+        pulse_out + tnd_out
+    }
+}
+
+impl SaveState for APU {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.pulse_1.save_state(writer);
+        self.pulse_2.save_state(writer);
+        self.triangle.save_state(writer);
+        self.noise.save_state(writer);
+        self.dmc.save_state(writer);
+        writer.write_u32(self.frame_cycle);
+        writer.write_bool(self.five_step_mode);
+        writer.write_bool(self.irq_inhibit);
+        writer.write_bool(self.frame_irq);
+        writer.write_bool(self.even_cycle);
+        writer.write_f64(self.sample_clock);
+        writer.write_f32(self.sample_sum);
+        writer.write_u32(self.sample_count);
+        self.high_pass.save_state(writer);
+        self.low_pass.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.pulse_1.load_state(reader)?;
+        self.pulse_2.load_state(reader)?;
+        self.triangle.load_state(reader)?;
+        self.noise.load_state(reader)?;
+        self.dmc.load_state(reader)?;
+        self.frame_cycle = reader.read_u32()?;
+        self.five_step_mode = reader.read_bool()?;
+        self.irq_inhibit = reader.read_bool()?;
+        self.frame_irq = reader.read_bool()?;
+        self.even_cycle = reader.read_bool()?;
+        self.sample_clock = reader.read_f64()?;
+        self.sample_sum = reader.read_f32()?;
+        self.sample_count = reader.read_u32()?;
+        self.high_pass.load_state(reader)?;
+        self.low_pass.load_state(reader)?;
+        // the pending samples were generated after the state was saved
+        self.samples.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn output_sample_mixes_pulse_nonlinearly() {
+        let mut apu = APU::new();
+        // the silent triangle still holds the first step of its sequence, so it contributes to the mix
+        let without_pulse = apu.output_sample();
+        apu.write_register(0x4015, 0x01);
+        // negated 25% duty (which starts high) at constant volume 15
+        apu.write_register(0x4000, 0xdf);
+        apu.write_register(0x4002, 0x08);
+        apu.write_register(0x4003, 0x08);
+        let expected = 95.88 / (8128.0 / 15.0 + 100.0);
+        assert!((apu.output_sample() - without_pulse - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn output_sample_mixes_triangle_into_tnd() {
+        let mut apu = APU::new();
+        // before it has run, the triangle holds the first step of its sequence, level 15
+        let expected = 159.79 / (1.0 / (15.0 / 8227.0) + 100.0);
+        assert!((apu.output_sample() - expected).abs() < 1e-6);
+    }
+
+    /// Clocks `apu` for `cycles` CPU cycles
+    fn run(apu: &mut APU, cycles: u32) {
+        for _ in 0..cycles {
+            apu.clock();
+        }
+    }
+
+    #[test]
+    fn frame_irq_raised_at_end_of_4_step_sequence_and_cleared_by_status_read() {
+        let mut apu = APU::new();
+        run(&mut apu, FRAME_STEP_4 - 2);
+        assert!(!apu.irq_pending());
+        run(&mut apu, 1);
+        assert!(apu.irq_pending());
+
+        assert_eq!(apu.read_status() & 0x40, 0x40);
+        assert!(!apu.irq_pending());
+        assert_eq!(apu.read_status() & 0x40, 0);
+    }
+
+    #[test]
+    fn half_frames_clock_length_counters() {
+        let mut apu = APU::new();
+        apu.write_register(0x4015, 0x01);
+        // a length of 2, not halted
+        apu.write_register(0x4000, 0x10);
+        apu.write_register(0x4003, 0x18);
+        assert_eq!(apu.peek_status() & 0x01, 0x01);
+
+        // the first half frame is the second step
+        run(&mut apu, FRAME_STEP_2);
+        assert_eq!(apu.peek_status() & 0x01, 0x01);
+        run(&mut apu, FRAME_STEP_4 - FRAME_STEP_2);
+        assert_eq!(apu.peek_status() & 0x01, 0);
+    }
+
+    #[test]
+    fn five_step_mode_never_raises_frame_irq() {
+        let mut apu = APU::new();
+        apu.write_register(0x4017, 0x80);
+        run(&mut apu, FRAME_PERIOD_5_STEP * 2);
+        assert!(!apu.irq_pending());
+    }
+
+    /// Sets a hook on `apu` that records every IRQ it reports
+    fn record_irqs(apu: &mut APU) -> Rc<RefCell<Vec<ApuIrqKind>>> {
+        let irqs = Rc::new(RefCell::new(Vec::new()));
+        let recorded = Rc::clone(&irqs);
+        apu.set_irq_hook(move |kind| recorded.borrow_mut().push(kind));
+        irqs
+    }
+
+    #[test]
+    fn irq_hook_reports_frame_irq() {
+        let mut apu = APU::new();
+        let irqs = record_irqs(&mut apu);
+
+        // the IRQ is raised three cycles before the end of the 4-step sequence
+        run(&mut apu, FRAME_STEP_4 - 2);
+        assert!(irqs.borrow().is_empty());
+        apu.clock();
+        assert_eq!(*irqs.borrow(), vec![ApuIrqKind::Frame]);
+        assert!(apu.irq_pending());
+
+        // the flag stays raised over the next cycles, but that is still one IRQ
+        run(&mut apu, 4);
+        assert_eq!(irqs.borrow().len(), 1);
+    }
+
+    #[test]
+    fn irq_hook_ignores_inhibited_frame_irq() {
+        let mut apu = APU::new();
+        let irqs = record_irqs(&mut apu);
+        apu.write_register(0x4017, 0x40);
+        run(&mut apu, FRAME_PERIOD_4_STEP * 2);
+        assert!(irqs.borrow().is_empty());
+    }
+
+    #[test]
+    fn irq_hook_reports_dmc_irq() {
+        let mut apu = APU::new();
+        let irqs = record_irqs(&mut apu);
+        apu.write_register(0x4017, 0x40);
+        // a one-byte sample, with the IRQ enabled and no looping
+        apu.write_register(0x4010, 0x80);
+        apu.write_register(0x4013, 0x00);
+        apu.write_register(0x4015, 0x10);
+        assert!(apu.dmc_fetch_address().is_some());
+        apu.dmc_fill_buffer(0x55);
+        assert_eq!(*irqs.borrow(), vec![ApuIrqKind::Dmc]);
+    }
+
+    #[test]
+    fn drain_samples_produces_samples_at_the_requested_rate() {
+        let mut apu = APU::new();
+        apu.set_sample_rate(48000);
+        // a tenth of a second of CPU cycles
+        run(&mut apu, Region::Ntsc.cpu_clock_rate() / 10);
+
+        let mut out = [0.0; 8000];
+        let count = apu.drain_samples(&mut out);
+        assert!((4799..=4801).contains(&count), "{} samples", count);
+        // everything was drained
+        assert_eq!(apu.drain_samples(&mut out), 0);
+    }
+
+    #[test]
+    fn drain_samples_fills_at_most_the_buffer() {
+        let mut apu = APU::new();
+        run(&mut apu, 10_000);
+        let mut out = [0.0; 16];
+        assert_eq!(apu.drain_samples(&mut out), 16);
+        assert!(apu.drain_samples(&mut out) > 0);
+    }
 }
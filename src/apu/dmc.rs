@@ -0,0 +1,298 @@
+// dmc.rs
+// Implements the APU's delta modulation channel
+
+use crate::state::{SaveState, StateReader, StateWriter};
+
+/// The timer periods, in CPU cycles, selected by the low four bits of the first register
+const DMC_PERIODS: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+/// The delta modulation channel, which plays 1-bit delta-encoded samples from CPU memory.
+/// It is controlled through four registers:
+/// * `IL-- RRRR`: IRQ enable, loop, and rate
+/// * `-DDD DDDD`: direct load of the output level
+/// * `AAAA AAAA`: sample address, as `0xC000 + A * 64`
+/// * `LLLL LLLL`: sample length, as `L * 16 + 1` bytes
+///
+/// Each bit of a sample byte moves the 7-bit output level up or down by two.
+/// The sample bytes are fetched from memory by the DMC itself, stealing cycles from the CPU.
+pub struct Dmc {
+    irq_enabled: bool,
+    looping: bool,
+    period: u16,
+    timer: u16,
+
+    // the output unit
+    level: u8,
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+
+    // the memory reader
+    buffer: Option<u8>,
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+
+    pub irq: bool,
+}
+
+impl Dmc {
+    /// Creates a silent DMC
+    pub fn new() -> Dmc {
+        Dmc {
+            irq_enabled: false,
+            looping: false,
+            period: DMC_PERIODS[0],
+            timer: 0,
+            level: 0,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+            buffer: None,
+            sample_address: 0xc000,
+            sample_length: 1,
+            current_address: 0xc000,
+            bytes_remaining: 0,
+            irq: false,
+        }
+    }
+
+    /// Writes one of the channel's four registers, numbered 0 - 3
+    pub fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = (value & 0x80) != 0;
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+                self.looping = (value & 0x40) != 0;
+                self.period = DMC_PERIODS[(value & 0x0f) as usize];
+            },
+            1 => self.level = value & 0x7f,
+            2 => self.sample_address = 0xc000 | ((value as u16) << 6),
+            _ => self.sample_length = ((value as u16) << 4) | 1,
+        }
+    }
+
+    /// Starts or stops playback through `0x4015`
+    /// Enabling the channel only restarts the sample if the previous one has finished.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    /// Returns whether any sample bytes remain to be played
+    pub fn is_active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    /// Restarts the sample from the beginning
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    /// Returns the address of the next sample byte if the buffer needs refilling
+    /// The bus performs the read (stalling the CPU) and hands the byte back through `fill_buffer`.
+    pub fn fetch_address(&self) -> Option<u16> {
+        if self.buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    /// Accepts a sample byte fetched from `fetch_address`
+    pub fn fill_buffer(&mut self, value: u8) {
+        self.buffer = Some(value);
+
+        // the address wraps around to 0x8000 rather than 0x0000
+        self.current_address = if self.current_address == 0xffff { 0x8000 } else { self.current_address + 1 };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    /// Clocks the timer, once per CPU cycle, playing one bit of the sample each time it expires
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+
+        if !self.silence {
+            // the level saturates rather than wrapping
+            if (self.shift & 1) != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(byte) => {
+                    self.silence = false;
+                    self.shift = byte;
+                },
+                None => self.silence = true,
+            }
+        }
+    }
+
+    /// Returns the channel's current output, 0 - 127
+    pub fn output(&self) -> u8 {
+        self.level
+    }
+}
+
+impl SaveState for Dmc {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.irq_enabled);
+        writer.write_bool(self.looping);
+        writer.write_u16(self.period);
+        writer.write_u16(self.timer);
+        writer.write_u8(self.level);
+        writer.write_u8(self.shift);
+        writer.write_u8(self.bits_remaining);
+        writer.write_bool(self.silence);
+        // an empty buffer is written as a zero byte
+        writer.write_bool(self.buffer.is_some());
+        writer.write_u8(self.buffer.unwrap_or(0));
+        writer.write_u16(self.sample_address);
+        writer.write_u16(self.sample_length);
+        writer.write_u16(self.current_address);
+        writer.write_u16(self.bytes_remaining);
+        writer.write_bool(self.irq);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.irq_enabled = reader.read_bool()?;
+        self.looping = reader.read_bool()?;
+        self.period = reader.read_u16()?;
+        if self.period == 0 {
+            return Err(String::from("Invalid DMC period in save state: 0"));
+        }
+        self.timer = reader.read_u16()?;
+        self.level = reader.read_u8()?;
+        self.shift = reader.read_u8()?;
+        self.bits_remaining = reader.read_u8()?;
+        if self.bits_remaining == 0 {
+            return Err(String::from("Invalid DMC bit count in save state: 0"));
+        }
+        self.silence = reader.read_bool()?;
+        let buffered = reader.read_bool()?;
+        let buffer = reader.read_u8()?;
+        self.buffer = if buffered { Some(buffer) } else { None };
+        self.sample_address = reader.read_u16()?;
+        self.sample_length = reader.read_u16()?;
+        self.current_address = reader.read_u16()?;
+        self.bytes_remaining = reader.read_u16()?;
+        self.irq = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The timer period selected by rate 15
+    const FASTEST_PERIOD: usize = 54;
+
+    /// Clocks `dmc` for long enough to play `bits` bits at the fastest rate
+    fn play_bits(dmc: &mut Dmc, bits: usize) {
+        for _ in 0..bits * FASTEST_PERIOD {
+            dmc.clock_timer();
+        }
+    }
+
+    #[test]
+    fn playback_moves_the_level_by_the_sample_bits() {
+        let mut dmc = Dmc::new();
+        dmc.write_register(0, 0x0f);
+        dmc.write_register(1, 64);
+        dmc.write_register(2, 0x00);
+        dmc.write_register(3, 0x00);
+        dmc.set_enabled(true);
+
+        assert_eq!(dmc.fetch_address(), Some(0xc000));
+        // bits are played least significant first: four ones, then four zeros
+        dmc.fill_buffer(0x0f);
+        assert_eq!(dmc.fetch_address(), None);
+        assert!(!dmc.is_active());
+
+        // the output unit finishes its silent byte before it takes the sample from the buffer
+        play_bits(&mut dmc, 8);
+        assert_eq!(dmc.output(), 64);
+        play_bits(&mut dmc, 4);
+        assert_eq!(dmc.output(), 72);
+        play_bits(&mut dmc, 4);
+        assert_eq!(dmc.output(), 64);
+    }
+
+    #[test]
+    fn level_saturates_at_the_ends_of_its_range() {
+        let mut dmc = Dmc::new();
+        dmc.write_register(0, 0x0f);
+        dmc.write_register(1, 126);
+        dmc.set_enabled(true);
+        dmc.fill_buffer(0xff);
+        play_bits(&mut dmc, 16);
+        assert_eq!(dmc.output(), 126);
+    }
+
+    #[test]
+    fn looping_sample_restarts_from_its_address() {
+        let mut dmc = Dmc::new();
+        dmc.write_register(0, 0x4f);
+        dmc.write_register(2, 0x01);
+        dmc.write_register(3, 0x01);
+        dmc.set_enabled(true);
+        // 17 bytes, starting at 0xC040
+        for offset in 0..17 {
+            assert_eq!(dmc.fetch_address(), Some(0xc040 + offset));
+            dmc.fill_buffer(0);
+            play_bits(&mut dmc, 8);
+        }
+        assert_eq!(dmc.fetch_address(), Some(0xc040));
+    }
+
+    /// Saves `dmc`, then loads the state into a new channel
+    fn reload(dmc: &Dmc) -> Result<(), String> {
+        let mut writer = StateWriter::new();
+        dmc.save_state(&mut writer);
+        let bytes = writer.into_bytes();
+        Dmc::new().load_state(&mut StateReader::new(&bytes))
+    }
+
+    #[test]
+    fn state_with_zero_period_or_bit_count_is_rejected() {
+        let mut dmc = Dmc::new();
+        assert_eq!(reload(&dmc), Ok(()));
+        dmc.period = 0;
+        assert!(reload(&dmc).is_err());
+
+        let mut dmc = Dmc::new();
+        dmc.bits_remaining = 0;
+        assert!(reload(&dmc).is_err());
+    }
+}
@@ -0,0 +1,121 @@
+// filter.rs
+// First-order filters, which approximate the filtering in the NES's audio output circuitry
+
+use std::f32::consts::PI;
+
+use crate::state::{SaveState, StateReader, StateWriter};
+
+/// A first-order high-pass filter, removing frequencies below its cutoff (and the DC offset)
+pub struct HighPass {
+    alpha: f32,
+    previous_input: f32,
+    previous_output: f32,
+}
+
+impl HighPass {
+    /// Creates a filter with the given cutoff, for a signal sampled at `sample_rate`
+    pub fn new(cutoff: f32, sample_rate: f32) -> HighPass {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        HighPass {
+            alpha: rc / (rc + dt),
+            previous_input: 0.0,
+            previous_output: 0.0,
+        }
+    }
+
+    /// Filters the next sample
+    pub fn process(&mut self, input: f32) -> f32 {
+        let output = self.alpha * (self.previous_output + input - self.previous_input);
+        self.previous_input = input;
+        self.previous_output = output;
+        output
+    }
+}
+
+/// A first-order low-pass filter, removing frequencies above its cutoff
+pub struct LowPass {
+    alpha: f32,
+    previous_output: f32,
+}
+
+impl LowPass {
+    /// Creates a filter with the given cutoff, for a signal sampled at `sample_rate`
+    pub fn new(cutoff: f32, sample_rate: f32) -> LowPass {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        LowPass {
+            alpha: dt / (rc + dt),
+            previous_output: 0.0,
+        }
+    }
+
+    /// Filters the next sample
+    pub fn process(&mut self, input: f32) -> f32 {
+        self.previous_output += self.alpha * (input - self.previous_output);
+        self.previous_output
+    }
+}
+
+impl SaveState for HighPass {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_f32(self.previous_input);
+        writer.write_f32(self.previous_output);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.previous_input = reader.read_f32()?;
+        self.previous_output = reader.read_f32()?;
+        Ok(())
+    }
+}
+
+impl SaveState for LowPass {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_f32(self.previous_output);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.previous_output = reader.read_f32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn high_pass_removes_dc_offset() {
+        let mut filter = HighPass::new(90.0, 48000.0);
+        let mut output = 0.0;
+        for _ in 0..48000 {
+            output = filter.process(0.5);
+        }
+        assert!(output.abs() < 1e-3);
+    }
+
+    #[test]
+    fn low_pass_settles_on_dc_level() {
+        let mut filter = LowPass::new(14000.0, 48000.0);
+        let mut output = 0.0;
+        for _ in 0..100 {
+            output = filter.process(0.5);
+        }
+        assert!((output - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn low_pass_attenuates_alternating_signal() {
+        // a signal at the Nyquist frequency, well above the cutoff at this rate
+        let mut filter = LowPass::new(1000.0, 48000.0);
+        let mut peak: f32 = 0.0;
+        for i in 0..1000 {
+            let output = filter.process(if i % 2 == 0 { 1.0 } else { -1.0 });
+            if i > 500 {
+                peak = peak.max(output.abs());
+            }
+        }
+        assert!(peak < 0.1);
+    }
+}
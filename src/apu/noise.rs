@@ -0,0 +1,180 @@
+// noise.rs
+// Implements the APU's noise channel
+
+use super::units::{Envelope, LengthCounter};
+use crate::state::{SaveState, StateReader, StateWriter};
+
+/// The timer periods, in CPU cycles, selected by the low four bits of the period register
+const NOISE_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+/// The noise channel, controlled through three registers:
+/// * `--LC VVVV`: length counter halt/envelope loop, constant volume, and volume/envelope period
+/// * `M--- PPPP`: mode and period
+/// * `LLLL L---`: length counter load
+///
+/// The noise is produced by a 15-bit linear feedback shift register; in mode 1, the feedback is taken from bit 6 rather than bit 1, producing a short, metallic-sounding sequence.
+pub struct Noise {
+    shift: u16,
+    short_mode: bool,
+    timer: u16,
+    period: u16,
+
+    pub length: LengthCounter,
+    envelope: Envelope,
+}
+
+impl Noise {
+    /// Creates a silent noise channel; the shift register starts at 1
+    pub fn new() -> Noise {
+        Noise {
+            shift: 1,
+            short_mode: false,
+            timer: 0,
+            period: NOISE_PERIODS[0],
+            length: LengthCounter::default(),
+            envelope: Envelope::default(),
+        }
+    }
+
+    /// Writes one of the channel's registers, numbered 0 - 3 (register 1 is unused)
+    pub fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.length.set_halted((value & 0x20) != 0);
+                self.envelope.write(value);
+            },
+            2 => {
+                self.short_mode = (value & 0x80) != 0;
+                self.period = NOISE_PERIODS[(value & 0x0f) as usize];
+            },
+            3 => {
+                self.length.load(value >> 3);
+                self.envelope.restart();
+            },
+            _ => {},
+        }
+    }
+
+    /// Clocks the timer, once per CPU cycle, shifting the register each time it expires
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period - 1;
+            self.clock_shift_register();
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Shifts the register right by one, feeding back the XOR of bit 0 and either bit 1 or bit 6
+    fn clock_shift_register(&mut self) {
+        let tap = if self.short_mode { 6 } else { 1 };
+        let feedback = (self.shift & 1) ^ ((self.shift >> tap) & 1);
+        self.shift = (self.shift >> 1) | (feedback << 14);
+    }
+
+    /// Clocks the envelope, on each quarter frame
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// Clocks the length counter, on each half frame
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    /// Returns the channel's current output, 0 - 15
+    pub fn output(&self) -> u8 {
+        if self.length.is_zero() || (self.shift & 1) != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+impl SaveState for Noise {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.shift);
+        writer.write_bool(self.short_mode);
+        writer.write_u16(self.timer);
+        writer.write_u16(self.period);
+        self.length.save_state(writer);
+        self.envelope.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.shift = reader.read_u16()?;
+        self.short_mode = reader.read_bool()?;
+        self.timer = reader.read_u16()?;
+        self.period = reader.read_u16()?;
+        if self.period == 0 {
+            return Err(String::from("Invalid noise period in save state: 0"));
+        }
+        self.length.load_state(reader)?;
+        self.envelope.load_state(reader)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the shift register's values over the first `count` shifts, in the given mode
+    fn shift_sequence(short_mode: bool, count: usize) -> Vec<u16> {
+        let mut noise = Noise::new();
+        noise.write_register(2, if short_mode { 0x80 } else { 0x00 });
+        (0..count)
+            .map(|_| {
+                noise.clock_shift_register();
+                noise.shift
+            })
+            .collect()
+    }
+
+    #[test]
+    fn long_mode_taps_bit_1() {
+        let sequence = shift_sequence(false, 16);
+        assert_eq!(sequence[..4], [0x4000, 0x2000, 0x1000, 0x0800]);
+        assert_eq!(sequence[13..], [0x0002, 0x4001, 0x6000]);
+    }
+
+    #[test]
+    fn short_mode_taps_bit_6() {
+        let sequence = shift_sequence(true, 16);
+        assert_eq!(sequence[13..], [0x0402, 0x0201, 0x4100]);
+    }
+
+    #[test]
+    fn sequence_lengths_match_the_modes() {
+        // the long sequence is maximal; from a seed of 1, the short one repeats after 93 shifts
+        for (short_mode, length) in [(false, 32767), (true, 93)] {
+            let sequence = shift_sequence(short_mode, length);
+            assert_eq!(sequence[length - 1], 1);
+            assert!(!sequence[..length - 1].contains(&1));
+        }
+    }
+
+    #[test]
+    fn timer_shifts_once_per_period() {
+        let mut noise = Noise::new();
+        // period index 1 is 8 CPU cycles
+        noise.write_register(2, 0x01);
+        for _ in 0..17 {
+            noise.clock_timer();
+        }
+        assert_eq!(noise.shift, 0x1000);
+    }
+
+    #[test]
+    fn state_with_zero_period_is_rejected() {
+        let mut noise = Noise::new();
+        noise.period = 0;
+        let mut writer = StateWriter::new();
+        noise.save_state(&mut writer);
+        let bytes = writer.into_bytes();
+        assert!(Noise::new().load_state(&mut StateReader::new(&bytes)).is_err());
+    }
+}
@@ -0,0 +1,226 @@
+// pulse.rs
+// Implements the APU's two pulse (square wave) channels
+
+use super::units::{Envelope, LengthCounter};
+use crate::state::{SaveState, StateReader, StateWriter};
+
+/// The waveforms for each duty cycle, in the order the sequencer steps through them
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],   // 12.5%
+    [0, 1, 1, 0, 0, 0, 0, 0],   // 25%
+    [0, 1, 1, 1, 1, 0, 0, 0],   // 50%
+    [1, 0, 0, 1, 1, 1, 1, 1],   // 25% negated
+];
+
+/// A pulse channel, controlled through four registers:
+/// * `DDLC VVVV`: duty, length counter halt/envelope loop, constant volume, and volume/envelope period
+/// * `EPPP NSSS`: sweep enable, period, negate, and shift
+/// * `TTTT TTTT`: timer low
+/// * `LLLL LTTT`: length counter load and timer high
+pub struct Pulse {
+    // the two channels differ only in how the sweep unit negates
+    ones_complement: bool,
+
+    duty: u8,
+    sequence: u8,
+    timer: u16,
+    period: u16,
+
+    pub length: LengthCounter,
+    envelope: Envelope,
+
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    /// Creates a pulse channel; the first channel's sweep unit negates with one's complement, the second with two's complement
+    pub fn new(ones_complement: bool) -> Pulse {
+        Pulse {
+            ones_complement,
+            duty: 0,
+            sequence: 0,
+            timer: 0,
+            period: 0,
+            length: LengthCounter::default(),
+            envelope: Envelope::default(),
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
+        }
+    }
+
+    /// Writes one of the channel's four registers, numbered 0 - 3
+    pub fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.duty = value >> 6;
+                self.length.set_halted((value & 0x20) != 0);
+                self.envelope.write(value);
+            },
+            1 => {
+                self.sweep_enabled = (value & 0x80) != 0;
+                self.sweep_period = (value >> 4) & 0x07;
+                self.sweep_negate = (value & 0x08) != 0;
+                self.sweep_shift = value & 0x07;
+                self.sweep_reload = true;
+            },
+            2 => self.period = (self.period & 0x0700) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00ff) | (((value & 0x07) as u16) << 8);
+                self.length.load(value >> 3);
+                self.sequence = 0;
+                self.envelope.restart();
+            },
+        }
+    }
+
+    /// Clocks the timer; this happens every APU cycle (every other CPU cycle)
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            // the sequencer counts down
+            self.sequence = (self.sequence + 7) & 0x07;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Clocks the envelope, on each quarter frame
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// Clocks the length counter and sweep unit, on each half frame
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.sweep_muting() {
+            self.period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    /// Returns the period the sweep unit is moving towards
+    fn sweep_target(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        if self.sweep_negate {
+            let change = if self.ones_complement { change + 1 } else { change };
+            self.period.saturating_sub(change)
+        } else {
+            self.period + change
+        }
+    }
+
+    /// Returns whether the sweep unit is muting the channel: very high notes, or a target out of the timer's range
+    /// This happens even when the sweep unit is disabled.
+    fn sweep_muting(&self) -> bool {
+        self.period < 8 || self.sweep_target() > 0x07ff
+    }
+
+    /// Returns the channel's current output, 0 - 15
+    pub fn output(&self) -> u8 {
+        if self.length.is_zero() || self.sweep_muting() || DUTY_TABLE[self.duty as usize][self.sequence as usize] == 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+impl SaveState for Pulse {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.duty);
+        writer.write_u8(self.sequence);
+        writer.write_u16(self.timer);
+        writer.write_u16(self.period);
+        self.length.save_state(writer);
+        self.envelope.save_state(writer);
+        writer.write_bool(self.sweep_enabled);
+        writer.write_u8(self.sweep_period);
+        writer.write_bool(self.sweep_negate);
+        writer.write_u8(self.sweep_shift);
+        writer.write_u8(self.sweep_divider);
+        writer.write_bool(self.sweep_reload);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.duty = reader.read_u8()?;
+        self.sequence = reader.read_u8()?;
+        self.timer = reader.read_u16()?;
+        self.period = reader.read_u16()?;
+        self.length.load_state(reader)?;
+        self.envelope.load_state(reader)?;
+        self.sweep_enabled = reader.read_bool()?;
+        self.sweep_period = reader.read_u8()?;
+        self.sweep_negate = reader.read_bool()?;
+        self.sweep_shift = reader.read_u8()?;
+        self.sweep_divider = reader.read_u8()?;
+        self.sweep_reload = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates an enabled pulse channel at constant volume 15, with a timer period of 8 and `duty_and_flags` in the top bits of its first register
+    fn pulse_with(duty_and_flags: u8, length_index: u8) -> Pulse {
+        let mut pulse = Pulse::new(false);
+        pulse.length.set_enabled(true);
+        pulse.write_register(0, duty_and_flags | 0x1f);
+        pulse.write_register(2, 0x08);
+        pulse.write_register(3, length_index << 3);
+        pulse
+    }
+
+    #[test]
+    fn sequencer_steps_through_the_duty_cycle() {
+        // 25% duty, with the length counter halted
+        let mut pulse = pulse_with(0x60, 1);
+        let mut outputs = vec![pulse.output()];
+        for _ in 0..7 {
+            // the timer reloads with the period, so it takes period + 1 clocks to step the sequencer
+            pulse.clock_timer();
+            outputs.push(pulse.output());
+            for _ in 0..8 {
+                pulse.clock_timer();
+            }
+        }
+        // the sequencer counts down from 0, so it reads the duty table backwards
+        assert_eq!(outputs, vec![0, 0, 0, 0, 0, 0, 15, 15]);
+    }
+
+    #[test]
+    fn length_counter_silences_the_channel() {
+        // the negated 25% duty starts high; a length index of 3 loads 2
+        let mut pulse = pulse_with(0xc0, 3);
+        assert_eq!(pulse.output(), 15);
+        pulse.clock_half_frame();
+        assert_eq!(pulse.output(), 15);
+        pulse.clock_half_frame();
+        assert_eq!(pulse.output(), 0);
+    }
+
+    #[test]
+    fn halted_length_counter_does_not_count_down() {
+        let mut pulse = pulse_with(0xe0, 3);
+        for _ in 0..4 {
+            pulse.clock_half_frame();
+        }
+        assert_eq!(pulse.output(), 15);
+    }
+}
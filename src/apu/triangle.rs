@@ -0,0 +1,177 @@
+// triangle.rs
+// Implements the APU's triangle channel
+
+use super::units::LengthCounter;
+use crate::state::{SaveState, StateReader, StateWriter};
+
+/// The 32-step sequence the triangle channel outputs
+const TRIANGLE_SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+/// The triangle channel, controlled through three registers:
+/// * `CRRR RRRR`: length counter halt/linear counter control, and linear counter reload value
+/// * `TTTT TTTT`: timer low
+/// * `LLLL LTTT`: length counter load and timer high
+///
+/// The triangle has no volume control; instead, it has a linear counter as well as a length counter, and the sequencer only advances while both are non-zero.
+pub struct Triangle {
+    sequence: u8,
+    timer: u16,
+    period: u16,
+
+    pub length: LengthCounter,
+
+    control: bool,
+    linear_counter: u8,
+    linear_reload_value: u8,
+    linear_reload: bool,
+}
+
+impl Triangle {
+    /// Creates a silent triangle channel
+    pub fn new() -> Triangle {
+        Triangle {
+            sequence: 0,
+            timer: 0,
+            period: 0,
+            length: LengthCounter::default(),
+            control: false,
+            linear_counter: 0,
+            linear_reload_value: 0,
+            linear_reload: false,
+        }
+    }
+
+    /// Writes one of the channel's registers, numbered 0 - 3 (register 1 is unused)
+    pub fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.control = (value & 0x80) != 0;
+                self.length.set_halted(self.control);
+                self.linear_reload_value = value & 0x7f;
+            },
+            2 => self.period = (self.period & 0x0700) | value as u16,
+            3 => {
+                self.period = (self.period & 0x00ff) | (((value & 0x07) as u16) << 8);
+                self.length.load(value >> 3);
+                self.linear_reload = true;
+            },
+            _ => {},
+        }
+    }
+
+    /// Clocks the timer; unlike the other channels, this happens every CPU cycle
+    /// When either counter is zero, the sequencer freezes where it is rather than resetting.
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if !self.length.is_zero() && self.linear_counter > 0 {
+                self.sequence = (self.sequence + 1) & 0x1f;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Clocks the linear counter, on each quarter frame
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    /// Clocks the length counter, on each half frame
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    /// Returns the channel's current output, 0 - 15
+    pub fn output(&self) -> u8 {
+        TRIANGLE_SEQUENCE[self.sequence as usize]
+    }
+}
+
+impl SaveState for Triangle {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.sequence);
+        writer.write_u16(self.timer);
+        writer.write_u16(self.period);
+        self.length.save_state(writer);
+        writer.write_bool(self.control);
+        writer.write_u8(self.linear_counter);
+        writer.write_u8(self.linear_reload_value);
+        writer.write_bool(self.linear_reload);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.sequence = reader.read_u8()?;
+        self.timer = reader.read_u16()?;
+        self.period = reader.read_u16()?;
+        self.length.load_state(reader)?;
+        self.control = reader.read_bool()?;
+        self.linear_counter = reader.read_u8()?;
+        self.linear_reload_value = reader.read_u8()?;
+        self.linear_reload = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates an enabled triangle channel with a timer period of 0 (so each timer clock steps the sequencer), and its linear counter loaded with `linear`
+    fn running_triangle(linear: u8) -> Triangle {
+        let mut triangle = Triangle::new();
+        triangle.length.set_enabled(true);
+        triangle.write_register(0, linear);
+        triangle.write_register(2, 0x00);
+        triangle.write_register(3, 0x08);
+        triangle.clock_quarter_frame();
+        triangle
+    }
+
+    #[test]
+    fn sequencer_steps_down_then_up() {
+        let mut triangle = running_triangle(10);
+        let mut outputs = vec![];
+        for _ in 0..18 {
+            triangle.clock_timer();
+            outputs.push(triangle.output());
+        }
+        assert_eq!(outputs, vec![14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2]);
+    }
+
+    #[test]
+    fn sequencer_freezes_when_linear_counter_expires() {
+        let mut triangle = running_triangle(2);
+        for _ in 0..3 {
+            triangle.clock_timer();
+        }
+        assert_eq!(triangle.output(), 12);
+
+        triangle.clock_quarter_frame();
+        triangle.clock_quarter_frame();
+        for _ in 0..3 {
+            triangle.clock_timer();
+        }
+        // the output holds its level rather than dropping to 0
+        assert_eq!(triangle.output(), 12);
+    }
+
+    #[test]
+    fn sequencer_freezes_when_length_counter_expires() {
+        let mut triangle = running_triangle(0x7f);
+        triangle.clock_timer();
+        triangle.length.set_enabled(false);
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 14);
+    }
+}
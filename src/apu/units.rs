@@ -0,0 +1,143 @@
+// units.rs
+// The building blocks shared by several of the APU's channels
+
+use crate::state::{SaveState, StateReader, StateWriter};
+
+/// The values loaded into a length counter, indexed by the top five bits of the channel's last register
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// Silences a channel after a set duration.
+/// The counter is clocked on every half frame, and the channel is muted once it reaches zero.
+#[derive(Default)]
+pub struct LengthCounter {
+    counter: u8,
+    halted: bool,
+    enabled: bool,
+}
+
+impl LengthCounter {
+    /// Enables or disables the counter through `0x4015`; disabling it silences the channel immediately
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    /// Halts (or resumes) the countdown
+    pub fn set_halted(&mut self, halted: bool) {
+        self.halted = halted;
+    }
+
+    /// Loads the counter from the length table; ignored while the channel is disabled
+    pub fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(index & 0x1f) as usize];
+        }
+    }
+
+    /// Counts down, unless halted
+    pub fn clock(&mut self) {
+        if self.counter > 0 && !self.halted {
+            self.counter -= 1;
+        }
+    }
+
+    /// Returns whether the counter has run out, muting the channel
+    pub fn is_zero(&self) -> bool {
+        self.counter == 0
+    }
+}
+
+/// Produces either a constant volume or a decaying sawtooth.
+/// The envelope is clocked on every quarter frame; each time its divider expires, the decay level counts down from 15, optionally looping.
+#[derive(Default)]
+pub struct Envelope {
+    start: bool,
+    looping: bool,
+    constant: bool,
+    // the constant volume, which is also the divider's period
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    /// Sets the envelope from the low six bits of the channel's first register: `--LC VVVV`
+    pub fn write(&mut self, value: u8) {
+        self.looping = (value & 0x20) != 0;
+        self.constant = (value & 0x10) != 0;
+        self.volume = value & 0x0f;
+    }
+
+    /// Restarts the envelope on the next clock; this happens whenever the channel's last register is written
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    /// Clocks the envelope's divider
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    /// Returns the current volume
+    pub fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+impl SaveState for LengthCounter {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.counter);
+        writer.write_bool(self.halted);
+        writer.write_bool(self.enabled);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.counter = reader.read_u8()?;
+        self.halted = reader.read_bool()?;
+        self.enabled = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+impl SaveState for Envelope {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.start);
+        writer.write_bool(self.looping);
+        writer.write_bool(self.constant);
+        writer.write_u8(self.volume);
+        writer.write_u8(self.divider);
+        writer.write_u8(self.decay);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.start = reader.read_bool()?;
+        self.looping = reader.read_bool()?;
+        self.constant = reader.read_bool()?;
+        self.volume = reader.read_u8()?;
+        self.divider = reader.read_u8()?;
+        self.decay = reader.read_u8()?;
+        Ok(())
+    }
+}
@@ -1,7 +1,13 @@
 // cpu.rs
 // Implements the 6502 variant used in the NES
 
-mod instruction;
+pub mod instruction;
+
+use std::fmt;
+
+use crate::disasm;
+use crate::mem::{Bus, Mem, RamInit};
+use crate::state::{SaveState, StateReader, StateWriter};
 
 /// The stack page is hard-wired to page 1
 const STACK_PAGE: u8 = 0x01;
@@ -10,19 +16,16 @@ const STACK_PAGE: u8 = 0x01;
 /// * the NMI vector, located at `0xFFFA - 0xFFFB`;
 /// * the reset vector, located at `0xFFFC - 0xFFFD`;
 /// * the IRQ vector, located `0xFFFE - 0xFFFF`
+///
 /// Since these are hard-coded, we can use named constants for them.
 pub const NMI_VECTOR: u16 = 0xfffa;
 pub const RESET_VECTOR: u16 = 0xfffc;
 pub const IRQ_VECTOR: u16 = 0xfffe;
 
-/// The NTSC version of the NES had a clock speed of 1.79 MHz, but differed from PAL
-pub const NTSC_SPEED: u32 = 1790000;
-/// The PAL version of the NES had a clock speed of 1.66 MHz
-pub const PAL_SPEED: u32 = 1660000;
-
 // Constants for our flag positions
 const N_FLAG: u8 = 0b10000000;
 const V_FLAG: u8 = 0b01000000;
+const U_FLAG: u8 = 0b00100000; // unused bit 5; always reads as set when pushed
 const B_FLAG: u8 = 0b00010000;
 const D_FLAG: u8 = 0b00001000;
 const I_FLAG: u8 = 0b00000100;
@@ -40,14 +43,87 @@ enum Flag {
     Carry,
 }
 
+/// The reason the CPU stopped executing code
+#[derive(PartialEq, Eq)]
+#[derive(Debug, Copy, Clone)]
+pub enum StopReason {
+    /// An opcode that isn't in the instruction table was fetched; holds the opcode and the address it was fetched from
+    IllegalOpcode(u8, u16),
+    /// An instruction that deliberately halts the processor (such as the unstable unofficial opcodes) was executed
+    Halt,
+    /// A `BRK` stopped execution
+    Brk,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::IllegalOpcode(opcode, address) => write!(f, "illegal opcode ${:02X} at ${:04X}", opcode, address),
+            StopReason::Halt => write!(f, "halted"),
+            StopReason::Brk => write!(f, "BRK"),
+        }
+    }
+}
+
+/// How a call to one of the run loops ended
+#[derive(PartialEq, Eq)]
+#[derive(Debug, Copy, Clone)]
+pub enum RunOutcome {
+    /// The loop ran for as long as it was asked to
+    Completed,
+    /// The CPU stopped before the loop finished; holds why, and the address of the instruction it stopped on
+    Halted { reason: StopReason, pc: u16 },
+}
+
+/// A single access the CPU makes to memory, as reported to a bus trace
+#[derive(PartialEq, Eq)]
+#[derive(Debug, Copy, Clone)]
+pub enum BusOp {
+    /// A read of the address
+    Read(u16),
+    /// A write of the value to the address
+    Write(u16, u8),
+}
+
+/// The levels of the CPU's interrupt inputs, as driven by the devices on its bus
+/// Several devices can pull the IRQ line at once, so the bus ORs their requests together before the CPU sees them.
+#[derive(PartialEq, Eq)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct InterruptLines {
+    /// The IRQ line is level-triggered: an IRQ is taken at each instruction boundary while it is asserted and the `I` flag is clear
+    pub irq: bool,
+    /// The NMI line is edge-triggered: one NMI is taken each time it goes from released to asserted
+    pub nmi: bool,
+}
+
 /// The struct that implements the NES's CPU.
-pub struct CPU {
+/// The CPU is generic over the memory it is attached to, so the 6502 core can be used with memory maps other than the NES's; by default, it is attached to the NES's bus.
+pub struct CPU<M = Bus> {
     // track cycle count since last vblank
     cycles: u64,
 
     // whether the processor is running
     running: bool,
 
+    // why the processor stopped, if it has
+    stop_reason: Option<StopReason>,
+
+    // the address of the instruction being executed, and of the one the processor stopped on
+    instruction_address: u16,
+    stop_address: u16,
+
+    // whether the last indexed address computation crossed a page boundary
+    page_crossed: bool,
+
+    // whether the D flag affects ADC and SBC; the NES's 2A03 has decimal mode disconnected
+    decimal_enabled: bool,
+
+    // whether BRK stops the processor instead of entering the IRQ handler
+    stop_on_brk: bool,
+
+    // the level of the NMI line when it was last polled, so that only its rising edge triggers an NMI
+    nmi_line: bool,
+
     // processor registers
     status: u8,
     pc: u16,
@@ -56,24 +132,18 @@ pub struct CPU {
     x: u8,
     y: u8,
 
-    // processor memory
-    pub memory: [u8; 65536],
+    // the bus through which all memory accesses go
+    bus: M,
+
+    // called with every access the CPU makes to the bus, in order
+    bus_trace: Option<Box<dyn FnMut(BusOp)>>,
 }
 
 impl Default for CPU {
+    /// Creates a CPU attached to a blank bus, with no cartridge inserted
     #[inline]
     fn default() -> CPU {
-        CPU {
-            cycles: 0,
-            running: false,
-            status: 0,
-            pc: 0,
-            sp: 0,
-            a: 0,
-            x: 0,
-            y: 0,
-            memory: [0; 65536]
-        }
+        CPU::new(Bus::default())
     }
 }
 
@@ -107,6 +177,78 @@ fn get_flag_constant(f: Flag) -> u8 {
 }
 
 impl CPU {
+    /// Creates a CPU attached to `bus`
+    /// The CPU starts stopped, with its registers cleared; call `power_on` to read the reset vector and start it.
+    /// To run a game, insert its cartridge with `Bus::set_mapper` (see `mapper::create_mapper`) before powering on, so the reset vector is read from the cartridge.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rust_nes::cpu::{CPU, RESET_VECTOR};
+    /// use rust_nes::mem::Bus;
+    ///
+    /// let mut cpu = CPU::new(Bus::default());
+    /// cpu.load_vector(RESET_VECTOR, 0x8000);
+    /// cpu.power_on();
+    /// cpu.load_program(0x8000, &[0xa2, 0x05, 0xe8]).unwrap();    // LDX #$05; INX
+    /// cpu.step();
+    /// cpu.step();
+    /// assert_eq!(cpu.x(), 0x06);
+    /// ```
+    pub fn new(bus: Bus) -> CPU {
+        CPU::with_memory(bus)
+    }
+}
+
+impl<M: Mem> CPU<M> {
+    /// Creates a CPU attached to any memory, rather than the NES's bus
+    /// Every read and write the CPU makes goes through `memory`, which can be reached afterwards with `bus` and `bus_mut`.
+    pub fn with_memory(memory: M) -> CPU<M> {
+        CPU {
+            cycles: 0,
+            running: false,
+            stop_reason: None,
+            instruction_address: 0,
+            stop_address: 0,
+            page_crossed: false,
+            decimal_enabled: true,
+            stop_on_brk: false,
+            nmi_line: false,
+            status: 0,
+            pc: 0,
+            sp: 0,
+            a: 0,
+            x: 0,
+            y: 0,
+            bus: memory,
+            bus_trace: None,
+        }
+    }
+
+    /// Reads a byte from the bus
+    fn read(&mut self, address: u16) -> u8 {
+        if let Some(trace) = &mut self.bus_trace {
+            trace(BusOp::Read(address));
+        }
+        self.bus.read(address)
+    }
+
+    /// Writes a byte to the bus
+    fn write(&mut self, address: u16, value: u8) {
+        if let Some(trace) = &mut self.bus_trace {
+            trace(BusOp::Write(address, value));
+        }
+        self.bus.write(address, value);
+    }
+
+    /// Reads the operand of a read-modify-write instruction
+    /// While the 6502 modifies the value, it writes the unmodified value back, which is visible to memory-mapped registers.
+    fn read_for_modify(&mut self, address: u16) -> u8 {
+        let value = self.read(address);
+        self.write(address, value);
+        value
+    }
+
     /// Sets the register flag `f` to the value `v`
     fn set_flag(&mut self, f: Flag, v: bool) {
         let flag_constant = get_flag_constant(f);
@@ -120,58 +262,17 @@ impl CPU {
     }
 
     /// Reads an 8-bit value for a register load according to the addressing mode
-    /// This function automatically reads the appropriate number of bytes and updates the status register according to the value read
+    /// The value is either the immediate operand or the byte at the operand's effective address; `page_crossed` is updated for the extra cycle indexed reads can take
     fn read_value(&mut self, mode: instruction::AddressingMode) -> u8 {
-        // Get the offset
-        let offset = if 
-            mode == instruction::AddressingMode::AbsoluteX || 
-            mode == instruction::AddressingMode::IndirectX || 
-            mode == instruction::AddressingMode::ZeroX {
-            self.x
-        } else if 
-            mode == instruction::AddressingMode::AbsoluteY ||
-            mode == instruction::AddressingMode::IndirectY ||
-            mode == instruction::AddressingMode::ZeroY {
-            self.y
-        } else {
-            0
-        };
-
-        let value: u8;
-
-        // Get the value
         if mode == instruction::AddressingMode::Immediate {
-            value = self.memory[self.pc as usize];
-            self.pc = self.pc.overflowing_add(1).0;
-        }
-        else if
-            mode == instruction::AddressingMode::Zero ||
-            mode == instruction::AddressingMode::ZeroX ||
-            mode == instruction::AddressingMode::ZeroY {
-                let address: u16 = self.read_zp_address(mode);
-                value = self.memory[address as usize];
-        }
-        else if
-            mode == instruction::AddressingMode::Absolute ||
-            mode == instruction::AddressingMode::AbsoluteX ||
-            mode == instruction::AddressingMode::AbsoluteY {
-                let address: u16 = self.read_absolute_address() + offset as u16;
-                value = self.memory[address as usize];
-        }
-        else if mode == instruction::AddressingMode::IndirectX {
-            let address: u16 = self.read_indexed_indirect_address();
-            value = self.memory[address as usize];
-        }
-        else if mode == instruction::AddressingMode::IndirectY {
-            let address: u16 = self.read_indirect_indexed_address();
-            value = self.memory[address as usize];
-        }
-        else {
-            // panic on invalid addressing mode
-            panic!("Illegal addressing mode");
+            let value = self.read(self.pc);
+            self.pc = self.pc.wrapping_add(1);
+            return value;
         }
 
-        value
+        let (address, page_crossed) = self.operand_address(mode);
+        self.page_crossed = page_crossed;
+        self.read(address)
     }
 
     /// Update the status register based on a given value
@@ -184,64 +285,83 @@ impl CPU {
         else
         {
             self.set_flag(Flag::Zero, false);
-            
-            if value > 127 {
-                self.set_flag(Flag::Negative, true);
-            }
+            self.set_flag(Flag::Negative, value > 127);
         }
     }
 
-    fn read_address(&mut self, mode: instruction::AddressingMode) -> u16 {
-        if mode == instruction::AddressingMode::Zero ||
-            mode == instruction::AddressingMode::ZeroX ||
-            mode == instruction::AddressingMode::ZeroY
-        {
-            return self.read_zp_address(mode);
-        }
-        else if
-            mode == instruction::AddressingMode::Absolute ||
-            mode == instruction::AddressingMode::AbsoluteX ||
-            mode == instruction::AddressingMode::AbsoluteY
-        {
-            return self.read_absolute_address() + 
-                if mode == instruction::AddressingMode::AbsoluteX { self.x as u16 }
-                else if mode == instruction::AddressingMode::AbsoluteY { self.y as u16 }
-                else { 0 };
-        }
-        else if
-            mode == instruction::AddressingMode::Indirect
-        {
-            return self.read_indirect_address();
-        }
-        else if mode == instruction::AddressingMode::IndirectX {
-            return self.read_indexed_indirect_address();
-        }
-        else if mode == instruction::AddressingMode::IndirectY {
-            return self.read_indirect_indexed_address();
+    /// Reads the operand of an instruction that accesses memory, returning the effective address and whether indexing crossed a page boundary
+    /// The pc is moved past the operand. Page crossings only happen in the indexed modes that add to a full address (absolute X and Y, and indirect Y); the zero page modes wrap within the page instead.
+    /// When the page is crossed, the 6502 first reads from the address before the carry into the high byte was fixed up, so that read is made here too.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the addressing mode doesn't address memory (implied, accumulator, immediate, and relative).
+    fn operand_address(&mut self, mode: instruction::AddressingMode) -> (u16, bool) {
+        match mode {
+            instruction::AddressingMode::Zero |
+            instruction::AddressingMode::ZeroX |
+            instruction::AddressingMode::ZeroY => (self.read_zp_address(mode), false),
+            instruction::AddressingMode::Absolute => (self.read_absolute_address(), false),
+            instruction::AddressingMode::AbsoluteX |
+            instruction::AddressingMode::AbsoluteY => {
+                let base = self.read_absolute_address();
+                let offset = if mode == instruction::AddressingMode::AbsoluteX { self.x } else { self.y };
+                let address = base.wrapping_add(offset as u16);
+                let page_crossed = (base & 0xff00) != (address & 0xff00);
+                if page_crossed {
+                    self.read(address.wrapping_sub(0x100));
+                }
+                (address, page_crossed)
+            },
+            instruction::AddressingMode::Indirect => (self.read_indirect_address(), false),
+            instruction::AddressingMode::IndirectX => (self.read_indexed_indirect_address(), false),
+            instruction::AddressingMode::IndirectY => {
+                let (address, page_crossed) = self.read_indirect_indexed_address();
+                if page_crossed {
+                    self.read(address.wrapping_sub(0x100));
+                }
+                (address, page_crossed)
+            },
+            _ => panic!("Illegal addressing mode"),
         }
-        else {
-            return 0;
+    }
+
+    /// Resolves the address for an instruction that writes memory (a store or a read-modify-write)
+    /// Unlike reads, which can skip it, writes in the indexed modes always take the cycle that fixes up the high byte, so the extra read is made even when the page isn't crossed.
+    fn write_operand_address(&mut self, mode: instruction::AddressingMode) -> u16 {
+        let (address, page_crossed) = self.operand_address(mode);
+        let indexed = matches!(
+            mode,
+            instruction::AddressingMode::AbsoluteX | instruction::AddressingMode::AbsoluteY | instruction::AddressingMode::IndirectY
+        );
+        if indexed && !page_crossed {
+            self.read(address);
         }
+        address
     }
 
     /// Reads a value from memory and returns the appropriate zero page address based on the addressing mode.
+    /// In the indexed modes, the 6502 reads the unindexed address (and discards the value) while it adds the index.
     fn read_zp_address(&mut self, mode: instruction::AddressingMode) -> u16 {
-        let address = self.memory[self.pc as usize].overflowing_add(
-            if mode == instruction::AddressingMode::ZeroX { self.x } 
-            else if mode == instruction::AddressingMode::ZeroY { self.y } 
-            else { 0 }
-        ).0;
+        let base = self.read(self.pc);
+        let index = if mode == instruction::AddressingMode::ZeroX { self.x }
+            else if mode == instruction::AddressingMode::ZeroY { self.y }
+            else { 0 };
+        if mode != instruction::AddressingMode::Zero {
+            self.read(base as u16);
+        }
+        let address = base.overflowing_add(index).0;
         self.pc = self.pc.overflowing_add(1).0;
         return address as u16;
     }
 
     /// Get the address located at self.pc, self.pc + 1
-    /// Increments the pc to the last byte of the address
+    /// Increments the pc past the last byte of the address; both the fetch and the increment wrap around the top of memory
     fn read_absolute_address(&mut self) -> u16 {
         let address =
-            (self.memory[self.pc as usize] as u16) |
-            ((self.memory[(self.pc + 1) as usize] as u16) << 8);
-        self.pc += 2;   // Skip the bytes of the address
+            (self.read(self.pc) as u16) |
+            ((self.read(self.pc.wrapping_add(1)) as u16) << 8);
+        self.pc = self.pc.wrapping_add(2);   // Skip the bytes of the address
         return address;
     }
 
@@ -250,27 +370,27 @@ impl CPU {
     ///
     /// This function reproduces the behavior of a well-known hardware bug of the 6502 that is caused when the low byte of the address is located on the last byte of a page. When this happens, the full 16-bit address is not incremented by one, rather, *only the low byte* is. This means if we have an instruction like
     ///
-    ///     jmp ($02FF)
+    /// ```text
+    /// jmp ($02FF)
+    /// ```
     ///
     /// instead of loading the address from `0x02FF - 0x0300`, the low byte will come from `0x02FF` and the high byte will come from `0x0200`. As such, an indirect jump should *never* use the last byte of a page in its indirection.
     fn read_indirect_address(&mut self) -> u16 {
         // fetch the address locations
-        let ptr_low: u8 = self.memory[self.pc as usize];
+        let ptr_low: u8 = self.read(self.pc);
         self.pc = self.pc.overflowing_add(1).0;
-        let mut ptr_high: u8 = self.memory[self.pc as usize];
+        let ptr_high: u8 = self.read(self.pc);
 
         // construct the indirection
-        let addr_low: u8 = self.memory[
-            (((ptr_high as u16) << 8) | 
-            (ptr_low as u16))
-            as usize
-        ];
-        ptr_high = ptr_high.overflowing_add(1).0;  // if it is 0xff, it will wrap around
-        let addr_high: u8 = self.memory[
-            (((ptr_high as u16) << 8) | 
-            (ptr_low as u16))
-            as usize
-        ];
+        let addr_low: u8 = self.read(
+            ((ptr_high as u16) << 8) | 
+            (ptr_low as u16)
+        );
+        // only the low byte of the pointer is incremented; if it is 0xff, it wraps around within the page
+        let addr_high: u8 = self.read(
+            ((ptr_high as u16) << 8) | 
+            (ptr_low.wrapping_add(1) as u16)
+        );
 
         // increment the PC
         self.pc = self.pc.overflowing_add(1).0;
@@ -282,27 +402,31 @@ impl CPU {
     /// Gets the address for the indirect indexed (indirect Y) addressing mode
     /// Reads one byte, giving the address in the zero page where the pointer is stored; the little-endian 16-bit address is then read and returned
     /// Since indirect indexed can only be used with the Y register, we don't need an offset
-    fn read_indirect_indexed_address(&mut self) -> u16 {
-        let zp_address: u8 = self.memory[self.pc as usize];
-        let mut address: u16 = 
-            (self.memory[zp_address as usize] as u16) |
-            ((self.memory[(zp_address + 1) as usize] as u16) << 8)
+    /// Also returns whether adding Y moved the address onto a different page than the pointer
+    fn read_indirect_indexed_address(&mut self) -> (u16, bool) {
+        let zp_address: u8 = self.read(self.pc);
+        let base: u16 = 
+            (self.read(zp_address as u16) as u16) |
+            ((self.read(zp_address.wrapping_add(1) as u16) as u16) << 8)
         ;
-        address += self.y as u16;
+        let address = base.wrapping_add(self.y as u16);
 
         // increment the PC
         self.pc = self.pc.overflowing_add(1).0;
 
-        address
+        (address, (base & 0xff00) != (address & 0xff00))
     }
 
     /// Gets the indexed indirect address (indirect X)
     /// Like indirect indexed, indexed indirect can only be used with the X register -- so we don't need an offset
+    /// In both indirect modes, the pointer's high byte wraps around within the zero page, so a pointer at `0xFF` takes its high byte from `0x00`
     fn read_indexed_indirect_address(&mut self) -> u16 {
-        let zp_address: u8 = self.memory[self.pc as usize].overflowing_add(self.x).0;
+        let base = self.read(self.pc);
+        self.read(base as u16);     // the pointer is read once before X is added, and the value discarded
+        let zp_address: u8 = base.overflowing_add(self.x).0;
         let address: u16 =
-            (self.memory[zp_address as usize] as u16) |
-            ((self.memory[(zp_address + 1) as usize] as u16) << 8);
+            (self.read(zp_address as u16) as u16) |
+            ((self.read(zp_address.wrapping_add(1) as u16) as u16) << 8);
         self.pc = self.pc.overflowing_add(1).0;   // increment the PC
         address
     }
@@ -310,8 +434,8 @@ impl CPU {
     /// Store an 8-bit value `value` in memory at address according to the addressing mode `mode`.
     /// Affects no flags.
     fn store(&mut self, value: u8, mode: instruction::AddressingMode) {
-        let address = self.read_address(mode);  // get the address
-        self.memory[address as usize] = value;  // perform the assignment
+        let address = self.write_operand_address(mode);  // get the address
+        self.write(address, value);  // perform the assignment
     }
 
     /// Push a value `value` onto the stack. Note the 6502's stack grows downwards.
@@ -319,7 +443,7 @@ impl CPU {
     /// It's also worth noting that the 6502 does not have overflow detection, so if the stack pointer wraps around, that's normal behavior for the processor
     fn push(&mut self, value: u8) {
         let address: u16 = ((STACK_PAGE as u16) << 8) | (self.sp as u16);
-        self.memory[address as usize] = value;
+        self.write(address, value);
         let t = self.sp.overflowing_sub(1);
         self.sp = t.0;
     }
@@ -331,33 +455,55 @@ impl CPU {
         let t = self.sp.overflowing_add(1);
         self.sp = t.0;
         let address: u16 = ((STACK_PAGE as u16) << 8) | (self.sp as u16);
-        let value = self.memory[address as usize];
+        let value = self.read(address);
         return value;
     }
 
+    /// Reads the stack at the current SP and discards the value
+    /// Instructions that pull from the stack (and JSR) spend a cycle adjusting the SP, during which the 6502 reads from the stack without using the value.
+    fn stack_dummy_read(&mut self) {
+        let address: u16 = ((STACK_PAGE as u16) << 8) | (self.sp as u16);
+        self.read(address);
+    }
+
     /// Performs subtraction, fetching values automatically according to `mode`. Also automatically stores result in the accumulator.
+    /// The 6502 computes `A - M - (1 - C)`; the carry flag acts as an inverted borrow, so it is set when no borrow occurred.
     fn sbc(&mut self, mode: instruction::AddressingMode) {
         // fetch our values
-        let minuend = self.a as u16 | if self.is_set(Flag::Carry) { 0x100 } else { 0 };
-        let subtrahend = self.read_value(mode);
+        let operand = self.read_value(mode);
+        self.subtract(operand);
+    }
 
-        // set the overflow flag if necessary (subtraction would take it out of the signed integer range)
-        self.set_flag(
-            Flag::Overflow, 
-            if (minuend ^ subtrahend as u16) & 0x80 != 0 { true } else { false }
-        );
+    /// Subtracts `operand` (and the inverted carry) from the accumulator, storing the result in the accumulator.
+    fn subtract(&mut self, operand: u8) {
+        let borrow: u16 = if self.is_set(Flag::Carry) { 0 } else { 1 };
+
+        // perform the subtraction; if a borrow occurred, the result wraps above 0xff
+        let result = (self.a as u16).wrapping_sub(operand as u16).wrapping_sub(borrow);
+        self.set_flag(Flag::Carry, result < 0x100);
 
-        // perform the subtraction
-        let result = minuend - subtrahend as u16;
+        // overflow occurs when the operands have different signs and the sign of the result differs from the minuend
         self.set_flag(
-            Flag::Carry, 
-            if result <= 0xff
+            Flag::Overflow,
+            ((self.a ^ operand) & (self.a ^ result as u8) & 0x80) != 0
         );
-        if self.is_set(Flag::Overflow) {
-            self.set_flag(Flag::Overflow, if result < 0x80 || result >= 0x180 { false } else { true });
-        }
         self.update_status(result as u8);
 
+        // in decimal mode, the flags are the same as in binary mode on the NMOS 6502, but the result is BCD-adjusted
+        if self.decimal_enabled && self.is_set(Flag::Decimal) {
+            let mut lo: i16 = (self.a & 0x0f) as i16 - (operand & 0x0f) as i16 - borrow as i16;
+            let mut hi: i16 = (self.a >> 4) as i16 - (operand >> 4) as i16;
+            if lo < 0 {
+                lo -= 6;
+                hi -= 1;
+            }
+            if hi < 0 {
+                hi -= 6;
+            }
+            self.a = (((hi as u8) << 4) & 0xf0) | ((lo as u8) & 0x0f);
+            return;
+        }
+
         // finally, set A
         self.a = result as u8;
     }
@@ -365,32 +511,69 @@ impl CPU {
     /// Performs addition, fetching values automatically according to `mode`. Also automatically stores result in the accumulator.
     fn adc(&mut self, mode: instruction::AddressingMode) {
         // fetch values
+        let operand = self.read_value(mode);
+        self.add(operand);
+    }
+
+    /// Adds `operand` (and the carry) to the accumulator, storing the result in the accumulator.
+    fn add(&mut self, operand: u8) {
         let addend = self.a as u16;
-        let augend = self.read_value(mode) as u16;
-        
-        // set the overflow flag if necessary (addition would take it out of the signed integer range)
-        self.set_flag(
-            Flag::Overflow, 
-            if (addend ^ augend) & 0x80 != 0 { false } else { true }
-        );
-        
+        let augend = operand as u16;
+
+        if self.decimal_enabled && self.is_set(Flag::Decimal) {
+            self.adc_decimal(augend as u8);
+            return;
+        }
+
         // perform the addition
         let result: u16 = addend + augend + if self.is_set(Flag::Carry) { 1 } else { 0 };
 
-        // update status flags, clearing the overflow flag based on the result
+        // update status flags
+        // overflow occurs when the operands have the same sign and the sign of the result differs from them
         self.set_flag(
             Flag::Carry, 
             result > 0xff
         );
-        if self.is_set(Flag::Overflow) {
-            self.set_flag(Flag::Overflow, if result < 0x80 || result >= 0x180 { false } else { true });
-        }
+        self.set_flag(
+            Flag::Overflow,
+            (!(addend ^ augend) & (addend ^ result) & 0x80) != 0
+        );
         self.update_status(result as u8);
 
         // finally, set accumulator
         self.a = (result & 0xff) as u8;
     }
 
+    /// Performs packed-BCD addition of `operand` to the accumulator, reproducing the NMOS 6502's decimal mode.
+    /// The Z flag reflects the binary sum, while N and V are taken from the intermediate result before the high nibble is adjusted; C reflects the decimal carry.
+    fn adc_decimal(&mut self, operand: u8) {
+        let carry: u8 = if self.is_set(Flag::Carry) { 1 } else { 0 };
+
+        // add the low nibbles, adjusting if the digit is out of range
+        let mut lo: u8 = (self.a & 0x0f) + (operand & 0x0f) + carry;
+        if lo > 9 {
+            lo += 6;
+        }
+
+        // add the high nibbles along with the carry from the low digit
+        let mut hi: u8 = (self.a >> 4) + (operand >> 4) + if lo > 0x0f { 1 } else { 0 };
+
+        // the documented NMOS quirks: Z comes from the binary result, N and V from the unadjusted high digit
+        self.set_flag(Flag::Zero, self.a.wrapping_add(operand).wrapping_add(carry) == 0);
+        self.set_flag(Flag::Negative, (hi & 0x08) != 0);
+        self.set_flag(
+            Flag::Overflow,
+            (!(self.a ^ operand) & (self.a ^ (hi << 4)) & 0x80) != 0
+        );
+
+        if hi > 9 {
+            hi += 6;
+        }
+        self.set_flag(Flag::Carry, hi > 0x0f);
+
+        self.a = (hi << 4) | (lo & 0x0f);
+    }
+
     /// Carry out the AND instruction, performing a logical AND between A and the fetched operand.
     fn and(&mut self, mode: instruction::AddressingMode) {
         let operand: u8 = self.read_value(mode);
@@ -400,474 +583,829 @@ impl CPU {
 
     /// Shifts bits at memory address `address` left one position.
     /// A bitshift means zero is shifted in and the outgoing bit is shifted into the Carry bit.
-    fn shift_left(&mut self, address: u16) {
-        let msb = (self.memory[address as usize] & 0x80) != 0;
-        self.memory[address as usize] <<= 1;
-        self.set_flag(Flag::Carry, msb);
-        self.update_status(self.memory[address as usize]);
+    /// Returns the result, which the unofficial combined instructions go on to use.
+    fn shift_left(&mut self, address: u16) -> u8 {
+        let value = self.read_for_modify(address);
+        let result = value << 1;
+        self.write(address, result);
+        self.set_flag(Flag::Carry, (value & 0x80) != 0);
+        self.update_status(result);
+        result
     }
 
     /// Shifts bits at `address` right one position.
     /// A zero is shifted in and the LSB is shifted into the carry bit.
-    fn shift_right(&mut self, address: u16) {
-        let lsb = (self.memory[address as usize] & 0x80) != 0;
-        self.memory[address as usize] >>= 1;
-        self.set_flag(Flag::Carry, lsb);
-        self.update_status(self.memory[address as usize]);
+    /// Returns the result, which the unofficial combined instructions go on to use.
+    fn shift_right(&mut self, address: u16) -> u8 {
+        let value = self.read_for_modify(address);
+        let result = value >> 1;
+        self.write(address, result);
+        self.set_flag(Flag::Carry, (value & 0x01) != 0);
+        self.update_status(result);
+        result
     }
 
     /// Rotates bits at `address` left one position.
     /// A rotation means Carry is shifted into the incoming position and the outgoing bit is shifted into the Carry bit.
-    fn rotate_left(&mut self, address: u16) {
+    /// Returns the result, which the unofficial combined instructions go on to use.
+    fn rotate_left(&mut self, address: u16) -> u8 {
         let c = self.is_set(Flag::Carry);
-        self.set_flag(Flag::Carry, self.memory[address as usize] & 0x80 != 0);  // if the MSB is set, set the carry bit
-        self.memory[address as usize] <<= 1;
-        self.memory[address as usize] |= c as u8;
-        self.update_status(self.memory[address as usize]);
+        let value = self.read_for_modify(address);
+        self.set_flag(Flag::Carry, value & 0x80 != 0);  // if the MSB is set, set the carry bit
+        let result = (value << 1) | c as u8;
+        self.write(address, result);
+        self.update_status(result);
+        result
     }
 
     /// Rotates bits at `address` right one position.
     /// The outgoing bit is shifted into the carry bit, and the original carry bit is shifted into the incoming bit position.
-    fn rotate_right(&mut self, address: u16) {
+    /// Returns the result, which the unofficial combined instructions go on to use.
+    fn rotate_right(&mut self, address: u16) -> u8 {
         let c = self.is_set(Flag::Carry);
-        self.set_flag(Flag::Carry, self.memory[address as usize] & 1 != 0); // if the LSB is set, set the carry
-        self.memory[address as usize] >>= 1;
-        self.memory[address as usize] |= if c { 0x80 } else { 0 };
-        self.update_status(self.memory[address as usize]);
+        let value = self.read_for_modify(address);
+        self.set_flag(Flag::Carry, value & 1 != 0); // if the LSB is set, set the carry
+        let result = (value >> 1) | if c { 0x80 } else { 0 };
+        self.write(address, result);
+        self.update_status(result);
+        result
     }
 
     /// Branches according to data in memory
-    fn branch(&mut self, condition: bool) {
+    /// Returns the number of cycles the branch takes beyond the base 2: a taken branch adds 1, and another is added if the target is on a different page than the next instruction
+    fn branch(&mut self, condition: bool) -> u8 {
         if condition {
-            let offset = self.memory[self.pc as usize] as i8;   // offset is signed
+            let offset = self.read(self.pc) as i8;   // offset is signed
             self.pc = self.pc.overflowing_add(1).0;
+            let next = self.pc;
             if offset < 0 {
-                self.pc = self.pc.overflowing_sub((offset as i16).abs() as u16).0;
+                self.pc = self.pc.overflowing_sub((offset as i16).unsigned_abs()).0;
             }
             else {
                 self.pc = self.pc.overflowing_add(offset as u16).0;
             }
+
+            if (next & 0xff00) != (self.pc & 0xff00) { 2 } else { 1 }
         }
         else {
+            // the offset is still fetched, even though it isn't used
+            self.read(self.pc);
             self.pc = self.pc.overflowing_add(1).0;
+            0
         }
     }
 
-    /// The interrupt entry routine
+    /// The interrupt entry sequence shared by IRQ, BRK, and NMI
     /// Interrupts occur as follows in 65xx processors:
     /// * The instruction updates memory and registers as necessary (prior to this function)
     /// * MSB of the PC is pushed
     /// * LSB of the PC is pushed
-    /// * Status is pushed
+    /// * Status (`status`) is pushed
     /// * The `I` flag is set
-    /// * The PC is loaded with the value from the vector
-    fn interrupt(&mut self) {
+    /// * The PC is loaded with the value from `vector`
+    fn enter_interrupt(&mut self, vector: u16, status: u8) {
         self.push((self.pc >> 8 & 0xFF) as u8); // push MSB
         self.push((self.pc & 0xFF) as u8);  // push LSB
-        self.push(self.status);
+        self.push(status);
         self.set_flag(Flag::Interrupt, true);
-        let address = (self.memory[IRQ_VECTOR as usize] as u16) | ((self.memory[(IRQ_VECTOR as usize) + 1] as u16) << 8);
+        let address = (self.read(vector) as u16) | ((self.read(vector.wrapping_add(1)) as u16) << 8);
         self.pc = address;
     }
 
+    /// The interrupt entry routine, using the IRQ vector
+    /// BRK and hardware IRQs share the vector, but differ in the status byte they push: BRK pushes it with the B flag set, while a hardware IRQ pushes it with B clear.
+    /// Bit 5 is always set in the pushed byte.
+    fn interrupt(&mut self, brk: bool) {
+        let status = if brk { self.status | B_FLAG } else { self.status & !B_FLAG } | U_FLAG;
+        self.enter_interrupt(IRQ_VECTOR, status);
+    }
+
     /// Transfers control to the given subroutine
-    /// * Fetches the address to which we are transfering control
-    /// * Figure out the return address, which is the address of the next instruction to be executed
-    /// * Push MSB of the return address
+    /// * Fetches the LSB of the address to which we are transfering control
+    /// * Reads the stack without using the value
+    /// * Push MSB of the return address, which is the address of the last byte of the JSR
     /// * Push LSB of the return address
+    /// * Fetches the MSB of the new address
     fn jsr(&mut self) {
-        let new_address = self.read_absolute_address(); // get the new address
-        let return_address = self.pc.overflowing_sub(1).0;   // get the return address
-        self.push((return_address >> 8 & 0xFF) as u8); // MSB
-        self.push((return_address & 0xFF) as u8);  // LSB
-        self.pc = new_address;
+        let lsb = self.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        self.stack_dummy_read();
+        self.push((self.pc >> 8 & 0xFF) as u8); // MSB
+        self.push((self.pc & 0xFF) as u8);  // LSB
+        let msb = self.read(self.pc);
+        self.pc = ((msb as u16) << 8) | lsb as u16;
     }
 
     /// Returns from an interrupt or subroutine
     /// Reads two bytes from the stack (LSB then MSB) and returns to that address
     /// Note that if `is_subroutine` is set, returns to the address + 1; else, returns to the exact address
+    /// When returning from an interrupt, the status is restored the same way as `PLP`: B is ignored and bit 5 stays set
+    /// Returning from a subroutine reads the byte at the pulled address (the last byte of the JSR) while moving past it.
     fn ret(&mut self, is_subroutine: bool) {
+        self.stack_dummy_read();
         if !is_subroutine {
-            self.status = self.pop();
+            let status = self.pop();
+            self.status = (status & !B_FLAG) | U_FLAG;
         }
         let lsb = self.pop();
         let msb = self.pop();
-        let address = (((msb as u16) << 8) | lsb as u16) + if is_subroutine { 1 } else { 0 };
-        self.pc = address;
+        let address = ((msb as u16) << 8) | lsb as u16;
+        if is_subroutine {
+            self.read(address);
+            self.pc = address.wrapping_add(1);
+        } else {
+            self.pc = address;
+        }
     }
 
     /// Compares two values and sets the status register appropriately.
     /// Generally, these comparisons result in the following:
     /// * `Z` set if values are equal, cleared if unequal
     /// * `C` set if left is greater or equal to the right value, else it is cleared
-    /// * `N` set based on the sign of the left value
+    /// * `N` set based on the sign of the difference `left - right`
     fn compare(&mut self, left: u8, right: u8) {
         self.set_flag(Flag::Zero, left == right);
         self.set_flag(Flag::Carry, left >= right);
-        self.set_flag(Flag::Negative, left >= 0x80);
+        self.set_flag(Flag::Negative, left.wrapping_sub(right) & 0x80 != 0);
     }
 
     /// Executes the instruction supplied; reads from memory appropriately
-    fn execute_instruction(&mut self, opcode: u8) {
+    /// Returns the number of cycles the instruction took, including any page-crossing and branch penalties
+    fn execute_instruction(&mut self, opcode: u8) -> u8 {
         // get the instruction based on its opcode
-        if !instruction::INSTRUCTIONS.contains_key(&opcode) {
-            // if the instruction isn't in the table, stop the CPU (illegal)
-            self.running = false;
+        let i = match instruction::decode(opcode) {
+            Some(i) => i,
+            None => {
+                // if the instruction isn't in the table, stop the CPU (illegal)
+                // the pc has already moved past the opcode, so step back to report where it was fetched
+                self.stop(StopReason::IllegalOpcode(opcode, self.pc.wrapping_sub(1)));
+                return 0;
+            },
+        };
+
+        // start with the base number of cycles
+        let mut cycles = i.time;
+        self.page_crossed = false;
+
+        // instructions without an operand still read the byte after the opcode during their second cycle, and discard it
+        if i.mode == instruction::AddressingMode::Implied || i.mode == instruction::AddressingMode::Accumulator {
+            self.read(self.pc);
         }
-        else {
-            // if the instruction does exist, we can look it up
-            let i: &instruction::Instruction = &instruction::INSTRUCTIONS[&opcode];
-
-            // add the number of cycles to the total
-            self.cycles += i.time as u64;
-
-            // use a match statement instead of if/else if/else
-            match i.mnemonic {
-                instruction::Mnemonic::ADC => {
-                    // Add with carry
-                    self.adc(i.mode);
-                },
-                instruction::Mnemonic::AND => {
-                    // Logical AND with accumulator
-                    self.and(i.mode);
-                },
-                instruction::Mnemonic::ASL => {
-                    // Arithmetic shift left
-                    // this instruction can operate on the accumulator
-                    if i.mode == instruction::AddressingMode::Accumulator {
-                        let msb = (self.a & 0x80) != 0;
-                        self.a <<= 1;
-                        self.set_flag(Flag::Carry, msb);
-                        self.update_status(self.a);
-                    } else {
-                        let address = self.read_address(i.mode);
-                        self.shift_left(address);
-                    }
-                },
-                instruction::Mnemonic::BIT => {
-                    // Test bits
-                    // Sets the Z flag as if A and [operand] were ANDed together; sets N and V to bits 7 and 6 of the operand, respecitvely.
-                    let address = self.read_address(i.mode);
-                    self.set_flag(Flag::Zero, (self.a & self.memory[address as usize]) != 0);
-                    self.set_flag(Flag::Negative, (self.memory[address as usize] & N_FLAG) != 0);
-                    self.set_flag(Flag::Overflow, (self.memory[address as usize] & V_FLAG) != 0);
-                },
-
-                // Branches
-                instruction::Mnemonic::BPL => {
-                    // Branch on plus (N = 0)
-                    self.branch(!self.is_set(Flag::Negative));
-                },
-                instruction::Mnemonic::BMI => {
-                    // Branch on minus (N = 1)
-                    self.branch(self.is_set(Flag::Negative));
-                },
-                instruction::Mnemonic::BVC => {
-                    // Branch on overflow clear
-                    self.branch(!self.is_set(Flag::Overflow));
-                },
-                instruction::Mnemonic::BVS => {
-                    // Branch on overflow set
-                    self.branch(self.is_set(Flag::Overflow));
-                },
-                instruction::Mnemonic::BCC => {
-                    // Branch on carry clear
-                    self.branch(!self.is_set(Flag::Carry));
-                },
-                instruction::Mnemonic::BCS => {
-                    // Branch on carry set
-                    self.branch(self.is_set(Flag::Carry));
-                },
-                instruction::Mnemonic::BNE => {
-                    // Branch on not equal (Z = 0)
-                    self.branch(!self.is_set(Flag::Zero));
-                },
-                instruction::Mnemonic::BEQ => {
-                    // Branch on equal (Z = 1)
-                    self.branch(self.is_set(Flag::Zero));
-                },
-                instruction::Mnemonic::BRK => {
-                    /*
-                    
-                    BRK sets the B flag and increments the pc by one
-                    This means it is technically a 2-byte opcode -- 0x00 and a padding byte
-                    BRK is used to trigger software interrupts
-                    
-                    */
-                    self.set_flag(Flag::B, true);
-                    self.pc = self.pc.overflowing_add(1).0;
-                    self.interrupt();
-                },
-                instruction::Mnemonic::CMP => {
-                    // Compare accumulator
-                    let rhs = self.read_value(i.mode);
-                    self.compare(self.a, rhs);
-                },
-                instruction::Mnemonic::CPX => {
-                    // Compare X
-                    let rhs = self.read_value(i.mode);
-                    self.compare(self.x, rhs);
-                },
-                instruction::Mnemonic::CPY => {
-                    // Compare Y
-                    let rhs = self.read_value(i.mode);
-                    self.compare(self.y, rhs);
-                },
-                instruction::Mnemonic::DEC => {
-                    // Decrement memory
-                    let address = self.read_address(i.mode);
-                    self.memory[address as usize] -= 1;
-                    self.update_status(self.memory[address as usize]);
-                },
-                instruction::Mnemonic::EOR => {
-                    // XOR with accumulator
-                    let value = self.read_value(i.mode);
-                    self.a ^= value;
-                    self.update_status(self.a);
-                },
-                instruction::Mnemonic::CLC => {
-                    self.set_flag(Flag::Carry, false);
-                },
-                instruction::Mnemonic::SEC => {
-                    self.set_flag(Flag::Carry, true);
-                },
-                instruction::Mnemonic::CLI => {
-                    self.set_flag(Flag::Interrupt, false);
-                },
-                instruction::Mnemonic::SEI => {
-                    self.set_flag(Flag::Interrupt, true);
-                },
-                instruction::Mnemonic::CLV => {
-                    self.set_flag(Flag::Overflow, false);
-                },
-                instruction::Mnemonic::CLD => {
-                    self.set_flag(Flag::Decimal, false);
-                },
-                instruction::Mnemonic::SED => {
-                    self.set_flag(Flag::Decimal, true);
-                },
-                instruction::Mnemonic::INC => {
-                    // Increment memory
-                    let address = self.read_address(i.mode);
-                    self.memory[address as usize] += 1;
-                    self.update_status(self.memory[address as usize]);
-                },
-                instruction::Mnemonic::JMP => {
-                    // JMP has two addressing modes
-                    if i.mode == instruction::AddressingMode::Absolute {
-                        self.pc = self.read_absolute_address();
-                    }
-                    else {
-                        self.pc = self.read_indirect_address();
-                    }
-                },
-                instruction::Mnemonic::JSR => {
-                    // Jump to subroutine
-                    self.jsr();
-                },
-                instruction::Mnemonic::LDA => {
-                    // LDA
-                    self.a = self.read_value(i.mode);
-                    self.update_status(self.a);
-                },
-                instruction::Mnemonic::LDX => {
-                    // LDX
-                    self.x = self.read_value(i.mode);
-                    self.update_status(self.x);
-                },
-                instruction::Mnemonic::LDY => {
-                    // LDY
-                    self.y = self.read_value(i.mode);
-                    self.update_status(self.y);
-                },
-                instruction::Mnemonic::LSR => {
-                    // Logical shift right
-                    // the accumulator may be used
-                    if i.mode == instruction::AddressingMode::Accumulator {
-                        let lsb = (self.a & 0x01) != 0;
-                        self.a >>= 1;
-                        self.set_flag(Flag::Carry, lsb);
-                        self.update_status(self.a);
-                    } else {
-                        let address = self.read_address(i.mode);
-                        self.shift_right(address);
-                    }
-                },
-                instruction::Mnemonic::NOP => {
-                    // NOP
-
-                    // Unofficial opcodes have different addressing modes, but do nothing with the value
-                    if i.mode != instruction::AddressingMode::Implied {
-                        self.read_value(i.mode);
-                    }
-                },
-                instruction::Mnemonic::ORA => {
-                    // Bitwise OR with accumulator
-                    let value = self.read_value(i.mode);
-                    self.a |= value;
+
+        // use a match statement instead of if/else if/else
+        match i.mnemonic {
+            instruction::Mnemonic::ADC => {
+                // Add with carry
+                self.adc(i.mode);
+            },
+            instruction::Mnemonic::AND => {
+                // Logical AND with accumulator
+                self.and(i.mode);
+            },
+            instruction::Mnemonic::ASL => {
+                // Arithmetic shift left
+                // this instruction can operate on the accumulator
+                if i.mode == instruction::AddressingMode::Accumulator {
+                    let msb = (self.a & 0x80) != 0;
+                    self.a <<= 1;
+                    self.set_flag(Flag::Carry, msb);
                     self.update_status(self.a);
-                },
-                instruction::Mnemonic::TAX => {
-                    self.x = self.a;
-                    self.update_status(self.x);
-                },
-                instruction::Mnemonic::TXA => {
-                    self.a = self.x;
+                } else {
+                    let address = self.write_operand_address(i.mode);
+                    self.shift_left(address);
+                }
+            },
+            instruction::Mnemonic::BIT => {
+                // Test bits
+                // Sets the Z flag as if A and [operand] were ANDed together; sets N and V to bits 7 and 6 of the operand, respecitvely.
+                let value = self.read_value(i.mode);
+                self.set_flag(Flag::Zero, (self.a & value) == 0);
+                self.set_flag(Flag::Negative, (value & N_FLAG) != 0);
+                self.set_flag(Flag::Overflow, (value & V_FLAG) != 0);
+            },
+
+            // Branches
+            instruction::Mnemonic::BPL => {
+                // Branch on plus (N = 0)
+                cycles += self.branch(!self.is_set(Flag::Negative));
+            },
+            instruction::Mnemonic::BMI => {
+                // Branch on minus (N = 1)
+                cycles += self.branch(self.is_set(Flag::Negative));
+            },
+            instruction::Mnemonic::BVC => {
+                // Branch on overflow clear
+                cycles += self.branch(!self.is_set(Flag::Overflow));
+            },
+            instruction::Mnemonic::BVS => {
+                // Branch on overflow set
+                cycles += self.branch(self.is_set(Flag::Overflow));
+            },
+            instruction::Mnemonic::BCC => {
+                // Branch on carry clear
+                cycles += self.branch(!self.is_set(Flag::Carry));
+            },
+            instruction::Mnemonic::BCS => {
+                // Branch on carry set
+                cycles += self.branch(self.is_set(Flag::Carry));
+            },
+            instruction::Mnemonic::BNE => {
+                // Branch on not equal (Z = 0)
+                cycles += self.branch(!self.is_set(Flag::Zero));
+            },
+            instruction::Mnemonic::BEQ => {
+                // Branch on equal (Z = 1)
+                cycles += self.branch(self.is_set(Flag::Zero));
+            },
+            instruction::Mnemonic::BRK => {
+                /*
+                
+                BRK pushes the status with the B flag set and increments the pc by one
+                This means it is technically a 2-byte opcode -- 0x00 and a padding byte
+                BRK is used to trigger software interrupts
+                
+                */
+                if self.stop_on_brk {
+                    self.stop(StopReason::Brk);
+                } else {
+                    self.pc = self.pc.overflowing_add(1).0;
+                    self.interrupt(true);
+                }
+            },
+            instruction::Mnemonic::CMP => {
+                // Compare accumulator
+                let rhs = self.read_value(i.mode);
+                self.compare(self.a, rhs);
+            },
+            instruction::Mnemonic::CPX => {
+                // Compare X
+                let rhs = self.read_value(i.mode);
+                self.compare(self.x, rhs);
+            },
+            instruction::Mnemonic::CPY => {
+                // Compare Y
+                let rhs = self.read_value(i.mode);
+                self.compare(self.y, rhs);
+            },
+            instruction::Mnemonic::DEC => {
+                // Decrement memory
+                let address = self.write_operand_address(i.mode);
+                let value = self.read_for_modify(address).wrapping_sub(1);
+                self.write(address, value);
+                self.update_status(value);
+            },
+            instruction::Mnemonic::EOR => {
+                // XOR with accumulator
+                let value = self.read_value(i.mode);
+                self.a ^= value;
+                self.update_status(self.a);
+            },
+            instruction::Mnemonic::CLC => {
+                self.set_flag(Flag::Carry, false);
+            },
+            instruction::Mnemonic::SEC => {
+                self.set_flag(Flag::Carry, true);
+            },
+            instruction::Mnemonic::CLI => {
+                self.set_flag(Flag::Interrupt, false);
+            },
+            instruction::Mnemonic::SEI => {
+                self.set_flag(Flag::Interrupt, true);
+            },
+            instruction::Mnemonic::CLV => {
+                self.set_flag(Flag::Overflow, false);
+            },
+            instruction::Mnemonic::CLD => {
+                self.set_flag(Flag::Decimal, false);
+            },
+            instruction::Mnemonic::SED => {
+                self.set_flag(Flag::Decimal, true);
+            },
+            instruction::Mnemonic::INC => {
+                // Increment memory
+                let address = self.write_operand_address(i.mode);
+                let value = self.read_for_modify(address).wrapping_add(1);
+                self.write(address, value);
+                self.update_status(value);
+            },
+            instruction::Mnemonic::JMP => {
+                // JMP has two addressing modes
+                if i.mode == instruction::AddressingMode::Absolute {
+                    self.pc = self.read_absolute_address();
+                }
+                else {
+                    self.pc = self.read_indirect_address();
+                }
+            },
+            instruction::Mnemonic::JSR => {
+                // Jump to subroutine
+                self.jsr();
+            },
+            instruction::Mnemonic::LDA => {
+                // LDA
+                self.a = self.read_value(i.mode);
+                self.update_status(self.a);
+            },
+            instruction::Mnemonic::LDX => {
+                // LDX
+                self.x = self.read_value(i.mode);
+                self.update_status(self.x);
+            },
+            instruction::Mnemonic::LDY => {
+                // LDY
+                self.y = self.read_value(i.mode);
+                self.update_status(self.y);
+            },
+            instruction::Mnemonic::LSR => {
+                // Logical shift right
+                // the accumulator may be used
+                if i.mode == instruction::AddressingMode::Accumulator {
+                    let lsb = (self.a & 0x01) != 0;
+                    self.a >>= 1;
+                    self.set_flag(Flag::Carry, lsb);
                     self.update_status(self.a);
-                },
-                instruction::Mnemonic::DEX => {
-                    self.x -= 1;
-                    self.update_status(self.x);
-                },
-                instruction::Mnemonic::INX => {
-                    self.x += 1;
-                    self.update_status(self.x);
-                },
-                instruction::Mnemonic::TAY => {
-                    self.y = self.a;
-                    self.update_status(self.y);
-                },
-                instruction::Mnemonic::TYA => {
-                    self.a = self.y;
+                } else {
+                    let address = self.write_operand_address(i.mode);
+                    self.shift_right(address);
+                }
+            },
+            instruction::Mnemonic::NOP => {
+                // NOP
+
+                // Unofficial opcodes have different addressing modes, but do nothing with the value
+                if i.mode != instruction::AddressingMode::Implied {
+                    self.read_value(i.mode);
+                }
+            },
+            instruction::Mnemonic::ORA => {
+                // Bitwise OR with accumulator
+                let value = self.read_value(i.mode);
+                self.a |= value;
+                self.update_status(self.a);
+            },
+            instruction::Mnemonic::TAX => {
+                self.x = self.a;
+                self.update_status(self.x);
+            },
+            instruction::Mnemonic::TXA => {
+                self.a = self.x;
+                self.update_status(self.a);
+            },
+            instruction::Mnemonic::DEX => {
+                self.x = self.x.wrapping_sub(1);
+                self.update_status(self.x);
+            },
+            instruction::Mnemonic::INX => {
+                self.x = self.x.wrapping_add(1);
+                self.update_status(self.x);
+            },
+            instruction::Mnemonic::TAY => {
+                self.y = self.a;
+                self.update_status(self.y);
+            },
+            instruction::Mnemonic::TYA => {
+                self.a = self.y;
+                self.update_status(self.a);
+            },
+            instruction::Mnemonic::DEY => {
+                self.y = self.y.wrapping_sub(1);
+                self.update_status(self.y);
+            },
+            instruction::Mnemonic::INY => {
+                self.y = self.y.wrapping_add(1);
+                self.update_status(self.y);
+            },
+            instruction::Mnemonic::ROL => {
+                // rotate left
+                // The accumulator may be used as an argument
+                if i.mode == instruction::AddressingMode::Accumulator {
+                    let c = self.is_set(Flag::Carry);
+                    self.set_flag(Flag::Carry, self.a & 0x80 != 0);  // if the MSB is set, set the carry bit
+                    self.a <<= 1;
+                    self.a |= c as u8;
                     self.update_status(self.a);
-                },
-                instruction::Mnemonic::DEY => {
-                    self.y -= 1;
-                    self.update_status(self.y);
-                },
-                instruction::Mnemonic::INY => {
-                    self.y += 1;
-                    self.update_status(self.y);
-                },
-                instruction::Mnemonic::ROL => {
-                    // rotate left
-                    // The accumulator may be used as an argument
-                    if i.mode == instruction::AddressingMode::Accumulator {
-                        let c = self.is_set(Flag::Carry);
-                        self.set_flag(Flag::Carry, self.a & 0x80 != 0);  // if the MSB is set, set the carry bit
-                        self.a <<= 1;
-                        self.a |= c as u8;
-                        self.update_status(self.a);
-                    } else {
-                        let address = self.read_address(i.mode);
-                        self.rotate_left(address);
-                    }
-                },
-                instruction::Mnemonic::ROR => {
-                    // rotate right
-                    if i.mode == instruction::AddressingMode::Accumulator {
-                        let c = self.is_set(Flag::Carry);
-                        self.set_flag(Flag::Carry, self.a & 0x01 != 0);  // if the MSB is set, set the carry bit
-                        self.a >>= 1;
-                        self.a |= if c { 0x80 } else { 0 };
-                        self.update_status(self.a);
-                    } else {
-                        let address = self.read_address(i.mode);
-                        self.rotate_right(address);
-                    }
-                },
-                instruction::Mnemonic::RTI => {
-                    // Return from interrupt
-                    self.ret(false);
-                },
-                instruction::Mnemonic::RTS => {
-                    // Return from subroutine
-                    self.ret(true);
-                },
-                instruction::Mnemonic::SBC => {
-                    // SBC, Imm
-                    self.sbc(i.mode);
-                },
-                instruction::Mnemonic::STA => {
-                    // STA - ZP
-                    self.store(self.a, i.mode);
-                },
-                instruction::Mnemonic::TXS => {
-                    // TXS
-                    self.sp = self.x;
-                    self.update_status(self.sp);
-                },
-                instruction::Mnemonic::TSX => {
-                    // TSX
-                    self.x = self.sp;
-                    self.update_status(self.x);
-                },
-                instruction::Mnemonic::PHA => {
-                    // PHA
-                    self.push(self.a);
-                },
-                instruction::Mnemonic::PLA => {
-                    // PLA
-                    self.a = self.pop();
+                } else {
+                    let address = self.write_operand_address(i.mode);
+                    self.rotate_left(address);
+                }
+            },
+            instruction::Mnemonic::ROR => {
+                // rotate right
+                if i.mode == instruction::AddressingMode::Accumulator {
+                    let c = self.is_set(Flag::Carry);
+                    self.set_flag(Flag::Carry, self.a & 0x01 != 0);  // if the MSB is set, set the carry bit
+                    self.a >>= 1;
+                    self.a |= if c { 0x80 } else { 0 };
                     self.update_status(self.a);
-                },
-                instruction::Mnemonic::PHP => {
-                    // PHP
-                    self.push(self.status);
-                },
-                instruction::Mnemonic::PLP => {
-                    // PLP
-                    self.status = self.pop();
-                },
-                instruction::Mnemonic::STX => {
-                    // STX
-                    self.store(self.x, i.mode);
-                },
-                instruction::Mnemonic::STY => {
-                    // STY
-                    self.store(self.y, i.mode);
-                },
-                instruction::Mnemonic::XAA => {
-                    /*
-
-                    XAA is an unofficial opcode that is very unpredictable
-                    It relies on analog effects and will not be reproduced in this emulator
-                    Instead, it will kill the CPU
-
-                    */
-                    self.running = false;
-                },
-                instruction::Mnemonic::LAX => {
-                    // Likewise, LAX will kill
-                    self.running = false;
+                } else {
+                    let address = self.write_operand_address(i.mode);
+                    self.rotate_right(address);
+                }
+            },
+            instruction::Mnemonic::RTI => {
+                // Return from interrupt
+                self.ret(false);
+            },
+            instruction::Mnemonic::RTS => {
+                // Return from subroutine
+                self.ret(true);
+            },
+            instruction::Mnemonic::SBC => {
+                // SBC, Imm
+                self.sbc(i.mode);
+            },
+            instruction::Mnemonic::STA => {
+                // STA - ZP
+                self.store(self.a, i.mode);
+            },
+            instruction::Mnemonic::TXS => {
+                // TXS
+                self.sp = self.x;
+                self.update_status(self.sp);
+            },
+            instruction::Mnemonic::TSX => {
+                // TSX
+                self.x = self.sp;
+                self.update_status(self.x);
+            },
+            instruction::Mnemonic::PHA => {
+                // PHA
+                self.push(self.a);
+            },
+            instruction::Mnemonic::PLA => {
+                // PLA
+                self.stack_dummy_read();
+                self.a = self.pop();
+                self.update_status(self.a);
+            },
+            instruction::Mnemonic::PHP => {
+                // PHP
+                // the status is always pushed with B and bit 5 set
+                self.push(self.status | B_FLAG | U_FLAG);
+            },
+            instruction::Mnemonic::PLP => {
+                // PLP
+                // B doesn't exist in the register, so it is ignored; bit 5 always stays set
+                self.stack_dummy_read();
+                let status = self.pop();
+                self.status = (status & !B_FLAG) | U_FLAG;
+            },
+            instruction::Mnemonic::STX => {
+                // STX
+                self.store(self.x, i.mode);
+            },
+            instruction::Mnemonic::STY => {
+                // STY
+                self.store(self.y, i.mode);
+            },
+            instruction::Mnemonic::XAA => {
+                /*
+
+                XAA is an unofficial opcode that is very unpredictable
+                It relies on analog effects and will not be reproduced in this emulator
+                Instead, it will kill the CPU
+
+                */
+                self.stop(StopReason::Halt);
+            },
+            instruction::Mnemonic::SAX => {
+                // Store A AND X (unofficial); affects no flags
+                self.store(self.a & self.x, i.mode);
+            },
+            instruction::Mnemonic::DCP => {
+                // Decrement memory, then compare it with A (unofficial)
+                let address = self.write_operand_address(i.mode);
+                let value = self.read_for_modify(address).wrapping_sub(1);
+                self.write(address, value);
+                self.compare(self.a, value);
+            },
+            instruction::Mnemonic::ISC => {
+                // Increment memory, then subtract it from A (unofficial)
+                let address = self.write_operand_address(i.mode);
+                let value = self.read_for_modify(address).wrapping_add(1);
+                self.write(address, value);
+                self.subtract(value);
+            },
+            instruction::Mnemonic::SLO => {
+                // Shift memory left, then OR it into A (unofficial)
+                let address = self.write_operand_address(i.mode);
+                self.a |= self.shift_left(address);
+                self.update_status(self.a);
+            },
+            instruction::Mnemonic::RLA => {
+                // Rotate memory left, then AND it into A (unofficial)
+                let address = self.write_operand_address(i.mode);
+                self.a &= self.rotate_left(address);
+                self.update_status(self.a);
+            },
+            instruction::Mnemonic::SRE => {
+                // Shift memory right, then XOR it into A (unofficial)
+                let address = self.write_operand_address(i.mode);
+                self.a ^= self.shift_right(address);
+                self.update_status(self.a);
+            },
+            instruction::Mnemonic::RRA => {
+                // Rotate memory right, then add it to A (unofficial)
+                // the carry out of the rotation feeds into the addition
+                let address = self.write_operand_address(i.mode);
+                let value = self.rotate_right(address);
+                self.add(value);
+            },
+            instruction::Mnemonic::LAX => {
+                // Load A and X with the same value (unofficial)
+                // The immediate form is unstable like XAA, so it will kill
+                if i.mode == instruction::AddressingMode::Immediate {
+                    self.stop(StopReason::Halt);
+                } else {
+                    let value = self.read_value(i.mode);
+                    self.a = value;
+                    self.x = value;
+                    self.update_status(value);
                 }
-            };
+            }
+        };
+
+        // indexed reads take an extra cycle when the effective address lands on a different page
+        if self.page_crossed && i.mnemonic.has_page_penalty() {
+            cycles += 1;
         }
+
+        cycles
     }
 
     // todo: in the routine that runs the cpu, check to make sure it is still marked as 'running'
 
+    /// Stops the CPU, recording why
+    fn stop(&mut self, reason: StopReason) {
+        self.running = false;
+        self.stop_reason = Some(reason);
+        self.stop_address = self.instruction_address;
+    }
+
     /// Returns whether or not the CPU is executing code
     pub fn is_running(&self) -> bool {
         self.running
     }
 
+    /// Returns why the CPU stopped, or `None` if it hasn't
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.stop_reason
+    }
+
+    /// Returns how a run loop that is finishing now ended: `Halted` with the stop reason and address if the CPU has stopped, otherwise `Completed`
+    pub fn run_outcome(&self) -> RunOutcome {
+        match self.stop_reason {
+            Some(reason) => RunOutcome::Halted { reason, pc: self.stop_address },
+            None => RunOutcome::Completed,
+        }
+    }
+
     /// Returns the number of cycles that have passed
     pub fn cycle_count(&self) -> u64 {
         self.cycles
     }
 
+    /// Triggers a non-maskable interrupt, transferring control to the routine at the NMI vector
+    /// The status is pushed with the B flag clear and bit 5 set; NMI cannot be masked by the `I` flag.
+    /// Like the other interrupt sequences, this takes 7 cycles.
+    pub fn nmi(&mut self) {
+        let status = (self.status & !B_FLAG) | U_FLAG;
+        self.enter_interrupt(NMI_VECTOR, status);
+        self.cycles += 7;
+    }
+
+    /// Triggers a hardware interrupt request, transferring control to the routine at the IRQ vector
+    /// This doesn't check the `I` flag; `step` only calls it when the flag is clear. It takes 7 cycles.
+    pub fn irq(&mut self) {
+        self.interrupt(false);
+        self.cycles += 7;
+    }
+
+    /// Samples the interrupt lines at an instruction boundary, servicing an NMI or IRQ if one is due
+    /// An NMI takes priority over an IRQ; an IRQ that is masked by the `I` flag stays pending for as long as its line is held.
+    /// Returns the number of cycles the interrupt sequence took, or 0 if no interrupt was serviced.
+    fn poll_interrupts(&mut self) -> u16 {
+        let lines = self.bus.interrupt_lines();
+        let nmi_edge = lines.nmi && !self.nmi_line;
+        self.nmi_line = lines.nmi;
+
+        if nmi_edge {
+            self.nmi();
+            7
+        } else if lines.irq && !self.is_set(Flag::Interrupt) {
+            self.irq();
+            7
+        } else {
+            0
+        }
+    }
+
+    /// Formats the CPU's state as a line of a nestest.log trace, before the instruction at PC is executed
+    /// The line lists the address, the instruction's bytes, its disassembly, the registers, and the cycle count, in nestest's columns:
+    /// `C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:7`
+    pub fn trace(&self) -> String {
+        let opcode = self.bus.peek(self.pc);
+        let (text, length) = disasm::disassemble_traced(&self.bus, self.pc, self.x, self.y);
+        let bytes: Vec<String> = (0..length as u16)
+            .map(|offset| format!("{:02X}", self.bus.peek(self.pc.wrapping_add(offset))))
+            .collect();
+        let marker = if disasm::is_unofficial(opcode) { '*' } else { ' ' };
+        format!(
+            "{:04X}  {:<8} {}{:<31} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+            self.pc,
+            bytes.join(" "),
+            marker,
+            text,
+            self.a,
+            self.x,
+            self.y,
+            self.flags() & !B_FLAG,
+            self.sp,
+            self.cycles
+        )
+    }
+
+    /// Stalls the CPU for `cycles` cycles, as when another device takes over the bus
+    pub fn stall(&mut self, cycles: u64) {
+        self.cycles += cycles;
+    }
+
+    /// Calls `trace` with every access the CPU makes to memory, in program order, replacing any previous trace
+    /// This includes the extra reads and writes the 6502 makes that don't affect the result, such as the write of the unmodified value in read-modify-write instructions; it doesn't include accesses made by other devices, such as DMA.
+    pub fn set_bus_trace(&mut self, trace: impl FnMut(BusOp) + 'static) {
+        self.bus_trace = Some(Box::new(trace));
+    }
+
+    /// Stops tracing memory accesses
+    pub fn clear_bus_trace(&mut self) {
+        self.bus_trace = None;
+    }
+
+    /// Returns the bus the CPU is attached to
+    pub fn bus(&self) -> &M {
+        &self.bus
+    }
+
+    /// Returns the bus the CPU is attached to, so devices such as cartridges can be attached
+    pub fn bus_mut(&mut self) -> &mut M {
+        &mut self.bus
+    }
+
+    /// Returns the accumulator
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rust_nes::cpu::CPU;
+    ///
+    /// let mut cpu = CPU::default();
+    /// cpu.load_program(0x0600, &[0xa9, 0x42]).unwrap();    // LDA #$42
+    /// cpu.set_pc(0x0600);
+    /// cpu.step();
+    /// assert_eq!(cpu.a(), 0x42);
+    /// ```
+    pub fn a(&self) -> u8 {
+        self.a
+    }
+
+    /// Returns the X register
+    pub fn x(&self) -> u8 {
+        self.x
+    }
+
+    /// Returns the Y register
+    pub fn y(&self) -> u8 {
+        self.y
+    }
+
+    /// Returns the program counter
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// Returns the stack pointer
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    /// Returns the raw status register
+    pub fn status(&self) -> u8 {
+        self.status
+    }
+
+    /// Returns the status register as software observes it, with the unused bit 5 set
+    pub fn flags(&self) -> u8 {
+        self.status | U_FLAG
+    }
+
+    /// Sets the accumulator
+    pub fn set_a(&mut self, value: u8) {
+        self.a = value;
+    }
+
+    /// Sets the X register
+    pub fn set_x(&mut self, value: u8) {
+        self.x = value;
+    }
+
+    /// Sets the Y register
+    pub fn set_y(&mut self, value: u8) {
+        self.y = value;
+    }
+
+    /// Sets the program counter
+    pub fn set_pc(&mut self, value: u16) {
+        self.pc = value;
+    }
+
+    /// Sets the stack pointer
+    pub fn set_sp(&mut self, value: u8) {
+        self.sp = value;
+    }
+
+    /// Sets the status register
+    pub fn set_status(&mut self, value: u8) {
+        self.status = value;
+    }
+
+    /// Enables or disables decimal mode for ADC and SBC.
+    /// The NMOS 6502 honors the D flag, but the NES's 2A03 does not; disabling it makes the D flag purely informational.
+    pub fn set_decimal_enabled(&mut self, enabled: bool) {
+        self.decimal_enabled = enabled;
+    }
+
+    /// Makes `BRK` stop the CPU with `StopReason::Brk` rather than entering the IRQ handler
+    /// This is useful for test programs that signal completion with `BRK`. It is off by default.
+    pub fn set_stop_on_brk(&mut self, enabled: bool) {
+        self.stop_on_brk = enabled;
+    }
+
     /// Resets the cycle count
     /// The purpose of the cycle count is to maintain an accurate emulation speed
     pub fn reset_cycle_count(&mut self) {
         self.cycles = 0;
     }
 
+    /// Copies `program` into memory, starting at `start`
+    /// Returns an error, leaving memory untouched, if the program would run past the end of the address space
+    pub fn load_program(&mut self, start: u16, program: &[u8]) -> Result<(), String> {
+        if start as usize + program.len() > 0x10000 {
+            return Err(format!(
+                "Program of {} bytes does not fit in memory when loaded at ${:04X}",
+                program.len(),
+                start
+            ));
+        }
+
+        for (i, byte) in program.iter().enumerate() {
+            self.write(start + i as u16, *byte);
+        }
+        Ok(())
+    }
+
     pub fn load_vector(&mut self, vector: u16, value: u16) {
-        self.memory[vector as usize] = (value & 0xFF) as u8;
-        self.memory[vector as usize + 1] = (value >> 8) as u8;
+        self.write(vector, (value & 0xFF) as u8);
+        self.write(vector.wrapping_add(1), (value >> 8) as u8);
     }
 
     /// Steps the processor, executing an instruction
-    pub fn step(&mut self) {
+    /// If the bus is asserting an interrupt the CPU responds to, the interrupt sequence is run in place of the instruction.
+    /// Returns the number of cycles the instruction consumed, including any cycles the CPU was stalled for by an OAM DMA it started
+    pub fn step(&mut self) -> u16 {
+        let interrupt_cycles = self.poll_interrupts();
+        if interrupt_cycles > 0 {
+            return interrupt_cycles;
+        }
+
+        self.instruction_address = self.pc;
+        self.bus.set_instruction_address(self.pc);
+
         // fetch the byte at the address indicated by the pc
-        let instruction = self.memory[self.pc as usize];
+        let instruction = self.read(self.pc);
         self.pc = self.pc.overflowing_add(1).0;   // increment the pc by one during the 'fetch cycle'
         
         // execute that instruction
-        self.execute_instruction(instruction);
+        let mut cycles = self.execute_instruction(instruction) as u16;
+        self.cycles += cycles as u64;
+
+        // the DMA takes 513 cycles, plus one to align with a read cycle if it starts on an odd cycle
+        if self.bus.take_oam_dma() {
+            let stall = if self.cycles % 2 == 1 { 514 } else { 513 };
+            self.cycles += stall as u64;
+            cycles += stall;
+        }
+
+        cycles
+    }
 
-        // todo: each instruction should increment the pc accordingly
+    /// Runs the processor for at least `cycles` cycles, stopping early if the CPU halts
+    /// Returns the number of cycles actually run; since instructions are executed whole, this may overshoot by up to one instruction
+    pub fn run_for(&mut self, cycles: u64) -> u64 {
+        let mut elapsed: u64 = 0;
+        while elapsed < cycles && self.running {
+            elapsed += self.step() as u64;
+        }
+        elapsed
     }
 
     /// Prints information about CPU internals
@@ -888,7 +1426,28 @@ impl CPU {
         );
     }
 
-    /// Resets the CPU, leaving it in a ready state
+    /// Powers on the CPU from a cold boot, leaving it in a ready state
+    /// Unlike `reset`, this clears the internal RAM and puts the registers in their power-up state (A, X, and Y cleared; status `0x34`) before running the reset sequence, leaving SP at `0xFD`.
+    pub fn power_on(&mut self) {
+        self.power_on_with(RamInit::Zeroed);
+    }
+
+    /// Powers on the CPU from a cold boot like `power_on`, filling the internal RAM according to `init`
+    pub fn power_on_with(&mut self, init: RamInit) {
+        self.bus.initialize_ram(init);
+        self.a = 0;
+        self.x = 0;
+        self.y = 0;
+        self.status = 0x34;
+        self.sp = 0;
+        self.cycles = 0;
+        self.reset();
+    }
+
+    /// Resets the CPU (a warm reset), leaving it in a ready state
+    /// RAM and most registers are preserved; only the reset vector is re-read and the stack and `I` flag are updated.
+    /// Like the real 6502, the reset sequence performs three fake pushes (decrementing SP by three without writing) and sets the `I` flag; the `D` flag is left unchanged.
+    /// Starting from a stack pointer of zero, this leaves SP at `0xFD`.
     pub fn reset(&mut self) {
         // get the start address
         // remember, the 6502 is little endian, so we fetch the high byte, then the low byte
@@ -896,8 +1455,1106 @@ impl CPU {
         let start_address: u16 = self.read_absolute_address();
         self.pc = start_address;
         self.running = true;
-        self.sp = 0xFF;
+        self.stop_reason = None;
+        self.sp = self.sp.wrapping_sub(3);
+        self.status |= I_FLAG | U_FLAG;
+        // like the interrupt sequences, which it shares its logic with, the reset sequence takes 7 cycles
+        self.cycles += 7;
 
         // todo: additional start routines
     }
 }
+
+impl<M: Mem + SaveState> SaveState for CPU<M> {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u64(self.cycles);
+        writer.write_bool(self.running);
+        // the stop reason is written as a tag followed by the opcode (which is only meaningful for illegal opcodes) and the address the CPU stopped at
+        let (tag, opcode) = match self.stop_reason {
+            None => (0, 0),
+            Some(StopReason::IllegalOpcode(opcode, _)) => (1, opcode),
+            Some(StopReason::Halt) => (2, 0),
+            Some(StopReason::Brk) => (3, 0),
+        };
+        writer.write_u8(tag);
+        writer.write_u8(opcode);
+        writer.write_u16(self.stop_address);
+        writer.write_bool(self.page_crossed);
+        writer.write_bool(self.nmi_line);
+        writer.write_u8(self.status);
+        writer.write_u16(self.pc);
+        writer.write_u8(self.sp);
+        writer.write_u8(self.a);
+        writer.write_u8(self.x);
+        writer.write_u8(self.y);
+        self.bus.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.cycles = reader.read_u64()?;
+        self.running = reader.read_bool()?;
+        let tag = reader.read_u8()?;
+        let opcode = reader.read_u8()?;
+        let address = reader.read_u16()?;
+        self.stop_reason = match tag {
+            0 => None,
+            1 => Some(StopReason::IllegalOpcode(opcode, address)),
+            2 => Some(StopReason::Halt),
+            3 => Some(StopReason::Brk),
+            _ => return Err(format!("Invalid stop reason in save state: {}", tag)),
+        };
+        self.stop_address = address;
+        self.page_crossed = reader.read_bool()?;
+        self.nmi_line = reader.read_bool()?;
+        self.status = reader.read_u8()?;
+        self.pc = reader.read_u16()?;
+        self.sp = reader.read_u8()?;
+        self.a = reader.read_u8()?;
+        self.x = reader.read_u8()?;
+        self.y = reader.read_u8()?;
+        self.bus.load_state(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use super::*;
+
+    /// Where test programs are loaded
+    const PROGRAM_START: u16 = 0x0600;
+
+    /// Creates a CPU with `program` loaded and the pc pointing at it
+    fn cpu_with_program(program: &[u8]) -> CPU {
+        let mut cpu = CPU::default();
+        cpu.load_program(PROGRAM_START, program).unwrap();
+        cpu.set_pc(PROGRAM_START);
+        cpu.set_sp(0xfd);
+        cpu
+    }
+
+    /// Runs `SBC #operand` with the given accumulator and carry, returning the CPU afterwards
+    fn sbc_immediate(a: u8, operand: u8, carry: bool) -> CPU {
+        let mut cpu = cpu_with_program(&[0xe9, operand]);
+        cpu.set_a(a);
+        cpu.set_status(if carry { C_FLAG } else { 0 });
+        cpu.step();
+        cpu
+    }
+
+    #[test]
+    fn sbc_without_borrow() {
+        let cpu = sbc_immediate(0x50, 0x10, true);
+        assert_eq!(cpu.a(), 0x40);
+        assert_eq!(cpu.status() & (C_FLAG | V_FLAG | Z_FLAG | N_FLAG), C_FLAG);
+    }
+
+    #[test]
+    fn sbc_with_borrow_in() {
+        // with carry clear, one more is subtracted
+        let cpu = sbc_immediate(0x50, 0x10, false);
+        assert_eq!(cpu.a(), 0x3f);
+        assert_ne!(cpu.status() & C_FLAG, 0);
+    }
+
+    #[test]
+    fn sbc_borrow_out_clears_carry() {
+        let cpu = sbc_immediate(0x50, 0xf0, true);
+        assert_eq!(cpu.a(), 0x60);
+        assert_eq!(cpu.status() & (C_FLAG | V_FLAG), 0);
+    }
+
+    #[test]
+    fn sbc_signed_overflow() {
+        // 80 - (-80) doesn't fit in a signed byte
+        let cpu = sbc_immediate(0x50, 0xb0, true);
+        assert_eq!(cpu.a(), 0xa0);
+        assert_eq!(cpu.status() & (C_FLAG | V_FLAG | N_FLAG), V_FLAG | N_FLAG);
+
+        // -128 - 1 doesn't either
+        let cpu = sbc_immediate(0x80, 0x01, true);
+        assert_eq!(cpu.a(), 0x7f);
+        assert_eq!(cpu.status() & (C_FLAG | V_FLAG | N_FLAG), C_FLAG | V_FLAG);
+    }
+
+    #[test]
+    fn sbc_zero_result() {
+        let cpu = sbc_immediate(0x42, 0x42, true);
+        assert_eq!(cpu.a(), 0x00);
+        assert_eq!(cpu.status() & (C_FLAG | Z_FLAG | N_FLAG), C_FLAG | Z_FLAG);
+    }
+
+    #[test]
+    fn sbc_borrow_chain() {
+        // 0x0100 - 0x0001, a byte at a time: the borrow out of the low byte is taken from the high byte
+        let mut cpu = cpu_with_program(&[
+            0x38,           // SEC
+            0xa9, 0x00,     // LDA #$00
+            0xe9, 0x01,     // SBC #$01
+            0xaa,           // TAX
+            0xa9, 0x01,     // LDA #$01
+            0xe9, 0x00,     // SBC #$00
+        ]);
+        for _ in 0..6 {
+            cpu.step();
+        }
+        assert_eq!(cpu.x(), 0xff);
+        assert_eq!(cpu.a(), 0x00);
+        assert_eq!(cpu.status() & (C_FLAG | Z_FLAG), C_FLAG | Z_FLAG);
+    }
+
+    /// Runs `BIT $10` with the given accumulator and operand, returning the CPU afterwards
+    fn bit_zero_page(a: u8, operand: u8) -> (CPU, usize) {
+        let mut cpu = cpu_with_program(&[0x24, 0x10]);
+        cpu.write(0x0010, operand);
+        cpu.set_a(a);
+
+        let reads = Rc::new(RefCell::new(0));
+        let counter = Rc::clone(&reads);
+        cpu.set_bus_trace(move |op| {
+            if op == BusOp::Read(0x0010) {
+                *counter.borrow_mut() += 1;
+            }
+        });
+        cpu.step();
+        let count = *reads.borrow();
+        (cpu, count)
+    }
+
+    #[test]
+    fn bit_sets_zero_when_no_bits_match() {
+        let (cpu, reads) = bit_zero_page(0x0f, 0xf0);
+        assert_eq!(cpu.status() & (Z_FLAG | N_FLAG | V_FLAG), Z_FLAG | N_FLAG | V_FLAG);
+        assert_eq!(reads, 1);
+        // A is left alone
+        assert_eq!(cpu.a(), 0x0f);
+    }
+
+    #[test]
+    fn bit_clears_zero_when_bits_match() {
+        let (cpu, reads) = bit_zero_page(0x0f, 0x01);
+        assert_eq!(cpu.status() & (Z_FLAG | N_FLAG | V_FLAG), 0);
+        assert_eq!(reads, 1);
+    }
+
+    #[test]
+    fn inc_wraps_to_zero() {
+        let mut cpu = cpu_with_program(&[0xe6, 0x10]);     // INC $10
+        cpu.write(0x0010, 0xff);
+        cpu.step();
+        assert_eq!(cpu.read(0x0010), 0x00);
+        assert_eq!(cpu.status() & (Z_FLAG | N_FLAG), Z_FLAG);
+    }
+
+    #[test]
+    fn dec_wraps_to_ff() {
+        let mut cpu = cpu_with_program(&[0xce, 0x00, 0x02]);   // DEC $0200
+        cpu.step();
+        assert_eq!(cpu.read(0x0200), 0xff);
+        assert_eq!(cpu.status() & (Z_FLAG | N_FLAG), N_FLAG);
+    }
+
+    #[test]
+    fn dex_wraps_to_ff() {
+        let mut cpu = cpu_with_program(&[0xa2, 0x00, 0xca]);   // LDX #$00; DEX
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.x(), 0xff);
+        assert_eq!(cpu.status() & (Z_FLAG | N_FLAG), N_FLAG);
+    }
+
+    #[test]
+    fn inx_wraps_to_zero() {
+        let mut cpu = cpu_with_program(&[0xa2, 0xff, 0xe8]);   // LDX #$FF; INX
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.x(), 0x00);
+        assert_eq!(cpu.status() & (Z_FLAG | N_FLAG), Z_FLAG);
+    }
+
+    #[test]
+    fn dey_and_iny_wrap() {
+        let mut cpu = cpu_with_program(&[0xa0, 0x00, 0x88, 0xc8]);     // LDY #$00; DEY; INY
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.y(), 0xff);
+        assert_eq!(cpu.status() & (Z_FLAG | N_FLAG), N_FLAG);
+        cpu.step();
+        assert_eq!(cpu.y(), 0x00);
+        assert_eq!(cpu.status() & (Z_FLAG | N_FLAG), Z_FLAG);
+    }
+
+    #[test]
+    fn absolute_operand_wraps_around_top_of_memory() {
+        // LDA $1234, with the opcode at 0xFFFE and the operand's high byte back at 0x0000
+        let mut cpu = CPU::default();
+        cpu.write(0xfffe, 0xad);
+        cpu.write(0xffff, 0x34);
+        cpu.write(0x0000, 0x12);
+        cpu.write(0x1234, 0x99);
+        cpu.set_pc(0xfffe);
+        cpu.step();
+        assert_eq!(cpu.a(), 0x99);
+        assert_eq!(cpu.pc(), 0x0001);
+    }
+
+    #[test]
+    fn adc_decimal_carries_into_the_next_digit() {
+        let mut cpu = cpu_with_program(&[0xf8, 0x18, 0xa9, 0x09, 0x69, 0x01]);    // SED; CLC; LDA #$09; ADC #$01
+        for _ in 0..4 {
+            cpu.step();
+        }
+        assert_eq!(cpu.a(), 0x10);
+        assert_eq!(cpu.status() & C_FLAG, 0);
+    }
+
+    #[test]
+    fn adc_decimal_sets_carry_past_99() {
+        let mut cpu = cpu_with_program(&[0xf8, 0x38, 0xa9, 0x58, 0x69, 0x46]);    // SED; SEC; LDA #$58; ADC #$46
+        for _ in 0..4 {
+            cpu.step();
+        }
+        assert_eq!(cpu.a(), 0x05);
+        assert_ne!(cpu.status() & C_FLAG, 0);
+    }
+
+    #[test]
+    fn sbc_decimal_borrows_from_the_next_digit() {
+        let mut cpu = cpu_with_program(&[0xf8, 0x38, 0xa9, 0x10, 0xe9, 0x01]);    // SED; SEC; LDA #$10; SBC #$01
+        for _ in 0..4 {
+            cpu.step();
+        }
+        assert_eq!(cpu.a(), 0x09);
+        assert_ne!(cpu.status() & C_FLAG, 0);
+
+        let mut cpu = cpu_with_program(&[0xf8, 0x38, 0xa9, 0x00, 0xe9, 0x01]);    // SED; SEC; LDA #$00; SBC #$01
+        for _ in 0..4 {
+            cpu.step();
+        }
+        assert_eq!(cpu.a(), 0x99);
+        assert_eq!(cpu.status() & C_FLAG, 0);
+    }
+
+    #[test]
+    fn decimal_mode_can_be_disabled() {
+        let mut cpu = cpu_with_program(&[0xf8, 0x18, 0xa9, 0x09, 0x69, 0x01]);    // SED; CLC; LDA #$09; ADC #$01
+        cpu.set_decimal_enabled(false);
+        for _ in 0..4 {
+            cpu.step();
+        }
+        assert_eq!(cpu.a(), 0x0a);
+        // the flag itself still works
+        assert_ne!(cpu.status() & D_FLAG, 0);
+    }
+
+    /// Runs one instruction with X set to `x`, returning the cycles it took
+    fn cycles_with_x(program: &[u8], x: u8) -> u16 {
+        let mut cpu = cpu_with_program(program);
+        cpu.set_x(x);
+        cpu.step()
+    }
+
+    #[test]
+    fn indexed_read_across_page_takes_extra_cycle() {
+        let lda = [0xbd, 0xff, 0x12];   // LDA $12FF,X
+        assert_eq!(cycles_with_x(&lda, 0), 4);
+        assert_eq!(cycles_with_x(&lda, 1), 5);
+    }
+
+    #[test]
+    fn indexed_store_has_no_page_penalty() {
+        let sta = [0x9d, 0xff, 0x12];   // STA $12FF,X
+        assert_eq!(cycles_with_x(&sta, 0), 5);
+        assert_eq!(cycles_with_x(&sta, 1), 5);
+    }
+
+    #[test]
+    fn branch_not_taken_takes_two_cycles() {
+        let mut cpu = cpu_with_program(&[0xd0, 0x10]);     // BNE +16, with Z set
+        cpu.set_status(Z_FLAG);
+        assert_eq!(cpu.step(), 2);
+        assert_eq!(cpu.pc(), PROGRAM_START + 2);
+    }
+
+    #[test]
+    fn branch_taken_on_page_takes_three_cycles() {
+        let mut cpu = cpu_with_program(&[0xd0, 0x10]);     // BNE +16
+        assert_eq!(cpu.step(), 3);
+        assert_eq!(cpu.pc(), PROGRAM_START + 0x12);
+    }
+
+    #[test]
+    fn branch_taken_across_page_takes_four_cycles() {
+        // from 0x06F0, the next instruction is at 0x06F2, and +0x20 lands on the next page
+        let mut cpu = CPU::default();
+        cpu.load_program(0x06f0, &[0xd0, 0x20]).unwrap();
+        cpu.set_pc(0x06f0);
+        assert_eq!(cpu.step(), 4);
+        assert_eq!(cpu.pc(), 0x0712);
+    }
+
+    #[test]
+    fn nmi_pushes_pc_and_status_and_jumps_to_vector() {
+        let mut cpu = cpu_with_program(&[]);
+        cpu.load_vector(NMI_VECTOR, 0x8123);
+        cpu.set_pc(0x1234);
+        cpu.set_status(C_FLAG | B_FLAG);
+        cpu.nmi();
+        assert_eq!(cpu.pc(), 0x8123);
+        assert_eq!(cpu.sp(), 0xfa);
+        assert_eq!(cpu.read(0x01fd), 0x12);
+        assert_eq!(cpu.read(0x01fc), 0x34);
+        // B is clear and bit 5 set in the pushed status
+        assert_eq!(cpu.read(0x01fb), C_FLAG | U_FLAG);
+        assert!(cpu.is_set(Flag::Interrupt));
+        assert_eq!(cpu.cycle_count(), 7);
+    }
+
+    #[test]
+    fn brk_pushes_status_with_b_set() {
+        let mut cpu = cpu_with_program(&[0x00, 0xea]);   // BRK, padding byte
+        cpu.load_vector(IRQ_VECTOR, 0x9000);
+        cpu.set_status(C_FLAG);
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x9000);
+        // the return address skips the padding byte
+        assert_eq!(cpu.read(0x01fd), 0x06);
+        assert_eq!(cpu.read(0x01fc), 0x02);
+        assert_eq!(cpu.read(0x01fb), C_FLAG | B_FLAG | U_FLAG);
+        assert!(cpu.is_set(Flag::Interrupt));
+    }
+
+    #[test]
+    fn irq_pushes_status_with_b_clear() {
+        let mut cpu = cpu_with_program(&[]);
+        cpu.load_vector(IRQ_VECTOR, 0x9000);
+        cpu.set_status(C_FLAG | B_FLAG);
+        cpu.irq();
+        assert_eq!(cpu.pc(), 0x9000);
+        assert_eq!(cpu.read(0x01fb), C_FLAG | U_FLAG);
+    }
+
+    #[test]
+    fn php_pushes_b_and_bit_5() {
+        let mut cpu = cpu_with_program(&[0x08]);   // PHP
+        cpu.set_status(N_FLAG);
+        cpu.step();
+        assert_eq!(cpu.read(0x01fd), N_FLAG | B_FLAG | U_FLAG);
+    }
+
+    #[test]
+    fn plp_ignores_b_and_keeps_bit_5() {
+        for pulled in [0x00, B_FLAG, U_FLAG, B_FLAG | U_FLAG | C_FLAG] {
+            let mut cpu = cpu_with_program(&[0x28]);   // PLP
+            cpu.set_sp(0xfc);
+            cpu.write(0x01fd, pulled);
+            cpu.step();
+            assert_eq!(cpu.status(), (pulled & !B_FLAG) | U_FLAG);
+        }
+    }
+
+    #[test]
+    fn rti_restores_status_like_plp() {
+        let mut cpu = cpu_with_program(&[0x40]);   // RTI
+        cpu.set_sp(0xfa);
+        cpu.write(0x01fb, B_FLAG | V_FLAG);
+        cpu.write(0x01fc, 0x34);
+        cpu.write(0x01fd, 0x12);
+        cpu.step();
+        assert_eq!(cpu.status(), V_FLAG | U_FLAG);
+        assert_eq!(cpu.pc(), 0x1234);
+    }
+
+    #[test]
+    fn reset_sets_i_and_offsets_stack() {
+        let mut cpu = cpu_with_program(&[]);
+        cpu.load_vector(RESET_VECTOR, 0x8000);
+        cpu.set_sp(0x00);
+        cpu.set_status(D_FLAG);
+        cpu.reset();
+        assert_eq!(cpu.sp(), 0xfd);
+        assert_eq!(cpu.pc(), 0x8000);
+        assert!(cpu.is_set(Flag::Interrupt));
+        // the NMOS 6502 leaves D alone
+        assert!(cpu.is_set(Flag::Decimal));
+    }
+
+    #[test]
+    fn load_program_rejects_oversized_program() {
+        let mut cpu = CPU::default();
+        assert!(cpu.load_program(0x0000, &vec![0xea; 0x10001]).is_err());
+    }
+
+    #[test]
+    fn load_program_fits_at_top_of_memory() {
+        let mut cpu = CPU::default();
+        assert!(cpu.load_program(0xfffe, &[0x01, 0x02]).is_ok());
+        assert_eq!(cpu.read(0xffff), 0x02);
+        // one byte too many, and memory is left alone
+        assert!(cpu.load_program(0xfffe, &[0x03, 0x04, 0x05]).is_err());
+        assert_eq!(cpu.read(0xfffe), 0x01);
+    }
+
+    #[test]
+    fn flags_report_bit_5_set() {
+        let mut cpu = cpu_with_program(&[]);
+        cpu.set_status(C_FLAG);
+        assert_eq!(cpu.status(), C_FLAG);
+        assert_eq!(cpu.flags(), C_FLAG | U_FLAG);
+    }
+
+    #[test]
+    fn register_setters_round_trip() {
+        let mut cpu = cpu_with_program(&[]);
+        cpu.set_a(0x01);
+        cpu.set_x(0x02);
+        cpu.set_y(0x03);
+        cpu.set_sp(0x04);
+        cpu.set_pc(0x0506);
+        assert_eq!((cpu.a(), cpu.x(), cpu.y(), cpu.sp(), cpu.pc()), (0x01, 0x02, 0x03, 0x04, 0x0506));
+    }
+
+    #[test]
+    fn step_returns_instruction_cycles() {
+        assert_eq!(cycles_with_x(&[0xa9, 0x00], 0), 2);   // LDA #$00
+        assert_eq!(cycles_with_x(&[0xbd, 0x34, 0x12], 0xff), 5);   // LDA $1234,X into $1333
+    }
+
+    #[test]
+    fn illegal_opcode_records_stop_reason() {
+        let mut cpu = cpu_with_program(&[0xea, 0x02]);   // NOP, then the unimplemented opcode $02
+        cpu.step();
+        cpu.step();
+        assert!(!cpu.is_running());
+        assert_eq!(cpu.stop_reason(), Some(StopReason::IllegalOpcode(0x02, 0x0601)));
+    }
+
+    #[test]
+    fn brk_stops_when_asked() {
+        let mut cpu = cpu_with_program(&[0xea, 0x00]);   // NOP; BRK
+        cpu.load_vector(IRQ_VECTOR, 0x9000);
+        cpu.set_stop_on_brk(true);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.run_outcome(), RunOutcome::Halted { reason: StopReason::Brk, pc: 0x0601 });
+        assert_eq!(cpu.sp(), 0xfd);
+    }
+
+    #[test]
+    fn jammed_cpu_reports_halt_reason_and_pc() {
+        // NOP; XAA #$00 jams the CPU at $0601
+        let mut cpu = cpu_with_program(&[0xea, 0x8b, 0x00]);
+        cpu.step();
+        assert_eq!(cpu.run_outcome(), RunOutcome::Completed);
+        cpu.step();
+        assert_eq!(cpu.run_outcome(), RunOutcome::Halted { reason: StopReason::Halt, pc: 0x0601 });
+    }
+
+    #[test]
+    fn lax_loads_a_and_x() {
+        let mut cpu = cpu_with_program(&[0xa7, 0x10]);   // LAX $10
+        cpu.write(0x0010, 0x80);
+        assert_eq!(cpu.step(), 3);
+        assert_eq!((cpu.a(), cpu.x()), (0x80, 0x80));
+        assert!(cpu.is_set(Flag::Negative));
+        assert!(!cpu.is_set(Flag::Zero));
+    }
+
+    #[test]
+    fn lax_absolute_y_takes_page_penalty() {
+        let mut cpu = cpu_with_program(&[0xbf, 0xff, 0x02]);   // LAX $02FF,Y
+        cpu.set_y(0x01);
+        cpu.write(0x0300, 0x55);
+        assert_eq!(cpu.step(), 5);
+        assert_eq!((cpu.a(), cpu.x()), (0x55, 0x55));
+    }
+
+    #[test]
+    fn lax_indirect_y_matches_nestest() {
+        // nestest: LAX ($89),Y with $89 pointing at $0300 and Y = $34
+        let mut cpu = cpu_with_program(&[0xb3, 0x89]);
+        cpu.write(0x0089, 0x00);
+        cpu.write(0x008a, 0x03);
+        cpu.write(0x0334, 0x89);
+        cpu.set_y(0x34);
+        assert_eq!(cpu.step(), 5);
+        assert_eq!((cpu.a(), cpu.x()), (0x89, 0x89));
+    }
+
+    #[test]
+    fn lax_zero_sets_z() {
+        let mut cpu = cpu_with_program(&[0xa3, 0x40]);   // LAX ($40,X)
+        cpu.set_x(0x02);
+        cpu.write(0x0042, 0x00);
+        cpu.write(0x0043, 0x04);
+        cpu.write(0x0400, 0x00);
+        cpu.set_a(0xff);
+        assert_eq!(cpu.step(), 6);
+        assert_eq!((cpu.a(), cpu.x()), (0x00, 0x00));
+        assert!(cpu.is_set(Flag::Zero));
+    }
+
+    /// Creates a CPU running `program` with X and Y set to 1 and zero-page pointers in place, so each addressing mode resolves as follows:
+    /// `$40` to `$0040`; `$40,X` and `$40,Y` to `$0041`; `$0300` to `$0300`; `$02FF,X` and `$02FF,Y` to `$0300`; `($20,X)` to `$0302`; and `($20),Y` to `$0300`
+    fn cpu_with_pointers(program: &[u8]) -> CPU {
+        let mut cpu = cpu_with_program(program);
+        cpu.set_x(0x01);
+        cpu.set_y(0x01);
+        cpu.write(0x0020, 0xff);
+        cpu.write(0x0021, 0x02);
+        cpu.write(0x0022, 0x03);
+        cpu
+    }
+
+    #[test]
+    fn sax_stores_a_and_x_without_flags() {
+        let cases: [(&[u8], u16); 4] = [
+            (&[0x87, 0x40], 0x0040),
+            (&[0x97, 0x40], 0x0041),
+            (&[0x8f, 0x00, 0x03], 0x0300),
+            (&[0x83, 0x20], 0x0302),
+        ];
+        for (program, address) in cases {
+            let mut cpu = cpu_with_pointers(program);
+            cpu.set_a(0x03);
+            cpu.set_status(Z_FLAG | N_FLAG);
+            cpu.step();
+            assert_eq!(cpu.read(address), 0x01, "opcode {:02X}", program[0]);
+            assert_eq!(cpu.status(), Z_FLAG | N_FLAG);
+        }
+    }
+
+    /// Every addressing mode of a read-modify-write illegal, given its opcodes in the order zp, zp,X, abs, abs,X, abs,Y, (zp,X), (zp),Y
+    fn rmw_cases(opcodes: [u8; 7]) -> [(Vec<u8>, u16); 7] {
+        [
+            (vec![opcodes[0], 0x40], 0x0040),
+            (vec![opcodes[1], 0x40], 0x0041),
+            (vec![opcodes[2], 0x00, 0x03], 0x0300),
+            (vec![opcodes[3], 0xff, 0x02], 0x0300),
+            (vec![opcodes[4], 0xff, 0x02], 0x0300),
+            (vec![opcodes[5], 0x20], 0x0302),
+            (vec![opcodes[6], 0x20], 0x0300),
+        ]
+    }
+
+    #[test]
+    fn dcp_decrements_then_compares() {
+        for (program, address) in rmw_cases([0xc7, 0xd7, 0xcf, 0xdf, 0xdb, 0xc3, 0xd3]) {
+            let mut cpu = cpu_with_pointers(&program);
+            cpu.write(address, 0x43);
+            cpu.set_a(0x40);
+            cpu.step();
+            assert_eq!(cpu.read(address), 0x42, "opcode {:02X}", program[0]);
+            // $40 - $42 is negative, even though $40 itself isn't
+            assert_eq!(cpu.status() & (N_FLAG | Z_FLAG | C_FLAG), N_FLAG);
+        }
+    }
+
+    #[test]
+    fn dcp_sets_z_and_c_on_equal() {
+        let mut cpu = cpu_with_pointers(&[0xc7, 0x40]);   // DCP $40
+        cpu.write(0x0040, 0x41);
+        cpu.set_a(0x40);
+        cpu.step();
+        assert_eq!(cpu.status() & (N_FLAG | Z_FLAG | C_FLAG), Z_FLAG | C_FLAG);
+    }
+
+    #[test]
+    fn isc_increments_then_subtracts() {
+        for (program, address) in rmw_cases([0xe7, 0xf7, 0xef, 0xff, 0xfb, 0xe3, 0xf3]) {
+            let mut cpu = cpu_with_pointers(&program);
+            cpu.write(address, 0x0f);
+            cpu.set_a(0x20);
+            cpu.set_status(C_FLAG);
+            cpu.step();
+            assert_eq!(cpu.read(address), 0x10, "opcode {:02X}", program[0]);
+            assert_eq!(cpu.a(), 0x10);
+            assert_eq!(cpu.status() & (N_FLAG | Z_FLAG | C_FLAG | V_FLAG), C_FLAG);
+        }
+    }
+
+    /// Runs a compare of `register` against the immediate `operand`, returning the N, Z and C flags afterwards
+    fn compare_flags(opcode: u8, register: u8, operand: u8) -> u8 {
+        let mut cpu = cpu_with_program(&[opcode, operand]);
+        match opcode {
+            0xe0 => cpu.set_x(register),
+            0xc0 => cpu.set_y(register),
+            _ => cpu.set_a(register),
+        }
+        cpu.step();
+        cpu.status() & (N_FLAG | Z_FLAG | C_FLAG)
+    }
+
+    #[test]
+    fn compares_set_n_from_the_difference() {
+        // CMP #, CPX # and CPY #
+        for opcode in [0xc9, 0xe0, 0xc0] {
+            // $80 - $10 = $70, which is positive even though $80 isn't
+            assert_eq!(compare_flags(opcode, 0x80, 0x10), C_FLAG, "opcode {:02X}", opcode);
+            // $10 - $20 = $F0, which is negative even though $10 isn't
+            assert_eq!(compare_flags(opcode, 0x10, 0x20), N_FLAG, "opcode {:02X}", opcode);
+            assert_eq!(compare_flags(opcode, 0x90, 0x90), Z_FLAG | C_FLAG, "opcode {:02X}", opcode);
+        }
+    }
+
+    /// Runs every addressing mode of a read-modify-write illegal with the given starting memory, accumulator, and status, checking the memory, accumulator, and N/V/Z/C flags afterwards
+    fn check_rmw(opcodes: [u8; 7], before: (u8, u8, u8), after: (u8, u8, u8)) {
+        let (memory, a, status) = before;
+        let (expected_memory, expected_a, expected_flags) = after;
+        for (program, address) in rmw_cases(opcodes) {
+            let mut cpu = cpu_with_pointers(&program);
+            cpu.write(address, memory);
+            cpu.set_a(a);
+            cpu.set_status(status);
+            cpu.step();
+            assert_eq!(cpu.read(address), expected_memory, "opcode {:02X}", program[0]);
+            assert_eq!(cpu.a(), expected_a, "opcode {:02X}", program[0]);
+            assert_eq!(cpu.status() & (N_FLAG | V_FLAG | Z_FLAG | C_FLAG), expected_flags, "opcode {:02X}", program[0]);
+        }
+    }
+
+    #[test]
+    fn slo_shifts_then_ors() {
+        check_rmw([0x07, 0x17, 0x0f, 0x1f, 0x1b, 0x03, 0x13], (0x81, 0x10, 0), (0x02, 0x12, C_FLAG));
+    }
+
+    #[test]
+    fn rla_rotates_then_ands() {
+        check_rmw([0x27, 0x37, 0x2f, 0x3f, 0x3b, 0x23, 0x33], (0x81, 0xff, C_FLAG), (0x03, 0x03, C_FLAG));
+    }
+
+    #[test]
+    fn sre_shifts_then_eors() {
+        check_rmw([0x47, 0x57, 0x4f, 0x5f, 0x5b, 0x43, 0x53], (0x03, 0x81, 0), (0x01, 0x80, N_FLAG | C_FLAG));
+    }
+
+    #[test]
+    fn rra_rotates_then_adds_with_the_rotated_carry() {
+        // the bit rotated out becomes the carry into the addition, which overflows $7F
+        check_rmw([0x67, 0x77, 0x6f, 0x7f, 0x7b, 0x63, 0x73], (0x01, 0x7f, 0), (0x00, 0x80, N_FLAG | V_FLAG));
+    }
+
+    #[test]
+    fn unofficial_nops_skip_operands() {
+        let cases: [(&[u8], u16, u16); 4] = [
+            (&[0x80, 0x12], 2, 2),               // NOP #$12
+            (&[0x04, 0x12], 2, 3),               // NOP $12
+            (&[0x0c, 0x34, 0x12], 3, 4),         // NOP $1234
+            (&[0x1c, 0xff, 0x12], 3, 5),         // NOP $12FF,X across a page
+        ];
+        for (program, length, cycles) in cases {
+            let mut cpu = cpu_with_program(program);
+            cpu.set_x(0x01);
+            let status = cpu.status();
+            assert_eq!(cpu.step(), cycles, "opcode {:02X}", program[0]);
+            assert_eq!(cpu.pc(), PROGRAM_START + length);
+            assert_eq!(cpu.status(), status);
+        }
+    }
+
+    #[test]
+    fn indexed_stores_use_fixed_cycle_counts() {
+        for (opcode, time) in [(0x9d, 5), (0x99, 5), (0x91, 6)] {
+            assert_eq!(instruction::decode(opcode).unwrap().time, time);
+        }
+
+        // STA $12FF,Y and STA ($20),Y, without and with a page cross
+        for y in [0x00, 0x01] {
+            let mut cpu = cpu_with_program(&[0x99, 0xff, 0x12]);
+            cpu.set_y(y);
+            assert_eq!(cpu.step(), 5);
+
+            let mut cpu = cpu_with_program(&[0x91, 0x20]);
+            cpu.write(0x0020, 0xff);
+            cpu.write(0x0021, 0x12);
+            cpu.set_y(y);
+            assert_eq!(cpu.step(), 6);
+        }
+    }
+
+    #[test]
+    fn jmp_indirect_wraps_within_page() {
+        let mut cpu = cpu_with_program(&[0x6c, 0xff, 0x02]);   // JMP ($02FF)
+        cpu.write(0x02ff, 0x34);
+        cpu.write(0x0200, 0x12);
+        cpu.write(0x0300, 0x56);   // the decoy high byte
+        cpu.step();
+        assert_eq!(cpu.pc(), 0x1234);
+    }
+
+    #[test]
+    fn indexed_indirect_pointer_wraps_in_zero_page() {
+        let mut cpu = cpu_with_program(&[0xa1, 0xff]);   // LDA ($FF,X)
+        cpu.write(0x00ff, 0x00);
+        cpu.write(0x0000, 0x04);
+        cpu.write(0x0100, 0x05);   // would be read if the pointer didn't wrap
+        cpu.write(0x0400, 0x42);
+        cpu.step();
+        assert_eq!(cpu.a(), 0x42);
+    }
+
+    #[test]
+    fn indirect_indexed_pointer_wraps_in_zero_page() {
+        let mut cpu = cpu_with_program(&[0xb1, 0xff]);   // LDA ($FF),Y
+        cpu.write(0x00ff, 0x00);
+        cpu.write(0x0000, 0x04);
+        cpu.write(0x0100, 0x05);
+        cpu.write(0x0401, 0x42);
+        cpu.set_y(0x01);
+        cpu.step();
+        assert_eq!(cpu.a(), 0x42);
+    }
+
+    #[test]
+    fn power_on_clears_ram_and_registers() {
+        let mut cpu = CPU::default();
+        cpu.load_vector(RESET_VECTOR, 0x8000);
+        cpu.write(0x0123, 0x55);
+        cpu.set_a(0x11);
+        cpu.set_x(0x22);
+        cpu.power_on();
+        assert_eq!(cpu.read(0x0123), 0x00);
+        assert_eq!((cpu.a(), cpu.x(), cpu.y()), (0x00, 0x00, 0x00));
+        assert_eq!(cpu.sp(), 0xfd);
+        assert_eq!(cpu.status(), 0x34);
+        assert_eq!(cpu.pc(), 0x8000);
+    }
+
+    #[test]
+    fn reset_preserves_ram_and_registers() {
+        let mut cpu = CPU::default();
+        cpu.load_vector(RESET_VECTOR, 0x8000);
+        cpu.power_on();
+        cpu.write(0x0123, 0x55);
+        cpu.set_a(0x11);
+        cpu.reset();
+        assert_eq!(cpu.read(0x0123), 0x55);
+        assert_eq!(cpu.a(), 0x11);
+        assert_eq!(cpu.sp(), 0xfa);
+        assert_eq!(cpu.pc(), 0x8000);
+    }
+
+    /// Powers on a CPU on the NES bus with the given RAM fill, returning the bytes at a few sampled addresses
+    fn ram_after_power_on(init: RamInit) -> [u8; 4] {
+        let mut cpu = CPU::default();
+        cpu.power_on_with(init);
+        [0x0000, 0x00ff, 0x0100, 0x07ff].map(|address| cpu.read(address))
+    }
+
+    #[test]
+    fn power_on_fills_ram() {
+        assert_eq!(ram_after_power_on(RamInit::Zeroed), [0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(ram_after_power_on(RamInit::Filled(0xa5)), [0xa5, 0xa5, 0xa5, 0xa5]);
+        // alternating $00 and $FF pages; $0700 is an odd page
+        assert_eq!(ram_after_power_on(RamInit::Pattern), [0x00, 0x00, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn run_for_runs_at_least_the_requested_cycles() {
+        let mut cpu = cpu_with_program(&[0xea; 0x100]);
+        cpu.load_vector(RESET_VECTOR, PROGRAM_START);
+        cpu.reset();
+        let start = cpu.cycle_count();
+        let elapsed = cpu.run_for(100);
+        assert!((100..=101).contains(&elapsed));
+        assert_eq!(cpu.cycle_count() - start, elapsed);
+    }
+
+    #[test]
+    fn run_for_stops_when_cpu_halts() {
+        let mut cpu = cpu_with_program(&[0xea, 0xea, 0x02]);
+        cpu.load_vector(RESET_VECTOR, PROGRAM_START);
+        cpu.reset();
+        assert!(cpu.run_for(100) < 100);
+        assert!(!cpu.is_running());
+    }
+
+    /// The opening of nestest.log, from its automated entry point at 0xC000, without the PPU columns
+    const NESTEST_OPENING: [&str; 10] = [
+        "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:7",
+        "C5F5  A2 00     LDX #$00                        A:00 X:00 Y:00 P:24 SP:FD CYC:10",
+        "C5F7  86 00     STX $00 = 00                    A:00 X:00 Y:00 P:26 SP:FD CYC:12",
+        "C5F9  86 10     STX $10 = 00                    A:00 X:00 Y:00 P:26 SP:FD CYC:15",
+        "C5FB  86 11     STX $11 = 00                    A:00 X:00 Y:00 P:26 SP:FD CYC:18",
+        "C5FD  20 2D C7  JSR $C72D                       A:00 X:00 Y:00 P:26 SP:FD CYC:21",
+        "C72D  EA        NOP                             A:00 X:00 Y:00 P:26 SP:FB CYC:27",
+        "C72E  38        SEC                             A:00 X:00 Y:00 P:26 SP:FB CYC:29",
+        "C72F  B0 04     BCS $C735                       A:00 X:00 Y:00 P:27 SP:FB CYC:31",
+        "C735  EA        NOP                             A:00 X:00 Y:00 P:27 SP:FB CYC:34",
+    ];
+
+    #[test]
+    fn trace_matches_the_opening_of_nestest_log() {
+        let mut cpu = CPU::default();
+        cpu.load_program(0xc000, &[0x4c, 0xf5, 0xc5]).unwrap();
+        cpu.load_program(0xc5f5, &[0xa2, 0x00, 0x86, 0x00, 0x86, 0x10, 0x86, 0x11, 0x20, 0x2d, 0xc7]).unwrap();
+        cpu.load_program(0xc72d, &[0xea, 0x38, 0xb0, 0x04, 0x00, 0x00, 0x00, 0x00, 0xea]).unwrap();
+        cpu.load_vector(0xfffc, 0xc000);
+        cpu.power_on();
+
+        for line in NESTEST_OPENING {
+            assert_eq!(cpu.trace(), line);
+            cpu.step();
+        }
+    }
+
+    #[test]
+    fn trace_marks_unofficial_opcodes() {
+        // LAX $10
+        let cpu = cpu_with_program(&[0xa7, 0x10]);
+        assert_eq!(&cpu.trace()[14..26], " *LAX $10 = ");
+    }
+
+    #[test]
+    fn new_cpu_reads_reset_vector_from_the_cartridge_on_its_bus() {
+        use crate::mapper::nrom::Nrom;
+        use crate::mapper::{Chr, Mirroring};
+
+        let mut prg = vec![0xea; 0x4000];
+        prg[0x3ffc] = 0x34;
+        prg[0x3ffd] = 0xc2;
+        let mut bus = Bus::default();
+        bus.set_mapper(Box::new(Nrom::new(prg, Chr::ram(0x2000), Mirroring::Horizontal)));
+
+        let mut cpu = CPU::new(bus);
+        assert!(!cpu.is_running());
+        cpu.power_on();
+        assert!(cpu.is_running());
+        assert_eq!(cpu.pc(), 0xc234);
+    }
+
+    #[test]
+    fn default_cpu_has_a_blank_bus() {
+        let mut cpu = CPU::default();
+        assert_eq!((cpu.pc(), cpu.a(), cpu.x(), cpu.y()), (0, 0, 0, 0));
+        assert_eq!(cpu.read(0x0000), 0);
+        assert_eq!(cpu.bus().ppu().ctrl(), 0);
+    }
+
+    #[test]
+    fn read_value_matches_operand_address_then_read() {
+        use instruction::AddressingMode::*;
+
+        // with x and y at 1; the page crossing cases read through $02FF
+        let cases: [(instruction::AddressingMode, &[u8], u16, bool); 10] = [
+            (Zero, &[0x40], 0x0040, false),
+            (ZeroX, &[0x40], 0x0041, false),
+            (ZeroY, &[0x40], 0x0041, false),
+            (Absolute, &[0x00, 0x03], 0x0300, false),
+            (AbsoluteX, &[0x00, 0x03], 0x0301, false),
+            (AbsoluteX, &[0xff, 0x02], 0x0300, true),
+            (AbsoluteY, &[0x10, 0x03], 0x0311, false),
+            (IndirectX, &[0x20], 0x0302, false),
+            (IndirectY, &[0x20], 0x0300, true),
+            (IndirectY, &[0x21], 0x0303, false),
+        ];
+        for (mode, operand, address, crossed) in cases {
+            let mut by_value = cpu_with_pointers(operand);
+            let mut by_address = cpu_with_pointers(operand);
+            by_value.write(address, 0xa5);
+            by_address.write(address, 0xa5);
+
+            let value = by_value.read_value(mode);
+            let (resolved, page_crossed) = by_address.operand_address(mode);
+            let loaded = by_address.read(resolved);
+
+            assert_eq!(resolved, address, "{:?}", mode);
+            assert_eq!(page_crossed, crossed, "{:?}", mode);
+            assert_eq!(value, 0xa5, "{:?}", mode);
+            assert_eq!(loaded, value, "{:?}", mode);
+            assert_eq!(by_value.page_crossed, page_crossed, "{:?}", mode);
+            assert_eq!(by_value.pc(), by_address.pc(), "{:?}", mode);
+            assert_eq!(by_value.pc(), PROGRAM_START + operand.len() as u16, "{:?}", mode);
+        }
+    }
+
+    /// A bus access seen by `LoggingMemory`
+    #[derive(Debug, PartialEq)]
+    enum Access {
+        Read(u16),
+        Write(u16, u8),
+    }
+
+    /// 64KB of plain RAM that logs every access made to it, for checking the CPU's access sequence
+    struct LoggingMemory {
+        memory: Vec<u8>,
+        log: Rc<RefCell<Vec<Access>>>,
+    }
+
+    impl Mem for LoggingMemory {
+        fn read(&mut self, address: u16) -> u8 {
+            self.log.borrow_mut().push(Access::Read(address));
+            self.memory[address as usize]
+        }
+
+        fn peek(&self, address: u16) -> u8 {
+            self.memory[address as usize]
+        }
+
+        fn write(&mut self, address: u16, value: u8) {
+            self.log.borrow_mut().push(Access::Write(address, value));
+            self.memory[address as usize] = value;
+        }
+    }
+
+    #[test]
+    fn custom_memory_sees_every_access_in_order() {
+        // LDA $10; STA $0200; INC $10
+        let program = [0xa5, 0x10, 0x8d, 0x00, 0x02, 0xe6, 0x10];
+        let mut memory = vec![0; 0x10000];
+        memory[0x0010] = 0x41;
+        let start = PROGRAM_START as usize;
+        memory[start..start + program.len()].copy_from_slice(&program);
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut cpu = CPU::with_memory(LoggingMemory { memory, log: Rc::clone(&log) });
+        cpu.set_pc(PROGRAM_START);
+
+        for _ in 0..3 {
+            cpu.step();
+        }
+
+        assert_eq!(*log.borrow(), vec![
+            Access::Read(0x0600), Access::Read(0x0601), Access::Read(0x0010),
+            Access::Read(0x0602), Access::Read(0x0603), Access::Read(0x0604), Access::Write(0x0200, 0x41),
+            Access::Read(0x0605), Access::Read(0x0606), Access::Read(0x0010), Access::Write(0x0010, 0x41), Access::Write(0x0010, 0x42),
+        ]);
+        assert_eq!(cpu.bus().memory[0x0200], 0x41);
+    }
+
+    /// Runs one instruction of `program` with X set to `x`, returning the accesses it made
+    fn bus_accesses(program: &[u8], x: u8) -> Vec<BusOp> {
+        let mut cpu = cpu_with_program(program);
+        cpu.set_x(x);
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&log);
+        cpu.set_bus_trace(move |op| sink.borrow_mut().push(op));
+        cpu.step();
+        let accesses = log.borrow().clone();
+        accesses
+    }
+
+    #[test]
+    fn inc_absolute_x_makes_dummy_read_and_write() {
+        // INC $1234,X reads the effective address once before the high byte is fixed up, then again for the value
+        let accesses = bus_accesses(&[0xfe, 0x34, 0x12], 0x01);
+        assert_eq!(accesses, vec![
+            BusOp::Read(0x0600), BusOp::Read(0x0601), BusOp::Read(0x0602),
+            BusOp::Read(0x1235), BusOp::Read(0x1235),
+            BusOp::Write(0x1235, 0x00), BusOp::Write(0x1235, 0x01),
+        ]);
+
+        // across a page, the first read is from the address before the carry
+        let accesses = bus_accesses(&[0xfe, 0xff, 0x12], 0x01);
+        assert_eq!(accesses[3..5], [BusOp::Read(0x1200), BusOp::Read(0x1300)]);
+    }
+
+    #[test]
+    fn implied_instructions_read_the_next_byte() {
+        // INX reads the byte after the opcode and discards it
+        assert_eq!(bus_accesses(&[0xe8, 0x55], 0), vec![BusOp::Read(0x0600), BusOp::Read(0x0601)]);
+    }
+
+    #[test]
+    fn zero_page_indexed_reads_the_unindexed_address() {
+        // LDA $10,X reads $10 while X is added
+        assert_eq!(bus_accesses(&[0xb5, 0x10], 0x05), vec![
+            BusOp::Read(0x0600), BusOp::Read(0x0601), BusOp::Read(0x0010), BusOp::Read(0x0015),
+        ]);
+    }
+
+    #[test]
+    fn untaken_branch_still_fetches_its_offset() {
+        // BNE with Z set
+        let mut cpu = cpu_with_program(&[0xd0, 0x10]);
+        cpu.set_flag(Flag::Zero, true);
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&log);
+        cpu.set_bus_trace(move |op| sink.borrow_mut().push(op));
+        cpu.step();
+        assert_eq!(*log.borrow(), vec![BusOp::Read(0x0600), BusOp::Read(0x0601)]);
+        assert_eq!(cpu.pc(), 0x0602);
+    }
+
+    #[test]
+    fn pulls_read_the_stack_before_popping() {
+        // PLA reads the byte after the opcode, then the stack at the old SP, then pops
+        assert_eq!(bus_accesses(&[0x68], 0), vec![
+            BusOp::Read(0x0600), BusOp::Read(0x0601), BusOp::Read(0x01fd), BusOp::Read(0x01fe),
+        ]);
+    }
+
+    #[test]
+    fn jsr_and_rts_access_order() {
+        // JSR $1234 reads the target's low byte, reads the stack, pushes the return address, then reads the high byte
+        assert_eq!(bus_accesses(&[0x20, 0x34, 0x12], 0), vec![
+            BusOp::Read(0x0600), BusOp::Read(0x0601), BusOp::Read(0x01fd),
+            BusOp::Write(0x01fd, 0x06), BusOp::Write(0x01fc, 0x02), BusOp::Read(0x0602),
+        ]);
+
+        // RTS reads the byte after the opcode and the stack, pulls the address, then reads the byte there before moving past it
+        let mut cpu = cpu_with_program(&[0x20, 0x00, 0x07]);
+        cpu.load_program(0x0700, &[0x60]).unwrap();
+        cpu.step();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&log);
+        cpu.set_bus_trace(move |op| sink.borrow_mut().push(op));
+        cpu.step();
+        assert_eq!(*log.borrow(), vec![
+            BusOp::Read(0x0700), BusOp::Read(0x0701), BusOp::Read(0x01fb),
+            BusOp::Read(0x01fc), BusOp::Read(0x01fd), BusOp::Read(0x0602),
+        ]);
+        assert_eq!(cpu.pc(), 0x0603);
+    }
+
+    #[test]
+    fn rmw_on_ppudata_writes_twice() {
+        // INC $2007 reads PPUDATA, writes the value back unmodified, then writes the incremented value; each access moves the VRAM address on
+        let mut cpu = CPU::default();
+        cpu.load_program(PROGRAM_START, &[0xee, 0x07, 0x20]).unwrap();
+        cpu.set_pc(PROGRAM_START);
+        cpu.bus_mut().write(0x2006, 0x21);
+        cpu.bus_mut().write(0x2006, 0x00);
+        cpu.bus_mut().ppu_mut().load_vram(0x2101, &[0xee, 0xee]);
+
+        cpu.step();
+        let ppu = cpu.bus().ppu();
+        // the read returned the (empty) read buffer, and the two writes landed on consecutive addresses
+        assert_eq!(ppu.vram(0x2101), 0x00);
+        assert_eq!(ppu.vram(0x2102), 0x01);
+        // one increment for the read, and one for each of the two writes
+        assert_eq!(ppu.vram_address(), 0x2103);
+    }
+
+    /// Runs `ADC #operand` with the given accumulator and carry, returning the CPU afterwards
+    fn adc_immediate(a: u8, operand: u8, carry: bool) -> CPU {
+        let mut cpu = cpu_with_program(&[0x69, operand]);
+        cpu.set_a(a);
+        cpu.set_status(if carry { C_FLAG } else { 0 });
+        cpu.step();
+        cpu
+    }
+
+    #[test]
+    fn adc_overflow_vectors() {
+        // two positives giving a negative overflow
+        let cpu = adc_immediate(0x50, 0x50, false);
+        assert_eq!(cpu.a(), 0xa0);
+        assert_eq!(cpu.status() & (C_FLAG | V_FLAG | N_FLAG), V_FLAG | N_FLAG);
+
+        // operands of different signs never overflow
+        let cpu = adc_immediate(0x50, 0x90, false);
+        assert_eq!(cpu.a(), 0xe0);
+        assert_eq!(cpu.status() & (C_FLAG | V_FLAG | N_FLAG), N_FLAG);
+
+        // two negatives giving a positive overflow, with a carry out
+        let cpu = adc_immediate(0xd0, 0x90, false);
+        assert_eq!(cpu.a(), 0x60);
+        assert_eq!(cpu.status() & (C_FLAG | V_FLAG | N_FLAG), C_FLAG | V_FLAG);
+
+        // the carry in counts towards the result's sign
+        let cpu = adc_immediate(0x7f, 0x00, true);
+        assert_eq!(cpu.a(), 0x80);
+        assert_eq!(cpu.status() & (C_FLAG | V_FLAG | N_FLAG), V_FLAG | N_FLAG);
+    }
+}
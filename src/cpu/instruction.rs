@@ -1,15 +1,28 @@
 // instruction.rs
 // Contains information about CPU instructions
 
-use phf::phf_map;
-
 #[derive(PartialEq, Eq)]
 #[derive(Debug, Copy, Clone)]
 pub enum Mnemonic {
     ADC, AND, ASL, BIT, BPL, BMI, BVC, BVS, BCC, BCS, BNE, BEQ, BRK, CMP, CPX, CPY, 
     DEC, EOR, CLC, SEC, CLI, SEI, CLV, CLD, SED, INC, JMP, JSR, LDA, LDX, LDY, LSR,
     NOP, ORA, TAX, TXA, DEX, INX, TAY, TYA, DEY, INY, ROL, ROR, RTI, RTS, SBC, STA,
-    TXS, TSX, PHA, PLA, PHP, PLP, STX, STY, XAA, LAX
+    TXS, TSX, PHA, PLA, PHP, PLP, STX, STY, XAA, LAX, SAX, DCP, ISC,
+    SLO, RLA, SRE, RRA
+}
+
+impl Mnemonic {
+    /// Returns whether the instruction takes an extra cycle when an indexed read crosses a page boundary.
+    /// Only instructions that read their operand are affected; stores and read-modify-write instructions always take the longer time.
+    pub fn has_page_penalty(&self) -> bool {
+        matches!(
+            self,
+            Mnemonic::LDA | Mnemonic::LDX | Mnemonic::LDY |
+            Mnemonic::ADC | Mnemonic::SBC | Mnemonic::CMP |
+            Mnemonic::AND | Mnemonic::ORA | Mnemonic::EOR |
+            Mnemonic::LAX | Mnemonic::NOP
+        )
+    }
 }
 
 #[derive(PartialEq, Eq)]
@@ -30,1025 +43,1793 @@ pub enum AddressingMode {
     Accumulator,
 }
 
+impl AddressingMode {
+    /// Returns the number of operand bytes following the opcode
+    pub fn operand_length(&self) -> u8 {
+        match self {
+            AddressingMode::Implied | AddressingMode::Accumulator => 0,
+            AddressingMode::Absolute | AddressingMode::AbsoluteX | AddressingMode::AbsoluteY | AddressingMode::Indirect => 2,
+            _ => 1,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Instruction {
+    #[allow(dead_code)]
     opcode: u8,
     pub mnemonic: Mnemonic,
     pub mode: AddressingMode,
     pub time: u8,
 }
 
+/// Looks up the instruction for an opcode, returning `None` if the CPU doesn't implement it
+#[inline]
+pub fn decode(opcode: u8) -> Option<&'static Instruction> {
+    INSTRUCTIONS[opcode as usize].as_ref()
+}
+
 // todo: maintain this in a CSV and load that way?
-// todo: unofficial/illegal opcodes
-pub static INSTRUCTIONS: phf::Map<u8, Instruction> = phf_map! {
+/// The decode table, indexed by opcode; opcodes the CPU doesn't implement are `None`
+/// A flat table is used rather than a map since the lookup is on the interpreter's hot path.
+pub static INSTRUCTIONS: [Option<Instruction>; 256] = {
+    let mut table = [None; 256];
+
     // ADC
-    0x69u8 => Instruction{
+    table[0x69] = Some(Instruction {
         opcode: 0x69,
         mnemonic: Mnemonic::ADC,
         mode: AddressingMode::Immediate,
         time: 2,
-    },
-    0x65u8 => Instruction{
+    });
+    table[0x65] = Some(Instruction {
         opcode: 0x65,
         mnemonic: Mnemonic::ADC,
         mode: AddressingMode::Zero,
         time: 3,
-    },
-    0x75u8 => Instruction{
+    });
+    table[0x75] = Some(Instruction {
         opcode: 0x75,
         mnemonic: Mnemonic::ADC,
         mode: AddressingMode::ZeroX,
         time: 4,
-    },
-    0x6du8 => Instruction{
+    });
+    table[0x6d] = Some(Instruction {
         opcode: 0x6d,
         mnemonic: Mnemonic::ADC,
         mode: AddressingMode::Absolute,
         time: 4,
-    },
-    0x7du8 => Instruction{
+    });
+    table[0x7d] = Some(Instruction {
         opcode: 0x7d,
         mnemonic: Mnemonic::ADC,
         mode: AddressingMode::AbsoluteX,
         time: 4,
-    },
-    0x79u8 => Instruction{
+    });
+    table[0x79] = Some(Instruction {
         opcode: 0x79,
         mnemonic: Mnemonic::ADC,
         mode: AddressingMode::AbsoluteY,
         time: 4,
-    },
-    0x61u8 => Instruction{
+    });
+    table[0x61] = Some(Instruction {
         opcode: 0x61,
         mnemonic: Mnemonic::ADC,
         mode: AddressingMode::IndirectX,
         time: 6,
-    },
-    0x71u8 => Instruction{
+    });
+    table[0x71] = Some(Instruction {
         opcode: 0x71,
         mnemonic: Mnemonic::ADC,
         mode: AddressingMode::IndirectY,
         time: 5,
-    },
+    });
 
     // AND
-    0x29u8 => Instruction{
+    table[0x29] = Some(Instruction {
         opcode: 0x29,
         mnemonic: Mnemonic::AND,
         mode: AddressingMode::Immediate,
         time: 2,
-    },
-    0x25u8 => Instruction{
+    });
+    table[0x25] = Some(Instruction {
         opcode: 0x25,
         mnemonic: Mnemonic::AND,
         mode: AddressingMode::Zero,
         time: 3,
-    },
-    0x35u8 => Instruction{
+    });
+    table[0x35] = Some(Instruction {
         opcode: 0x35,
         mnemonic: Mnemonic::AND,
         mode: AddressingMode::ZeroX,
         time: 4,
-    },
-    0x2du8 => Instruction{
+    });
+    table[0x2d] = Some(Instruction {
         opcode: 0x2d,
         mnemonic: Mnemonic::AND,
         mode: AddressingMode::Absolute,
         time: 4,
-    },
-    0x3du8 => Instruction{
+    });
+    table[0x3d] = Some(Instruction {
         opcode: 0x3d,
         mnemonic: Mnemonic::AND,
         mode: AddressingMode::AbsoluteX,
         time: 4,
-    },
-    0x39u8 => Instruction{
+    });
+    table[0x39] = Some(Instruction {
         opcode: 0x39,
         mnemonic: Mnemonic::AND,
         mode: AddressingMode::AbsoluteY,
         time: 4,
-    },
-    0x21u8 => Instruction{
+    });
+    table[0x21] = Some(Instruction {
         opcode: 0x21,
         mnemonic: Mnemonic::AND,
         mode: AddressingMode::IndirectX,
         time: 6,
-    },
-    0x31u8 => Instruction{
+    });
+    table[0x31] = Some(Instruction {
         opcode: 0x31,
         mnemonic: Mnemonic::AND,
         mode: AddressingMode::IndirectY,
         time: 5,
-    },
+    });
 
     // ASL
-    0x0au8 => Instruction {
+    table[0x0a] = Some(Instruction {
         opcode: 0x0a,
         mnemonic: Mnemonic::ASL,
         mode: AddressingMode::Accumulator,
         time: 2,
-    },
-    0x06u8 => Instruction{
+    });
+    table[0x06] = Some(Instruction {
         opcode: 0x06,
         mnemonic: Mnemonic::ASL,
         mode: AddressingMode::Zero,
         time: 5,
-    },
-    0x16u8 => Instruction{
+    });
+    table[0x16] = Some(Instruction {
         opcode: 0x16,
         mnemonic: Mnemonic::ASL,
         mode: AddressingMode::ZeroX,
         time: 6,
-    },
-    0x0eu8 => Instruction{
+    });
+    table[0x0e] = Some(Instruction {
         opcode: 0x0e,
         mnemonic: Mnemonic::ASL,
         mode: AddressingMode::Absolute,
         time: 6,
-    },
-    0x1eu8 => Instruction{
+    });
+    table[0x1e] = Some(Instruction {
         opcode: 0x1e,
         mnemonic: Mnemonic::ASL,
         mode: AddressingMode::AbsoluteX,
         time: 7,
-    },
+    });
 
     // BIT
-    0x24u8 => Instruction{
+    table[0x24] = Some(Instruction {
         opcode: 0x24,
         mnemonic: Mnemonic::BIT,
         mode: AddressingMode::Zero,
         time: 3,
-    },
-    0x2cu8 => Instruction{
+    });
+    table[0x2c] = Some(Instruction {
         opcode: 0x2c,
         mnemonic: Mnemonic::BIT,
         mode: AddressingMode::Absolute,
         time: 4,
-    },
+    });
 
     /*
     
     Branching instructions
-    Note that the cycles (time) listed here are inaccurate, technically;
+    The cycles (time) listed here are for a branch not taken;
     * a branch  not taken requires 2 cycles
     * a branch taken adds 1 cycle
     * if a page boundary is crossed, another cycle is added
-    This averages out to about 3 cycles, which is what we will use here
+    The extra cycles are added by the CPU when the branch is executed
 
     */
-    0x10u8 => Instruction{
+    table[0x10] = Some(Instruction {
         opcode: 0x10,
         mnemonic: Mnemonic::BPL,
         mode: AddressingMode::Relative,
-        time: 3,
-    },
-    0x30u8 => Instruction{
+        time: 2,
+    });
+    table[0x30] = Some(Instruction {
         opcode: 0x30,
         mnemonic: Mnemonic::BMI,
         mode: AddressingMode::Relative,
-        time: 3,
-    },
-    0x50u8 => Instruction{
+        time: 2,
+    });
+    table[0x50] = Some(Instruction {
         opcode: 0x50,
         mnemonic: Mnemonic::BVC,
         mode: AddressingMode::Relative,
-        time: 3,
-    },
-    0x70u8 => Instruction{
+        time: 2,
+    });
+    table[0x70] = Some(Instruction {
         opcode: 0x70,
         mnemonic: Mnemonic::BVS,
         mode: AddressingMode::Relative,
-        time: 3,
-    },
-    0x90u8 => Instruction{
+        time: 2,
+    });
+    table[0x90] = Some(Instruction {
         opcode: 0x90,
         mnemonic: Mnemonic::BCC,
         mode: AddressingMode::Relative,
-        time: 3,
-    },
-    0xb0u8 => Instruction{
+        time: 2,
+    });
+    table[0xb0] = Some(Instruction {
         opcode: 0xb0,
         mnemonic: Mnemonic::BCS,
         mode: AddressingMode::Relative,
-        time: 3,
-    },
-    0xd0u8 => Instruction{
+        time: 2,
+    });
+    table[0xd0] = Some(Instruction {
         opcode: 0xd0,
         mnemonic: Mnemonic::BNE,
         mode: AddressingMode::Relative,
-        time: 3,
-    },
-    0xf0u8 => Instruction{
+        time: 2,
+    });
+    table[0xf0] = Some(Instruction {
         opcode: 0xf0,
         mnemonic: Mnemonic::BEQ,
         mode: AddressingMode::Relative,
-        time: 3,
-    },
+        time: 2,
+    });
 
     // BRK
-    0x00u8 => Instruction{
+    table[0x00] = Some(Instruction {
         opcode: 0x00,
         mnemonic: Mnemonic::BRK,
         mode: AddressingMode::Implied,
         time: 7,
-    },
+    });
     
     // CMP
-    0xc9u8 => Instruction{
+    table[0xc9] = Some(Instruction {
         opcode: 0xc9,
         mnemonic: Mnemonic::CMP,
         mode: AddressingMode::Immediate,
         time: 2,
-    },
-    0xc5u8 => Instruction{
+    });
+    table[0xc5] = Some(Instruction {
         opcode: 0xc5,
         mnemonic: Mnemonic::CMP,
         mode: AddressingMode::Zero,
         time: 3,
-    },
-    0xd5u8 => Instruction{
+    });
+    table[0xd5] = Some(Instruction {
         opcode: 0xd5,
         mnemonic: Mnemonic::CMP,
         mode: AddressingMode::ZeroX,
         time: 4,
-    },
-    0xcdu8 => Instruction{
+    });
+    table[0xcd] = Some(Instruction {
         opcode: 0xcd,
         mnemonic: Mnemonic::CMP,
         mode: AddressingMode::Absolute,
         time: 4,
-    },
-    0xddu8 => Instruction{
+    });
+    table[0xdd] = Some(Instruction {
         opcode: 0xdd,
         mnemonic: Mnemonic::CMP,
         mode: AddressingMode::AbsoluteX,
         time: 4,
-    },
-    0xd9u8 => Instruction{
+    });
+    table[0xd9] = Some(Instruction {
         opcode: 0xd9,
         mnemonic: Mnemonic::CMP,
         mode: AddressingMode::AbsoluteY,
         time: 4,
-    },
-    0xc1u8 => Instruction{
+    });
+    table[0xc1] = Some(Instruction {
         opcode: 0xc1,
         mnemonic: Mnemonic::CMP,
         mode: AddressingMode::IndirectX,
         time: 6,
-    },
-    0xd1u8 => Instruction{
+    });
+    table[0xd1] = Some(Instruction {
         opcode: 0xd1,
         mnemonic: Mnemonic::CMP,
         mode: AddressingMode::IndirectY,
         time: 5,
-    },
+    });
 
     // CPX
-    0xe0u8 => Instruction{
+    table[0xe0] = Some(Instruction {
         opcode: 0xe0,
         mnemonic: Mnemonic::CPX,
         mode: AddressingMode::Immediate,
         time: 2,
-    },
-    0xe4u8 => Instruction{
+    });
+    table[0xe4] = Some(Instruction {
         opcode: 0xe4,
         mnemonic: Mnemonic::CPX,
         mode: AddressingMode::Zero,
         time: 3,
-    },
-    0xecu8 => Instruction{
+    });
+    table[0xec] = Some(Instruction {
         opcode: 0xec,
         mnemonic: Mnemonic::CPX,
         mode: AddressingMode::Absolute,
         time: 4,
-    },
+    });
 
     // CPY
-    0xc0u8 => Instruction{
+    table[0xc0] = Some(Instruction {
         opcode: 0xc0,
         mnemonic: Mnemonic::CPY,
         mode: AddressingMode::Immediate,
         time: 2,
-    },
-    0xc4u8 => Instruction{
+    });
+    table[0xc4] = Some(Instruction {
         opcode: 0xc4,
         mnemonic: Mnemonic::CPY,
         mode: AddressingMode::Zero,
         time: 3,
-    },
-    0xccu8 => Instruction{
+    });
+    table[0xcc] = Some(Instruction {
         opcode: 0xcc,
         mnemonic: Mnemonic::CPY,
         mode: AddressingMode::Absolute,
         time: 4,
-    },
+    });
 
     // DEC
-    0xc6u8 => Instruction{
+    table[0xc6] = Some(Instruction {
         opcode: 0xc6,
         mnemonic: Mnemonic::DEC,
         mode: AddressingMode::Zero,
         time: 5,
-    },
-    0xd6u8 => Instruction {
+    });
+    table[0xd6] = Some(Instruction {
         opcode: 0xd6,
         mnemonic: Mnemonic::DEC,
         mode: AddressingMode::ZeroX,
         time: 6,
-    },
-    0xceu8 => Instruction{
+    });
+    table[0xce] = Some(Instruction {
         opcode: 0xce,
         mnemonic: Mnemonic::DEC,
         mode: AddressingMode::Absolute,
         time: 6,
-    },
-    0xdeu8 => Instruction{
+    });
+    table[0xde] = Some(Instruction {
         opcode: 0xde,
         mnemonic: Mnemonic::DEC,
         mode: AddressingMode::AbsoluteX,
         time: 7,
-    },
+    });
 
     // EOR
-    0x49u8 => Instruction{
+    table[0x49] = Some(Instruction {
         opcode: 0x49,
         mnemonic: Mnemonic::EOR,
         mode: AddressingMode::Immediate,
         time: 2,
-    },
-    0x45u8 => Instruction{
+    });
+    table[0x45] = Some(Instruction {
         opcode: 0x45,
         mnemonic: Mnemonic::EOR,
         mode: AddressingMode::Zero,
         time: 3,
-    },
-    0x55u8 => Instruction{
+    });
+    table[0x55] = Some(Instruction {
         opcode: 0x55,
         mnemonic: Mnemonic::EOR,
         mode: AddressingMode::ZeroX,
         time: 4,
-    },
-    0x4du8 => Instruction{
+    });
+    table[0x4d] = Some(Instruction {
         opcode: 0x4d,
         mnemonic: Mnemonic::EOR,
         mode: AddressingMode::Absolute,
         time: 4,
-    },
-    0x5du8 => Instruction{
+    });
+    table[0x5d] = Some(Instruction {
         opcode: 0x5d,
         mnemonic: Mnemonic::EOR,
         mode: AddressingMode::AbsoluteX,
         time: 4,
-    },
-    0x59u8 => Instruction{
+    });
+    table[0x59] = Some(Instruction {
         opcode: 0x59,
         mnemonic: Mnemonic::EOR,
         mode: AddressingMode::AbsoluteY,
         time: 4,
-    },
-    0x41u8 => Instruction{
+    });
+    table[0x41] = Some(Instruction {
         opcode: 0x41,
         mnemonic: Mnemonic::EOR,
         mode: AddressingMode::IndirectX,
         time: 6,
-    },
-    0x51u8 => Instruction{
+    });
+    table[0x51] = Some(Instruction {
         opcode: 0x51,
         mnemonic: Mnemonic::EOR,
         mode: AddressingMode::IndirectY,
         time: 5,
-    },
+    });
 
     // Flag instructions
     // All of these require two cycles
-    0x18u8 => Instruction{
+    table[0x18] = Some(Instruction {
         opcode: 0x18,
         mnemonic: Mnemonic::CLC,
         mode: AddressingMode::Implied,
         time: 2,
-    },
-    0x38u8 => Instruction{
+    });
+    table[0x38] = Some(Instruction {
         opcode: 0x38,
         mnemonic: Mnemonic::SEC,
         mode: AddressingMode::Implied,
         time: 2,
-    },
-    0x58u8 => Instruction{
+    });
+    table[0x58] = Some(Instruction {
         opcode: 0x58,
         mnemonic: Mnemonic::CLI,
         mode: AddressingMode::Implied,
         time: 2,
-    },
-    0x78u8 => Instruction{
+    });
+    table[0x78] = Some(Instruction {
         opcode: 0x78,
         mnemonic: Mnemonic::SEI,
         mode: AddressingMode::Implied,
         time: 2,
-    },
-    0xb8u8 => Instruction{
+    });
+    table[0xb8] = Some(Instruction {
         opcode: 0xb8,
         mnemonic: Mnemonic::CLV,
         mode: AddressingMode::Implied,
         time: 2,
-    },
-    0xd8u8 => Instruction{
+    });
+    table[0xd8] = Some(Instruction {
         opcode: 0xd8,
         mnemonic: Mnemonic::CLD,
         mode: AddressingMode::Implied,
         time: 2,
-    },
-    0xf8u8 => Instruction{
+    });
+    table[0xf8] = Some(Instruction {
         opcode: 0xf8,
         mnemonic: Mnemonic::SED,
         mode: AddressingMode::Implied,
         time: 2,
-    },
+    });
 
     // INC
-    0xe6u8 => Instruction{
+    table[0xe6] = Some(Instruction {
         opcode: 0xe6,
         mnemonic: Mnemonic::INC,
         mode: AddressingMode::Zero,
         time: 5,
-    },
-    0xf6u8 => Instruction{
+    });
+    table[0xf6] = Some(Instruction {
         opcode: 0xf6,
         mnemonic: Mnemonic::INC,
         mode: AddressingMode::ZeroX,
         time: 6,
-    },
-    0xeeu8 => Instruction{
+    });
+    table[0xee] = Some(Instruction {
         opcode: 0xee,
         mnemonic: Mnemonic::INC,
         mode: AddressingMode::Absolute,
         time: 6,
-    },
-    0xfeu8 => Instruction{
+    });
+    table[0xfe] = Some(Instruction {
         opcode: 0xfe,
         mnemonic: Mnemonic::INC,
         mode: AddressingMode::AbsoluteX,
         time: 7,
-    },
+    });
 
     // JMP
-    0x4cu8 => Instruction{
+    table[0x4c] = Some(Instruction {
         opcode: 0x4c,
         mnemonic: Mnemonic::JMP,
         mode: AddressingMode::Absolute,
         time: 3,
-    },
-    0x6cu8 => Instruction{
+    });
+    table[0x6c] = Some(Instruction {
         opcode: 0x6c,
         mnemonic: Mnemonic::JMP,
         mode: AddressingMode::Indirect,
         time: 5,
-    },
+    });
 
     // JSR
-    0x20u8 => Instruction{
+    table[0x20] = Some(Instruction {
         opcode: 0x20,
         mnemonic: Mnemonic::JSR,
         mode: AddressingMode::Absolute,
         time: 6,
-    },
+    });
 
     // LDA
-    0xa9u8 => Instruction{
+    table[0xa9] = Some(Instruction {
         opcode: 0xa9,
         mnemonic: Mnemonic::LDA,
         mode: AddressingMode::Immediate,
         time: 2,
-    },
-    0xa5u8 => Instruction{
+    });
+    table[0xa5] = Some(Instruction {
         opcode: 0xa5,
         mnemonic: Mnemonic::LDA,
         mode: AddressingMode::Zero,
         time: 3,
-    },
-    0xb5u8 => Instruction{
+    });
+    table[0xb5] = Some(Instruction {
         opcode: 0xb5,
         mnemonic: Mnemonic::LDA,
         mode: AddressingMode::ZeroX,
         time: 4,
-    },
-    0xadu8 => Instruction{
+    });
+    table[0xad] = Some(Instruction {
         opcode: 0xad,
         mnemonic: Mnemonic::LDA,
         mode: AddressingMode::Absolute,
         time: 4,
-    },
-    0xbdu8 => Instruction{
+    });
+    table[0xbd] = Some(Instruction {
         opcode: 0xbd,
         mnemonic: Mnemonic::LDA,
         mode: AddressingMode::AbsoluteX,
         time: 4,
-    },
-    0xb9u8 => Instruction{
+    });
+    table[0xb9] = Some(Instruction {
         opcode: 0xb9,
         mnemonic: Mnemonic::LDA,
         mode: AddressingMode::AbsoluteY,
         time: 4,
-    },
-    0xa1u8 => Instruction{
+    });
+    table[0xa1] = Some(Instruction {
         opcode: 0xa1,
         mnemonic: Mnemonic::LDA,
         mode: AddressingMode::IndirectX,
         time: 6,
-    },
-    0xb1u8 => Instruction{
+    });
+    table[0xb1] = Some(Instruction {
         opcode: 0xb1,
         mnemonic: Mnemonic::LDA,
         mode: AddressingMode::IndirectY,
         time: 5,
-    },
+    });
 
     // LDX
-    0xa2u8 => Instruction{
+    table[0xa2] = Some(Instruction {
         opcode: 0xa2,
         mnemonic: Mnemonic::LDX,
         mode: AddressingMode::Immediate,
         time: 2,
-    },
-    0xa6u8 => Instruction{
+    });
+    table[0xa6] = Some(Instruction {
         opcode: 0xa6,
         mnemonic: Mnemonic::LDX,
         mode: AddressingMode::Zero,
         time: 3,
-    },
-    0xb6u8 => Instruction{
+    });
+    table[0xb6] = Some(Instruction {
         opcode: 0xb6,
         mnemonic: Mnemonic::LDX,
         mode: AddressingMode::ZeroY,
         time: 4,
-    },
-    0xaeu8 => Instruction{
+    });
+    table[0xae] = Some(Instruction {
         opcode: 0xae,
         mnemonic: Mnemonic::LDX,
         mode: AddressingMode::Absolute,
         time: 4,
-    },
-    0xbeu8 => Instruction{
+    });
+    table[0xbe] = Some(Instruction {
         opcode: 0xbe,
         mnemonic: Mnemonic::LDX,
         mode: AddressingMode::AbsoluteY,
         time: 4,
-    },
+    });
 
     // LDY
-    0xa0u8 => Instruction{
+    table[0xa0] = Some(Instruction {
         opcode: 0xa0,
         mnemonic: Mnemonic::LDY,
         mode: AddressingMode::Immediate,
         time: 2,
-    },
-    0xa4u8 => Instruction{
+    });
+    table[0xa4] = Some(Instruction {
         opcode: 0xa4,
         mnemonic: Mnemonic::LDY,
         mode: AddressingMode::Zero,
         time: 3,
-    },
-    0xb4u8 => Instruction{
+    });
+    table[0xb4] = Some(Instruction {
         opcode: 0xb4,
         mnemonic: Mnemonic::LDY,
         mode: AddressingMode::ZeroX,
         time: 4,
-    },
-    0xacu8 => Instruction{
+    });
+    table[0xac] = Some(Instruction {
         opcode: 0xac,
         mnemonic: Mnemonic::LDY,
         mode: AddressingMode::Absolute,
         time: 4,
-    },
-    0xbcu8 => Instruction{
+    });
+    table[0xbc] = Some(Instruction {
         opcode: 0xbc,
         mnemonic: Mnemonic::LDY,
         mode: AddressingMode::AbsoluteX,
         time: 4,
-    },
+    });
 
     // LSR
-    0x4au8 => Instruction{
+    table[0x4a] = Some(Instruction {
         opcode: 0x4a,
         mnemonic: Mnemonic::LSR,
         mode: AddressingMode::Accumulator,
         time: 2,
-    },
-    0x46u8 => Instruction{
+    });
+    table[0x46] = Some(Instruction {
         opcode: 0x46,
         mnemonic: Mnemonic::LSR,
         mode: AddressingMode::Zero,
         time: 5,
-    },
-    0x56u8 => Instruction{
+    });
+    table[0x56] = Some(Instruction {
         opcode: 0x56,
         mnemonic: Mnemonic::LSR,
         mode: AddressingMode::ZeroX,
         time: 6,
-    },
-    0x4eu8 => Instruction{
+    });
+    table[0x4e] = Some(Instruction {
         opcode: 0x4e,
         mnemonic: Mnemonic::LSR,
         mode: AddressingMode::Absolute,
         time: 6,
-    },
-    0x5eu8 => Instruction{
+    });
+    table[0x5e] = Some(Instruction {
         opcode: 0x5e,
         mnemonic: Mnemonic::LSR,
         mode: AddressingMode::AbsoluteX,
         time: 7,
-    },
+    });
 
     // NOP
-    0xeau8 => Instruction{
+    table[0xea] = Some(Instruction {
         opcode: 0xea,
         mnemonic: Mnemonic::NOP,
         mode: AddressingMode::Implied,
         time: 2,
-    },
-    0x80u8 => Instruction{  // Unofficial
+    });
+    table[0x80] = Some(Instruction {  // Unofficial
         opcode: 0x80,
         mnemonic: Mnemonic::NOP,
         mode: AddressingMode::Immediate,
         time: 2,
-    },
-    0xdau8 => Instruction{  // Unofficial
+    });
+    table[0xda] = Some(Instruction {  // Unofficial
         opcode: 0xda,
         mnemonic: Mnemonic::NOP,
         mode: AddressingMode::Implied,
         time: 2,
-    },
-    0xfau8 => Instruction{  // Unofficial
+    });
+    table[0xfa] = Some(Instruction {  // Unofficial
         opcode: 0xfa,
         mnemonic: Mnemonic::NOP,
         mode: AddressingMode::Implied,
         time: 2,
-    },
+    });
+    table[0x1a] = Some(Instruction {  // Unofficial
+        opcode: 0x1a,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Implied,
+        time: 2,
+    });
+    table[0x3a] = Some(Instruction {  // Unofficial
+        opcode: 0x3a,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Implied,
+        time: 2,
+    });
+    table[0x5a] = Some(Instruction {  // Unofficial
+        opcode: 0x5a,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Implied,
+        time: 2,
+    });
+    table[0x7a] = Some(Instruction {  // Unofficial
+        opcode: 0x7a,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Implied,
+        time: 2,
+    });
+    table[0x82] = Some(Instruction {  // Unofficial
+        opcode: 0x82,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    });
+    table[0x89] = Some(Instruction {  // Unofficial
+        opcode: 0x89,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    });
+    table[0xc2] = Some(Instruction {  // Unofficial
+        opcode: 0xc2,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    });
+    table[0xe2] = Some(Instruction {  // Unofficial
+        opcode: 0xe2,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    });
+    table[0x04] = Some(Instruction {  // Unofficial
+        opcode: 0x04,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Zero,
+        time: 3,
+    });
+    table[0x44] = Some(Instruction {  // Unofficial
+        opcode: 0x44,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Zero,
+        time: 3,
+    });
+    table[0x64] = Some(Instruction {  // Unofficial
+        opcode: 0x64,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Zero,
+        time: 3,
+    });
+    table[0x14] = Some(Instruction {  // Unofficial
+        opcode: 0x14,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::ZeroX,
+        time: 4,
+    });
+    table[0x34] = Some(Instruction {  // Unofficial
+        opcode: 0x34,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::ZeroX,
+        time: 4,
+    });
+    table[0x54] = Some(Instruction {  // Unofficial
+        opcode: 0x54,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::ZeroX,
+        time: 4,
+    });
+    table[0x74] = Some(Instruction {  // Unofficial
+        opcode: 0x74,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::ZeroX,
+        time: 4,
+    });
+    table[0xd4] = Some(Instruction {  // Unofficial
+        opcode: 0xd4,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::ZeroX,
+        time: 4,
+    });
+    table[0xf4] = Some(Instruction {  // Unofficial
+        opcode: 0xf4,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::ZeroX,
+        time: 4,
+    });
+    table[0x0c] = Some(Instruction {  // Unofficial
+        opcode: 0x0c,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::Absolute,
+        time: 4,
+    });
+    table[0x1c] = Some(Instruction {  // Unofficial
+        opcode: 0x1c,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::AbsoluteX,
+        time: 4,
+    });
+    table[0x3c] = Some(Instruction {  // Unofficial
+        opcode: 0x3c,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::AbsoluteX,
+        time: 4,
+    });
+    table[0x5c] = Some(Instruction {  // Unofficial
+        opcode: 0x5c,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::AbsoluteX,
+        time: 4,
+    });
+    table[0x7c] = Some(Instruction {  // Unofficial
+        opcode: 0x7c,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::AbsoluteX,
+        time: 4,
+    });
+    table[0xdc] = Some(Instruction {  // Unofficial
+        opcode: 0xdc,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::AbsoluteX,
+        time: 4,
+    });
+    table[0xfc] = Some(Instruction {  // Unofficial
+        opcode: 0xfc,
+        mnemonic: Mnemonic::NOP,
+        mode: AddressingMode::AbsoluteX,
+        time: 4,
+    });
 
     // ORA
-    0x09u8 => Instruction{
+    table[0x09] = Some(Instruction {
         opcode: 0x09,
         mnemonic: Mnemonic::ORA,
         mode: AddressingMode::Immediate,
         time: 2,
-    },
-    0x05u8 => Instruction{
+    });
+    table[0x05] = Some(Instruction {
         opcode: 0x05,
         mnemonic: Mnemonic::ORA,
         mode: AddressingMode::Zero,
         time: 3,
-    },
-    0x15u8 => Instruction{
+    });
+    table[0x15] = Some(Instruction {
         opcode: 0x15,
         mnemonic: Mnemonic::ORA,
         mode: AddressingMode::ZeroX,
         time: 4,
-    },
-    0x0du8 => Instruction{
+    });
+    table[0x0d] = Some(Instruction {
         opcode: 0x0d,
         mnemonic: Mnemonic::ORA,
         mode: AddressingMode::Absolute,
         time: 4,
-    },
-    0x1du8 => Instruction{
+    });
+    table[0x1d] = Some(Instruction {
         opcode: 0x1d,
         mnemonic: Mnemonic::ORA,
         mode: AddressingMode::AbsoluteX,
         time: 4,
-    },
-    0x19u8 => Instruction{
+    });
+    table[0x19] = Some(Instruction {
         opcode: 0x19,
         mnemonic: Mnemonic::ORA,
         mode: AddressingMode::AbsoluteY,
         time: 4,
-    },
-    0x01u8 => Instruction{
+    });
+    table[0x01] = Some(Instruction {
         opcode: 0x01,
         mnemonic: Mnemonic::ORA,
         mode: AddressingMode::IndirectX,
         time: 6,
-    },
-    0x11u8 => Instruction{
+    });
+    table[0x11] = Some(Instruction {
         opcode: 0x11,
         mnemonic: Mnemonic::ORA,
         mode: AddressingMode::IndirectY,
         time: 5,
-    },
+    });
 
     // Register instructions
     // These require two cycles
-    0xaau8 => Instruction{
+    table[0xaa] = Some(Instruction {
         opcode: 0xaa,
         mnemonic: Mnemonic::TAX,
         mode: AddressingMode::Implied,
         time: 2,
-    },
-    0x8au8 => Instruction{
+    });
+    table[0x8a] = Some(Instruction {
         opcode: 0x8a,
         mnemonic: Mnemonic::TXA,
         mode: AddressingMode::Implied,
         time: 2,
-    },
-    0xcau8 => Instruction{
+    });
+    table[0xca] = Some(Instruction {
         opcode: 0xca,
         mnemonic: Mnemonic::DEX,
         mode: AddressingMode::Implied,
         time: 2,
-    },
-    0xe8u8 => Instruction{
+    });
+    table[0xe8] = Some(Instruction {
         opcode: 0xe8,
         mnemonic: Mnemonic::INX,
         mode: AddressingMode::Implied,
         time: 2,
-    },
-    0xa8u8 => Instruction{
+    });
+    table[0xa8] = Some(Instruction {
         opcode: 0xa8,
         mnemonic: Mnemonic::TAY,
         mode: AddressingMode::Implied,
         time: 2,
-    },
-    0x98u8 => Instruction{
+    });
+    table[0x98] = Some(Instruction {
         opcode: 0x98,
         mnemonic: Mnemonic::TYA,
         mode: AddressingMode::Implied,
         time: 2,
-    },
-    0x88u8 => Instruction{
+    });
+    table[0x88] = Some(Instruction {
         opcode: 0x88,
         mnemonic: Mnemonic::DEY,
         mode: AddressingMode::Implied,
         time: 2,
-    },
-    0xc8u8 => Instruction{
+    });
+    table[0xc8] = Some(Instruction {
         opcode: 0xc8,
         mnemonic: Mnemonic::INY,
         mode: AddressingMode::Implied,
         time: 2,
-    },
+    });
 
     // ROL
-    0x2au8 => Instruction{
+    table[0x2a] = Some(Instruction {
         opcode: 0x2a,
         mnemonic: Mnemonic::ROL,
         mode: AddressingMode::Accumulator,
         time: 2,
-    },
-    0x26u8 => Instruction{
+    });
+    table[0x26] = Some(Instruction {
         opcode: 0x26,
         mnemonic: Mnemonic::ROL,
         mode: AddressingMode::Zero,
         time: 5,
-    },
-    0x36u8 => Instruction{
+    });
+    table[0x36] = Some(Instruction {
         opcode: 0x36,
         mnemonic: Mnemonic::ROL,
         mode: AddressingMode::ZeroX,
         time: 6,
-    },
-    0x2eu8 => Instruction{
+    });
+    table[0x2e] = Some(Instruction {
         opcode: 0x2e,
         mnemonic: Mnemonic::ROL,
         mode: AddressingMode::Absolute,
         time: 6,
-    },
-    0x3eu8 => Instruction{
+    });
+    table[0x3e] = Some(Instruction {
         opcode: 0x3e,
         mnemonic: Mnemonic::ROL,
         mode: AddressingMode::AbsoluteX,
         time: 7,
-    },
+    });
 
     // ROR
-    0x6au8 => Instruction{
+    table[0x6a] = Some(Instruction {
         opcode: 0x6a,
         mnemonic: Mnemonic::ROR,
         mode: AddressingMode::Accumulator,
         time: 2,
-    },
-    0x66u8 => Instruction{
+    });
+    table[0x66] = Some(Instruction {
         opcode: 0x66,
         mnemonic: Mnemonic::ROR,
         mode: AddressingMode::Zero,
         time: 5,
-    },
-    0x76u8 => Instruction{
+    });
+    table[0x76] = Some(Instruction {
         opcode: 0x76,
         mnemonic: Mnemonic::ROR,
         mode: AddressingMode::ZeroX,
         time: 6,
-    },
-    0x6eu8 => Instruction{
+    });
+    table[0x6e] = Some(Instruction {
         opcode: 0x6e,
         mnemonic: Mnemonic::ROR,
         mode: AddressingMode::Absolute,
         time: 6,
-    },
-    0x7eu8 => Instruction{
+    });
+    table[0x7e] = Some(Instruction {
         opcode: 0x7e,
         mnemonic: Mnemonic::ROR,
         mode: AddressingMode::AbsoluteX,
         time: 7,
-    },
+    });
 
     // RTI
-    0x40u8 => Instruction{
+    table[0x40] = Some(Instruction {
         opcode: 0x40,
         mnemonic: Mnemonic::RTI,
         mode: AddressingMode::Implied,
         time: 6,
-    },
+    });
 
     // RTS
-    0x60u8 => Instruction{
+    table[0x60] = Some(Instruction {
         opcode: 0x60,
         mnemonic: Mnemonic::RTS,
         mode: AddressingMode::Implied,
         time: 6,
-    },
+    });
 
     // SBC
-    0xe9u8 => Instruction{
+    table[0xe9] = Some(Instruction {
         opcode: 0xe9,
         mnemonic: Mnemonic::SBC,
         mode: AddressingMode::Immediate,
         time: 2,
-    },
-    0xe5u8 => Instruction{
+    });
+    table[0xeb] = Some(Instruction {  // Unofficial
+        opcode: 0xeb,
+        mnemonic: Mnemonic::SBC,
+        mode: AddressingMode::Immediate,
+        time: 2,
+    });
+    table[0xe5] = Some(Instruction {
         opcode: 0xe5,
         mnemonic: Mnemonic::SBC,
         mode: AddressingMode::Zero,
         time: 3,
-    },
-    0xf5u8 => Instruction{
+    });
+    table[0xf5] = Some(Instruction {
         opcode: 0xf5,
         mnemonic: Mnemonic::SBC,
         mode: AddressingMode::ZeroX,
         time: 4,
-    },
-    0xedu8 => Instruction{
+    });
+    table[0xed] = Some(Instruction {
         opcode: 0xed,
         mnemonic: Mnemonic::SBC,
         mode: AddressingMode::Absolute,
         time: 4,
-    },
-    0xfdu8 => Instruction{
+    });
+    table[0xfd] = Some(Instruction {
         opcode: 0xfd,
         mnemonic: Mnemonic::SBC,
         mode: AddressingMode::AbsoluteX,
         time: 4,
-    },
-    0xf9u8 => Instruction{
+    });
+    table[0xf9] = Some(Instruction {
         opcode: 0xf9,
         mnemonic: Mnemonic::SBC,
         mode: AddressingMode::AbsoluteY,
         time: 4,
-    },
-    0xe1u8 => Instruction{
+    });
+    table[0xe1] = Some(Instruction {
         opcode: 0xe1,
         mnemonic: Mnemonic::SBC,
         mode: AddressingMode::IndirectX,
         time: 6,
-    },
-    0xf1u8 => Instruction{
+    });
+    table[0xf1] = Some(Instruction {
         opcode: 0xf1,
         mnemonic: Mnemonic::SBC,
         mode: AddressingMode::IndirectY,
         time: 5,
-    },
+    });
 
     // STA
-    0x85u8 => Instruction{
+    table[0x85] = Some(Instruction {
         opcode: 0x85,
         mnemonic: Mnemonic::STA,
         mode: AddressingMode::Zero,
         time: 3,
-    },
-    0x95u8 => Instruction{
+    });
+    table[0x95] = Some(Instruction {
         opcode: 0x95,
         mnemonic: Mnemonic::STA,
         mode: AddressingMode::ZeroX,
         time: 4,
-    },
-    0x8du8 => Instruction{
+    });
+    table[0x8d] = Some(Instruction {
         opcode: 0x8d,
         mnemonic: Mnemonic::STA,
         mode: AddressingMode::Absolute,
         time: 4,
-    },
-    0x9du8 => Instruction{
+    });
+    table[0x9d] = Some(Instruction {
         opcode: 0x9d,
         mnemonic: Mnemonic::STA,
         mode: AddressingMode::AbsoluteX,
         time: 5,
-    },
-    0x99u8 => Instruction{
+    });
+    table[0x99] = Some(Instruction {
         opcode: 0x99,
         mnemonic: Mnemonic::STA,
         mode: AddressingMode::AbsoluteY,
         time: 5,
-    },
-    0x81u8 => Instruction{
+    });
+    table[0x81] = Some(Instruction {
         opcode: 0x81,
         mnemonic: Mnemonic::STA,
         mode: AddressingMode::IndirectX,
         time: 6,
-    },
-    0x91u8 => Instruction{
+    });
+    table[0x91] = Some(Instruction {
         opcode: 0x91,
         mnemonic: Mnemonic::STA,
         mode: AddressingMode::IndirectY,
         time: 6,
-    },
+    });
 
     // Stack instructions
-    0x9au8 => Instruction{
+    table[0x9a] = Some(Instruction {
         opcode: 0x9a,
         mnemonic: Mnemonic::TXS,
         mode: AddressingMode::Implied,
         time: 2,
-    },
-    0xbau8 => Instruction{
+    });
+    table[0xba] = Some(Instruction {
         opcode: 0xba,
         mnemonic: Mnemonic::TSX,
         mode: AddressingMode::Implied,
         time: 2,
-    },
-    0x48u8 => Instruction{
+    });
+    table[0x48] = Some(Instruction {
         opcode: 0x48,
         mnemonic: Mnemonic::PHA,
         mode: AddressingMode::Implied,
         time: 3,
-    },
-    0x68u8 => Instruction{
+    });
+    table[0x68] = Some(Instruction {
         opcode: 0x68,
         mnemonic: Mnemonic::PLA,
         mode: AddressingMode::Implied,
         time: 4,
-    },
-    0x08u8 => Instruction{
+    });
+    table[0x08] = Some(Instruction {
         opcode: 0x08,
         mnemonic: Mnemonic::PHP,
         mode: AddressingMode::Implied,
         time: 3,
-    },
-    0x28u8 => Instruction{
+    });
+    table[0x28] = Some(Instruction {
         opcode: 0x28,
         mnemonic: Mnemonic::PLP,
         mode: AddressingMode::Implied,
         time: 4,
-    },
+    });
 
     // STX
-    0x86u8 => Instruction{
+    table[0x86] = Some(Instruction {
         opcode: 0x86,
         mnemonic: Mnemonic::STX,
         mode: AddressingMode::Zero,
         time: 3,
-    },
-    0x96u8 => Instruction{
+    });
+    table[0x96] = Some(Instruction {
         opcode: 0x96,
         mnemonic: Mnemonic::STX,
         mode: AddressingMode::ZeroY,
         time: 4,
-    },
-    0x8eu8 => Instruction{
+    });
+    table[0x8e] = Some(Instruction {
         opcode: 0x8e,
         mnemonic: Mnemonic::STX,
         mode: AddressingMode::Absolute,
         time: 4,
-    },
+    });
 
     // STY
-    0x84u8 => Instruction{
+    table[0x84] = Some(Instruction {
         opcode: 0x84,
         mnemonic: Mnemonic::STY,
         mode: AddressingMode::Zero,
         time: 3,
-    },
-    0x94u8 => Instruction{
+    });
+    table[0x94] = Some(Instruction {
         opcode: 0x94,
         mnemonic: Mnemonic::STY,
         mode: AddressingMode::ZeroX,
         time: 4,
-    },
-    0x8cu8 => Instruction{
+    });
+    table[0x8c] = Some(Instruction {
         opcode: 0x8c,
         mnemonic: Mnemonic::STY,
         mode: AddressingMode::Absolute,
         time: 4,
-    },
+    });
 
     // Unofficial opcodes
-    0x8bu8 => Instruction{
+    table[0x8b] = Some(Instruction {
         opcode: 0x8b,
         mnemonic: Mnemonic::XAA,
         mode: AddressingMode::Immediate,
         time: 2,
-    },
-    0xabu8 => Instruction{
+    });
+    table[0xab] = Some(Instruction {
         opcode: 0xab,
         mnemonic: Mnemonic::LAX,
         mode: AddressingMode::Immediate,
         time: 2,
-    }
+    });
+
+    // LAX
+    table[0xa7] = Some(Instruction {
+        opcode: 0xa7,
+        mnemonic: Mnemonic::LAX,
+        mode: AddressingMode::Zero,
+        time: 3,
+    });
+    table[0xb7] = Some(Instruction {
+        opcode: 0xb7,
+        mnemonic: Mnemonic::LAX,
+        mode: AddressingMode::ZeroY,
+        time: 4,
+    });
+    table[0xaf] = Some(Instruction {
+        opcode: 0xaf,
+        mnemonic: Mnemonic::LAX,
+        mode: AddressingMode::Absolute,
+        time: 4,
+    });
+    table[0xbf] = Some(Instruction {
+        opcode: 0xbf,
+        mnemonic: Mnemonic::LAX,
+        mode: AddressingMode::AbsoluteY,
+        time: 4,
+    });
+    table[0xa3] = Some(Instruction {
+        opcode: 0xa3,
+        mnemonic: Mnemonic::LAX,
+        mode: AddressingMode::IndirectX,
+        time: 6,
+    });
+    table[0xb3] = Some(Instruction {
+        opcode: 0xb3,
+        mnemonic: Mnemonic::LAX,
+        mode: AddressingMode::IndirectY,
+        time: 5,
+    });
+
+    // SAX
+    table[0x87] = Some(Instruction {
+        opcode: 0x87,
+        mnemonic: Mnemonic::SAX,
+        mode: AddressingMode::Zero,
+        time: 3,
+    });
+    table[0x97] = Some(Instruction {
+        opcode: 0x97,
+        mnemonic: Mnemonic::SAX,
+        mode: AddressingMode::ZeroY,
+        time: 4,
+    });
+    table[0x8f] = Some(Instruction {
+        opcode: 0x8f,
+        mnemonic: Mnemonic::SAX,
+        mode: AddressingMode::Absolute,
+        time: 4,
+    });
+    table[0x83] = Some(Instruction {
+        opcode: 0x83,
+        mnemonic: Mnemonic::SAX,
+        mode: AddressingMode::IndirectX,
+        time: 6,
+    });
+
+    // DCP
+    table[0xc7] = Some(Instruction {
+        opcode: 0xc7,
+        mnemonic: Mnemonic::DCP,
+        mode: AddressingMode::Zero,
+        time: 5,
+    });
+    table[0xd7] = Some(Instruction {
+        opcode: 0xd7,
+        mnemonic: Mnemonic::DCP,
+        mode: AddressingMode::ZeroX,
+        time: 6,
+    });
+    table[0xcf] = Some(Instruction {
+        opcode: 0xcf,
+        mnemonic: Mnemonic::DCP,
+        mode: AddressingMode::Absolute,
+        time: 6,
+    });
+    table[0xdf] = Some(Instruction {
+        opcode: 0xdf,
+        mnemonic: Mnemonic::DCP,
+        mode: AddressingMode::AbsoluteX,
+        time: 7,
+    });
+    table[0xdb] = Some(Instruction {
+        opcode: 0xdb,
+        mnemonic: Mnemonic::DCP,
+        mode: AddressingMode::AbsoluteY,
+        time: 7,
+    });
+    table[0xc3] = Some(Instruction {
+        opcode: 0xc3,
+        mnemonic: Mnemonic::DCP,
+        mode: AddressingMode::IndirectX,
+        time: 8,
+    });
+    table[0xd3] = Some(Instruction {
+        opcode: 0xd3,
+        mnemonic: Mnemonic::DCP,
+        mode: AddressingMode::IndirectY,
+        time: 8,
+    });
+
+    // ISC
+    table[0xe7] = Some(Instruction {
+        opcode: 0xe7,
+        mnemonic: Mnemonic::ISC,
+        mode: AddressingMode::Zero,
+        time: 5,
+    });
+    table[0xf7] = Some(Instruction {
+        opcode: 0xf7,
+        mnemonic: Mnemonic::ISC,
+        mode: AddressingMode::ZeroX,
+        time: 6,
+    });
+    table[0xef] = Some(Instruction {
+        opcode: 0xef,
+        mnemonic: Mnemonic::ISC,
+        mode: AddressingMode::Absolute,
+        time: 6,
+    });
+    table[0xff] = Some(Instruction {
+        opcode: 0xff,
+        mnemonic: Mnemonic::ISC,
+        mode: AddressingMode::AbsoluteX,
+        time: 7,
+    });
+    table[0xfb] = Some(Instruction {
+        opcode: 0xfb,
+        mnemonic: Mnemonic::ISC,
+        mode: AddressingMode::AbsoluteY,
+        time: 7,
+    });
+    table[0xe3] = Some(Instruction {
+        opcode: 0xe3,
+        mnemonic: Mnemonic::ISC,
+        mode: AddressingMode::IndirectX,
+        time: 8,
+    });
+    table[0xf3] = Some(Instruction {
+        opcode: 0xf3,
+        mnemonic: Mnemonic::ISC,
+        mode: AddressingMode::IndirectY,
+        time: 8,
+    });
+
+    // SLO
+    table[0x07] = Some(Instruction {
+        opcode: 0x07,
+        mnemonic: Mnemonic::SLO,
+        mode: AddressingMode::Zero,
+        time: 5,
+    });
+    table[0x17] = Some(Instruction {
+        opcode: 0x17,
+        mnemonic: Mnemonic::SLO,
+        mode: AddressingMode::ZeroX,
+        time: 6,
+    });
+    table[0x0f] = Some(Instruction {
+        opcode: 0x0f,
+        mnemonic: Mnemonic::SLO,
+        mode: AddressingMode::Absolute,
+        time: 6,
+    });
+    table[0x1f] = Some(Instruction {
+        opcode: 0x1f,
+        mnemonic: Mnemonic::SLO,
+        mode: AddressingMode::AbsoluteX,
+        time: 7,
+    });
+    table[0x1b] = Some(Instruction {
+        opcode: 0x1b,
+        mnemonic: Mnemonic::SLO,
+        mode: AddressingMode::AbsoluteY,
+        time: 7,
+    });
+    table[0x03] = Some(Instruction {
+        opcode: 0x03,
+        mnemonic: Mnemonic::SLO,
+        mode: AddressingMode::IndirectX,
+        time: 8,
+    });
+    table[0x13] = Some(Instruction {
+        opcode: 0x13,
+        mnemonic: Mnemonic::SLO,
+        mode: AddressingMode::IndirectY,
+        time: 8,
+    });
+
+    // RLA
+    table[0x27] = Some(Instruction {
+        opcode: 0x27,
+        mnemonic: Mnemonic::RLA,
+        mode: AddressingMode::Zero,
+        time: 5,
+    });
+    table[0x37] = Some(Instruction {
+        opcode: 0x37,
+        mnemonic: Mnemonic::RLA,
+        mode: AddressingMode::ZeroX,
+        time: 6,
+    });
+    table[0x2f] = Some(Instruction {
+        opcode: 0x2f,
+        mnemonic: Mnemonic::RLA,
+        mode: AddressingMode::Absolute,
+        time: 6,
+    });
+    table[0x3f] = Some(Instruction {
+        opcode: 0x3f,
+        mnemonic: Mnemonic::RLA,
+        mode: AddressingMode::AbsoluteX,
+        time: 7,
+    });
+    table[0x3b] = Some(Instruction {
+        opcode: 0x3b,
+        mnemonic: Mnemonic::RLA,
+        mode: AddressingMode::AbsoluteY,
+        time: 7,
+    });
+    table[0x23] = Some(Instruction {
+        opcode: 0x23,
+        mnemonic: Mnemonic::RLA,
+        mode: AddressingMode::IndirectX,
+        time: 8,
+    });
+    table[0x33] = Some(Instruction {
+        opcode: 0x33,
+        mnemonic: Mnemonic::RLA,
+        mode: AddressingMode::IndirectY,
+        time: 8,
+    });
+
+    // SRE
+    table[0x47] = Some(Instruction {
+        opcode: 0x47,
+        mnemonic: Mnemonic::SRE,
+        mode: AddressingMode::Zero,
+        time: 5,
+    });
+    table[0x57] = Some(Instruction {
+        opcode: 0x57,
+        mnemonic: Mnemonic::SRE,
+        mode: AddressingMode::ZeroX,
+        time: 6,
+    });
+    table[0x4f] = Some(Instruction {
+        opcode: 0x4f,
+        mnemonic: Mnemonic::SRE,
+        mode: AddressingMode::Absolute,
+        time: 6,
+    });
+    table[0x5f] = Some(Instruction {
+        opcode: 0x5f,
+        mnemonic: Mnemonic::SRE,
+        mode: AddressingMode::AbsoluteX,
+        time: 7,
+    });
+    table[0x5b] = Some(Instruction {
+        opcode: 0x5b,
+        mnemonic: Mnemonic::SRE,
+        mode: AddressingMode::AbsoluteY,
+        time: 7,
+    });
+    table[0x43] = Some(Instruction {
+        opcode: 0x43,
+        mnemonic: Mnemonic::SRE,
+        mode: AddressingMode::IndirectX,
+        time: 8,
+    });
+    table[0x53] = Some(Instruction {
+        opcode: 0x53,
+        mnemonic: Mnemonic::SRE,
+        mode: AddressingMode::IndirectY,
+        time: 8,
+    });
+
+    // RRA
+    table[0x67] = Some(Instruction {
+        opcode: 0x67,
+        mnemonic: Mnemonic::RRA,
+        mode: AddressingMode::Zero,
+        time: 5,
+    });
+    table[0x77] = Some(Instruction {
+        opcode: 0x77,
+        mnemonic: Mnemonic::RRA,
+        mode: AddressingMode::ZeroX,
+        time: 6,
+    });
+    table[0x6f] = Some(Instruction {
+        opcode: 0x6f,
+        mnemonic: Mnemonic::RRA,
+        mode: AddressingMode::Absolute,
+        time: 6,
+    });
+    table[0x7f] = Some(Instruction {
+        opcode: 0x7f,
+        mnemonic: Mnemonic::RRA,
+        mode: AddressingMode::AbsoluteX,
+        time: 7,
+    });
+    table[0x7b] = Some(Instruction {
+        opcode: 0x7b,
+        mnemonic: Mnemonic::RRA,
+        mode: AddressingMode::AbsoluteY,
+        time: 7,
+    });
+    table[0x63] = Some(Instruction {
+        opcode: 0x63,
+        mnemonic: Mnemonic::RRA,
+        mode: AddressingMode::IndirectX,
+        time: 8,
+    });
+    table[0x73] = Some(Instruction {
+        opcode: 0x73,
+        mnemonic: Mnemonic::RRA,
+        mode: AddressingMode::IndirectY,
+        time: 8,
+    });
+
+    table
 };
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use AddressingMode::*;
+    use Mnemonic::*;
+
+    /// Every entry of the `phf` map the decode table replaced, as (opcode, mnemonic, mode, cycles)
+    const MAP_ENTRIES: [(u8, Mnemonic, AddressingMode, u8); 233] = [
+        (0x00, BRK, Implied, 7),
+        (0x01, ORA, IndirectX, 6),
+        (0x03, SLO, IndirectX, 8),
+        (0x04, NOP, Zero, 3),
+        (0x05, ORA, Zero, 3),
+        (0x06, ASL, Zero, 5),
+        (0x07, SLO, Zero, 5),
+        (0x08, PHP, Implied, 3),
+        (0x09, ORA, Immediate, 2),
+        (0x0a, ASL, Accumulator, 2),
+        (0x0c, NOP, Absolute, 4),
+        (0x0d, ORA, Absolute, 4),
+        (0x0e, ASL, Absolute, 6),
+        (0x0f, SLO, Absolute, 6),
+        (0x10, BPL, Relative, 2),
+        (0x11, ORA, IndirectY, 5),
+        (0x13, SLO, IndirectY, 8),
+        (0x14, NOP, ZeroX, 4),
+        (0x15, ORA, ZeroX, 4),
+        (0x16, ASL, ZeroX, 6),
+        (0x17, SLO, ZeroX, 6),
+        (0x18, CLC, Implied, 2),
+        (0x19, ORA, AbsoluteY, 4),
+        (0x1a, NOP, Implied, 2),
+        (0x1b, SLO, AbsoluteY, 7),
+        (0x1c, NOP, AbsoluteX, 4),
+        (0x1d, ORA, AbsoluteX, 4),
+        (0x1e, ASL, AbsoluteX, 7),
+        (0x1f, SLO, AbsoluteX, 7),
+        (0x20, JSR, Absolute, 6),
+        (0x21, AND, IndirectX, 6),
+        (0x23, RLA, IndirectX, 8),
+        (0x24, BIT, Zero, 3),
+        (0x25, AND, Zero, 3),
+        (0x26, ROL, Zero, 5),
+        (0x27, RLA, Zero, 5),
+        (0x28, PLP, Implied, 4),
+        (0x29, AND, Immediate, 2),
+        (0x2a, ROL, Accumulator, 2),
+        (0x2c, BIT, Absolute, 4),
+        (0x2d, AND, Absolute, 4),
+        (0x2e, ROL, Absolute, 6),
+        (0x2f, RLA, Absolute, 6),
+        (0x30, BMI, Relative, 2),
+        (0x31, AND, IndirectY, 5),
+        (0x33, RLA, IndirectY, 8),
+        (0x34, NOP, ZeroX, 4),
+        (0x35, AND, ZeroX, 4),
+        (0x36, ROL, ZeroX, 6),
+        (0x37, RLA, ZeroX, 6),
+        (0x38, SEC, Implied, 2),
+        (0x39, AND, AbsoluteY, 4),
+        (0x3a, NOP, Implied, 2),
+        (0x3b, RLA, AbsoluteY, 7),
+        (0x3c, NOP, AbsoluteX, 4),
+        (0x3d, AND, AbsoluteX, 4),
+        (0x3e, ROL, AbsoluteX, 7),
+        (0x3f, RLA, AbsoluteX, 7),
+        (0x40, RTI, Implied, 6),
+        (0x41, EOR, IndirectX, 6),
+        (0x43, SRE, IndirectX, 8),
+        (0x44, NOP, Zero, 3),
+        (0x45, EOR, Zero, 3),
+        (0x46, LSR, Zero, 5),
+        (0x47, SRE, Zero, 5),
+        (0x48, PHA, Implied, 3),
+        (0x49, EOR, Immediate, 2),
+        (0x4a, LSR, Accumulator, 2),
+        (0x4c, JMP, Absolute, 3),
+        (0x4d, EOR, Absolute, 4),
+        (0x4e, LSR, Absolute, 6),
+        (0x4f, SRE, Absolute, 6),
+        (0x50, BVC, Relative, 2),
+        (0x51, EOR, IndirectY, 5),
+        (0x53, SRE, IndirectY, 8),
+        (0x54, NOP, ZeroX, 4),
+        (0x55, EOR, ZeroX, 4),
+        (0x56, LSR, ZeroX, 6),
+        (0x57, SRE, ZeroX, 6),
+        (0x58, CLI, Implied, 2),
+        (0x59, EOR, AbsoluteY, 4),
+        (0x5a, NOP, Implied, 2),
+        (0x5b, SRE, AbsoluteY, 7),
+        (0x5c, NOP, AbsoluteX, 4),
+        (0x5d, EOR, AbsoluteX, 4),
+        (0x5e, LSR, AbsoluteX, 7),
+        (0x5f, SRE, AbsoluteX, 7),
+        (0x60, RTS, Implied, 6),
+        (0x61, ADC, IndirectX, 6),
+        (0x63, RRA, IndirectX, 8),
+        (0x64, NOP, Zero, 3),
+        (0x65, ADC, Zero, 3),
+        (0x66, ROR, Zero, 5),
+        (0x67, RRA, Zero, 5),
+        (0x68, PLA, Implied, 4),
+        (0x69, ADC, Immediate, 2),
+        (0x6a, ROR, Accumulator, 2),
+        (0x6c, JMP, Indirect, 5),
+        (0x6d, ADC, Absolute, 4),
+        (0x6e, ROR, Absolute, 6),
+        (0x6f, RRA, Absolute, 6),
+        (0x70, BVS, Relative, 2),
+        (0x71, ADC, IndirectY, 5),
+        (0x73, RRA, IndirectY, 8),
+        (0x74, NOP, ZeroX, 4),
+        (0x75, ADC, ZeroX, 4),
+        (0x76, ROR, ZeroX, 6),
+        (0x77, RRA, ZeroX, 6),
+        (0x78, SEI, Implied, 2),
+        (0x79, ADC, AbsoluteY, 4),
+        (0x7a, NOP, Implied, 2),
+        (0x7b, RRA, AbsoluteY, 7),
+        (0x7c, NOP, AbsoluteX, 4),
+        (0x7d, ADC, AbsoluteX, 4),
+        (0x7e, ROR, AbsoluteX, 7),
+        (0x7f, RRA, AbsoluteX, 7),
+        (0x80, NOP, Immediate, 2),
+        (0x81, STA, IndirectX, 6),
+        (0x82, NOP, Immediate, 2),
+        (0x83, SAX, IndirectX, 6),
+        (0x84, STY, Zero, 3),
+        (0x85, STA, Zero, 3),
+        (0x86, STX, Zero, 3),
+        (0x87, SAX, Zero, 3),
+        (0x88, DEY, Implied, 2),
+        (0x89, NOP, Immediate, 2),
+        (0x8a, TXA, Implied, 2),
+        (0x8b, XAA, Immediate, 2),
+        (0x8c, STY, Absolute, 4),
+        (0x8d, STA, Absolute, 4),
+        (0x8e, STX, Absolute, 4),
+        (0x8f, SAX, Absolute, 4),
+        (0x90, BCC, Relative, 2),
+        (0x91, STA, IndirectY, 6),
+        (0x94, STY, ZeroX, 4),
+        (0x95, STA, ZeroX, 4),
+        (0x96, STX, ZeroY, 4),
+        (0x97, SAX, ZeroY, 4),
+        (0x98, TYA, Implied, 2),
+        (0x99, STA, AbsoluteY, 5),
+        (0x9a, TXS, Implied, 2),
+        (0x9d, STA, AbsoluteX, 5),
+        (0xa0, LDY, Immediate, 2),
+        (0xa1, LDA, IndirectX, 6),
+        (0xa2, LDX, Immediate, 2),
+        (0xa3, LAX, IndirectX, 6),
+        (0xa4, LDY, Zero, 3),
+        (0xa5, LDA, Zero, 3),
+        (0xa6, LDX, Zero, 3),
+        (0xa7, LAX, Zero, 3),
+        (0xa8, TAY, Implied, 2),
+        (0xa9, LDA, Immediate, 2),
+        (0xaa, TAX, Implied, 2),
+        (0xab, LAX, Immediate, 2),
+        (0xac, LDY, Absolute, 4),
+        (0xad, LDA, Absolute, 4),
+        (0xae, LDX, Absolute, 4),
+        (0xaf, LAX, Absolute, 4),
+        (0xb0, BCS, Relative, 2),
+        (0xb1, LDA, IndirectY, 5),
+        (0xb3, LAX, IndirectY, 5),
+        (0xb4, LDY, ZeroX, 4),
+        (0xb5, LDA, ZeroX, 4),
+        (0xb6, LDX, ZeroY, 4),
+        (0xb7, LAX, ZeroY, 4),
+        (0xb8, CLV, Implied, 2),
+        (0xb9, LDA, AbsoluteY, 4),
+        (0xba, TSX, Implied, 2),
+        (0xbc, LDY, AbsoluteX, 4),
+        (0xbd, LDA, AbsoluteX, 4),
+        (0xbe, LDX, AbsoluteY, 4),
+        (0xbf, LAX, AbsoluteY, 4),
+        (0xc0, CPY, Immediate, 2),
+        (0xc1, CMP, IndirectX, 6),
+        (0xc2, NOP, Immediate, 2),
+        (0xc3, DCP, IndirectX, 8),
+        (0xc4, CPY, Zero, 3),
+        (0xc5, CMP, Zero, 3),
+        (0xc6, DEC, Zero, 5),
+        (0xc7, DCP, Zero, 5),
+        (0xc8, INY, Implied, 2),
+        (0xc9, CMP, Immediate, 2),
+        (0xca, DEX, Implied, 2),
+        (0xcc, CPY, Absolute, 4),
+        (0xcd, CMP, Absolute, 4),
+        (0xce, DEC, Absolute, 6),
+        (0xcf, DCP, Absolute, 6),
+        (0xd0, BNE, Relative, 2),
+        (0xd1, CMP, IndirectY, 5),
+        (0xd3, DCP, IndirectY, 8),
+        (0xd4, NOP, ZeroX, 4),
+        (0xd5, CMP, ZeroX, 4),
+        (0xd6, DEC, ZeroX, 6),
+        (0xd7, DCP, ZeroX, 6),
+        (0xd8, CLD, Implied, 2),
+        (0xd9, CMP, AbsoluteY, 4),
+        (0xda, NOP, Implied, 2),
+        (0xdb, DCP, AbsoluteY, 7),
+        (0xdc, NOP, AbsoluteX, 4),
+        (0xdd, CMP, AbsoluteX, 4),
+        (0xde, DEC, AbsoluteX, 7),
+        (0xdf, DCP, AbsoluteX, 7),
+        (0xe0, CPX, Immediate, 2),
+        (0xe1, SBC, IndirectX, 6),
+        (0xe2, NOP, Immediate, 2),
+        (0xe3, ISC, IndirectX, 8),
+        (0xe4, CPX, Zero, 3),
+        (0xe5, SBC, Zero, 3),
+        (0xe6, INC, Zero, 5),
+        (0xe7, ISC, Zero, 5),
+        (0xe8, INX, Implied, 2),
+        (0xe9, SBC, Immediate, 2),
+        (0xea, NOP, Implied, 2),
+        (0xeb, SBC, Immediate, 2),
+        (0xec, CPX, Absolute, 4),
+        (0xed, SBC, Absolute, 4),
+        (0xee, INC, Absolute, 6),
+        (0xef, ISC, Absolute, 6),
+        (0xf0, BEQ, Relative, 2),
+        (0xf1, SBC, IndirectY, 5),
+        (0xf3, ISC, IndirectY, 8),
+        (0xf4, NOP, ZeroX, 4),
+        (0xf5, SBC, ZeroX, 4),
+        (0xf6, INC, ZeroX, 6),
+        (0xf7, ISC, ZeroX, 6),
+        (0xf8, SED, Implied, 2),
+        (0xf9, SBC, AbsoluteY, 4),
+        (0xfa, NOP, Implied, 2),
+        (0xfb, ISC, AbsoluteY, 7),
+        (0xfc, NOP, AbsoluteX, 4),
+        (0xfd, SBC, AbsoluteX, 4),
+        (0xfe, INC, AbsoluteX, 7),
+        (0xff, ISC, AbsoluteX, 7),
+    ];
+
+    #[test]
+    fn table_decodes_every_opcode_the_map_did() {
+        for (opcode, mnemonic, mode, time) in MAP_ENTRIES {
+            let instruction = decode(opcode).unwrap_or_else(|| panic!("${:02X} no longer decodes", opcode));
+            assert_eq!((instruction.opcode, instruction.mnemonic, instruction.mode, instruction.time), (opcode, mnemonic, mode, time));
+        }
+    }
+
+    #[test]
+    fn table_has_no_opcodes_the_map_lacked() {
+        for opcode in 0..=255u8 {
+            let in_map = MAP_ENTRIES.iter().any(|entry| entry.0 == opcode);
+            assert_eq!(decode(opcode).is_some(), in_map, "${:02X}", opcode);
+        }
+    }
+
+    #[test]
+    fn operand_lengths_follow_the_mode() {
+        assert_eq!(decode(0xea).unwrap().mode.operand_length(), 0);
+        assert_eq!(decode(0xa9).unwrap().mode.operand_length(), 1);
+        assert_eq!(decode(0x6c).unwrap().mode.operand_length(), 2);
+    }
+}
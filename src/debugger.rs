@@ -0,0 +1,196 @@
+// debugger.rs
+// A simple interactive monitor for stepping through programs
+
+use std::collections::HashSet;
+use std::io::{self, BufRead, Write};
+
+use crate::cpu::CPU;
+use crate::mem::Mem;
+
+/// The number of bytes shown on each line of a memory dump
+const DUMP_BYTES_PER_LINE: u16 = 16;
+
+/// A command entered at the debugger prompt
+#[derive(PartialEq, Eq)]
+#[derive(Debug, Copy, Clone)]
+pub enum Command {
+    /// `s`: execute one instruction, printing its trace line
+    Step,
+    /// `c`: run until a breakpoint is reached or the CPU stops
+    Continue,
+    /// `b <addr>`: stop before executing the instruction at the address
+    Breakpoint(u16),
+    /// `m <addr> <len>`: dump `len` bytes of memory starting at the address
+    Memory(u16, u16),
+    /// `r`: print the registers
+    Registers,
+    /// `q`: leave the debugger
+    Quit,
+}
+
+/// Parses a number for a command; numbers are hexadecimal, with an optional `$` or `0x` prefix
+fn parse_number(text: &str) -> Result<u16, String> {
+    let digits = text.strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid number: {}", text))
+}
+
+/// Parses a line entered at the debugger prompt
+pub fn parse_command(line: &str) -> Result<Command, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["s"] => Ok(Command::Step),
+        ["c"] => Ok(Command::Continue),
+        ["b", address] => Ok(Command::Breakpoint(parse_number(address)?)),
+        ["m", address, length] => Ok(Command::Memory(parse_number(address)?, parse_number(length)?)),
+        ["r"] => Ok(Command::Registers),
+        ["q"] => Ok(Command::Quit),
+        [] => Err(String::from("No command entered")),
+        _ => Err(format!("Unknown command: {}", line.trim())),
+    }
+}
+
+/// An interactive debugger driving a CPU.
+/// The debugger only keeps its own breakpoints; everything else goes through the CPU's and bus's public interfaces.
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: HashSet<u16>,
+}
+
+impl Debugger {
+    /// Creates a debugger with no breakpoints
+    pub fn new() -> Debugger {
+        Debugger::default()
+    }
+
+    /// Adds a breakpoint at `address`
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    /// Returns whether there is a breakpoint at `address`
+    pub fn has_breakpoint(&self, address: u16) -> bool {
+        self.breakpoints.contains(&address)
+    }
+
+    /// Executes a single command against `cpu`, returning the text to show the user
+    pub fn execute(&mut self, cpu: &mut CPU, command: Command) -> String {
+        match command {
+            Command::Step => {
+                let trace = cpu.trace();
+                cpu.step();
+                trace
+            },
+            Command::Continue => {
+                // always execute at least one instruction, so continuing from a breakpoint makes progress
+                while cpu.is_running() {
+                    cpu.step();
+                    if self.has_breakpoint(cpu.pc()) {
+                        return format!("Breakpoint at ${:04X}", cpu.pc());
+                    }
+                }
+                match cpu.stop_reason() {
+                    Some(reason) => format!("CPU stopped: {}", reason),
+                    None => String::from("CPU stopped"),
+                }
+            },
+            Command::Breakpoint(address) => {
+                self.add_breakpoint(address);
+                format!("Breakpoint set at ${:04X}", address)
+            },
+            Command::Memory(start, length) => {
+                let mut lines = Vec::new();
+                let end = start as u32 + length as u32;
+                let mut address = start as u32;
+                while address < end {
+                    let line_end = end.min(address + DUMP_BYTES_PER_LINE as u32);
+                    let bytes: Vec<String> = (address..line_end)
+                        .map(|a| format!("{:02X}", cpu.bus().peek(a as u16)))
+                        .collect();
+                    lines.push(format!("{:04X}: {}", address as u16, bytes.join(" ")));
+                    address = line_end;
+                }
+                lines.join("\n")
+            },
+            Command::Registers => format!(
+                "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+                cpu.pc(),
+                cpu.a(),
+                cpu.x(),
+                cpu.y(),
+                cpu.flags(),
+                cpu.sp(),
+                cpu.cycle_count()
+            ),
+            Command::Quit => String::new(),
+        }
+    }
+
+    /// Runs the debugger, reading commands from `input` and writing results to `output` until `q` is entered or the input ends
+    pub fn run<R: BufRead, W: Write>(&mut self, cpu: &mut CPU, input: R, mut output: W) -> io::Result<()> {
+        write!(output, "> ")?;
+        output.flush()?;
+        for line in input.lines() {
+            match parse_command(&line?) {
+                Ok(Command::Quit) => break,
+                Ok(command) => writeln!(output, "{}", self.execute(cpu, command))?,
+                Err(e) => writeln!(output, "{}", e)?,
+            }
+            write!(output, "> ")?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_are_parsed_with_hexadecimal_operands() {
+        assert_eq!(parse_command("s"), Ok(Command::Step));
+        assert_eq!(parse_command("  c  "), Ok(Command::Continue));
+        assert_eq!(parse_command("b $0604"), Ok(Command::Breakpoint(0x0604)));
+        assert_eq!(parse_command("b c000"), Ok(Command::Breakpoint(0xc000)));
+        assert_eq!(parse_command("m 0x200 20"), Ok(Command::Memory(0x0200, 0x20)));
+        assert_eq!(parse_command("r"), Ok(Command::Registers));
+        assert_eq!(parse_command("q"), Ok(Command::Quit));
+    }
+
+    #[test]
+    fn malformed_commands_are_rejected() {
+        assert!(parse_command("").is_err());
+        assert!(parse_command("x").is_err());
+        assert!(parse_command("b").is_err());
+        assert!(parse_command("b $10000").is_err());
+        assert!(parse_command("m $0200").is_err());
+    }
+
+    #[test]
+    fn scripted_session_steps_breaks_and_dumps() {
+        // LDA #$42; STA $0200; STA $0201; JMP $0608
+        // powering on clears RAM, so the program is loaded afterwards
+        let mut cpu = CPU::default();
+        cpu.load_vector(0xfffc, 0x0600);
+        cpu.power_on();
+        cpu.load_program(0x0600, &[0xa9, 0x42, 0x8d, 0x00, 0x02, 0x8d, 0x01, 0x02, 0x4c, 0x08, 0x06]).unwrap();
+
+        let script = "s\nb 605\nc\nr\nm 200 2\nx\nq\ns\n";
+        let mut output = Vec::new();
+        Debugger::new().run(&mut cpu, script.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().map(|line| line.trim_start_matches("> ")).collect();
+
+        assert!(lines[0].starts_with("0600  A9 42     LDA #$42"));
+        assert_eq!(lines[1], "Breakpoint set at $0605");
+        assert_eq!(lines[2], "Breakpoint at $0605");
+        assert!(lines[3].starts_with("PC:0605 A:42"));
+        assert_eq!(lines[4], "0200: 42 00");
+        assert_eq!(lines[5], "Unknown command: x");
+        // nothing runs after quitting
+        assert_eq!(lines.len(), 7);
+        assert_eq!(cpu.pc(), 0x0605);
+    }
+}
@@ -0,0 +1,222 @@
+// disasm.rs
+// Translates machine code back into assembly, for debugging
+
+use crate::cpu::instruction::{self, AddressingMode, Mnemonic};
+use crate::mem::Mem;
+
+/// Disassembles the instruction at `address`, returning its text (such as `LDA $1234,X`) and its length in bytes
+/// Memory is peeked rather than read, so disassembling has no side effects. Opcodes the CPU doesn't implement are shown as `.byte $xx`.
+pub fn disassemble(mem: &dyn Mem, address: u16) -> (String, u8) {
+    let opcode = mem.peek(address);
+    let instruction = match instruction::decode(opcode) {
+        Some(instruction) => instruction,
+        None => return (format!(".byte ${:02X}", opcode), 1),
+    };
+
+    let low = mem.peek(address.wrapping_add(1));
+    let high = mem.peek(address.wrapping_add(2));
+    let word = u16::from_le_bytes([low, high]);
+
+    let operand = match instruction.mode {
+        AddressingMode::Implied => String::new(),
+        AddressingMode::Accumulator => String::from(" A"),
+        AddressingMode::Immediate => format!(" #${:02X}", low),
+        AddressingMode::Zero => format!(" ${:02X}", low),
+        AddressingMode::ZeroX => format!(" ${:02X},X", low),
+        AddressingMode::ZeroY => format!(" ${:02X},Y", low),
+        AddressingMode::Absolute => format!(" ${:04X}", word),
+        AddressingMode::AbsoluteX => format!(" ${:04X},X", word),
+        AddressingMode::AbsoluteY => format!(" ${:04X},Y", word),
+        AddressingMode::Indirect => format!(" (${:04X})", word),
+        AddressingMode::IndirectX => format!(" (${:02X},X)", low),
+        AddressingMode::IndirectY => format!(" (${:02X}),Y", low),
+        AddressingMode::Relative => {
+            // show where the branch goes rather than the raw offset
+            let target = address.wrapping_add(2).wrapping_add(low as i8 as u16);
+            format!(" ${:04X}", target)
+        },
+    };
+
+    let text = format!("{:?}{}", instruction.mnemonic, operand);
+    (text, 1 + instruction.mode.operand_length())
+}
+
+/// Disassembles the instructions from `start` through `end` (inclusive), returning each line along with its address
+/// If the last instruction would run past `end`, it is cut short and its remaining bytes within the range are listed as `.byte`.
+pub fn disassemble_range(mem: &dyn Mem, start: u16, end: u16) -> Vec<(u16, String)> {
+    let mut lines = Vec::new();
+    let end = end as u32;
+    let mut address = start as u32;
+    while address <= end {
+        let (text, length) = disassemble(mem, address as u16);
+        if address + length as u32 - 1 > end {
+            for truncated in address..=end {
+                lines.push((truncated as u16, format!(".byte ${:02X}", mem.peek(truncated as u16))));
+            }
+            break;
+        }
+
+        lines.push((address as u16, text));
+        address += length as u32;
+    }
+    lines
+}
+
+/// Returns whether `opcode` is one of the undocumented opcodes, which nestest marks with a `*`
+pub fn is_unofficial(opcode: u8) -> bool {
+    match instruction::decode(opcode) {
+        Some(instruction) => match instruction.mnemonic {
+            Mnemonic::XAA | Mnemonic::LAX | Mnemonic::SAX | Mnemonic::DCP | Mnemonic::ISC |
+            Mnemonic::SLO | Mnemonic::RLA | Mnemonic::SRE | Mnemonic::RRA => true,
+            // only one of each of these is official; the rest are duplicates
+            Mnemonic::NOP => opcode != 0xea,
+            Mnemonic::SBC => opcode == 0xeb,
+            _ => false,
+        },
+        None => false,
+    }
+}
+
+/// Disassembles the instruction at `address` in the style of nestest.log, returning its text and its length in bytes
+/// Memory operands are annotated with the address they resolve to (using the given index registers) and the value there, such as `LDA ($80),Y = 0200 @ 0210 = 5A`.
+pub fn disassemble_traced(mem: &dyn Mem, address: u16, x: u8, y: u8) -> (String, u8) {
+    let opcode = mem.peek(address);
+    let instruction = match instruction::decode(opcode) {
+        Some(instruction) => instruction,
+        None => return disassemble(mem, address),
+    };
+
+    let low = mem.peek(address.wrapping_add(1));
+    let high = mem.peek(address.wrapping_add(2));
+    let word = u16::from_le_bytes([low, high]);
+    // pointers in the zero page wrap around within it
+    let zero_page_word = |pointer: u8| {
+        u16::from_le_bytes([mem.peek(pointer as u16), mem.peek(pointer.wrapping_add(1) as u16)])
+    };
+
+    let operand = match instruction.mode {
+        AddressingMode::Zero => format!(" ${:02X} = {:02X}", low, mem.peek(low as u16)),
+        AddressingMode::ZeroX => {
+            let effective = low.wrapping_add(x);
+            format!(" ${:02X},X @ {:02X} = {:02X}", low, effective, mem.peek(effective as u16))
+        },
+        AddressingMode::ZeroY => {
+            let effective = low.wrapping_add(y);
+            format!(" ${:02X},Y @ {:02X} = {:02X}", low, effective, mem.peek(effective as u16))
+        },
+        AddressingMode::Absolute => match instruction.mnemonic {
+            Mnemonic::JMP | Mnemonic::JSR => format!(" ${:04X}", word),
+            _ => format!(" ${:04X} = {:02X}", word, mem.peek(word)),
+        },
+        AddressingMode::AbsoluteX => {
+            let effective = word.wrapping_add(x as u16);
+            format!(" ${:04X},X @ {:04X} = {:02X}", word, effective, mem.peek(effective))
+        },
+        AddressingMode::AbsoluteY => {
+            let effective = word.wrapping_add(y as u16);
+            format!(" ${:04X},Y @ {:04X} = {:02X}", word, effective, mem.peek(effective))
+        },
+        AddressingMode::Indirect => {
+            // the 6502 doesn't carry into the high byte of the pointer
+            let target_high = mem.peek((word & 0xff00) | (word.wrapping_add(1) & 0x00ff));
+            let target = u16::from_le_bytes([mem.peek(word), target_high]);
+            format!(" (${:04X}) = {:04X}", word, target)
+        },
+        AddressingMode::IndirectX => {
+            let pointer = low.wrapping_add(x);
+            let effective = zero_page_word(pointer);
+            format!(" (${:02X},X) @ {:02X} = {:04X} = {:02X}", low, pointer, effective, mem.peek(effective))
+        },
+        AddressingMode::IndirectY => {
+            let base = zero_page_word(low);
+            let effective = base.wrapping_add(y as u16);
+            format!(" (${:02X}),Y = {:04X} @ {:04X} = {:02X}", low, base, effective, mem.peek(effective))
+        },
+        _ => {
+            // the remaining modes have nothing to annotate
+            return disassemble(mem, address);
+        },
+    };
+
+    let mnemonic = match instruction.mnemonic {
+        // nestest uses a different name for this one
+        Mnemonic::ISC => String::from("ISB"),
+        mnemonic => format!("{:?}", mnemonic),
+    };
+    (format!("{}{}", mnemonic, operand), 1 + instruction.mode.operand_length())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::Bus;
+
+    /// Disassembles `bytes`, placed at the start of RAM
+    fn disassemble_bytes(bytes: &[u8]) -> (String, u8) {
+        let mut bus = Bus::default();
+        for (address, byte) in bytes.iter().enumerate() {
+            bus.write(address as u16, *byte);
+        }
+        disassemble(&bus, 0x0000)
+    }
+
+    #[test]
+    fn each_addressing_mode_is_formatted() {
+        let cases: [(&[u8], &str, u8); 13] = [
+            (&[0xea], "NOP", 1),
+            (&[0x0a], "ASL A", 1),
+            (&[0xa9, 0x42], "LDA #$42", 2),
+            (&[0xa5, 0x10], "LDA $10", 2),
+            (&[0xb5, 0x10], "LDA $10,X", 2),
+            (&[0xb6, 0x10], "LDX $10,Y", 2),
+            (&[0xad, 0x34, 0x12], "LDA $1234", 3),
+            (&[0xbd, 0x34, 0x12], "LDA $1234,X", 3),
+            (&[0xb9, 0x34, 0x12], "LDA $1234,Y", 3),
+            (&[0x6c, 0xfc, 0xff], "JMP ($FFFC)", 3),
+            (&[0xa1, 0x20], "LDA ($20,X)", 2),
+            (&[0xb1, 0x20], "LDA ($20),Y", 2),
+            // branches show their target; from 0x0000, the instruction after is at 0x0002
+            (&[0xd0, 0xfe], "BNE $0000", 2),
+        ];
+        for (bytes, text, length) in cases {
+            assert_eq!(disassemble_bytes(bytes), (String::from(text), length));
+        }
+    }
+
+    #[test]
+    fn unknown_opcode_is_shown_as_a_byte() {
+        assert_eq!(disassemble_bytes(&[0x02]), (String::from(".byte $02"), 1));
+    }
+
+    #[test]
+    fn range_lists_truncated_instruction_as_bytes() {
+        let mut bus = Bus::default();
+        for (address, byte) in [0xe8, 0xad, 0x34].iter().enumerate() {
+            bus.write(address as u16, *byte);
+        }
+        let lines = disassemble_range(&bus, 0x0000, 0x0002);
+        assert_eq!(lines, vec![
+            (0x0000, String::from("INX")),
+            (0x0001, String::from(".byte $AD")),
+            (0x0002, String::from(".byte $34")),
+        ]);
+    }
+
+    #[test]
+    fn range_lists_a_program_line_by_line() {
+        let mut bus = Bus::default();
+        // LDX #$08; loop: DEX; STA $0200,X; BNE loop; RTS
+        let program = [0xa2, 0x08, 0xca, 0x9d, 0x00, 0x02, 0xd0, 0xfa, 0x60];
+        for (offset, byte) in program.iter().enumerate() {
+            bus.write(0x0300 + offset as u16, *byte);
+        }
+        let lines = disassemble_range(&bus, 0x0300, 0x0308);
+        assert_eq!(lines, vec![
+            (0x0300, String::from("LDX #$08")),
+            (0x0302, String::from("DEX")),
+            (0x0303, String::from("STA $0200,X")),
+            (0x0306, String::from("BNE $0302")),
+            (0x0308, String::from("RTS")),
+        ]);
+    }
+}
@@ -0,0 +1,337 @@
+// ines.rs
+// Describes the contents of a ROM image in the iNES format
+
+use crate::mapper::Mirroring;
+use crate::nes::Region;
+
+/// The length of the iNES header
+pub const HEADER_SIZE: usize = 16;
+
+/// Every iNES file begins with "NES" followed by an MS-DOS end-of-file
+const MAGIC: [u8; 4] = [b'N', b'E', b'S', 0x1a];
+
+/// The length of the optional trainer between the header and the PRG ROM
+pub const TRAINER_SIZE: usize = 512;
+
+/// PRG ROM sizes are given in 16KB units
+const PRG_ROM_UNIT: usize = 0x4000;
+/// CHR ROM sizes are given in 8KB units
+const CHR_ROM_UNIT: usize = 0x2000;
+/// iNES 1.0 PRG RAM sizes are given in 8KB units, which is `64 << 7` bytes
+const PRG_RAM_UNIT_SHIFT: u8 = 7;
+
+/// The metadata stored in the 16-byte header of an iNES file
+#[derive(PartialEq, Eq)]
+#[derive(Debug, Clone)]
+pub struct NesFormat {
+    /// The size of the PRG ROM, in bytes
+    pub prg_rom_size: usize,
+    /// The size of the CHR ROM, in bytes; zero means the board uses CHR RAM
+    pub chr_rom_size: usize,
+
+    /// The iNES mapper number, identifying the board
+    pub mapper_number: u16,
+    /// The NES 2.0 submapper number, distinguishing board variants
+    pub submapper_number: u8,
+
+    /// Whether the nametables are hard-wired for horizontal or vertical mirroring
+    pub mirroring: Mirroring,
+    /// Whether the cartridge contains battery-backed PRG RAM (or other persistent memory)
+    pub battery_memory_present: bool,
+    /// Whether a 512-byte trainer precedes the PRG ROM
+    pub trainer_present: bool,
+    /// Whether the cartridge supplies its own VRAM for four distinct nametables
+    pub four_screen_mode: bool,
+
+    /// Whether the header is in the NES 2.0 format
+    pub nes2: bool,
+    /// The volatile PRG RAM size is `64 << prg_ram_shift_count` bytes, or none when zero
+    pub prg_ram_shift_count: u8,
+    /// The non-volatile PRG RAM size is `64 << prg_nvram_shift_count` bytes, or none when zero
+    pub prg_nvram_shift_count: u8,
+    /// The volatile CHR RAM size is `64 << chr_ram_shift_count` bytes, or none when zero
+    pub chr_ram_shift_count: u8,
+    /// The non-volatile CHR RAM size is `64 << chr_nvram_shift_count` bytes, or none when zero
+    pub chr_nvram_shift_count: u8,
+
+    /// The television system the game was made for; games made for several are treated as NTSC
+    pub timing: Region,
+}
+
+/// The contents of an iNES file, split into its sections
+pub struct RomImage {
+    pub header: NesFormat,
+    pub prg_rom: Vec<u8>,
+    /// The CHR ROM; empty if the board uses CHR RAM instead
+    pub chr_rom: Vec<u8>,
+    /// Whether the board uses CHR RAM instead of CHR ROM
+    pub chr_ram: bool,
+    /// The 512-byte trainer, if the file has one
+    pub trainer: Option<Vec<u8>>,
+}
+
+/// Parses an iNES file, returning its header along with the data in each section
+pub fn load_rom(buf: &[u8]) -> Result<RomImage, String> {
+    let header = NesFormat::read_ines(buf)?;
+
+    // anything past the declared data is padding and is ignored
+    let expected = header.file_size();
+    if buf.len() < expected {
+        return Err(format!("File is truncated: expected {} bytes but found {}", expected, buf.len()));
+    }
+
+    // the trainer, if present, sits between the header and the PRG ROM
+    let mut offset = HEADER_SIZE;
+    let trainer = if header.trainer_present {
+        offset += TRAINER_SIZE;
+        Some(buf[HEADER_SIZE..offset].to_vec())
+    } else {
+        None
+    };
+
+    let prg_rom = buf[offset..offset + header.prg_rom_size].to_vec();
+    offset += header.prg_rom_size;
+    let chr_rom = buf[offset..offset + header.chr_rom_size].to_vec();
+
+    Ok(RomImage {
+        chr_ram: header.chr_rom_size == 0,
+        header,
+        prg_rom,
+        chr_rom,
+        trainer,
+    })
+}
+
+impl NesFormat {
+    /// Parses the header at the start of an iNES file.
+    /// Both the original iNES format and NES 2.0 are understood; NES 2.0 is identified by bits 2 and 3 of flags 7 being `0b10`.
+    pub fn read_ines(buf: &[u8]) -> Result<NesFormat, String> {
+        if buf.len() < HEADER_SIZE {
+            return Err(format!("File is too short for an iNES header ({} bytes)", buf.len()));
+        }
+        if buf[0..4] != MAGIC {
+            return Err(String::from("Not an iNES file (bad magic number)"));
+        }
+
+        let flags_6 = buf[6];
+        let flags_7 = buf[7];
+        let nes2 = (flags_7 & 0x0c) == 0x08;
+
+        let mut header = NesFormat {
+            prg_rom_size: buf[4] as usize * PRG_ROM_UNIT,
+            chr_rom_size: buf[5] as usize * CHR_ROM_UNIT,
+            mapper_number: ((flags_7 & 0xf0) | (flags_6 >> 4)) as u16,
+            submapper_number: 0,
+            mirroring: if (flags_6 & 0x01) == 0 { Mirroring::Horizontal } else { Mirroring::Vertical },
+            battery_memory_present: (flags_6 & 0x02) != 0,
+            trainer_present: (flags_6 & 0x04) != 0,
+            four_screen_mode: (flags_6 & 0x08) != 0,
+            nes2,
+            prg_ram_shift_count: 0,
+            prg_nvram_shift_count: 0,
+            chr_ram_shift_count: 0,
+            chr_nvram_shift_count: 0,
+            timing: Region::Ntsc,
+        };
+
+        if nes2 {
+            header.mapper_number |= ((buf[8] & 0x0f) as u16) << 8;
+            header.submapper_number = buf[8] >> 4;
+            header.prg_rom_size = rom_size(buf[4], buf[9] & 0x0f, PRG_ROM_UNIT);
+            header.chr_rom_size = rom_size(buf[5], buf[9] >> 4, CHR_ROM_UNIT);
+            header.prg_ram_shift_count = buf[10] & 0x0f;
+            header.prg_nvram_shift_count = buf[10] >> 4;
+            header.chr_ram_shift_count = buf[11] & 0x0f;
+            header.chr_nvram_shift_count = buf[11] >> 4;
+            header.timing = match buf[12] & 0x03 {
+                1 => Region::Pal,
+                3 => Region::Dendy,
+                // 0 is NTSC, and 2 is a game that runs on either
+                _ => Region::Ntsc,
+            };
+        } else {
+            // iNES 1.0 gives the PRG RAM size in 8KB units, with 0 meaning 8KB for compatibility
+            let units = buf[8].max(1) as u32;
+            header.prg_ram_shift_count = PRG_RAM_UNIT_SHIFT + units.next_power_of_two().trailing_zeros() as u8;
+            // bit 0 of byte 9 marks PAL games, although few dumps set it
+            if (buf[9] & 0x01) != 0 {
+                header.timing = Region::Pal;
+            }
+        }
+
+        Ok(header)
+    }
+
+    /// Returns the length of the file the header describes: the header, the trainer (if any), and the PRG and CHR ROM
+    pub fn file_size(&self) -> usize {
+        let trainer = if self.trainer_present { TRAINER_SIZE } else { 0 };
+        // exponent-multiplier sizes can be absurdly large, so don't let a bad header overflow
+        (HEADER_SIZE + trainer).saturating_add(self.prg_rom_size).saturating_add(self.chr_rom_size)
+    }
+
+    /// Returns the mirroring the PPU should use, taking four-screen VRAM into account
+    pub fn nametable_mirroring(&self) -> Mirroring {
+        if self.four_screen_mode {
+            Mirroring::FourScreen
+        } else {
+            self.mirroring
+        }
+    }
+}
+
+/// Computes a NES 2.0 ROM size from its LSB and MSB nibble.
+/// An MSB nibble of `0xF` switches to exponent-multiplier notation, where the LSB holds the exponent in bits 2-7 and the multiplier in bits 0-1.
+fn rom_size(lsb: u8, msb: u8, unit: usize) -> usize {
+    if msb == 0x0f {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0x03) as usize * 2 + 1;
+        2usize.saturating_pow(exponent).saturating_mul(multiplier)
+    } else {
+        (((msb as usize) << 8) | lsb as usize) * unit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The header of Super Mario Bros.: NROM, 32KB PRG ROM, 8KB CHR ROM, vertical mirroring
+    const NROM_HEADER: [u8; 16] = [0x4e, 0x45, 0x53, 0x1a, 0x02, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+    /// The header of Super Mario Bros. 3: MMC3, 256KB PRG ROM, 128KB CHR ROM, horizontal mirroring
+    const MMC3_HEADER: [u8; 16] = [0x4e, 0x45, 0x53, 0x1a, 0x10, 0x10, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+    #[test]
+    fn parses_nrom_header() {
+        let header = NesFormat::read_ines(&NROM_HEADER).unwrap();
+        assert_eq!(header.mapper_number, 0);
+        assert_eq!(header.prg_rom_size, 0x8000);
+        assert_eq!(header.chr_rom_size, 0x2000);
+        assert_eq!(header.mirroring, Mirroring::Vertical);
+        assert!(!header.battery_memory_present);
+        assert!(!header.trainer_present);
+        assert!(!header.nes2);
+    }
+
+    #[test]
+    fn parses_mmc3_header() {
+        let header = NesFormat::read_ines(&MMC3_HEADER).unwrap();
+        assert_eq!(header.mapper_number, 4);
+        assert_eq!(header.prg_rom_size, 0x40000);
+        assert_eq!(header.chr_rom_size, 0x20000);
+        assert_eq!(header.mirroring, Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn parses_nes2_header() {
+        let mut bytes = MMC3_HEADER;
+        bytes[6] = 0x4a;    // battery, four-screen
+        bytes[7] = 0x18;    // NES 2.0, mapper high nibble 1
+        bytes[8] = 0x21;    // submapper 2, mapper bits 8-11 = 1
+        bytes[9] = 0x10;    // CHR ROM size MSB
+        bytes[10] = 0x70;   // 8KB of battery-backed PRG RAM
+        bytes[12] = 0x01;   // PAL
+        let header = NesFormat::read_ines(&bytes).unwrap();
+        assert!(header.nes2);
+        assert_eq!(header.mapper_number, 0x114);
+        assert_eq!(header.submapper_number, 2);
+        assert_eq!(header.chr_rom_size, 0x110 * 0x2000);
+        assert_eq!(header.prg_nvram_shift_count, 7);
+        assert!(header.battery_memory_present);
+        assert_eq!(header.nametable_mirroring(), Mirroring::FourScreen);
+        assert_eq!(header.timing, Region::Pal);
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut bytes = NROM_HEADER;
+        bytes[3] = 0x00;
+        assert!(NesFormat::read_ines(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_header() {
+        assert!(NesFormat::read_ines(&NROM_HEADER[..10]).is_err());
+    }
+
+    /// Builds an iNES file from `header`, with a trainer filled with `0x77` if the header has the trainer flag, and PRG and CHR ROM filled with `0x11` and `0x22`
+    fn rom_file(header: [u8; 16]) -> Vec<u8> {
+        let parsed = NesFormat::read_ines(&header).unwrap();
+        let mut file = header.to_vec();
+        if parsed.trainer_present {
+            file.extend(vec![0x77; TRAINER_SIZE]);
+        }
+        file.extend(vec![0x11; parsed.prg_rom_size]);
+        file.extend(vec![0x22; parsed.chr_rom_size]);
+        file
+    }
+
+    #[test]
+    fn load_rom_splits_sections() {
+        let rom = load_rom(&rom_file(NROM_HEADER)).unwrap();
+        assert_eq!(rom.prg_rom.len(), 0x8000);
+        assert_eq!(rom.chr_rom.len(), 0x2000);
+        assert!(rom.prg_rom.iter().all(|&byte| byte == 0x11));
+        assert!(rom.chr_rom.iter().all(|&byte| byte == 0x22));
+        assert!(!rom.chr_ram);
+        assert!(rom.trainer.is_none());
+    }
+
+    #[test]
+    fn load_rom_skips_trainer() {
+        let mut header = NROM_HEADER;
+        header[6] |= 0x04;
+        let rom = load_rom(&rom_file(header)).unwrap();
+        assert_eq!(rom.trainer, Some(vec![0x77; TRAINER_SIZE]));
+        assert!(rom.prg_rom.iter().all(|&byte| byte == 0x11));
+        assert!(rom.chr_rom.iter().all(|&byte| byte == 0x22));
+    }
+
+    #[test]
+    fn load_rom_flags_chr_ram() {
+        let mut header = NROM_HEADER;
+        header[5] = 0;
+        let rom = load_rom(&rom_file(header)).unwrap();
+        assert!(rom.chr_rom.is_empty());
+        assert!(rom.chr_ram);
+    }
+
+    #[test]
+    fn accepts_correctly_sized_file() {
+        let file = rom_file(NROM_HEADER);
+        assert_eq!(file.len(), NesFormat::read_ines(&NROM_HEADER).unwrap().file_size());
+        assert!(load_rom(&file).is_ok());
+    }
+
+    #[test]
+    fn rejects_truncated_file() {
+        let mut file = rom_file(NROM_HEADER);
+        file.truncate(file.len() - 1);
+        let error = load_rom(&file).err().unwrap();
+        assert!(error.contains("40976"), "{}", error);
+        assert!(error.contains("40975"), "{}", error);
+    }
+
+    #[test]
+    fn tolerates_trailing_bytes() {
+        let mut file = rom_file(NROM_HEADER);
+        file.extend([0xff; 100]);
+        let rom = load_rom(&file).unwrap();
+        assert_eq!(rom.chr_rom.len(), 0x2000);
+        assert!(rom.chr_rom.iter().all(|&byte| byte == 0x22));
+    }
+
+    #[test]
+    fn ines_1_prg_ram_zero_means_8kb() {
+        let header = NesFormat::read_ines(&NROM_HEADER).unwrap();
+        assert_eq!(64usize << header.prg_ram_shift_count, 0x2000);
+    }
+
+    #[test]
+    fn ines_1_prg_ram_counts_8kb_units() {
+        let mut bytes = NROM_HEADER;
+        bytes[8] = 4;
+        let header = NesFormat::read_ines(&bytes).unwrap();
+        assert_eq!(64usize << header.prg_ram_shift_count, 0x8000);
+    }
+}
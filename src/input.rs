@@ -0,0 +1,267 @@
+// input.rs
+// Implements the standard controller
+
+use crate::state::{SaveState, StateReader, StateWriter};
+
+/// The buttons on a standard controller, in the order they are reported
+#[derive(PartialEq, Eq)]
+#[derive(Debug, Copy, Clone)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Button {
+    /// Returns the button's bit in the controller's shift register
+    fn mask(self) -> u8 {
+        1 << (self as u8)
+    }
+}
+
+/// A snapshot of which buttons are held, as a bitset with one bit per `Button`
+#[derive(PartialEq, Eq)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ControllerState {
+    bits: u8,
+}
+
+impl ControllerState {
+    /// Creates a state from its bitset, with `A` in bit 0 through `Right` in bit 7
+    pub fn from_bits(bits: u8) -> ControllerState {
+        ControllerState { bits }
+    }
+
+    /// Returns the state as a bitset, with `A` in bit 0 through `Right` in bit 7
+    pub fn bits(self) -> u8 {
+        self.bits
+    }
+
+    /// Presses or releases a button
+    pub fn set(&mut self, button: Button, pressed: bool) {
+        if pressed {
+            self.bits |= button.mask();
+        } else {
+            self.bits &= !button.mask();
+        }
+    }
+
+    /// Returns the state with a button pressed
+    pub fn with(mut self, button: Button) -> ControllerState {
+        self.set(button, true);
+        self
+    }
+
+    /// Returns whether a button is held
+    pub fn is_pressed(self, button: Button) -> bool {
+        (self.bits & button.mask()) != 0
+    }
+}
+
+/// A standard controller.
+/// The controller is read serially: writing 1 to `0x4016` holds the strobe high, continuously loading the buttons into a shift register, and writing 0 releases it.
+/// Each read then returns the next button in bit 0, in the order A, B, Select, Start, Up, Down, Left, Right; after all eight, reads return 1.
+#[derive(Default)]
+pub struct Controller {
+    buttons: ControllerState,
+    shift: u8,
+    strobe: bool,
+}
+
+impl Controller {
+    /// Presses or releases a button
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.buttons.set(button, pressed);
+    }
+
+    /// Returns whether a button is held
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.buttons.is_pressed(button)
+    }
+
+    /// Sets every button at once
+    pub fn set_state(&mut self, state: ControllerState) {
+        self.buttons = state;
+    }
+
+    /// Returns the buttons currently held
+    pub fn state(&self) -> ControllerState {
+        self.buttons
+    }
+
+    /// Handles a write to `0x4016`; bit 0 is the strobe
+    pub fn write(&mut self, value: u8) {
+        self.strobe = (value & 1) != 0;
+        if self.strobe {
+            self.shift = self.buttons.bits();
+        }
+    }
+
+    /// Handles a read of the controller's port, returning the next button in bit 0
+    pub fn read(&mut self) -> u8 {
+        let bit = self.peek();
+        if !self.strobe {
+            // ones are shifted in behind the buttons
+            self.shift = (self.shift >> 1) | 0x80;
+        }
+        bit
+    }
+
+    /// Returns the bit the next read would return, without shifting
+    pub fn peek(&self) -> u8 {
+        if self.strobe {
+            // while the strobe is held, the shift register keeps reloading, so A is always reported
+            self.buttons.bits() & 1
+        } else {
+            self.shift & 1
+        }
+    }
+}
+
+impl SaveState for Controller {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.buttons.bits());
+        writer.write_u8(self.shift);
+        writer.write_bool(self.strobe);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.buttons = ControllerState::from_bits(reader.read_u8()?);
+        self.shift = reader.read_u8()?;
+        self.strobe = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+/// Records the controller states for each frame, and plays them back, for deterministic replays.
+/// The recording is serialized as two bytes per frame (one per controller, as in `ControllerState::bits`), in frame order.
+#[derive(Default)]
+pub struct Recorder {
+    frames: Vec<[ControllerState; 2]>,
+    position: usize,
+}
+
+impl Recorder {
+    /// Creates an empty recording
+    pub fn new() -> Recorder {
+        Recorder::default()
+    }
+
+    /// Loads a recording from its serialized form
+    pub fn from_bytes(bytes: &[u8]) -> Result<Recorder, String> {
+        if !bytes.len().is_multiple_of(2) {
+            return Err(format!("Input recording has an odd length ({} bytes)", bytes.len()));
+        }
+
+        let frames = bytes
+            .chunks_exact(2)
+            .map(|frame| [ControllerState::from_bits(frame[0]), ControllerState::from_bits(frame[1])])
+            .collect();
+        Ok(Recorder { frames, position: 0 })
+    }
+
+    /// Serializes the recording
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.frames
+            .iter()
+            .flat_map(|frame| [frame[0].bits(), frame[1].bits()])
+            .collect()
+    }
+
+    /// Appends a frame's controller states to the recording
+    pub fn record(&mut self, states: [ControllerState; 2]) {
+        self.frames.push(states);
+    }
+
+    /// Returns the controller states for the next frame of playback, or `None` once the recording is exhausted
+    pub fn next_frame(&mut self) -> Option<[ControllerState; 2]> {
+        let states = self.frames.get(self.position).copied();
+        if states.is_some() {
+            self.position += 1;
+        }
+        states
+    }
+
+    /// Returns every frame in the recording
+    pub fn frames(&self) -> &[[ControllerState; 2]] {
+        &self.frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Strobes `controller`, then reads `count` bits from it
+    fn strobe_and_read(controller: &mut Controller, count: usize) -> Vec<u8> {
+        controller.write(1);
+        controller.write(0);
+        (0..count).map(|_| controller.read()).collect()
+    }
+
+    #[test]
+    fn buttons_shift_out_in_order_then_ones() {
+        let mut controller = Controller::default();
+        controller.set_button(Button::A, true);
+        controller.set_button(Button::Start, true);
+        assert_eq!(strobe_and_read(&mut controller, 10), vec![1, 0, 0, 1, 0, 0, 0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn buttons_are_latched_by_the_strobe() {
+        let mut controller = Controller::default();
+        controller.set_button(Button::Right, true);
+        controller.write(1);
+        controller.write(0);
+        // releasing the button after the strobe doesn't change what is shifted out
+        controller.set_button(Button::Right, false);
+        let bits: Vec<u8> = (0..8).map(|_| controller.read()).collect();
+        assert_eq!(bits, vec![0, 0, 0, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn held_strobe_always_reports_a() {
+        let mut controller = Controller::default();
+        controller.set_button(Button::A, true);
+        controller.set_button(Button::B, true);
+        controller.write(1);
+        for _ in 0..4 {
+            assert_eq!(controller.read(), 1);
+        }
+        controller.set_button(Button::A, false);
+        assert_eq!(controller.read(), 0);
+    }
+
+    #[test]
+    fn controller_state_bits_follow_button_order() {
+        let state = ControllerState::default().with(Button::A).with(Button::Right);
+        assert_eq!(state.bits(), 0x81);
+        assert!(state.is_pressed(Button::Right));
+        assert!(!state.is_pressed(Button::Up));
+    }
+
+    #[test]
+    fn recording_round_trips_through_bytes() {
+        let mut recorder = Recorder::new();
+        recorder.record([ControllerState::from_bits(0x81), ControllerState::from_bits(0x00)]);
+        recorder.record([ControllerState::from_bits(0x10), ControllerState::from_bits(0xff)]);
+        let bytes = recorder.to_bytes();
+        assert_eq!(bytes, vec![0x81, 0x00, 0x10, 0xff]);
+
+        let mut loaded = Recorder::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.frames(), recorder.frames());
+        assert_eq!(loaded.next_frame(), Some(recorder.frames()[0]));
+        assert_eq!(loaded.next_frame(), Some(recorder.frames()[1]));
+        assert_eq!(loaded.next_frame(), None);
+    }
+
+    #[test]
+    fn recording_with_odd_length_is_rejected() {
+        assert!(Recorder::from_bytes(&[0x01, 0x02, 0x03]).is_err());
+    }
+}
@@ -0,0 +1,20 @@
+// lib.rs
+// The emulator core, usable independently of the frontend in main.rs
+
+// The mnemonics are named after the official 6502 instructions
+#![allow(clippy::upper_case_acronyms)]
+#![allow(clippy::needless_return)]
+
+pub mod apu;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod ines;
+pub mod input;
+pub mod mapper;
+pub mod mem;
+pub mod nes;
+#[cfg(feature = "png")]
+pub mod png;
+pub mod ppu;
+pub mod state;
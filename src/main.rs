@@ -1,19 +1,19 @@
 // main.rs
 
-use std::fs::File;
-use std::io::Read;
-use std::time::{Duration, Instant};
-use std::thread::sleep;
+use std::fs;
 use std::io;
 use std::io::Write;
+use std::path::Path;
 
-pub mod cpu;
+use rust_nes::{cpu, debugger};
+use rust_nes::cpu::RunOutcome;
+use rust_nes::nes::{Region, NES};
 
 fn main() {
-    // Create the CPU object
-    let mut nes_cpu: cpu::CPU = cpu::CPU::default();
-    
-    // set up our vectors
+    // passing --debug runs the program under the interactive debugger instead of freely
+    let debug = std::env::args().any(|arg| arg == "--debug");
+
+    // the vectors used for raw programs
     const RESET: u16 = 0x0600;
     const IRQ: u16 = 0x0620;
 
@@ -23,40 +23,53 @@ fn main() {
     let mut s = String::new();
     io::stdin().read_line(&mut s).expect("Failed to read from stdin");
     let filename = format!("samples/{}", s.trim());
-    let mut file = File::open(filename).unwrap();
-    
-    // load the program into memory
-    file.read(&mut nes_cpu.memory[RESET as usize..]).unwrap();
-
-    // update the vectors
-    nes_cpu.load_vector(cpu::RESET_VECTOR, RESET);
-    nes_cpu.load_vector(cpu::IRQ_VECTOR, IRQ);
-
-    // reset the system
-    nes_cpu.reset();
-
-    // maintain an accurate speed
-    let emu_speed = cpu::NTSC_SPEED as u64; // depends on whether it is running in NTSC or PAL mode
-    let mut now = Instant::now();
-    let mut update = false;
-
-    // run the program
-    while nes_cpu.is_running() {
-        if update {
-            now = Instant::now();
-            update = false;
+
+    let mut nes = if filename.ends_with(".nes") {
+        // insert the cartridge; the CPU then finds the reset vector in the ROM itself
+        match NES::from_rom_file(Path::new(&filename), None) {
+            Ok(nes) => nes,
+            Err(e) => {
+                println!("Could not load ROM: {}", e);
+                return;
+            }
+        }
+    } else {
+        let program = match fs::read(&filename) {
+            Ok(program) => program,
+            Err(e) => {
+                println!("Could not read {}: {}", filename, e);
+                return;
+            }
+        };
+
+        // a raw binary is loaded at a fixed address, so set up our vectors to point to it
+        let mut nes_cpu: cpu::CPU = cpu::CPU::default();
+        nes_cpu.load_vector(cpu::RESET_VECTOR, RESET);
+        nes_cpu.load_vector(cpu::IRQ_VECTOR, IRQ);
+
+        // power on the system; this clears RAM, so the program is loaded afterwards
+        nes_cpu.power_on();
+        if let Err(e) = nes_cpu.load_program(RESET, &program) {
+            println!("Could not load program: {}", e);
+            return;
         }
+        NES::new(nes_cpu, Region::Ntsc)
+    };
 
-        if nes_cpu.cycle_count() < emu_speed {
-            nes_cpu.step();
-        } else {
-            println!("Cycles passed: {}", nes_cpu.cycle_count());
-            let second = Duration::new(1, 0);
-            sleep(second - now.elapsed());
-            update = true;
+    if debug {
+        println!("Commands: s (step), c (continue), b <addr> (breakpoint), m <addr> <len> (memory), r (registers), q (quit)");
+        let mut debugger = debugger::Debugger::new();
+        if let Err(e) = debugger.run(&mut nes.cpu, io::stdin().lock(), io::stdout()) {
+            println!("Debugger error: {}", e);
         }
+        nes.cpu.print_cpu_information();
+        return;
     }
 
-    // print info on exit
-    nes_cpu.print_cpu_information();
+    // run the program at the speed of the console's region until the CPU stops
+    match nes.run_realtime() {
+        RunOutcome::Halted { reason, pc } => println!("CPU halted: {} at ${:04X}", reason, pc),
+        RunOutcome::Completed => println!("CPU stopped"),
+    }
+    nes.cpu.print_cpu_information();
 }
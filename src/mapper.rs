@@ -0,0 +1,308 @@
+// mapper.rs
+// Defines the interface between the console and the circuitry on a cartridge
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::ines::NesFormat;
+use crate::state::{SaveState, StateReader, StateWriter};
+
+pub mod cnrom;
+pub mod mmc1;
+pub mod mmc3;
+pub mod nrom;
+pub mod uxrom;
+
+/// How the PPU's two physical nametables are arranged in its four logical nametable slots.
+/// On most boards this is hard-wired, but some mappers can change it at runtime.
+#[derive(PartialEq, Eq)]
+#[derive(Debug, Copy, Clone)]
+pub enum Mirroring {
+    /// The nametables are arranged side-by-side, so `0x2000` mirrors `0x2400` (for vertical scrolling)
+    Horizontal,
+    /// The nametables are stacked, so `0x2000` mirrors `0x2800` (for horizontal scrolling)
+    Vertical,
+    /// All four slots show the first physical nametable
+    SingleScreenLower,
+    /// All four slots show the second physical nametable
+    SingleScreenUpper,
+    /// The cartridge supplies extra VRAM, so all four slots are distinct
+    FourScreen,
+}
+
+impl SaveState for Mirroring {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(*self as u8);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        *self = match reader.read_u8()? {
+            0 => Mirroring::Horizontal,
+            1 => Mirroring::Vertical,
+            2 => Mirroring::SingleScreenLower,
+            3 => Mirroring::SingleScreenUpper,
+            4 => Mirroring::FourScreen,
+            value => return Err(format!("Invalid mirroring in save state: {}", value)),
+        };
+        Ok(())
+    }
+}
+
+/// A cartridge mapper.
+/// The mapper sits between the cartridge's memory and the two buses it is attached to: the CPU sees PRG ROM (and sometimes RAM) in `0x4020 - 0xFFFF`, while the PPU sees CHR ROM or RAM in `0x0000 - 0x1FFF`.
+/// Writes to ROM are how most mappers receive commands, such as bank switching.
+/// A mapper's save state holds its registers and RAM, but not its ROM, so a state can only be loaded into a mapper for the same cartridge.
+pub trait Mapper: SaveState {
+    /// Reads a byte from the CPU's view of the cartridge
+    /// Returns `None` if nothing on the cartridge responds to the address, leaving the last value on the data bus (open bus).
+    fn cpu_read(&self, address: u16) -> Option<u8>;
+
+    /// Writes a byte to the CPU's view of the cartridge
+    fn cpu_write(&mut self, address: u16, value: u8);
+
+    /// Reads a byte from the PPU's view of the cartridge (the pattern tables)
+    fn ppu_read(&self, address: u16) -> u8;
+
+    /// Writes a byte to the PPU's view of the cartridge (the pattern tables)
+    fn ppu_write(&mut self, address: u16, value: u8);
+
+    /// Returns the current nametable mirroring
+    fn mirroring(&self) -> Mirroring;
+
+    /// Returns whether the PRG RAM at `0x6000 - 0x7FFF` is write-protected
+    /// Some boards can lock their RAM through a register bit, so a game crashing doesn't corrupt its save; while this returns true, the bus drops writes to that range before they reach the mapper.
+    /// Boards without write protection always return false, which is the default.
+    fn prg_ram_write_protected(&self) -> bool {
+        false
+    }
+
+    /// Returns whether the board ignores a write to its registers at `0x8000 - 0xFFFF` made on the cycle right after another write
+    /// The MMC1 only latches one serial write per pair of consecutive write cycles, so the dummy write of a read-modify-write instruction (such as `INC $FFFF`) only counts once; the bus drops the second write for boards that return true.
+    /// Other boards see every write, which is the default.
+    fn ignores_consecutive_writes(&self) -> bool {
+        false
+    }
+
+    /// Clocks the mapper's scanline counter; the PPU calls this once per rendered scanline
+    /// Most boards have no counter, so this does nothing by default.
+    fn clock_irq(&mut self) {}
+
+    /// Returns whether the mapper is asserting the CPU's IRQ line
+    fn irq_pending(&self) -> bool {
+        false
+    }
+
+    /// Returns the PRG RAM mapped at `0x6000 - 0x7FFF`, which battery-backed cartridges keep between sessions
+    /// Boards without PRG RAM return `None`, which is the default.
+    fn prg_ram(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Returns the PRG RAM mapped at `0x6000 - 0x7FFF`, as `prg_ram` does
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+}
+
+/// The size of the CHR RAM on boards without CHR ROM
+const CHR_RAM_SIZE: usize = 0x2000;
+
+/// The memory a board wires to the PPU's pattern tables: either CHR ROM from the ROM image, or CHR RAM that the game fills in itself
+/// Mappers address it by offset, wrapping offsets past its end; writes to ROM are ignored.
+pub struct Chr {
+    memory: Vec<u8>,
+    writable: bool,
+}
+
+impl Chr {
+    /// Creates read-only CHR from the CHR ROM in a ROM image
+    pub fn rom(data: Vec<u8>) -> Chr {
+        Chr {
+            memory: data,
+            writable: false,
+        }
+    }
+
+    /// Creates `size` bytes of writable CHR RAM, cleared
+    pub fn ram(size: usize) -> Chr {
+        Chr {
+            memory: vec![0; size],
+            writable: true,
+        }
+    }
+
+    /// Returns whether the CHR is RAM rather than ROM
+    pub fn is_ram(&self) -> bool {
+        self.writable
+    }
+
+    /// Returns the size of the CHR, in bytes
+    pub fn len(&self) -> usize {
+        self.memory.len()
+    }
+
+    /// Returns whether there is no CHR at all
+    pub fn is_empty(&self) -> bool {
+        self.memory.is_empty()
+    }
+
+    /// Reads the byte at `offset`, which wraps around the end of the CHR
+    pub fn read(&self, offset: usize) -> u8 {
+        if self.memory.is_empty() {
+            0
+        } else {
+            self.memory[offset % self.memory.len()]
+        }
+    }
+
+    /// Writes the byte at `offset`, which wraps around the end of the CHR, if it is RAM
+    pub fn write(&mut self, offset: usize, value: u8) {
+        if self.writable && !self.memory.is_empty() {
+            let index = offset % self.memory.len();
+            self.memory[index] = value;
+        }
+    }
+}
+
+/// Only CHR RAM is saved, since CHR ROM comes from the ROM image
+impl SaveState for Chr {
+    fn save_state(&self, writer: &mut StateWriter) {
+        if self.writable {
+            writer.write_bytes(&self.memory);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        if self.writable {
+            reader.read_into(&mut self.memory)?;
+        }
+        Ok(())
+    }
+}
+
+/// A mapper shared between the CPU's bus and the PPU, both of which are wired to the cartridge
+pub type SharedMapper = Rc<RefCell<Box<dyn Mapper>>>;
+
+/// The size of the PRG RAM that battery-backed NROM boards map at `0x6000 - 0x7FFF`
+const NROM_PRG_RAM_SIZE: usize = 0x2000;
+
+/// The trainer is loaded at `0x7000`, which is this far into the PRG RAM
+const TRAINER_OFFSET: usize = 0x1000;
+
+/// Constructs the mapper for the board described by `header`, from the PRG and CHR data in the ROM image.
+/// When the image has no CHR ROM, the board is given 8KB of CHR RAM instead.
+/// Returns an error if the mapper isn't supported.
+pub fn create_mapper(header: &NesFormat, prg: Vec<u8>, chr: Vec<u8>) -> Result<Box<dyn Mapper>, String> {
+    let mirroring = header.nametable_mirroring();
+    let chr = if chr.is_empty() { Chr::ram(CHR_RAM_SIZE) } else { Chr::rom(chr) };
+    let mapper: Box<dyn Mapper> = match header.mapper_number {
+        // the few NROM games with PRG RAM (such as Family BASIC) keep it battery-backed, and dumps with trainers need RAM to hold them
+        0 if header.battery_memory_present || header.trainer_present => Box::new(nrom::Nrom::with_prg_ram(prg, chr, mirroring, NROM_PRG_RAM_SIZE)),
+        0 => Box::new(nrom::Nrom::new(prg, chr, mirroring)),
+        1 => Box::new(mmc1::Mmc1::new(prg, chr)),
+        2 => Box::new(uxrom::Uxrom::new(prg, chr, mirroring)),
+        3 => Box::new(cnrom::Cnrom::new(prg, chr, mirroring)),
+        4 => Box::new(mmc3::Mmc3::new(prg, chr, mirroring)),
+        n => return Err(format!("Unsupported mapper: {}", n)),
+    };
+    Ok(mapper)
+}
+
+/// Copies a ROM image's trainer into the mapper's PRG RAM at `0x7000 - 0x71FF`, where the code in it expects to run from
+/// Returns an error if the board has no PRG RAM to hold it.
+pub fn load_trainer(mapper: &mut dyn Mapper, trainer: &[u8]) -> Result<(), String> {
+    match mapper.prg_ram_mut() {
+        Some(ram) if ram.len() >= TRAINER_OFFSET + trainer.len() => {
+            ram[TRAINER_OFFSET..TRAINER_OFFSET + trainer.len()].copy_from_slice(trainer);
+            Ok(())
+        },
+        _ => Err(String::from("The ROM has a trainer, but the board has no PRG RAM at 0x7000 to load it into")),
+    }
+}
+
+/// Creates a ROM image of `size` bytes in which each byte holds the number of the 1KB block it is in, so reads through a mapper show which part of the ROM is mapped
+#[cfg(test)]
+pub(crate) fn numbered_rom(size: usize) -> Vec<u8> {
+    (0..size).map(|i| (i / 0x400) as u8).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::Region;
+
+    /// Describes a board with the given mapper number, 32KB of PRG ROM, 8KB of CHR ROM, and the given mirroring
+    fn header(mapper_number: u16, mirroring: Mirroring, four_screen_mode: bool) -> NesFormat {
+        NesFormat {
+            prg_rom_size: 0x8000,
+            chr_rom_size: 0x2000,
+            mapper_number,
+            submapper_number: 0,
+            mirroring,
+            battery_memory_present: false,
+            trainer_present: false,
+            four_screen_mode,
+            nes2: false,
+            prg_ram_shift_count: 0,
+            prg_nvram_shift_count: 0,
+            chr_ram_shift_count: 0,
+            chr_nvram_shift_count: 0,
+            timing: Region::Ntsc,
+        }
+    }
+
+    #[test]
+    fn creates_each_supported_mapper() {
+        for mapper_number in 0..=4 {
+            let mapper = create_mapper(&header(mapper_number, Mirroring::Vertical, false), numbered_rom(0x8000), numbered_rom(0x2000)).unwrap();
+            // every board powers up with the end of the PRG ROM at the top of memory, for the vectors
+            assert_eq!(mapper.cpu_read(0xffff), Some(31), "mapper {}", mapper_number);
+            assert_eq!(mapper.ppu_read(0x0400), 1, "mapper {}", mapper_number);
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_mapper() {
+        assert!(create_mapper(&header(5, Mirroring::Horizontal, false), numbered_rom(0x8000), numbered_rom(0x2000)).is_err());
+    }
+
+    #[test]
+    fn wires_up_mirroring() {
+        let mapper = create_mapper(&header(0, Mirroring::Vertical, false), numbered_rom(0x8000), numbered_rom(0x2000)).unwrap();
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+        let mapper = create_mapper(&header(0, Mirroring::Horizontal, true), numbered_rom(0x8000), numbered_rom(0x2000)).unwrap();
+        assert_eq!(mapper.mirroring(), Mirroring::FourScreen);
+    }
+
+    #[test]
+    fn battery_backed_nrom_has_prg_ram() {
+        let mapper = create_mapper(&header(0, Mirroring::Vertical, false), numbered_rom(0x8000), numbered_rom(0x2000)).unwrap();
+        assert!(mapper.prg_ram().is_none());
+        let battery_backed = NesFormat {
+            battery_memory_present: true,
+            ..header(0, Mirroring::Vertical, false)
+        };
+        let mapper = create_mapper(&battery_backed, numbered_rom(0x8000), numbered_rom(0x2000)).unwrap();
+        assert_eq!(mapper.prg_ram().map(|ram| ram.len()), Some(0x2000));
+    }
+
+    #[test]
+    fn missing_chr_rom_gets_chr_ram() {
+        for mapper_number in 0..=4 {
+            let mut mapper = create_mapper(&header(mapper_number, Mirroring::Vertical, false), numbered_rom(0x8000), Vec::new()).unwrap();
+            mapper.ppu_write(0x0010, 0x77);
+            mapper.ppu_write(0x1ff0, 0x88);
+            assert_eq!(mapper.ppu_read(0x0010), 0x77, "mapper {}", mapper_number);
+            assert_eq!(mapper.ppu_read(0x1ff0), 0x88, "mapper {}", mapper_number);
+        }
+    }
+
+    #[test]
+    fn chr_rom_ignores_writes() {
+        for mapper_number in 0..=4 {
+            let mut mapper = create_mapper(&header(mapper_number, Mirroring::Vertical, false), numbered_rom(0x8000), numbered_rom(0x2000)).unwrap();
+            mapper.ppu_write(0x0010, 0x77);
+            assert_eq!(mapper.ppu_read(0x0010), 0, "mapper {}", mapper_number);
+        }
+    }
+}
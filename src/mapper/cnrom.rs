@@ -0,0 +1,101 @@
+// cnrom.rs
+// Implements CNROM (mapper 3), which switches CHR ROM in 8KB banks
+
+use super::{Chr, Mapper, Mirroring};
+use crate::state::{SaveState, StateReader, StateWriter};
+
+/// The size of a switchable CHR bank
+const CHR_BANK_SIZE: usize = 0x2000;
+
+/// CNROM has a single register, written anywhere in `0x8000 - 0xFFFF`, that selects one of up to four 8KB CHR banks.
+/// The PRG ROM is fixed, exactly as on NROM.
+pub struct Cnrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    chr_bank: u8,
+}
+
+impl Cnrom {
+    /// Creates a new CNROM board from the cartridge's PRG ROM and its CHR ROM or RAM
+    pub fn new(prg_rom: Vec<u8>, chr: Chr, mirroring: Mirroring) -> Cnrom {
+        Cnrom {
+            prg_rom,
+            chr,
+            mirroring,
+            chr_bank: 0,
+        }
+    }
+
+    /// Returns the number of 8KB CHR banks on the board
+    fn chr_banks(&self) -> usize {
+        (self.chr.len() / CHR_BANK_SIZE).max(1)
+    }
+
+    /// Returns the offset into the CHR of the given pattern table address, in the selected bank
+    fn chr_offset(&self, address: u16) -> usize {
+        let bank = self.chr_bank as usize % self.chr_banks();
+        bank * CHR_BANK_SIZE + (address & 0x1fff) as usize
+    }
+}
+
+impl Mapper for Cnrom {
+    fn cpu_read(&self, address: u16) -> Option<u8> {
+        if address >= 0x8000 && !self.prg_rom.is_empty() {
+            // a 16KB ROM is mirrored, so 0x8000 and 0xC000 alias
+            Some(self.prg_rom[(address - 0x8000) as usize % self.prg_rom.len()])
+        } else {
+            None
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if address >= 0x8000 {
+            self.chr_bank = value & 0x03;
+        }
+    }
+
+    fn ppu_read(&self, address: u16) -> u8 {
+        self.chr.read(self.chr_offset(address))
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        let offset = self.chr_offset(address);
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+impl SaveState for Cnrom {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.chr_bank);
+        self.chr.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.chr_bank = reader.read_u8()?;
+        self.chr.load_state(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::numbered_rom;
+
+    #[test]
+    fn write_selects_chr_bank() {
+        let mut cnrom = Cnrom::new(numbered_rom(0x8000), Chr::rom(numbered_rom(0x8000)), Mirroring::Horizontal);
+        assert_eq!(cnrom.ppu_read(0x0000), 0);
+
+        cnrom.cpu_write(0x8000, 2);
+        assert_eq!(cnrom.ppu_read(0x0000), 2 * 8);
+        assert_eq!(cnrom.ppu_read(0x1fff), 2 * 8 + 7);
+        // the PRG ROM is unaffected
+        assert_eq!(cnrom.cpu_read(0x8000), Some(0));
+        assert_eq!(cnrom.cpu_read(0xc000), Some(16));
+    }
+}
@@ -0,0 +1,350 @@
+// mmc1.rs
+// Implements the MMC1 (mapper 1), used by many early games beyond the simplest
+
+use super::{Chr, Mapper, Mirroring};
+use crate::state::{SaveState, StateReader, StateWriter};
+
+/// The size of a switchable PRG ROM bank
+const PRG_BANK_SIZE: usize = 0x4000;
+/// The size of a switchable CHR bank
+const CHR_BANK_SIZE: usize = 0x1000;
+/// The size of the PRG RAM at `0x6000 - 0x7FFF`
+const PRG_RAM_SIZE: usize = 0x2000;
+
+/// The value of the shift register when it is empty; the 1 marks when five bits have been shifted in
+const SHIFT_RESET: u8 = 0x10;
+
+/// The MMC1 is configured through a 5-bit serial port.
+/// Each write to `0x8000 - 0xFFFF` shifts bit 0 of the value into a shift register; on the fifth write, the accumulated value is copied into one of four internal registers, selected by bits 13 and 14 of the address of that write:
+/// * `0x8000 - 0x9FFF`: control (mirroring, PRG banking mode, and CHR banking mode)
+/// * `0xA000 - 0xBFFF`: CHR bank 0
+/// * `0xC000 - 0xDFFF`: CHR bank 1
+/// * `0xE000 - 0xFFFF`: PRG bank, along with the PRG RAM enable bit
+///
+/// Writing a value with bit 7 set resets the shift register and locks the last PRG bank at `0xC000`.
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    prg_ram: Vec<u8>,
+
+    shift: u8,
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+}
+
+impl Mmc1 {
+    /// Creates a new MMC1 board from the cartridge's PRG ROM and its CHR ROM or RAM
+    pub fn new(prg_rom: Vec<u8>, chr: Chr) -> Mmc1 {
+        Mmc1 {
+            prg_rom,
+            chr,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            shift: SHIFT_RESET,
+            control: 0x0c,  // the MMC1 powers up with the last bank fixed at 0xC000
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+        }
+    }
+
+    /// Returns whether the PRG RAM can be written; bit 4 of the PRG bank register disables it
+    pub fn prg_ram_enabled(&self) -> bool {
+        (self.prg_bank & 0x10) == 0
+    }
+
+    /// Writes the serial port, loading an internal register on every fifth write
+    fn write_serial(&mut self, address: u16, value: u8) {
+        if (value & 0x80) != 0 {
+            self.shift = SHIFT_RESET;
+            self.control |= 0x0c;
+            return;
+        }
+
+        // the register is full once the marker bit reaches bit 0
+        let complete = (self.shift & 1) != 0;
+        self.shift = (self.shift >> 1) | ((value & 1) << 4);
+        if complete {
+            let data = self.shift;
+            match address & 0x6000 {
+                0x0000 => self.control = data,
+                0x2000 => self.chr_bank_0 = data,
+                0x4000 => self.chr_bank_1 = data,
+                _ => self.prg_bank = data,
+            }
+            self.shift = SHIFT_RESET;
+        }
+    }
+
+    /// Returns the index of the PRG bank mapped to the given address in `0x8000 - 0xFFFF`
+    fn prg_bank_for(&self, address: u16) -> usize {
+        let bank = (self.prg_bank & 0x0f) as usize;
+        let last = (self.prg_rom.len() / PRG_BANK_SIZE).saturating_sub(1);
+        let upper = address >= 0xc000;
+        match (self.control >> 2) & 0x03 {
+            // switch 32KB at a time, ignoring the low bit of the bank number
+            0 | 1 => (bank & !1) | upper as usize,
+            // fix the first bank at 0x8000, switch 0xC000
+            2 => if upper { bank } else { 0 },
+            // fix the last bank at 0xC000, switch 0x8000
+            _ => if upper { last } else { bank },
+        }
+    }
+
+    /// Returns the index of the CHR bank mapped to the given pattern table address
+    fn chr_bank_for(&self, address: u16) -> usize {
+        let upper = (address & 0x1000) != 0;
+        if (self.control & 0x10) == 0 {
+            // switch 8KB at a time, ignoring the low bit of the bank number
+            ((self.chr_bank_0 & !1) as usize) | upper as usize
+        } else if upper {
+            self.chr_bank_1 as usize
+        } else {
+            self.chr_bank_0 as usize
+        }
+    }
+
+    /// Returns the offset into the CHR of the given pattern table address, in the bank mapped there
+    fn chr_offset(&self, address: u16) -> usize {
+        self.chr_bank_for(address) * CHR_BANK_SIZE + (address as usize & (CHR_BANK_SIZE - 1))
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_read(&self, address: u16) -> Option<u8> {
+        if address >= 0x8000 && !self.prg_rom.is_empty() {
+            let offset = self.prg_bank_for(address) * PRG_BANK_SIZE + (address as usize & (PRG_BANK_SIZE - 1));
+            Some(self.prg_rom[offset % self.prg_rom.len()])
+        } else if address >= 0x6000 {
+            Some(self.prg_ram[(address - 0x6000) as usize])
+        } else {
+            None
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if address >= 0x8000 {
+            self.write_serial(address, value);
+        } else if address >= 0x6000 {
+            self.prg_ram[(address - 0x6000) as usize] = value;
+        }
+    }
+
+    fn ppu_read(&self, address: u16) -> u8 {
+        self.chr.read(self.chr_offset(address))
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        let offset = self.chr_offset(address);
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0x03 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn prg_ram_write_protected(&self) -> bool {
+        !self.prg_ram_enabled()
+    }
+
+    fn ignores_consecutive_writes(&self) -> bool {
+        true
+    }
+}
+
+impl SaveState for Mmc1 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.prg_ram);
+        writer.write_u8(self.shift);
+        writer.write_u8(self.control);
+        writer.write_u8(self.chr_bank_0);
+        writer.write_u8(self.chr_bank_1);
+        writer.write_u8(self.prg_bank);
+        self.chr.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        reader.read_into(&mut self.prg_ram)?;
+        self.shift = reader.read_u8()?;
+        self.control = reader.read_u8()?;
+        self.chr_bank_0 = reader.read_u8()?;
+        self.chr_bank_1 = reader.read_u8()?;
+        self.prg_bank = reader.read_u8()?;
+        self.chr.load_state(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CPU;
+    use crate::mapper::numbered_rom;
+    use crate::mem::{Bus, Mem};
+
+    /// Creates an MMC1 board with 128KB of PRG ROM and 32KB of CHR ROM, each numbered by 1KB block
+    fn numbered_mmc1() -> Mmc1 {
+        Mmc1::new(numbered_rom(0x20000), Chr::rom(numbered_rom(0x8000)))
+    }
+
+    /// Loads `value` into one of the board's registers directly, as five serial writes
+    fn load_register(mmc1: &mut Mmc1, address: u16, value: u8) {
+        for bit in 0..5 {
+            mmc1.cpu_write(address, (value >> bit) & 1);
+        }
+    }
+
+    #[test]
+    fn powers_up_with_last_bank_fixed() {
+        let mmc1 = numbered_mmc1();
+        assert_eq!(mmc1.cpu_read(0x8000), Some(0));
+        assert_eq!(mmc1.cpu_read(0xc000), Some(7 * 16));
+    }
+
+    #[test]
+    fn five_writes_select_prg_bank() {
+        let mut mmc1 = numbered_mmc1();
+        load_register(&mut mmc1, 0xe000, 0x03);
+        assert_eq!(mmc1.cpu_read(0x8000), Some(3 * 16));
+        assert_eq!(mmc1.cpu_read(0xc000), Some(7 * 16));
+
+        // four writes aren't enough to load a register
+        for _ in 0..4 {
+            mmc1.cpu_write(0xe000, 1);
+        }
+        assert_eq!(mmc1.cpu_read(0x8000), Some(3 * 16));
+    }
+
+    #[test]
+    fn reset_bit_clears_shift_register() {
+        let mut mmc1 = numbered_mmc1();
+        mmc1.cpu_write(0xe000, 1);
+        mmc1.cpu_write(0xe000, 1);
+        mmc1.cpu_write(0x8000, 0x80);
+        load_register(&mut mmc1, 0xe000, 0x02);
+        assert_eq!(mmc1.cpu_read(0x8000), Some(2 * 16));
+    }
+
+    #[test]
+    fn prg_banking_modes() {
+        let mut mmc1 = numbered_mmc1();
+        load_register(&mut mmc1, 0xe000, 0x05);
+
+        // fix the first bank at 0x8000
+        load_register(&mut mmc1, 0x8000, 0x08);
+        assert_eq!(mmc1.cpu_read(0x8000), Some(0));
+        assert_eq!(mmc1.cpu_read(0xc000), Some(5 * 16));
+
+        // switch 32KB, ignoring the low bit of the bank
+        load_register(&mut mmc1, 0x8000, 0x00);
+        assert_eq!(mmc1.cpu_read(0x8000), Some(4 * 16));
+        assert_eq!(mmc1.cpu_read(0xc000), Some(5 * 16));
+    }
+
+    #[test]
+    fn chr_banking_modes() {
+        let mut mmc1 = numbered_mmc1();
+        load_register(&mut mmc1, 0xa000, 0x03);
+        load_register(&mut mmc1, 0xc000, 0x05);
+
+        // 8KB mode uses CHR bank 0 without its low bit
+        assert_eq!(mmc1.ppu_read(0x0000), 2 * 4);
+        assert_eq!(mmc1.ppu_read(0x1000), 3 * 4);
+
+        // 4KB mode switches each half separately
+        load_register(&mut mmc1, 0x8000, 0x10);
+        assert_eq!(mmc1.ppu_read(0x0000), 3 * 4);
+        assert_eq!(mmc1.ppu_read(0x1000), 5 * 4);
+    }
+
+    #[test]
+    fn control_selects_mirroring() {
+        let mut mmc1 = numbered_mmc1();
+        for (value, mirroring) in [
+            (0, Mirroring::SingleScreenLower),
+            (1, Mirroring::SingleScreenUpper),
+            (2, Mirroring::Vertical),
+            (3, Mirroring::Horizontal),
+        ] {
+            load_register(&mut mmc1, 0x8000, 0x0c | value);
+            assert_eq!(mmc1.mirroring(), mirroring);
+        }
+    }
+
+    /// Creates a bus with an MMC1 board inserted, with 32KB of PRG ROM and 8KB of CHR ROM
+    fn bus_with_mmc1() -> Bus {
+        let mut bus = Bus::default();
+        bus.set_mapper(Box::new(Mmc1::new(vec![0; 0x8000], Chr::rom(vec![0; 0x2000]))));
+        bus
+    }
+
+    /// Loads `value` into the register selected by `address`, shifting it in a bit at a time through the serial port
+    /// Each write follows a read, as it would in a program, since the board ignores writes on consecutive cycles.
+    fn write_register(bus: &mut Bus, address: u16, value: u8) {
+        for bit in 0..5 {
+            bus.read(address);
+            bus.write(address, (value >> bit) & 1);
+        }
+    }
+
+    #[test]
+    fn write_protect_drops_prg_ram_writes() {
+        let mut bus = bus_with_mmc1();
+        bus.write(0x6000, 0x11);
+        assert_eq!(bus.read(0x6000), 0x11);
+
+        // bit 4 of the PRG bank register disables the RAM
+        write_register(&mut bus, 0xe000, 0x10);
+        bus.write(0x6000, 0x22);
+        assert_eq!(bus.read(0x6000), 0x11);
+
+        write_register(&mut bus, 0xe000, 0x00);
+        bus.write(0x6000, 0x33);
+        assert_eq!(bus.read(0x6000), 0x33);
+    }
+
+    #[test]
+    fn rmw_dummy_write_is_the_only_one_latched() {
+        // INC $FFFF writes the old value ($01, a one bit) and then the new one ($02, a zero bit) on back-to-back cycles
+        let mut prg = vec![0; 0x8000];
+        prg[0x7fff] = 0x01;
+        let mut bus = Bus::default();
+        bus.set_mapper(Box::new(Mmc1::new(prg, Chr::rom(vec![0; 0x2000]))));
+        let mut cpu = CPU::with_memory(bus);
+        cpu.load_program(0x0600, &[0xee, 0xff, 0xff].repeat(5)).unwrap();
+        cpu.set_pc(0x0600);
+
+        for _ in 0..5 {
+            cpu.step();
+        }
+
+        // only the five ones were shifted in, loading $1F into the PRG bank register, whose bit 4 protects the RAM
+        assert!(cpu.bus().mapper().unwrap().prg_ram_write_protected());
+    }
+
+    #[test]
+    fn write_after_a_read_is_latched() {
+        let mut bus = bus_with_mmc1();
+        bus.write(0xe000, 1);
+        // this write comes right after the last, so it's ignored
+        bus.write(0xe000, 0);
+        for _ in 0..4 {
+            bus.read(0xe000);
+            bus.write(0xe000, 1);
+        }
+        assert!(bus.mapper().unwrap().prg_ram_write_protected());
+    }
+}
@@ -0,0 +1,300 @@
+// mmc3.rs
+// Implements the MMC3 (mapper 4), which adds a scanline counter that can interrupt the CPU
+
+use super::{Chr, Mapper, Mirroring};
+use crate::state::{SaveState, StateReader, StateWriter};
+
+/// The size of a switchable PRG ROM bank
+const PRG_BANK_SIZE: usize = 0x2000;
+/// The size of a switchable CHR bank
+const CHR_BANK_SIZE: usize = 0x0400;
+/// The size of the PRG RAM at `0x6000 - 0x7FFF`
+const PRG_RAM_SIZE: usize = 0x2000;
+
+/// The MMC3 has eight bank registers, `R0 - R7`, written through a pair of ports.
+/// A write to an even address in `0x8000 - 0x9FFF` selects which register the next write to an odd address loads; the rest of `0x8000 - 0xFFFF` is split the same way, with even and odd addresses in each 8KB range being separate registers:
+/// * `0x8000`/`0x8001`: bank select and bank data
+/// * `0xA000`/`0xA001`: mirroring and PRG RAM protection
+/// * `0xC000`/`0xC001`: IRQ latch and IRQ reload
+/// * `0xE000`/`0xE001`: IRQ disable and IRQ enable
+///
+/// PRG is switched in 8KB banks: `R6` and `R7` are switchable, and the last two banks are fixed, with bit 6 of the bank select swapping `R6` with the second-to-last bank.
+/// CHR is switched as two 2KB banks (`R0`, `R1`) and four 1KB banks (`R2 - R5`), with bit 7 of the bank select swapping the two pattern tables.
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    prg_ram: Vec<u8>,
+
+    bank_select: u8,
+    banks: [u8; 8],
+    mirroring: Mirroring,
+    four_screen: bool,
+    prg_ram_enabled: bool,
+    prg_ram_protected: bool,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+impl Mmc3 {
+    /// Creates a new MMC3 board from the cartridge's PRG ROM and its CHR ROM or RAM
+    /// If `mirroring` is `FourScreen`, the board has its own VRAM and the mirroring register is ignored.
+    pub fn new(prg_rom: Vec<u8>, chr: Chr, mirroring: Mirroring) -> Mmc3 {
+        Mmc3 {
+            prg_rom,
+            chr,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            bank_select: 0,
+            banks: [0; 8],
+            mirroring,
+            four_screen: mirroring == Mirroring::FourScreen,
+            prg_ram_enabled: true,
+            prg_ram_protected: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+
+    /// Returns the index of the 8KB PRG bank mapped to the given address in `0x8000 - 0xFFFF`
+    fn prg_bank_for(&self, address: u16) -> usize {
+        let count = (self.prg_rom.len() / PRG_BANK_SIZE).max(2);
+        let second_last = count - 2;
+        let swapped = (self.bank_select & 0x40) != 0;
+        match (address >> 13) & 0x03 {
+            0 => if swapped { second_last } else { self.banks[6] as usize },
+            1 => self.banks[7] as usize,
+            2 => if swapped { self.banks[6] as usize } else { second_last },
+            _ => count - 1,
+        }
+    }
+
+    /// Returns the index of the 1KB CHR bank mapped to the given pattern table address
+    fn chr_bank_for(&self, address: u16) -> usize {
+        // with the inversion bit set, the 2KB banks move to 0x1000 and the 1KB banks to 0x0000
+        let address = if (self.bank_select & 0x80) != 0 { address ^ 0x1000 } else { address };
+        let slot = ((address >> 10) & 0x07) as usize;
+        match slot {
+            0 | 1 => (self.banks[0] & !1) as usize | slot,
+            2 | 3 => (self.banks[1] & !1) as usize | (slot & 1),
+            _ => self.banks[slot - 2] as usize,
+        }
+    }
+
+    /// Returns the offset into the CHR of the given pattern table address, in the bank mapped there
+    fn chr_offset(&self, address: u16) -> usize {
+        self.chr_bank_for(address) * CHR_BANK_SIZE + (address as usize & (CHR_BANK_SIZE - 1))
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_read(&self, address: u16) -> Option<u8> {
+        if address >= 0x8000 && !self.prg_rom.is_empty() {
+            let offset = self.prg_bank_for(address) * PRG_BANK_SIZE + (address as usize & (PRG_BANK_SIZE - 1));
+            Some(self.prg_rom[offset % self.prg_rom.len()])
+        } else if address >= 0x6000 && self.prg_ram_enabled {
+            Some(self.prg_ram[(address - 0x6000) as usize])
+        } else {
+            None
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if address >= 0x8000 {
+            let odd = (address & 1) != 0;
+            match (address & 0x6000, odd) {
+                (0x0000, false) => self.bank_select = value,
+                (0x0000, true) => self.banks[(self.bank_select & 0x07) as usize] = value,
+                (0x2000, false) => {
+                    if !self.four_screen {
+                        self.mirroring = if (value & 1) == 0 { Mirroring::Vertical } else { Mirroring::Horizontal };
+                    }
+                },
+                (0x2000, true) => {
+                    self.prg_ram_enabled = (value & 0x80) != 0;
+                    self.prg_ram_protected = (value & 0x40) != 0;
+                },
+                (0x4000, false) => self.irq_latch = value,
+                (0x4000, true) => {
+                    self.irq_counter = 0;
+                    self.irq_reload = true;
+                },
+                (_, false) => {
+                    // disabling the IRQ also acknowledges any pending one
+                    self.irq_enabled = false;
+                    self.irq_pending = false;
+                },
+                (_, true) => self.irq_enabled = true,
+            }
+        } else if address >= 0x6000 {
+            self.prg_ram[(address - 0x6000) as usize] = value;
+        }
+    }
+
+    fn ppu_read(&self, address: u16) -> u8 {
+        self.chr.read(self.chr_offset(address))
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        let offset = self.chr_offset(address);
+        self.chr.write(offset, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    /// The counter is reloaded from the latch when it is zero (or a reload was requested), and is decremented otherwise.
+    /// Reaching zero while IRQs are enabled asserts the IRQ line.
+    fn clock_irq(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    /// Writes are only allowed when the RAM is both enabled and not protected
+    fn prg_ram_write_protected(&self) -> bool {
+        !self.prg_ram_enabled || self.prg_ram_protected
+    }
+}
+
+impl SaveState for Mmc3 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.prg_ram);
+        writer.write_u8(self.bank_select);
+        writer.write_bytes(&self.banks);
+        self.mirroring.save_state(writer);
+        writer.write_bool(self.prg_ram_enabled);
+        writer.write_bool(self.prg_ram_protected);
+        writer.write_u8(self.irq_latch);
+        writer.write_u8(self.irq_counter);
+        writer.write_bool(self.irq_reload);
+        writer.write_bool(self.irq_enabled);
+        writer.write_bool(self.irq_pending);
+        self.chr.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        reader.read_into(&mut self.prg_ram)?;
+        self.bank_select = reader.read_u8()?;
+        reader.read_into(&mut self.banks)?;
+        self.mirroring.load_state(reader)?;
+        self.prg_ram_enabled = reader.read_bool()?;
+        self.prg_ram_protected = reader.read_bool()?;
+        self.irq_latch = reader.read_u8()?;
+        self.irq_counter = reader.read_u8()?;
+        self.irq_reload = reader.read_bool()?;
+        self.irq_enabled = reader.read_bool()?;
+        self.irq_pending = reader.read_bool()?;
+        self.chr.load_state(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::numbered_rom;
+
+    /// Creates an MMC3 board with the IRQ latch loaded with `latch`, a reload requested, and IRQs enabled
+    fn mmc3_with_irq(latch: u8) -> Mmc3 {
+        let mut mmc3 = Mmc3::new(numbered_rom(0x20000), Chr::ram(0x2000), Mirroring::Vertical);
+        mmc3.cpu_write(0xc000, latch);
+        mmc3.cpu_write(0xc001, 0);
+        mmc3.cpu_write(0xe001, 0);
+        mmc3
+    }
+
+    #[test]
+    fn irq_counter_counts_down_from_latch() {
+        let mut mmc3 = mmc3_with_irq(3);
+        // the first clock reloads the counter, and each one after decrements it
+        for _ in 0..3 {
+            mmc3.clock_irq();
+            assert!(!mmc3.irq_pending());
+        }
+        mmc3.clock_irq();
+        assert!(mmc3.irq_pending());
+
+        // disabling acknowledges the IRQ
+        mmc3.cpu_write(0xe000, 0);
+        assert!(!mmc3.irq_pending());
+    }
+
+    #[test]
+    fn irq_counter_reloads_after_reaching_zero() {
+        let mut mmc3 = mmc3_with_irq(2);
+        for _ in 0..3 {
+            mmc3.clock_irq();
+        }
+        assert!(mmc3.irq_pending());
+        mmc3.cpu_write(0xe000, 0);
+        mmc3.cpu_write(0xe001, 0);
+        for _ in 0..2 {
+            mmc3.clock_irq();
+            assert!(!mmc3.irq_pending());
+        }
+        mmc3.clock_irq();
+        assert!(mmc3.irq_pending());
+    }
+
+    #[test]
+    fn disabled_irq_is_not_asserted() {
+        let mut mmc3 = mmc3_with_irq(1);
+        mmc3.cpu_write(0xe000, 0);
+        for _ in 0..4 {
+            mmc3.clock_irq();
+        }
+        assert!(!mmc3.irq_pending());
+    }
+
+    #[test]
+    fn bank_select_swaps_prg_banks() {
+        let mut mmc3 = Mmc3::new(numbered_rom(0x20000), Chr::ram(0x2000), Mirroring::Vertical);
+        mmc3.cpu_write(0x8000, 6);
+        mmc3.cpu_write(0x8001, 3);
+        assert_eq!(mmc3.cpu_read(0x8000), Some(3 * 8));
+        assert_eq!(mmc3.cpu_read(0xc000), Some(14 * 8));
+        assert_eq!(mmc3.cpu_read(0xe000), Some(15 * 8));
+
+        // bit 6 swaps R6 with the second-to-last bank
+        mmc3.cpu_write(0x8000, 0x46);
+        assert_eq!(mmc3.cpu_read(0x8000), Some(14 * 8));
+        assert_eq!(mmc3.cpu_read(0xc000), Some(3 * 8));
+    }
+
+    #[test]
+    fn prg_ram_protection_is_reported_to_the_bus() {
+        let mut mmc3 = Mmc3::new(numbered_rom(0x20000), Chr::ram(0x2000), Mirroring::Vertical);
+        assert!(!mmc3.prg_ram_write_protected());
+        // enabled but protected
+        mmc3.cpu_write(0xa001, 0xc0);
+        assert!(mmc3.prg_ram_write_protected());
+        // disabled
+        mmc3.cpu_write(0xa001, 0x00);
+        assert!(mmc3.prg_ram_write_protected());
+    }
+}
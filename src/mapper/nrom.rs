@@ -0,0 +1,140 @@
+// nrom.rs
+// Implements NROM (mapper 0), the board used by the earliest cartridges
+
+use super::{Chr, Mapper, Mirroring};
+use crate::state::{SaveState, StateReader, StateWriter};
+
+/// NROM has no bank switching at all.
+/// 16KB or 32KB of PRG ROM is mapped to `0x8000 - 0xFFFF`, with a 16KB ROM mirrored into both halves, and 8KB of CHR ROM (or RAM) is mapped into the pattern tables.
+/// A few boards also have PRG RAM at `0x6000 - 0x7FFF`.
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    // empty on boards without PRG RAM
+    prg_ram: Vec<u8>,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    /// Creates a new NROM board from the cartridge's PRG ROM and its CHR ROM or RAM
+    pub fn new(prg_rom: Vec<u8>, chr: Chr, mirroring: Mirroring) -> Nrom {
+        Nrom::with_prg_ram(prg_rom, chr, mirroring, 0)
+    }
+
+    /// Creates a new NROM board with `prg_ram_size` bytes of PRG RAM, mirrored through `0x6000 - 0x7FFF`
+    pub fn with_prg_ram(prg_rom: Vec<u8>, chr: Chr, mirroring: Mirroring, prg_ram_size: usize) -> Nrom {
+        Nrom {
+            prg_rom,
+            chr,
+            prg_ram: vec![0; prg_ram_size],
+            mirroring,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_read(&self, address: u16) -> Option<u8> {
+        if address >= 0x8000 && !self.prg_rom.is_empty() {
+            // a 16KB ROM is mirrored, so 0x8000 and 0xC000 alias
+            Some(self.prg_rom[(address - 0x8000) as usize % self.prg_rom.len()])
+        } else if (0x6000..0x8000).contains(&address) && !self.prg_ram.is_empty() {
+            Some(self.prg_ram[(address - 0x6000) as usize % self.prg_ram.len()])
+        } else {
+            None
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        // NROM has no registers, and ROM can't be written
+        if (0x6000..0x8000).contains(&address) && !self.prg_ram.is_empty() {
+            let index = (address - 0x6000) as usize % self.prg_ram.len();
+            self.prg_ram[index] = value;
+        }
+    }
+
+    fn ppu_read(&self, address: u16) -> u8 {
+        self.chr.read((address & 0x1fff) as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write((address & 0x1fff) as usize, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        if self.prg_ram.is_empty() {
+            None
+        } else {
+            Some(&self.prg_ram)
+        }
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        if self.prg_ram.is_empty() {
+            None
+        } else {
+            Some(&mut self.prg_ram)
+        }
+    }
+}
+
+impl SaveState for Nrom {
+    fn save_state(&self, writer: &mut StateWriter) {
+        // NROM has no registers; everything is hard-wired, so only the RAM (if any) changes
+        writer.write_bytes(&self.prg_ram);
+        self.chr.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        reader.read_into(&mut self.prg_ram)?;
+        self.chr.load_state(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::numbered_rom;
+
+    #[test]
+    fn prg_16k_is_mirrored() {
+        let nrom = Nrom::new(numbered_rom(0x4000), Chr::rom(numbered_rom(0x2000)), Mirroring::Horizontal);
+        assert_eq!(nrom.cpu_read(0x8000), Some(0));
+        assert_eq!(nrom.cpu_read(0xc000), Some(0));
+        assert_eq!(nrom.cpu_read(0xbfff), Some(15));
+        assert_eq!(nrom.cpu_read(0xffff), Some(15));
+    }
+
+    #[test]
+    fn prg_32k_fills_both_halves() {
+        let nrom = Nrom::new(numbered_rom(0x8000), Chr::rom(numbered_rom(0x2000)), Mirroring::Vertical);
+        assert_eq!(nrom.cpu_read(0x8000), Some(0));
+        assert_eq!(nrom.cpu_read(0xc000), Some(16));
+        assert_eq!(nrom.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn chr_rom_is_mapped_to_the_pattern_tables() {
+        let nrom = Nrom::new(numbered_rom(0x4000), Chr::rom(numbered_rom(0x2000)), Mirroring::Horizontal);
+        assert_eq!(nrom.ppu_read(0x0000), 0);
+        assert_eq!(nrom.ppu_read(0x1234), 4);
+        assert_eq!(nrom.ppu_read(0x1fff), 7);
+    }
+
+    #[test]
+    fn chr_ram_is_writable() {
+        let mut nrom = Nrom::new(numbered_rom(0x4000), Chr::ram(0x2000), Mirroring::Horizontal);
+        nrom.ppu_write(0x1234, 0x99);
+        assert_eq!(nrom.ppu_read(0x1234), 0x99);
+    }
+
+    #[test]
+    fn low_cartridge_addresses_are_open() {
+        let nrom = Nrom::new(numbered_rom(0x4000), Chr::rom(numbered_rom(0x2000)), Mirroring::Horizontal);
+        assert_eq!(nrom.cpu_read(0x6000), None);
+        assert_eq!(nrom.cpu_read(0x4020), None);
+    }
+}
@@ -0,0 +1,99 @@
+// uxrom.rs
+// Implements UxROM (mapper 2), which switches PRG ROM in 16KB banks
+
+use super::{Chr, Mapper, Mirroring};
+use crate::state::{SaveState, StateReader, StateWriter};
+
+/// The size of a switchable PRG ROM bank
+const PRG_BANK_SIZE: usize = 0x4000;
+
+/// UxROM has a single register, written anywhere in `0x8000 - 0xFFFF`, that selects the 16KB PRG bank at `0x8000`.
+/// The last bank is always mapped at `0xC000`, and the 8KB of CHR is fixed.
+pub struct Uxrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    prg_bank: u8,
+}
+
+impl Uxrom {
+    /// Creates a new UxROM board from the cartridge's PRG ROM and its CHR ROM or RAM
+    pub fn new(prg_rom: Vec<u8>, chr: Chr, mirroring: Mirroring) -> Uxrom {
+        Uxrom {
+            prg_rom,
+            chr,
+            mirroring,
+            prg_bank: 0,
+        }
+    }
+
+    /// Returns the number of 16KB PRG banks on the board
+    fn prg_banks(&self) -> usize {
+        (self.prg_rom.len() / PRG_BANK_SIZE).max(1)
+    }
+}
+
+impl Mapper for Uxrom {
+    fn cpu_read(&self, address: u16) -> Option<u8> {
+        if address >= 0x8000 && !self.prg_rom.is_empty() {
+            let bank = if address >= 0xc000 {
+                self.prg_banks() - 1
+            } else {
+                self.prg_bank as usize % self.prg_banks()
+            };
+            let offset = bank * PRG_BANK_SIZE + (address as usize & (PRG_BANK_SIZE - 1));
+            Some(self.prg_rom[offset % self.prg_rom.len()])
+        } else {
+            None
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if address >= 0x8000 {
+            self.prg_bank = value;
+        }
+    }
+
+    fn ppu_read(&self, address: u16) -> u8 {
+        self.chr.read((address & 0x1fff) as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write((address & 0x1fff) as usize, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+impl SaveState for Uxrom {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.prg_bank);
+        self.chr.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.prg_bank = reader.read_u8()?;
+        self.chr.load_state(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::numbered_rom;
+
+    #[test]
+    fn write_selects_bank_at_8000() {
+        let mut uxrom = Uxrom::new(numbered_rom(0x20000), Chr::ram(0x2000), Mirroring::Vertical);
+        assert_eq!(uxrom.cpu_read(0x8000), Some(0));
+        assert_eq!(uxrom.cpu_read(0xc000), Some(7 * 16));
+
+        uxrom.cpu_write(0x8000, 5);
+        assert_eq!(uxrom.cpu_read(0x8000), Some(5 * 16));
+        assert_eq!(uxrom.cpu_read(0xbfff), Some(5 * 16 + 15));
+        // the last bank stays fixed
+        assert_eq!(uxrom.cpu_read(0xc000), Some(7 * 16));
+    }
+}
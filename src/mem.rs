@@ -0,0 +1,808 @@
+// mem.rs
+// Implements the memory types the CPU reads from and writes to
+
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::apu::APU;
+use crate::cpu::InterruptLines;
+use crate::input::Controller;
+use crate::mapper::{Mapper, SharedMapper};
+use crate::ppu::PPU;
+use crate::state::{SaveState, StateReader, StateWriter};
+
+/// The size of the NES's internal work RAM
+const CPU_RAM_SIZE: usize = 0x800;
+
+/// The CPU's internal RAM is mirrored through `0x0000 - 0x1FFF`, so only the low 11 bits of the address are decoded
+const CPU_RAM_MASK: u16 = 0x07ff;
+
+/// The end of the region mapped to the CPU's internal RAM (and its mirrors)
+const CPU_RAM_END: u16 = 0x1fff;
+
+/// The end of the region mapped to the PPU's registers (and their mirrors)
+const PPU_REGISTERS_END: u16 = 0x3fff;
+
+/// The end of the region mapped to the APU's channel registers
+const APU_CHANNELS_END: u16 = 0x4013;
+
+/// The APU's status register
+const APU_STATUS: u16 = 0x4015;
+/// Bit 5 of the APU's status register isn't connected, so it reads as open bus
+const APU_STATUS_OPEN_BUS: u8 = 0b00100000;
+
+/// The APU's frame counter (write), and the second controller port (read)
+const APU_FRAME_COUNTER: u16 = 0x4017;
+
+/// The first controller port; writes strobe both controllers
+const CONTROLLER_1: u16 = 0x4016;
+/// The second controller port
+const CONTROLLER_2: u16 = 0x4017;
+
+/// A DMC sample fetch takes the bus away from the CPU for (typically) four cycles
+const DMC_STALL_CYCLES: u16 = 4;
+
+/// Writing a page number here copies that page of CPU memory into the PPU's OAM
+const OAM_DMA: u16 = 0x4014;
+
+/// The start of the region mapped to the cartridge
+const CARTRIDGE_START: u16 = 0x4020;
+
+/// The range where cartridges map their PRG RAM, which the mapper may write-protect
+const PRG_RAM_START: u16 = 0x6000;
+const PRG_RAM_END: u16 = 0x7fff;
+
+/// The start of the range where cartridges map their PRG ROM, and where most mappers take their register writes
+const PRG_ROM_START: u16 = 0x8000;
+
+/// How the internal RAM is filled on power-up.
+/// Real consoles power up with console-specific garbage in RAM, and some software depends on a particular fill.
+#[derive(PartialEq, Eq)]
+#[derive(Debug, Copy, Clone)]
+pub enum RamInit {
+    /// Every byte is `0x00`
+    Zeroed,
+    /// Every byte is the given value
+    Filled(u8),
+    /// Pages alternate between `0x00` and `0xFF`, starting with `0x00`
+    Pattern,
+}
+
+/// Anything that can be attached to the CPU's address space.
+/// Reads take `&mut self` because reading some memory-mapped registers has side effects.
+pub trait Mem {
+    /// Reads the byte at `address`
+    fn read(&mut self, address: u16) -> u8;
+
+    /// Returns the byte a read of `address` would return, without any side effects
+    /// This is for tools such as debuggers and disassemblers, which shouldn't disturb the machine.
+    fn peek(&self, address: u16) -> u8;
+
+    /// Writes `value` to `address`
+    fn write(&mut self, address: u16, value: u8);
+
+    /// Fills any RAM according to `init`, as on a cold boot; the CPU calls this when it is powered on
+    /// By default this does nothing, leaving the memory as it is.
+    fn initialize_ram(&mut self, _init: RamInit) {}
+
+    /// Records the address of the instruction the CPU is about to execute
+    /// By default this does nothing; the NES's bus uses it to report which instruction wrote to a watched address.
+    fn set_instruction_address(&mut self, _address: u16) {}
+
+    /// Returns whether an OAM DMA was performed since the last call, clearing the flag
+    /// The CPU uses this to stall for the duration of the transfer. By default, there is no DMA.
+    fn take_oam_dma(&mut self) -> bool {
+        false
+    }
+
+    /// Returns the levels of the IRQ and NMI lines, which the CPU samples between instructions
+    /// By default, nothing asserts either line.
+    fn interrupt_lines(&self) -> InterruptLines {
+        InterruptLines::default()
+    }
+}
+
+/// The 2KB of work RAM inside the NES.
+/// Only 11 address lines are connected, so the RAM appears four times in `0x0000 - 0x1FFF`.
+pub struct CpuRam {
+    memory: [u8; CPU_RAM_SIZE],
+}
+
+impl Default for CpuRam {
+    #[inline]
+    fn default() -> CpuRam {
+        CpuRam {
+            memory: [0; CPU_RAM_SIZE],
+        }
+    }
+}
+
+impl CpuRam {
+    /// Fills the RAM according to `init`
+    pub fn initialize(&mut self, init: RamInit) {
+        for (address, byte) in self.memory.iter_mut().enumerate() {
+            *byte = match init {
+                RamInit::Zeroed => 0x00,
+                RamInit::Filled(value) => value,
+                RamInit::Pattern => if (address >> 8) & 1 == 0 { 0x00 } else { 0xff },
+            };
+        }
+    }
+}
+
+impl Mem for CpuRam {
+    fn read(&mut self, address: u16) -> u8 {
+        self.peek(address)
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.memory[(address & CPU_RAM_MASK) as usize]
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.memory[(address & CPU_RAM_MASK) as usize] = value;
+    }
+}
+
+/// Plain RAM filling the whole 64KB address space, with no memory-mapped devices
+/// This is for running the 6502 core on its own, such as in CPU tests, rather than as part of an NES.
+pub struct FlatMemory {
+    memory: Box<[u8; 0x10000]>,
+}
+
+impl Default for FlatMemory {
+    #[inline]
+    fn default() -> FlatMemory {
+        FlatMemory {
+            memory: Box::new([0; 0x10000]),
+        }
+    }
+}
+
+impl Mem for FlatMemory {
+    fn read(&mut self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.memory[address as usize] = value;
+    }
+}
+
+impl SaveState for CpuRam {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.memory);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        reader.read_into(&mut self.memory)
+    }
+}
+
+/// A write to a watched address, as recorded by the bus
+#[derive(PartialEq, Eq)]
+#[derive(Debug, Clone)]
+pub struct WatchEvent {
+    /// The address that was written
+    pub address: u16,
+    /// The label the watch was added with
+    pub label: String,
+    /// The value at the address before the write
+    pub old_value: u8,
+    /// The value written
+    pub new_value: u8,
+    /// The address of the instruction that performed the write
+    pub pc: u16,
+}
+
+/// The CPU's view of the address space.
+/// The bus decodes each address and dispatches it to the device responsible for it:
+/// * `0x0000 - 0x1FFF` goes to the internal RAM (and its mirrors)
+/// * `0x2000 - 0x3FFF` goes to the PPU's registers (and their mirrors)
+/// * `0x4000 - 0x4013`, `0x4015`, and `0x4017` (when written) go to the APU's registers
+/// * `0x4016` and `0x4017` (when read) go to the controllers
+/// * `0x4020 - 0xFFFF` goes to the cartridge's mapper, if one is inserted, and is otherwise backed by plain memory so raw programs can be run
+///
+/// Reads that nothing responds to (the write-only and unused I/O registers, and addresses the cartridge doesn't decode) return the last value on the data bus, known as open bus.
+/// Registers that only drive some of their bits, such as the controller ports, fill in the rest from open bus too.
+pub struct Bus {
+    ram: CpuRam,
+    ppu: PPU,
+    apu: APU,
+    controllers: [Controller; 2],
+    mapper: Option<SharedMapper>,
+    unmapped: Box<[u8; 0x10000]>,
+    // the last value read or written over the data bus, which lingers on it until the next access
+    open_bus: u8,
+    // whether the last access was a write, for mappers that ignore writes on consecutive cycles
+    last_access_was_write: bool,
+    // set when an OAM DMA has been performed, until the CPU accounts for the stall
+    oam_dma: bool,
+    // labels for the watched addresses, and the writes to them that haven't been taken yet
+    write_watches: HashMap<u16, String>,
+    watch_events: Vec<WatchEvent>,
+    // the address of the instruction the CPU is executing, for watch events
+    instruction_address: u16,
+    // whether the PPU is brought up to date before each access to its registers, rather than only at the end of each step
+    ppu_catch_up: bool,
+    // master cycles the CPU has run that are too few to make up a PPU dot; only PAL consoles leave any
+    ppu_remainder: i64,
+    // the CPU cycles (bus accesses) made so far in the current step, and how many of them the PPU has been run for
+    step_cycles: i64,
+    ppu_synced_cycles: i64,
+    // set when the PPU finishes a frame, until the step ends
+    frame_ended: bool,
+}
+
+impl Default for Bus {
+    #[inline]
+    fn default() -> Bus {
+        Bus {
+            ram: CpuRam::default(),
+            ppu: PPU::default(),
+            apu: APU::default(),
+            controllers: [Controller::default(), Controller::default()],
+            mapper: None,
+            unmapped: Box::new([0; 0x10000]),
+            open_bus: 0,
+            last_access_was_write: false,
+            oam_dma: false,
+            write_watches: HashMap::new(),
+            watch_events: Vec::new(),
+            instruction_address: 0,
+            ppu_catch_up: false,
+            ppu_remainder: 0,
+            step_cycles: 0,
+            ppu_synced_cycles: 0,
+            frame_ended: false,
+        }
+    }
+}
+
+impl Bus {
+    /// Inserts a cartridge, attaching its mapper to the cartridge space and to the PPU
+    pub fn set_mapper(&mut self, mapper: Box<dyn Mapper>) {
+        let mapper = Rc::new(RefCell::new(mapper));
+        self.ppu.set_mapper(Rc::clone(&mapper));
+        self.mapper = Some(mapper);
+    }
+
+    /// Returns the cartridge's mapper, if one is inserted
+    pub fn mapper(&self) -> Option<Ref<'_, Box<dyn Mapper>>> {
+        self.mapper.as_ref().map(|mapper| mapper.borrow())
+    }
+
+    /// Returns the cartridge's mapper, if one is inserted
+    pub fn mapper_mut(&mut self) -> Option<RefMut<'_, Box<dyn Mapper>>> {
+        self.mapper.as_ref().map(|mapper| mapper.borrow_mut())
+    }
+
+    /// Returns the PPU
+    pub fn ppu(&self) -> &PPU {
+        &self.ppu
+    }
+
+    /// Returns the PPU
+    pub fn ppu_mut(&mut self) -> &mut PPU {
+        &mut self.ppu
+    }
+
+    /// Returns the APU
+    pub fn apu(&self) -> &APU {
+        &self.apu
+    }
+
+    /// Returns the APU
+    pub fn apu_mut(&mut self) -> &mut APU {
+        &mut self.apu
+    }
+
+    /// Advances the APU by one CPU cycle, servicing any sample fetch the DMC makes
+    /// Returns the number of cycles the fetch stalled the CPU for.
+    pub fn clock_apu(&mut self) -> u16 {
+        self.apu.clock();
+        match self.apu.dmc_fetch_address() {
+            Some(address) => {
+                let value = self.read(address);
+                self.apu.dmc_fill_buffer(value);
+                DMC_STALL_CYCLES
+            },
+            None => 0,
+        }
+    }
+
+    /// Returns the controller plugged into `port` (0 or 1)
+    ///
+    /// # Panics
+    ///
+    /// Panics if `port` is not 0 or 1.
+    pub fn controller(&self, port: usize) -> &Controller {
+        &self.controllers[port]
+    }
+
+    /// Returns the controller plugged into `port` (0 or 1)
+    ///
+    /// # Panics
+    ///
+    /// Panics if `port` is not 0 or 1.
+    pub fn controller_mut(&mut self, port: usize) -> &mut Controller {
+        &mut self.controllers[port]
+    }
+
+    /// Copies the 256 bytes of page `page` into OAM, starting at the current OAMADDR
+    fn oam_dma(&mut self, page: u8) {
+        let start = (page as u16) << 8;
+        for offset in 0..0x100 {
+            let value = self.read(start | offset);
+            self.ppu.write_register(0x2004, value);
+        }
+        self.oam_dma = true;
+    }
+
+    /// Watches `address` for writes; each write to it is recorded as a `WatchEvent` with the given label
+    /// Only the exact address is watched, not its mirrors. Watching an address again replaces its label.
+    pub fn add_write_watch(&mut self, address: u16, label: &str) {
+        self.write_watches.insert(address, String::from(label));
+    }
+
+    /// Stops watching `address` for writes
+    pub fn remove_write_watch(&mut self, address: u16) {
+        self.write_watches.remove(&address);
+    }
+
+    /// Returns the watch events recorded since the last call, oldest first
+    pub fn take_watch_events(&mut self) -> Vec<WatchEvent> {
+        std::mem::take(&mut self.watch_events)
+    }
+
+    /// Makes the bus run the PPU up to the current CPU cycle before each access to the PPU's registers
+    /// Otherwise, the PPU only runs when `finish_step` is called, so an instruction that reads PPUSTATUS sees the PPU as it was when the instruction began, and the vblank race can't happen. It is off by default; the NES turns it on.
+    pub fn set_ppu_catch_up(&mut self, enabled: bool) {
+        self.ppu_catch_up = enabled;
+    }
+
+    /// Starts a CPU step, so the PPU is caught up to the step's bus accesses from their start
+    /// Accesses made between steps (such as the reset sequence's reads of the reset vector) aren't counted.
+    pub fn start_step(&mut self) {
+        self.step_cycles = 0;
+        self.ppu_synced_cycles = 0;
+    }
+
+    /// Ends a CPU step (an instruction or interrupt sequence) that took `cpu_cycles` cycles, running the PPU for the part of it the PPU hasn't been run for yet
+    /// Returns true if the PPU finished a frame at any point during the step.
+    pub fn finish_step(&mut self, cpu_cycles: i64) -> bool {
+        self.run_ppu_to(cpu_cycles);
+        self.start_step();
+        std::mem::replace(&mut self.frame_ended, false)
+    }
+
+    /// Runs the PPU until it has caught up with the first `cpu_cycles` cycles of the current step
+    /// On PAL consoles, a CPU cycle isn't a whole number of dots; the remainder is left for the PPU to catch up on next time.
+    fn run_ppu_to(&mut self, cpu_cycles: i64) {
+        let owed = cpu_cycles - self.ppu_synced_cycles;
+        if owed <= 0 {
+            return;
+        }
+
+        let region = self.ppu.region();
+        self.ppu_remainder += owed * region.cpu_clock_factor();
+        let dots = self.ppu_remainder / region.ppu_clock_factor();
+        self.ppu_remainder %= region.ppu_clock_factor();
+        for _ in 0..dots {
+            self.frame_ended |= self.ppu.step_dot();
+        }
+        self.ppu_synced_cycles = cpu_cycles;
+    }
+
+    /// Counts a CPU cycle's bus access, first bringing the PPU up to the start of that cycle if the access is to its registers
+    fn begin_access(&mut self, address: u16) {
+        self.step_cycles += 1;
+        if self.ppu_catch_up && address > CPU_RAM_END && address <= PPU_REGISTERS_END {
+            self.run_ppu_to(self.step_cycles - 1);
+        }
+    }
+}
+
+/// The bus's state includes each device attached to it, but not its watches, which belong to the debugging session
+impl SaveState for Bus {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.ram.save_state(writer);
+        self.ppu.save_state(writer);
+        self.apu.save_state(writer);
+        for controller in self.controllers.iter() {
+            controller.save_state(writer);
+        }
+        writer.write_bool(self.oam_dma);
+        writer.write_u8(self.open_bus);
+        writer.write_bool(self.last_access_was_write);
+        writer.write_i64(self.ppu_remainder);
+
+        // the cartridge space is only backed by plain memory when there is no cartridge
+        match &self.mapper {
+            Some(mapper) => mapper.borrow().save_state(writer),
+            None => writer.write_bytes(&self.unmapped[CARTRIDGE_START as usize..]),
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.ram.load_state(reader)?;
+        self.ppu.load_state(reader)?;
+        self.apu.load_state(reader)?;
+        for controller in self.controllers.iter_mut() {
+            controller.load_state(reader)?;
+        }
+        self.oam_dma = reader.read_bool()?;
+        self.open_bus = reader.read_u8()?;
+        self.last_access_was_write = reader.read_bool()?;
+        self.ppu_remainder = reader.read_i64()?;
+
+        match &self.mapper {
+            Some(mapper) => mapper.borrow_mut().load_state(reader),
+            None => reader.read_into(&mut self.unmapped[CARTRIDGE_START as usize..]),
+        }
+    }
+}
+
+impl Mem for Bus {
+    fn read(&mut self, address: u16) -> u8 {
+        self.begin_access(address);
+        self.last_access_was_write = false;
+        if address == APU_STATUS {
+            // the status register is inside the CPU, so reading it doesn't drive the external data bus
+            return self.apu.read_status() | (self.open_bus & APU_STATUS_OPEN_BUS);
+        }
+
+        let value = if address <= CPU_RAM_END {
+            self.ram.read(address)
+        } else if address <= PPU_REGISTERS_END {
+            self.ppu.read_register(address)
+        } else if address == CONTROLLER_1 || address == CONTROLLER_2 {
+            // only the low bits are driven by the controller; the top three are open bus
+            let port = (address - CONTROLLER_1) as usize;
+            (self.open_bus & 0xe0) | self.controllers[port].read()
+        } else if address < CARTRIDGE_START {
+            self.open_bus
+        } else {
+            match &self.mapper {
+                Some(mapper) => mapper.borrow().cpu_read(address).unwrap_or(self.open_bus),
+                None => self.unmapped[address as usize],
+            }
+        };
+        self.open_bus = value;
+        value
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        if address <= CPU_RAM_END {
+            self.ram.peek(address)
+        } else if address <= PPU_REGISTERS_END {
+            self.ppu.peek_register(address)
+        } else if address == APU_STATUS {
+            self.apu.peek_status() | (self.open_bus & APU_STATUS_OPEN_BUS)
+        } else if address == CONTROLLER_1 || address == CONTROLLER_2 {
+            let port = (address - CONTROLLER_1) as usize;
+            (self.open_bus & 0xe0) | self.controllers[port].peek()
+        } else if address < CARTRIDGE_START {
+            self.open_bus
+        } else {
+            match &self.mapper {
+                Some(mapper) => mapper.borrow().cpu_read(address).unwrap_or(self.open_bus),
+                None => self.unmapped[address as usize],
+            }
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.begin_access(address);
+        let consecutive = std::mem::replace(&mut self.last_access_was_write, true);
+        if let Some(label) = self.write_watches.get(&address) {
+            let event = WatchEvent {
+                address,
+                label: label.clone(),
+                old_value: self.peek(address),
+                new_value: value,
+                pc: self.instruction_address,
+            };
+            self.watch_events.push(event);
+        }
+
+        self.open_bus = value;
+        if address <= CPU_RAM_END {
+            self.ram.write(address, value);
+        } else if address <= PPU_REGISTERS_END {
+            self.ppu.write_register(address, value);
+        } else if address <= APU_CHANNELS_END || address == APU_STATUS || address == APU_FRAME_COUNTER {
+            self.apu.write_register(address, value);
+        } else if address == OAM_DMA {
+            self.oam_dma(value);
+        } else if address == CONTROLLER_1 {
+            for controller in self.controllers.iter_mut() {
+                controller.write(value);
+            }
+        } else if address < CARTRIDGE_START {
+            // the remaining I/O addresses are unused
+        } else {
+            match &self.mapper {
+                Some(mapper) => {
+                    let mut mapper = mapper.borrow_mut();
+                    let protected = (PRG_RAM_START..=PRG_RAM_END).contains(&address) && mapper.prg_ram_write_protected();
+                    let ignored = consecutive && address >= PRG_ROM_START && mapper.ignores_consecutive_writes();
+                    if !protected && !ignored {
+                        mapper.cpu_write(address, value);
+                    }
+                },
+                None => self.unmapped[address as usize] = value,
+            }
+        }
+    }
+
+    fn initialize_ram(&mut self, init: RamInit) {
+        self.ram.initialize(init);
+    }
+
+    fn set_instruction_address(&mut self, address: u16) {
+        self.instruction_address = address;
+    }
+
+    fn take_oam_dma(&mut self) -> bool {
+        let dma = self.oam_dma;
+        self.oam_dma = false;
+        dma
+    }
+
+    /// The APU and the cartridge share the IRQ line, and the PPU drives the NMI line
+    fn interrupt_lines(&self) -> InterruptLines {
+        let mapper_irq = self.mapper.as_ref().is_some_and(|mapper| mapper.borrow().irq_pending());
+        InterruptLines {
+            irq: self.apu.irq_pending() || mapper_irq,
+            nmi: self.ppu.nmi_line(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CPU;
+    use crate::input::Button;
+    use crate::mapper::nrom::Nrom;
+    use crate::mapper::{Chr, Mirroring};
+
+    #[test]
+    fn bus_mirrors_ram_writes() {
+        let mut bus = Bus::default();
+        bus.write(0x0000, 0x42);
+        assert_eq!(bus.read(0x0800), 0x42);
+        bus.write(0x0801, 0x24);
+        assert_eq!(bus.read(0x0001), 0x24);
+    }
+
+    #[test]
+    fn cpu_ram_mirrors_every_2kb() {
+        let mut ram = CpuRam::default();
+        ram.write(0x1805, 0x5a);
+        for base in [0x0000, 0x0800, 0x1000, 0x1800] {
+            assert_eq!(ram.read(base + 0x05), 0x5a);
+        }
+    }
+
+    #[test]
+    fn cpu_ram_stays_in_bounds_across_the_mirrored_range() {
+        let mut ram = CpuRam::default();
+        for address in 0x0000..=0x1fff {
+            ram.write(address, address as u8);
+        }
+        // the last mirror's writes land on top of the first's
+        for address in 0x0000..=0x07ff {
+            assert_eq!(ram.read(address), address as u8);
+        }
+    }
+
+    #[test]
+    fn cpu_reaches_ppu_registers_through_bus() {
+        let mut cpu = CPU::default();
+        cpu.load_program(0x0600, &[0xa9, 0x90, 0x8d, 0x00, 0x20]).unwrap();   // LDA #$90; STA $2000
+        cpu.set_pc(0x0600);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.bus().ppu().ctrl(), 0x90);
+    }
+
+    /// Creates a CPU on the NES bus with page 2 of RAM holding 0, 1, 2, ..., 255 and `program` loaded at `0x0600`
+    fn cpu_with_sprite_page(program: &[u8]) -> CPU {
+        let mut cpu = CPU::default();
+        let page: Vec<u8> = (0..=255).collect();
+        cpu.load_program(0x0200, &page).unwrap();
+        cpu.load_program(0x0600, program).unwrap();
+        cpu.set_pc(0x0600);
+        cpu
+    }
+
+    #[test]
+    fn oam_dma_copies_page_and_stalls() {
+        // LDA #$02; STA $4014
+        let mut cpu = cpu_with_sprite_page(&[0xa9, 0x02, 0x8d, 0x14, 0x40]);
+        assert_eq!(cpu.step(), 2);
+        // the write finishes on an even cycle, so the DMA takes 513 cycles
+        assert_eq!(cpu.step(), 4 + 513);
+        let oam = cpu.bus().ppu().oam();
+        assert!(oam.iter().enumerate().all(|(i, &byte)| byte == i as u8));
+    }
+
+    #[test]
+    fn oam_dma_on_odd_cycle_takes_extra_cycle_and_starts_at_oamaddr() {
+        // LDA #$10; STA $2003; LDA $02; LDA #$02; STA $4014
+        let mut cpu = cpu_with_sprite_page(&[0xa9, 0x10, 0x8d, 0x03, 0x20, 0xa5, 0x02, 0xa9, 0x02, 0x8d, 0x14, 0x40]);
+        for _ in 0..4 {
+            cpu.step();
+        }
+        assert_eq!(cpu.step(), 4 + 514);
+        let oam = cpu.bus().ppu().oam();
+        assert_eq!(oam[0x10], 0x00);
+        assert_eq!(oam[0x0f], 0xff);
+    }
+
+    /// Creates a bus with the PPU stopped at dot 336 of the last visible scanline, 6 dots (2 CPU cycles) before it sets the vblank flag
+    fn bus_before_vblank(catch_up: bool) -> Bus {
+        let mut bus = Bus::default();
+        bus.set_ppu_catch_up(catch_up);
+        bus.ppu_mut().write_register(0x2000, 0x80);
+        while bus.ppu().scanline() != 240 || bus.ppu().dot() != 336 {
+            bus.ppu_mut().clock();
+        }
+        bus.start_step();
+        bus
+    }
+
+    #[test]
+    fn ppustatus_read_on_the_vblank_dot_suppresses_vblank() {
+        let mut bus = bus_before_vblank(true);
+        bus.read(0x0000);
+        bus.read(0x0001);
+        // the third cycle starts on the dot the vblank flag is set
+        assert_eq!(bus.read(0x2002) & 0x80, 0);
+        bus.finish_step(3);
+        assert_eq!(bus.ppu().status() & 0x80, 0);
+        assert!(!bus.ppu().nmi_line());
+    }
+
+    #[test]
+    fn ppu_only_runs_at_the_end_of_the_step_without_catch_up() {
+        let mut bus = bus_before_vblank(false);
+        bus.read(0x0000);
+        bus.read(0x0001);
+        // the PPU hasn't reached vblank yet, so the read doesn't race with it
+        assert_eq!(bus.read(0x2002) & 0x80, 0);
+        bus.finish_step(3);
+        assert_eq!(bus.ppu().status() & 0x80, 0x80);
+        assert!(bus.ppu().nmi_line());
+    }
+
+    #[test]
+    fn dmc_sample_fetch_stalls_the_cpu() {
+        let mut bus = Bus::default();
+        // a one-byte sample at 0xC000
+        bus.write(0x4010, 0x0f);
+        bus.write(0x4013, 0x00);
+        bus.write(0x4015, 0x10);
+        assert_eq!(bus.clock_apu(), DMC_STALL_CYCLES);
+        // the buffer is full, and there is nothing left to fetch
+        for _ in 0..1000 {
+            assert_eq!(bus.clock_apu(), 0);
+        }
+    }
+
+    #[test]
+    fn controller_ports_are_read_through_the_bus() {
+        let mut bus = Bus::default();
+        bus.controller_mut(0).set_button(Button::B, true);
+        bus.controller_mut(1).set_button(Button::A, true);
+        // a single strobe latches both controllers
+        bus.write(0x4016, 1);
+        bus.write(0x4016, 0);
+        assert_eq!(bus.read(0x4016) & 0x01, 0);
+        assert_eq!(bus.read(0x4016) & 0x01, 1);
+        assert_eq!(bus.read(0x4017) & 0x01, 1);
+        assert_eq!(bus.read(0x4017) & 0x01, 0);
+    }
+
+    #[test]
+    fn write_watch_records_the_storing_instruction() {
+        // LDA #$42; STA $0200; STA $0201; LDA #$43; STA $0200
+        let mut cpu = CPU::default();
+        cpu.load_program(0x0600, &[0xa9, 0x42, 0x8d, 0x00, 0x02, 0x8d, 0x01, 0x02, 0xa9, 0x43, 0x8d, 0x00, 0x02]).unwrap();
+        cpu.set_pc(0x0600);
+        cpu.bus_mut().add_write_watch(0x0200, "sprite page");
+        for _ in 0..3 {
+            cpu.step();
+        }
+
+        let events = cpu.bus_mut().take_watch_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].address, 0x0200);
+        assert_eq!(events[0].label, "sprite page");
+        assert_eq!((events[0].old_value, events[0].new_value), (0x00, 0x42));
+        assert_eq!(events[0].pc, 0x0602);
+        // the queue is emptied by taking it
+        assert!(cpu.bus_mut().take_watch_events().is_empty());
+
+        cpu.step();
+        cpu.step();
+        let events = cpu.bus_mut().take_watch_events();
+        assert_eq!((events[0].old_value, events[0].new_value, events[0].pc), (0x42, 0x43, 0x060a));
+    }
+
+    #[test]
+    fn unmapped_reads_return_the_last_value_on_the_bus() {
+        let mut bus = Bus::default();
+        bus.set_mapper(Box::new(Nrom::new(vec![0; 0x4000], Chr::rom(vec![0; 0x2000]), Mirroring::Vertical)));
+        bus.write(0x0010, 0x5a);
+        assert_eq!(bus.read(0x0010), 0x5a);
+
+        // unused I/O, write-only APU registers, and cartridge space NROM doesn't decode
+        assert_eq!(bus.read(0x4018), 0x5a);
+        assert_eq!(bus.read(0x4000), 0x5a);
+        assert_eq!(bus.read(0x6000), 0x5a);
+
+        // the controller ports only drive their low bits
+        assert_eq!(bus.read(0x4016) & 0xe0, 0x40);
+
+        // $4015 doesn't drive bit 5
+        bus.write(0x0011, 0xff);
+        bus.read(0x0011);
+        assert_eq!(bus.read(0x4015) & APU_STATUS_OPEN_BUS, APU_STATUS_OPEN_BUS);
+        bus.read(0x0010);
+        assert_eq!(bus.read(0x4015) & APU_STATUS_OPEN_BUS, 0);
+    }
+
+    #[test]
+    fn undriven_ppu_register_bits_return_the_ppu_bus() {
+        let mut bus = Bus::default();
+        bus.write(0x2003, 0x15);
+        // PPUSTATUS only drives its top three bits, and PPUSCROLL none
+        assert_eq!(bus.read(0x2002) & 0x1f, 0x15);
+        assert_eq!(bus.read(0x2005), 0x15);
+    }
+
+    #[test]
+    fn apu_frame_irq_is_held_while_masked_and_taken_when_unmasked() {
+        let mut cpu = CPU::default();
+        cpu.load_vector(crate::cpu::RESET_VECTOR, 0x0200);
+        cpu.load_vector(crate::cpu::IRQ_VECTOR, 0x0300);
+        cpu.power_on();
+        cpu.load_program(0x0200, &[0xea, 0xea, 0xea]).unwrap();
+        // the handler starts with a NOP
+        cpu.load_program(0x0300, &[0xea]).unwrap();
+
+        // run the frame counter until it raises its IRQ
+        while !cpu.bus().apu().irq_pending() {
+            cpu.bus_mut().apu_mut().clock();
+        }
+        assert!(cpu.bus().interrupt_lines().irq);
+
+        // I is set after power on, so the NOP runs and the IRQ stays pending
+        assert_eq!(cpu.step(), 2);
+        assert_eq!(cpu.pc(), 0x0201);
+        assert!(cpu.bus().apu().irq_pending());
+
+        // with I clear, the IRQ is taken in place of the next instruction
+        cpu.set_status(cpu.status() & !0x04);
+        let sp = cpu.sp();
+        assert_eq!(cpu.step(), 7);
+        assert_eq!(cpu.pc(), 0x0300);
+        assert_eq!(cpu.sp(), sp.wrapping_sub(3));
+        let stack = |offset: u8| cpu.bus().peek(0x0100 + sp.wrapping_sub(offset) as u16);
+        // the return address, then the status with B clear and bit 5 set
+        assert_eq!((stack(0), stack(1)), (0x02, 0x01));
+        assert_eq!(stack(2) & 0x30, 0x20);
+        // I is set again, so the still-asserted line doesn't interrupt the handler
+        assert_ne!(cpu.status() & 0x04, 0);
+        assert_eq!(cpu.step(), 2);
+        assert_eq!(cpu.pc(), 0x0301);
+    }
+}
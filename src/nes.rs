@@ -0,0 +1,968 @@
+// nes.rs
+// Ties the components of the system together and keeps them in sync
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::cpu::{RunOutcome, CPU};
+use crate::ines;
+use crate::input::{ControllerState, Recorder};
+use crate::mapper;
+use crate::ppu::{DOTS_PER_SCANLINE, PPU};
+use crate::state::rewind::Rewind;
+use crate::state::{self, SaveState, StateReader, StateWriter, STATE_MAGIC, STATE_VERSION};
+
+/// When rewinding is enabled, a snapshot is taken every this many frames
+const REWIND_INTERVAL: u32 = 4;
+
+/// The television system a console was built for, which sets the speed of its clocks and the shape of its frames
+#[derive(PartialEq, Eq)]
+#[derive(Debug, Copy, Clone)]
+pub enum Region {
+    /// The North American and Japanese console: 262 scanlines at about 60 frames per second
+    Ntsc,
+    /// The European console: 312 scanlines at about 50 frames per second, with slower CPU and PPU clocks
+    Pal,
+    /// The Dendy and similar famiclones: PAL's frame and master clock, but with NTSC's CPU-to-PPU ratio, so its CPU runs faster than a PAL console's
+    Dendy,
+}
+
+impl Default for Region {
+    #[inline]
+    fn default() -> Region {
+        Region::Ntsc
+    }
+}
+
+impl Region {
+    /// Returns the rate of the master clock, in Hz; every other clock in the system is derived from it
+    /// NTSC consoles run at 21.477272 MHz, and PAL consoles and Dendys at 26.601712 MHz.
+    pub fn master_clock_rate(self) -> u64 {
+        match self {
+            Region::Ntsc => 21_477_272,
+            Region::Pal | Region::Dendy => 26_601_712,
+        }
+    }
+
+    /// Returns the number of master cycles per CPU cycle
+    pub fn cpu_clock_factor(self) -> i64 {
+        match self {
+            Region::Ntsc => 12,
+            Region::Pal => 16,
+            Region::Dendy => 15,
+        }
+    }
+
+    /// Returns the number of master cycles per PPU dot
+    pub fn ppu_clock_factor(self) -> i64 {
+        match self {
+            Region::Ntsc => 4,
+            Region::Pal | Region::Dendy => 5,
+        }
+    }
+
+    /// Returns the rate of the CPU clock, in Hz (about 1.79 MHz on NTSC consoles and 1.66 MHz on PAL consoles)
+    pub fn cpu_clock_rate(self) -> u32 {
+        (self.master_clock_rate() / self.cpu_clock_factor() as u64) as u32
+    }
+
+    /// Returns the number of scanlines in a frame: 262 on NTSC consoles, and 312 on PAL consoles and Dendys
+    /// Every region has 240 visible scanlines and a pre-render line; the rest are the post-render line and vblank.
+    pub fn scanlines_per_frame(self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    /// Returns the scanline on which vblank starts
+    /// The Dendy has 50 post-render lines before vblank rather than one, so its vblank is as short as an NTSC console's.
+    pub fn vblank_scanline(self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    /// Returns whether odd frames skip a dot of the pre-render scanline while rendering is enabled, which only NTSC consoles do
+    pub fn skips_odd_frame_dot(self) -> bool {
+        self == Region::Ntsc
+    }
+
+    /// Returns the number of CPU cycles in a frame, rounded up (29781 on NTSC consoles, where a frame is about 29780.67 cycles)
+    pub fn cpu_cycles_per_frame(self) -> u64 {
+        self.frame_master_cycles().div_ceil(self.cpu_clock_factor() as u64)
+    }
+
+    /// Returns the number of frames per second (about 60.1 on NTSC consoles and 50.0 on PAL consoles and Dendys)
+    pub fn frame_rate(self) -> f64 {
+        self.master_clock_rate() as f64 / self.frame_master_cycles() as f64
+    }
+
+    /// Returns the length of a frame in real time, in nanoseconds
+    fn frame_nanos(self) -> u64 {
+        self.frame_master_cycles() * 1_000_000_000 / self.master_clock_rate()
+    }
+
+    /// Returns the length of a frame in master cycles
+    fn frame_master_cycles(self) -> u64 {
+        DOTS_PER_SCANLINE as u64 * self.scanlines_per_frame() as u64 * self.ppu_clock_factor() as u64
+    }
+}
+
+/// The whole console.
+/// The NES owns each of the components and schedules them against the master clock.
+pub struct NES {
+    pub cpu: CPU,
+    region: Region,
+    // whether the cartridge's PRG RAM is battery-backed, and so should be kept between sessions
+    battery: bool,
+
+    // master cycles that have elapsed but haven't been consumed by the CPU yet
+    // since instructions take several cycles, the CPU may run ahead, making this negative
+    cycles: i64,
+
+    // CPU cycles run since the start of the current frame
+    frame_cycles: u64,
+
+    // records the controller states of each frame
+    recorder: Option<Recorder>,
+    // supplies the controller states of each frame, overriding the frontend
+    playback: Option<Recorder>,
+
+    // recent snapshots, when rewinding is enabled
+    rewind: Option<Rewind>,
+}
+
+impl NES {
+    /// Creates a new system around the given CPU, timed for `region`
+    pub fn new(cpu: CPU, region: Region) -> NES {
+        let mut nes = NES {
+            cpu,
+            region,
+            battery: false,
+            cycles: 0,
+            frame_cycles: 0,
+            recorder: None,
+            playback: None,
+            rewind: None,
+        };
+        let bus = nes.cpu.bus_mut();
+        bus.set_ppu_catch_up(true);
+        bus.ppu_mut().set_region(region);
+        bus.apu_mut().set_cpu_clock_rate(region.cpu_clock_rate());
+        nes
+    }
+
+    /// Creates a system running the game in an iNES or NES 2.0 ROM image, powered on and ready to run from the reset vector
+    /// The system is timed for `region`, or if that is `None`, for the region given in the image's header.
+    /// Returns an error if the image is malformed or uses an unsupported mapper.
+    pub fn from_rom_bytes(rom: &[u8], region: Option<Region>) -> Result<NES, String> {
+        let image = ines::load_rom(rom)?;
+        let mut mapper = mapper::create_mapper(&image.header, image.prg_rom, image.chr_rom)?;
+        if let Some(trainer) = &image.trainer {
+            mapper::load_trainer(mapper.as_mut(), trainer)?;
+        }
+        let mut cpu = CPU::default();
+        // the 2A03 has no decimal mode
+        cpu.set_decimal_enabled(false);
+        cpu.bus_mut().set_mapper(mapper);
+        cpu.power_on();
+        let mut nes = NES::new(cpu, region.unwrap_or(image.header.timing));
+        nes.battery = image.header.battery_memory_present;
+        Ok(nes)
+    }
+
+    /// Creates a system running the game in the ROM file at `path`, as `from_rom_bytes` does
+    pub fn from_rom_file(path: &Path, region: Option<Region>) -> Result<NES, String> {
+        let rom = fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        NES::from_rom_bytes(&rom, region)
+    }
+
+    /// Advances the system by `master_cycles` cycles of the master clock
+    /// The CPU is stepped whenever at least one CPU cycle's worth of master cycles is pending, and the cycles its instruction took are subtracted afterwards.
+    /// Returns `RunOutcome::Halted` if the CPU has stopped.
+    pub fn clock(&mut self, master_cycles: u64) -> RunOutcome {
+        let cpu_clock_factor = self.region.cpu_clock_factor();
+        self.cycles += master_cycles as i64;
+        while self.cycles >= cpu_clock_factor && self.cpu.is_running() {
+            let cpu_cycles = self.step();
+            self.cycles -= cpu_cycles * cpu_clock_factor;
+        }
+        self.cpu.run_outcome()
+    }
+
+    /// Runs the system until the PPU finishes the frame it is drawing, leaving the picture in the framebuffer and the frame's audio ready to drain
+    /// Instructions are executed whole, so the CPU may finish a few cycles into the next frame; the PPU keeps its position, so frames don't drift.
+    /// This stops early if the CPU stops, returning `RunOutcome::Halted`.
+    pub fn run_frame(&mut self) -> RunOutcome {
+        let frame = self.ppu().frame_count();
+        while self.cpu.is_running() && self.ppu().frame_count() == frame {
+            self.step();
+        }
+        self.cpu.run_outcome()
+    }
+
+    /// Executes one CPU instruction, then brings the APU and PPU up to date with it
+    /// If the PPU is signalling an NMI, or the APU or cartridge an IRQ the CPU isn't masking, the CPU services it in place of the instruction.
+    /// Returns the number of CPU cycles that elapsed.
+    fn step(&mut self) -> i64 {
+        self.cpu.bus_mut().start_step();
+        let mut cpu_cycles = self.cpu.step() as i64;
+
+        // the APU is clocked on CPU cycles (it divides them down itself)
+        // it runs first, since DMC sample fetches stall the CPU, lengthening the instruction
+        let mut elapsed = 0;
+        while elapsed < cpu_cycles {
+            let stall = self.cpu.bus_mut().clock_apu() as i64;
+            if stall > 0 {
+                self.cpu.stall(stall as u64);
+                cpu_cycles += stall;
+            }
+            elapsed += 1;
+        }
+
+        // the PPU then catches up with the rest of the instruction; the bus has already run it up to any access the instruction made to its registers
+        let frame_ended = self.cpu.bus_mut().finish_step(cpu_cycles);
+        if frame_ended {
+            self.end_frame();
+        }
+
+        self.frame_cycles += cpu_cycles as u64;
+        let cycles_per_frame = self.region.cpu_cycles_per_frame();
+        if self.frame_cycles >= cycles_per_frame {
+            self.frame_cycles -= cycles_per_frame;
+        }
+        cpu_cycles
+    }
+
+    /// Returns the region the system is timed for
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Returns the PPU, which is reached through the CPU's bus
+    pub fn ppu(&self) -> &PPU {
+        self.cpu.bus().ppu()
+    }
+
+    /// Returns the PPU, which is reached through the CPU's bus
+    pub fn ppu_mut(&mut self) -> &mut PPU {
+        self.cpu.bus_mut().ppu_mut()
+    }
+
+    /// Returns the most recently rendered picture as a 256x240 PNG image
+    #[cfg(feature = "png")]
+    pub fn frame_png(&self) -> Vec<u8> {
+        crate::png::encode_rgba(crate::ppu::SCREEN_WIDTH as u32, crate::ppu::SCREEN_HEIGHT as u32, &self.ppu().frame_rgba())
+    }
+
+    /// Writes the most recently rendered picture to the file at `path` as a PNG image
+    #[cfg(feature = "png")]
+    pub fn write_frame_png(&self, path: &Path) -> Result<(), String> {
+        crate::png::write_rgba(path, crate::ppu::SCREEN_WIDTH as u32, crate::ppu::SCREEN_HEIGHT as u32, &self.ppu().frame_rgba())
+    }
+
+    /// Sets the buttons held on the controller in `port` (0 or 1); frontends call this with a fresh snapshot each frame
+    /// While a playback is attached, the recording supplies the input, so this is ignored.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `port` is not 0 or 1.
+    pub fn set_controller_state(&mut self, port: usize, state: ControllerState) {
+        if self.playback.is_none() {
+            self.cpu.bus_mut().controller_mut(port).set_state(state);
+        }
+    }
+
+    /// Starts recording the controller states of each frame
+    pub fn attach_recorder(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Stops recording, returning the recording
+    pub fn detach_recorder(&mut self) -> Option<Recorder> {
+        self.recorder.take()
+    }
+
+    /// Plays back a recording, which overrides the controller states set by the frontend until it runs out
+    pub fn attach_playback(&mut self, mut playback: Recorder) {
+        // the current frame gets the first recorded states
+        if let Some(states) = playback.next_frame() {
+            self.set_controller_states(states);
+        }
+        self.playback = Some(playback);
+    }
+
+    /// Records the finished frame's input, and loads the next frame's input from the playback
+    fn end_frame(&mut self) {
+        if let Some(recorder) = &mut self.recorder {
+            let bus = self.cpu.bus();
+            recorder.record([bus.controller(0).state(), bus.controller(1).state()]);
+        }
+
+        let next = self.playback.as_mut().and_then(|playback| playback.next_frame());
+        match next {
+            Some(states) => self.set_controller_states(states),
+            None => self.playback = None,
+        }
+
+        if let Some(mut rewind) = self.rewind.take() {
+            if rewind.end_frame() {
+                rewind.push(self.save_state());
+            }
+            self.rewind = Some(rewind);
+        }
+    }
+
+    /// Sets the state of both controllers, whether or not a playback is attached
+    fn set_controller_states(&mut self, states: [ControllerState; 2]) {
+        let bus = self.cpu.bus_mut();
+        bus.controller_mut(0).set_state(states[0]);
+        bus.controller_mut(1).set_state(states[1]);
+    }
+
+    /// Returns a copy of the cartridge's battery-backed RAM, for the frontend to write out when the game is closed
+    /// Returns `None` if the cartridge has no battery, or no RAM for it to back.
+    pub fn save_sram(&self) -> Option<Vec<u8>> {
+        if !self.battery {
+            return None;
+        }
+        let mapper = self.cpu.bus().mapper()?;
+        mapper.prg_ram().map(|ram| ram.to_vec())
+    }
+
+    /// Restores the cartridge's battery-backed RAM from a copy made by `save_sram`, normally before the game starts
+    /// Returns an error if the cartridge has no battery-backed RAM, or if `sram` isn't the same size as it.
+    pub fn load_sram(&mut self, sram: &[u8]) -> Result<(), String> {
+        if !self.battery {
+            return Err(String::from("The cartridge has no battery-backed RAM"));
+        }
+        let mut mapper = self.cpu.bus_mut().mapper_mut().ok_or_else(|| String::from("No cartridge is inserted"))?;
+        let ram = mapper.prg_ram_mut().ok_or_else(|| String::from("The cartridge has no battery-backed RAM"))?;
+        if ram.len() != sram.len() {
+            return Err(format!("Save RAM is {} bytes, but the cartridge has {} bytes", sram.len(), ram.len()));
+        }
+        ram.copy_from_slice(sram);
+        Ok(())
+    }
+
+    /// Saves the state of the whole machine
+    /// The state doesn't include the cartridge's ROM, so it can only be loaded back into a machine running the same game.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        for byte in STATE_MAGIC.iter() {
+            writer.write_u8(*byte);
+        }
+        writer.write_u32(STATE_VERSION);
+        self.cpu.save_state(&mut writer);
+        writer.write_i64(self.cycles);
+        writer.write_u64(self.frame_cycles);
+        writer.into_bytes()
+    }
+
+    /// Restores the state of the whole machine from one produced by `save_state`
+    /// Returns an error if the state is malformed; the machine should then be reset or loaded with another state, as it may be partially restored.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        let mut reader = StateReader::new(state);
+        for byte in STATE_MAGIC.iter() {
+            if reader.read_u8()? != *byte {
+                return Err(String::from("Not a save state"));
+            }
+        }
+        let version = reader.read_u32()?;
+        if version != STATE_VERSION {
+            return Err(format!("Save state version {} is not supported (expected version {})", version, STATE_VERSION));
+        }
+        self.cpu.load_state(&mut reader)?;
+        self.cycles = reader.read_i64()?;
+        self.frame_cycles = reader.read_u64()?;
+        if !reader.is_finished() {
+            return Err(String::from("Save state has trailing data"));
+        }
+        Ok(())
+    }
+
+    /// Starts keeping snapshots for rewinding, covering roughly the last `capacity_frames` frames
+    /// A snapshot is taken every few frames rather than every frame, so rewinding steps back several frames at a time.
+    pub fn enable_rewind(&mut self, capacity_frames: usize) {
+        let capacity = capacity_frames.div_ceil(REWIND_INTERVAL as usize);
+        self.rewind = Some(Rewind::new(capacity, REWIND_INTERVAL));
+    }
+
+    /// Stops rewinding, discarding the snapshots
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+    }
+
+    /// Restores the most recent snapshot and discards it, so repeated calls step further back
+    /// Returns false if rewinding isn't enabled or there are no snapshots left.
+    /// Input playback isn't rewound, so a replay should be detached before rewinding.
+    pub fn rewind(&mut self) -> bool {
+        let state = match self.rewind.as_mut().and_then(|rewind| rewind.pop()) {
+            Some(state) => state,
+            None => return false,
+        };
+        self.load_state(&state).is_ok()
+    }
+
+    /// Returns a hash of the machine's state, for quickly checking whether two machines (or two runs) are in the same state
+    /// This covers everything a save state does: the registers, RAM, VRAM, OAM, and palette, the cartridge's banks and RAM, and the timing of each device.
+    /// The hash is stable across platforms and builds, as long as the save state format doesn't change.
+    pub fn state_hash(&self) -> u64 {
+        let mut writer = StateWriter::new();
+        self.cpu.save_state(&mut writer);
+        writer.write_i64(self.cycles);
+        writer.write_u64(self.frame_cycles);
+        state::hash(&writer.into_bytes())
+    }
+
+    /// Returns the number of CPU cycles run since the start of the current frame
+    pub fn cycles_this_frame(&self) -> u64 {
+        self.frame_cycles
+    }
+
+    /// Runs the system in real time until the CPU stops, returning why it stopped
+    /// This is a thin wrapper around `run_frame` that sleeps until each frame is due; the emulation itself never sleeps, so frontends with their own timing (and tests, which want none) can drive `run_frame` or `clock` directly.
+    pub fn run_realtime(&mut self) -> RunOutcome {
+        let frame_duration = Duration::from_nanos(self.region.frame_nanos());
+        let mut deadline = Instant::now();
+        while self.cpu.is_running() {
+            self.run_frame();
+            deadline += frame_duration;
+            let now = Instant::now();
+            if deadline > now {
+                sleep(deadline - now);
+            } else {
+                // we've fallen behind; rather than rushing to catch up, continue from here
+                deadline = now;
+            }
+        }
+        self.cpu.run_outcome()
+    }
+}
+
+/// The first line at which a CPU trace departs from a reference log, as found by `compare_trace`
+#[derive(PartialEq, Eq)]
+#[derive(Debug, Clone)]
+pub struct TraceMismatch {
+    /// The line of the reference log, counting from 1
+    pub line: usize,
+    /// The line the reference log expected
+    pub expected: String,
+    /// The line the CPU produced, or why it couldn't produce one
+    pub actual: String,
+    /// The last line that matched, if any, for context
+    pub previous: Option<String>,
+}
+
+impl fmt::Display for TraceMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "trace mismatch at line {}", self.line)?;
+        if let Some(previous) = &self.previous {
+            writeln!(f, "   after: {}", previous)?;
+        }
+        writeln!(f, "expected: {}", self.expected)?;
+        write!(f, "  actual: {}", self.actual)
+    }
+}
+
+/// Runs the system an instruction at a time, comparing the trace of each instruction (see `CPU::trace`) with a line of a reference log, such as nestest's golden log
+/// Blank lines and trailing whitespace are ignored, as are the PPU columns nestest.log has and the CPU's trace doesn't.
+/// Returns the first line that doesn't match; if the CPU stops before the end of the reference, the next line is reported as a mismatch.
+pub fn compare_trace(nes: &mut NES, reference: &str) -> Result<(), TraceMismatch> {
+    let mut previous = None;
+    for (index, line) in reference.lines().enumerate() {
+        let expected = without_ppu_columns(line.trim_end());
+        if expected.is_empty() {
+            continue;
+        }
+
+        let actual = match nes.cpu.stop_reason() {
+            Some(reason) => format!("(the CPU stopped: {})", reason),
+            None => nes.cpu.trace(),
+        };
+        if actual.trim_end() != expected {
+            return Err(TraceMismatch {
+                line: index + 1,
+                expected,
+                actual,
+                previous,
+            });
+        }
+
+        nes.step();
+        previous = Some(expected);
+    }
+    Ok(())
+}
+
+/// Removes the `PPU:` columns from a line of nestest.log, leaving the columns the CPU's trace has
+fn without_ppu_columns(line: &str) -> String {
+    match (line.find(" PPU:"), line.find(" CYC:")) {
+        (Some(start), Some(end)) if start < end => format!("{}{}", &line[..start], &line[end..]),
+        _ => line.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{StopReason, RESET_VECTOR};
+    use crate::input::Button;
+    use crate::mem::Mem;
+
+    /// Creates a system running `code` from `0xC000`, powered on
+    fn nes_with_program(code: &[u8]) -> NES {
+        nes_in_region(code, Region::Ntsc)
+    }
+
+    /// Creates a system like `nes_with_program`, for a console of the given region
+    fn nes_in_region(code: &[u8], region: Region) -> NES {
+        let mut cpu = CPU::default();
+        cpu.load_program(0xc000, code).unwrap();
+        cpu.load_vector(RESET_VECTOR, 0xc000);
+        cpu.power_on();
+        NES::new(cpu, region)
+    }
+
+    /// LDX #$05; INX; JMP $C000
+    const COUNTING_LOOP: [u8; 6] = [0xa2, 0x05, 0xe8, 0x4c, 0x00, 0xc0];
+
+    #[test]
+    fn run_frame_reports_halt_reason_and_pc() {
+        // NOP; XAA #$00 jams the CPU at $C001
+        let mut nes = nes_with_program(&[0xea, 0x8b, 0x00]);
+        assert_eq!(nes.run_frame(), RunOutcome::Halted { reason: StopReason::Halt, pc: 0xc001 });
+    }
+
+    #[test]
+    fn clock_reports_illegal_opcode() {
+        // NOP; NOP; then the unimplemented opcode $02 at $C002
+        let mut nes = nes_with_program(&[0xea, 0xea, 0x02]);
+        assert_eq!(nes.clock(12), RunOutcome::Completed);
+        assert_eq!(nes.clock(1000), RunOutcome::Halted { reason: StopReason::IllegalOpcode(0x02, 0xc002), pc: 0xc002 });
+    }
+
+    #[test]
+    fn clock_steps_cpu_once_per_twelve_master_cycles() {
+        let mut nes = nes_with_program(&COUNTING_LOOP);
+        let start = nes.cpu.cycle_count();
+        nes.clock(12_000);
+        let elapsed = nes.cpu.cycle_count() - start;
+        // instructions run whole, so the CPU may be up to one instruction ahead
+        assert!((1000..=1003).contains(&elapsed), "{} cycles", elapsed);
+        nes.clock(12_000);
+        let elapsed = nes.cpu.cycle_count() - start;
+        assert!((2000..=2003).contains(&elapsed), "{} cycles", elapsed);
+    }
+
+    #[test]
+    fn cycles_this_frame_wraps_each_frame() {
+        let mut nes = nes_with_program(&COUNTING_LOOP);
+        let per_frame = Region::Ntsc.cpu_cycles_per_frame();
+        let start = nes.cpu.cycle_count();
+        nes.clock(per_frame * 12 / 2);
+        let halfway = nes.cycles_this_frame();
+        assert!(halfway > 0 && halfway < per_frame);
+        // after another whole frame, the count has wrapped around rather than kept growing
+        nes.clock(per_frame * 12);
+        let elapsed = nes.cpu.cycle_count() - start;
+        assert_eq!(nes.cycles_this_frame(), elapsed % per_frame);
+        assert!(nes.cycles_this_frame() < per_frame);
+    }
+
+    #[test]
+    fn reset_cycle_count_starts_a_new_window() {
+        let mut nes = nes_with_program(&COUNTING_LOOP);
+        nes.clock(12_000);
+        assert!(nes.cpu.cycle_count() >= 1000);
+        nes.cpu.reset_cycle_count();
+        nes.clock(12_000);
+        assert!((1000..=1003).contains(&nes.cpu.cycle_count()));
+    }
+
+    /// Enables NMIs and loops forever; the NMI handler at `0xC010` counts NMIs in `0x0000`
+    fn nmi_counting_program() -> Vec<u8> {
+        let mut code = vec![0xea; 0x3ffc];
+        // LDA #$80; STA $2000; JMP $C005
+        code[..8].copy_from_slice(&[0xa9, 0x80, 0x8d, 0x00, 0x20, 0x4c, 0x05, 0xc0]);
+        // INC $00; RTI
+        code[0x10..0x13].copy_from_slice(&[0xe6, 0x00, 0x40]);
+        code[0x3ffa] = 0x10;
+        code[0x3ffb] = 0xc0;
+        code
+    }
+
+    #[test]
+    fn vblank_nmi_reaches_cpu_once_per_frame() {
+        let mut nes = nes_with_program(&nmi_counting_program());
+        let frame = Region::Ntsc.cpu_cycles_per_frame() * Region::Ntsc.cpu_clock_factor() as u64;
+        nes.clock(frame);
+        assert_eq!(nes.cpu.bus().peek(0x0000), 1);
+        nes.clock(frame * 2);
+        assert_eq!(nes.cpu.bus().peek(0x0000), 3);
+    }
+
+    /// LDA #$80; STA $2000; LDA $2002; JMP $C008, with the NMI handler from `nmi_counting_program`
+    fn ppustatus_polling_program() -> Vec<u8> {
+        let mut code = nmi_counting_program();
+        code[..11].copy_from_slice(&[0xa9, 0x80, 0x8d, 0x00, 0x20, 0xad, 0x02, 0x20, 0x4c, 0x08, 0xc0]);
+        code
+    }
+
+    /// Runs the first two instructions of `ppustatus_polling_program`, then moves the PPU to `dot` of `scanline`, ready for the read of PPUSTATUS
+    fn nes_polling_from(scanline: u16, dot: u16) -> NES {
+        let mut nes = nes_with_program(&ppustatus_polling_program());
+        nes.step();
+        nes.step();
+        let ppu = nes.ppu_mut();
+        while ppu.scanline() != scanline || ppu.dot() != dot {
+            ppu.clock();
+        }
+        nes
+    }
+
+    #[test]
+    fn ppustatus_read_sees_the_ppu_partway_through_the_instruction() {
+        // the read is on the fourth cycle of LDA $2002, 9 dots in, by which time vblank has started
+        let mut nes = nes_polling_from(241, 0);
+        nes.step();
+        assert_eq!(nes.cpu.a() & 0x80, 0x80);
+    }
+
+    #[test]
+    fn ppustatus_read_racing_vblank_suppresses_the_nmi() {
+        // from dot 333, the read happens on the dot the flag is set
+        let mut nes = nes_polling_from(240, 333);
+        for _ in 0..10 {
+            nes.step();
+        }
+        assert_eq!(nes.cpu.a() & 0x80, 0);
+        assert_eq!(nes.cpu.bus().peek(0x0000), 0);
+
+        // a read 3 dots earlier misses the flag, and the NMI happens
+        let mut nes = nes_polling_from(240, 330);
+        for _ in 0..10 {
+            nes.step();
+        }
+        assert_eq!(nes.cpu.bus().peek(0x0000), 1);
+    }
+
+    /// Strobes the controllers, reads the first controller eight times into `0x0000 - 0x0007`, then loops forever
+    fn controller_reading_program() -> Vec<u8> {
+        // LDA #$01; STA $4016; LDA #$00; STA $4016; LDX #$00
+        let mut code = vec![0xa9, 0x01, 0x8d, 0x16, 0x40, 0xa9, 0x00, 0x8d, 0x16, 0x40, 0xa2, 0x00];
+        // loop: LDA $4016; STA $00,X; INX; CPX #$08; BNE loop; JMP self
+        code.extend_from_slice(&[0xad, 0x16, 0x40, 0x95, 0x00, 0xe8, 0xe0, 0x08, 0xd0, 0xf6, 0x4c, 0x16, 0xc0]);
+        code
+    }
+
+    #[test]
+    fn controller_state_is_read_through_cpu_instructions() {
+        let mut nes = nes_with_program(&controller_reading_program());
+        nes.set_controller_state(0, ControllerState::default().with(Button::A).with(Button::Up));
+        for _ in 0..50 {
+            nes.step();
+        }
+
+        let bits: Vec<u8> = (0..8).map(|address| nes.cpu.bus().peek(address)).collect();
+        // the top three bits are open bus, left holding the high byte of the address, 0x40
+        assert_eq!(bits, vec![0x41, 0x40, 0x40, 0x40, 0x41, 0x40, 0x40, 0x40]);
+    }
+
+    /// The input a test recording holds for `frame`: a different combination of buttons on each controller every frame
+    fn recorded_input(frame: usize) -> [ControllerState; 2] {
+        [ControllerState::from_bits(frame as u8), ControllerState::from_bits((frame * 7) as u8)]
+    }
+
+    #[test]
+    fn recorded_input_is_played_back_frame_by_frame() {
+        let mut nes = nes_with_program(&COUNTING_LOOP);
+        nes.attach_recorder(Recorder::new());
+        for frame in 0..60 {
+            let [first, second] = recorded_input(frame);
+            nes.set_controller_state(0, first);
+            nes.set_controller_state(1, second);
+            nes.run_frame();
+        }
+        let bytes = nes.detach_recorder().unwrap().to_bytes();
+        assert_eq!(bytes.len(), 120);
+
+        let mut nes = nes_with_program(&COUNTING_LOOP);
+        nes.attach_playback(Recorder::from_bytes(&bytes).unwrap());
+        for frame in 0..60 {
+            let bus = nes.cpu.bus();
+            assert_eq!([bus.controller(0).state(), bus.controller(1).state()], recorded_input(frame));
+            nes.run_frame();
+        }
+    }
+
+    #[test]
+    fn live_input_is_ignored_during_playback() {
+        let mut playback = Recorder::new();
+        playback.record(recorded_input(3));
+        playback.record(recorded_input(4));
+
+        let mut nes = nes_with_program(&COUNTING_LOOP);
+        nes.attach_playback(playback);
+        nes.set_controller_state(0, ControllerState::default().with(Button::Start));
+        assert_eq!(nes.cpu.bus().controller(0).state(), recorded_input(3)[0]);
+
+        // once the recording runs out, the frontend's input is used again
+        nes.run_frame();
+        nes.run_frame();
+        nes.set_controller_state(0, ControllerState::default().with(Button::Start));
+        assert!(nes.cpu.bus().controller(0).is_pressed(Button::Start));
+    }
+
+    /// The first instructions of `COUNTING_LOOP`, as nestest.log would show them
+    const COUNTING_LOOP_TRACE: &str = "\
+C000  A2 05     LDX #$05                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+C002  E8        INX                             A:00 X:05 Y:00 P:24 SP:FD PPU:  0, 27 CYC:9
+C003  4C 00 C0  JMP $C000                       A:00 X:06 Y:00 P:24 SP:FD PPU:  0, 33 CYC:11
+C000  A2 05     LDX #$05                        A:00 X:06 Y:00 P:24 SP:FD PPU:  0, 42 CYC:14
+";
+
+    #[test]
+    fn compare_trace_accepts_matching_log() {
+        let mut nes = nes_with_program(&COUNTING_LOOP);
+        assert_eq!(compare_trace(&mut nes, COUNTING_LOOP_TRACE), Ok(()));
+        assert_eq!(nes.cpu.pc(), 0xc002);
+    }
+
+    #[test]
+    fn compare_trace_reports_first_mismatch() {
+        // the log claims INX left X at 7
+        let reference = COUNTING_LOOP_TRACE.replace("X:06 Y:00 P:24 SP:FD PPU:  0, 33", "X:07 Y:00 P:24 SP:FD PPU:  0, 33");
+        let mut nes = nes_with_program(&COUNTING_LOOP);
+        let mismatch = compare_trace(&mut nes, &reference).unwrap_err();
+        assert_eq!(mismatch.line, 3);
+        assert!(mismatch.expected.contains("X:07"));
+        assert!(mismatch.actual.contains("X:06"));
+        assert!(mismatch.previous.unwrap().starts_with("C002  E8"));
+    }
+
+    #[test]
+    fn loading_a_state_rewinds_the_machine() {
+        let mut nes = nes_with_program(&nmi_counting_program());
+        nes.clock(100_000);
+        let saved = nes.save_state();
+        let saved_hash = nes.state_hash();
+        nes.clock(50_000);
+        let later = nes.save_state();
+
+        nes.load_state(&saved).unwrap();
+        assert_eq!(nes.state_hash(), saved_hash);
+        assert_eq!(nes.save_state(), saved);
+        // re-running from the saved point arrives at the same state again
+        nes.clock(50_000);
+        assert_eq!(nes.save_state(), later);
+    }
+
+    #[test]
+    fn state_loads_into_a_fresh_machine() {
+        let mut nes = nes_with_program(&nmi_counting_program());
+        nes.run_frame();
+        nes.run_frame();
+        let saved = nes.save_state();
+
+        let mut other = nes_with_program(&nmi_counting_program());
+        other.load_state(&saved).unwrap();
+        assert_eq!(other.state_hash(), nes.state_hash());
+        nes.run_frame();
+        other.run_frame();
+        assert_eq!(other.state_hash(), nes.state_hash());
+        assert_eq!(other.cpu.bus().peek(0x0000), 3);
+    }
+
+    #[test]
+    fn malformed_states_are_rejected() {
+        let mut nes = nes_with_program(&COUNTING_LOOP);
+        let mut state = nes.save_state();
+        assert_eq!(nes.load_state(b"NOPE"), Err(String::from("Not a save state")));
+        assert!(nes.load_state(&state[..state.len() - 1]).is_err());
+        state.push(0);
+        assert_eq!(nes.load_state(&state), Err(String::from("Save state has trailing data")));
+    }
+
+    #[test]
+    fn state_with_another_version_is_rejected() {
+        let mut nes = nes_with_program(&COUNTING_LOOP);
+        let mut state = nes.save_state();
+        state[4..8].copy_from_slice(&(STATE_VERSION + 1).to_le_bytes());
+        let error = nes.load_state(&state).unwrap_err();
+        assert!(error.contains(&format!("version {}", STATE_VERSION + 1)), "{}", error);
+    }
+
+    #[test]
+    fn state_hash_of_a_fixed_run_is_known() {
+        // if this changes, either emulation or the state format has changed; check which before updating it
+        let mut nes = nes_with_program(&nmi_counting_program());
+        for _ in 0..10 {
+            nes.run_frame();
+        }
+        assert_eq!(nes.state_hash(), 0x69beea3ffcdc8cc3);
+    }
+
+    #[test]
+    fn state_hash_tells_states_apart() {
+        let mut nes = nes_with_program(&COUNTING_LOOP);
+        let before = nes.state_hash();
+        assert_eq!(nes.state_hash(), before);
+        nes.step();
+        assert_ne!(nes.state_hash(), before);
+    }
+
+    /// Returns the registers and the NMI count of `nes`, to compare against earlier points of a run
+    fn checkpoint(nes: &NES) -> (u16, u8, u8, u8) {
+        (nes.cpu.pc(), nes.cpu.a(), nes.cpu.sp(), nes.cpu.bus().peek(0x0000))
+    }
+
+    #[test]
+    fn rewind_restores_earlier_snapshots_newest_first() {
+        let mut nes = nes_with_program(&nmi_counting_program());
+        assert!(!nes.rewind());
+        nes.enable_rewind(60);
+
+        // a snapshot is taken every REWIND_INTERVAL frames
+        let mut checkpoints = vec![];
+        for frame in 1..=20 {
+            nes.run_frame();
+            if frame % REWIND_INTERVAL == 0 {
+                checkpoints.push(checkpoint(&nes));
+            }
+        }
+
+        for expected in checkpoints.iter().rev().take(3) {
+            assert!(nes.rewind());
+            assert_eq!(checkpoint(&nes), *expected);
+        }
+        // the machine keeps running from the restored point
+        let count = nes.cpu.bus().peek(0x0000);
+        nes.run_frame();
+        assert_eq!(nes.cpu.bus().peek(0x0000), count + 1);
+    }
+
+    #[test]
+    fn rewind_only_reaches_back_as_far_as_its_capacity() {
+        let mut nes = nes_with_program(&nmi_counting_program());
+        nes.enable_rewind(8);
+        for _ in 0..40 {
+            nes.run_frame();
+        }
+        assert!(nes.rewind());
+        assert!(nes.rewind());
+        assert!(!nes.rewind());
+    }
+
+    #[test]
+    fn run_frame_advances_exactly_one_frame() {
+        let mut nes = nes_with_program(&COUNTING_LOOP);
+        nes.run_frame();
+        let cycles_per_frame = Region::Ntsc.cpu_cycles_per_frame();
+        for _ in 0..2 {
+            let frame = nes.ppu().frame_count();
+            let cycles = nes.cpu.cycle_count();
+            assert_eq!(nes.run_frame(), RunOutcome::Completed);
+            assert_eq!(nes.ppu().frame_count(), frame + 1);
+            // instructions run whole, so a frame may end a few cycles late (or early, after a late one)
+            let elapsed = nes.cpu.cycle_count() - cycles;
+            assert!(elapsed.abs_diff(cycles_per_frame) <= 4, "{} cycles", elapsed);
+        }
+    }
+
+    #[test]
+    fn run_frame_leaves_a_frame_of_audio() {
+        let mut nes = nes_with_program(&COUNTING_LOOP);
+        nes.run_frame();
+        let mut samples = vec![0.0; 4096];
+        nes.cpu.bus_mut().apu_mut().drain_samples(&mut samples);
+
+        nes.run_frame();
+        let count = nes.cpu.bus_mut().apu_mut().drain_samples(&mut samples);
+        // 44.1kHz at about 60 frames a second
+        assert!((730..=738).contains(&count), "{} samples", count);
+    }
+
+    #[test]
+    fn frames_do_not_drift() {
+        let mut nes = nes_with_program(&COUNTING_LOOP);
+        let start = nes.cpu.cycle_count();
+        for _ in 0..120 {
+            nes.run_frame();
+        }
+        // leftover cycles carry over, so the total stays within an instruction of the exact length
+        let exact = 120 * 341 * 262 / 3;
+        assert!((nes.cpu.cycle_count() - start).abs_diff(exact) <= 4);
+    }
+
+    #[test]
+    fn run_frame_does_not_wait_for_real_time() {
+        let mut nes = nes_with_program(&COUNTING_LOOP);
+        let frames = 600;
+        let start = Instant::now();
+        for _ in 0..frames {
+            nes.run_frame();
+        }
+        // paced to the console, these frames would take ten seconds
+        let real_time = Duration::from_nanos(Region::Ntsc.frame_nanos() * frames);
+        assert!(start.elapsed() < real_time / 2, "{:?}", start.elapsed());
+        assert_eq!(nes.ppu().frame_count(), frames);
+    }
+
+    #[test]
+    fn pal_machine_has_312_scanlines_at_50_hz() {
+        let mut nes = nes_in_region(&COUNTING_LOOP, Region::Pal);
+        assert_eq!(nes.ppu().scanlines_per_frame(), 312);
+        nes.run_frame();
+
+        let frame = nes.ppu().frame_count();
+        let cycles = nes.cpu.cycle_count();
+        let mut last_scanline = 0;
+        while nes.ppu().frame_count() < frame + 10 {
+            nes.step();
+            last_scanline = last_scanline.max(nes.ppu().scanline());
+        }
+        assert_eq!(last_scanline, 311);
+
+        // a PAL frame is 341 * 312 dots of 5 master cycles, or 33247.5 CPU cycles of 16
+        let elapsed = nes.cpu.cycle_count() - cycles;
+        assert!(elapsed.abs_diff(332_475) <= 4, "{} cycles", elapsed);
+        let seconds = elapsed as f64 / Region::Pal.cpu_clock_rate() as f64;
+        assert!((seconds - 0.2).abs() < 0.001, "{} seconds", seconds);
+        assert!((Region::Pal.frame_rate() - 50.007).abs() < 0.001);
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn frame_png_decodes_to_the_rendered_frame() {
+        // with rendering off, the whole frame shows the backdrop colour, $16 (red)
+        let mut nes = nes_with_program(&COUNTING_LOOP);
+        let bus = nes.cpu.bus_mut();
+        bus.write(0x2006, 0x3f);
+        bus.write(0x2006, 0x00);
+        bus.write(0x2007, 0x16);
+        // while the address points at the palette, it's shown in place of the backdrop, so move it away
+        bus.write(0x2006, 0x00);
+        bus.write(0x2006, 0x00);
+        nes.run_frame();
+        nes.run_frame();
+
+        let (width, height, rgba) = crate::png::tests::decode_rgba(&nes.frame_png());
+        assert_eq!((width, height), (256, 240));
+        let pixel = (120 * 256 + 100) * 4;
+        let (r, g, b) = crate::ppu::palette::NTSC_PALETTE[0x16];
+        assert_eq!(rgba[pixel..pixel + 4], [r, g, b, 0xff]);
+        assert!(rgba == nes.ppu().frame_rgba());
+
+        let path = std::env::temp_dir().join(format!("rust_nes_frame_{}.png", std::process::id()));
+        nes.write_frame_png(&path).unwrap();
+        let written = std::fs::read(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(written.unwrap() == nes.frame_png());
+    }
+}
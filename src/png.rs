@@ -0,0 +1,201 @@
+// png.rs
+// Encodes pictures as PNG images, for screenshots
+
+use std::fs;
+use std::path::Path;
+
+/// Every PNG file begins with this signature
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+/// IHDR colour type 6: each pixel is red, green, blue, and alpha
+const COLOUR_TYPE_RGBA: u8 = 6;
+
+/// A stored deflate block holds at most 65535 bytes
+const MAX_STORED_BLOCK: usize = 0xffff;
+
+/// Encodes an RGBA picture, four bytes per pixel in rows from the top, as a PNG image
+/// The image data is stored uncompressed, which keeps the encoder small; screenshots are only about 250KB.
+///
+/// # Panics
+///
+/// Panics if `rgba` isn't exactly `width * height * 4` bytes.
+pub fn encode_rgba(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    let stride = width as usize * 4;
+    assert_eq!(rgba.len(), stride * height as usize, "RGBA data doesn't match the picture's dimensions");
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 8 bits per channel, RGBA, deflate compression, adaptive filtering, and no interlacing
+    header.extend_from_slice(&[8, COLOUR_TYPE_RGBA, 0, 0, 0]);
+
+    // each row is preceded by its filter type; 0 leaves the row unfiltered
+    let mut scanlines = Vec::with_capacity(rgba.len() + height as usize);
+    for row in rgba.chunks_exact(stride.max(1)) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Encodes an RGBA picture as `encode_rgba` does, and writes it to the file at `path`
+pub fn write_rgba(path: &Path, width: u32, height: u32, rgba: &[u8]) -> Result<(), String> {
+    fs::write(path, encode_rgba(width, height, rgba)).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
+
+/// Appends a chunk: its length, type, and data, followed by a CRC of the type and data
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wraps `data` in a zlib stream made of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // a 32KB window with no preset dictionary; the check bits make the header a multiple of 31
+    let mut stream = vec![0x78, 0x01];
+
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        // an empty stream still needs one (final) block
+        stream.extend_from_slice(&[0x01, 0x00, 0x00, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let length = block.len() as u16;
+        stream.push(last as u8);
+        stream.extend_from_slice(&length.to_le_bytes());
+        stream.extend_from_slice(&(!length).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+/// Computes the CRC-32 (as used by PNG and zlib) of `bytes`
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for byte in bytes.iter() {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb88320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Computes the Adler-32 checksum that ends a zlib stream
+fn adler32(bytes: &[u8]) -> u32 {
+    const MODULUS: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in bytes.iter() {
+        a = (a + *byte as u32) % MODULUS;
+        b = (b + a) % MODULUS;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Reads a big-endian u32 at `offset`
+    fn be_u32(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+    }
+
+    /// Decodes a PNG made by `encode_rgba`, checking its checksums, and returns its width, height, and RGBA data
+    /// Only what the encoder produces is understood: RGBA at 8 bits per channel, unfiltered rows, and stored deflate blocks.
+    pub(crate) fn decode_rgba(png: &[u8]) -> (u32, u32, Vec<u8>) {
+        assert_eq!(png[..8], SIGNATURE);
+        let (mut width, mut height) = (0, 0);
+        let mut zlib = Vec::new();
+        let mut offset = 8;
+        loop {
+            let length = be_u32(png, offset) as usize;
+            let kind = &png[offset + 4..offset + 8];
+            let data = &png[offset + 8..offset + 8 + length];
+            assert_eq!(be_u32(png, offset + 8 + length), crc32(&png[offset + 4..offset + 8 + length]), "bad CRC");
+            offset += 12 + length;
+            match kind {
+                b"IHDR" => {
+                    width = be_u32(data, 0);
+                    height = be_u32(data, 4);
+                    assert_eq!(data[8..], [8, COLOUR_TYPE_RGBA, 0, 0, 0]);
+                },
+                b"IDAT" => zlib.extend_from_slice(data),
+                b"IEND" => break,
+                _ => panic!("unexpected chunk"),
+            }
+        }
+        assert_eq!(offset, png.len());
+
+        // the zlib header must be a multiple of 31
+        assert_eq!(((zlib[0] as u16) << 8 | zlib[1] as u16) % 31, 0);
+        let mut scanlines = Vec::new();
+        let mut position = 2;
+        loop {
+            let last = zlib[position] & 1 != 0;
+            assert_eq!(zlib[position] & 0x06, 0, "only stored blocks are expected");
+            let length = u16::from_le_bytes([zlib[position + 1], zlib[position + 2]]);
+            let complement = u16::from_le_bytes([zlib[position + 3], zlib[position + 4]]);
+            assert_eq!(length, !complement);
+            position += 5;
+            scanlines.extend_from_slice(&zlib[position..position + length as usize]);
+            position += length as usize;
+            if last {
+                break;
+            }
+        }
+        assert_eq!(be_u32(&zlib, position), adler32(&scanlines));
+
+        let stride = width as usize * 4;
+        let mut rgba = Vec::new();
+        for row in scanlines.chunks_exact(stride + 1) {
+            assert_eq!(row[0], 0, "rows should be unfiltered");
+            rgba.extend_from_slice(&row[1..]);
+        }
+        assert_eq!(rgba.len(), stride * height as usize);
+        (width, height, rgba)
+    }
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32(b"IEND"), 0xae426082);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+    }
+
+    #[test]
+    fn encoded_picture_decodes_to_the_same_pixels() {
+        let rgba: Vec<u8> = (0..3 * 2 * 4).map(|i| i as u8 * 7).collect();
+        let (width, height, decoded) = decode_rgba(&encode_rgba(3, 2, &rgba));
+        assert_eq!((width, height), (3, 2));
+        assert_eq!(decoded, rgba);
+    }
+
+    #[test]
+    fn large_pictures_span_several_stored_blocks() {
+        let rgba: Vec<u8> = (0..256 * 240 * 4).map(|i| (i % 251) as u8).collect();
+        let (width, height, decoded) = decode_rgba(&encode_rgba(256, 240, &rgba));
+        assert_eq!((width, height), (256, 240));
+        assert!(decoded == rgba);
+    }
+
+    #[test]
+    fn empty_picture_is_valid() {
+        let (width, height, decoded) = decode_rgba(&encode_rgba(0, 0, &[]));
+        assert_eq!((width, height), (0, 0));
+        assert!(decoded.is_empty());
+    }
+}
@@ -1,6 +1,1927 @@
 // ppu.rs
 // Implements the Picture Processing Unit
 
-mod ppu {
-    // todo: everything
+pub mod palette;
+
+use crate::mapper::{Mirroring, SharedMapper};
+use crate::nes::Region;
+use crate::state::{self, SaveState, StateReader, StateWriter};
+use palette::NTSC_PALETTE;
+
+/// The size of the PPU's internal VRAM, which holds two nametables
+const VRAM_SIZE: usize = 0x800;
+/// The size of the palette RAM
+const PALETTE_SIZE: usize = 0x20;
+/// The size of the object attribute memory, which holds 64 four-byte sprites
+pub const OAM_SIZE: usize = 0x100;
+
+/// The width of the picture, in pixels
+pub const SCREEN_WIDTH: usize = 256;
+/// The height of the picture, in pixels
+pub const SCREEN_HEIGHT: usize = 240;
+
+/// Each scanline is 341 PPU dots long
+pub const DOTS_PER_SCANLINE: u16 = 341;
+
+/// PPUCTRL bit 2: increment the VRAM address by 32 (one row) rather than 1 after each PPUDATA access
+const CTRL_INCREMENT: u8 = 0b00000100;
+/// PPUCTRL bit 3: 8x8 sprites use the pattern table at `0x1000` rather than `0x0000`
+const CTRL_SPRITE_TABLE: u8 = 0b00001000;
+/// PPUCTRL bit 4: the background uses the pattern table at `0x1000` rather than `0x0000`
+const CTRL_BACKGROUND_TABLE: u8 = 0b00010000;
+/// PPUCTRL bit 5: sprites are 8x16 rather than 8x8
+const CTRL_SPRITE_SIZE: u8 = 0b00100000;
+/// PPUCTRL bit 7: generate an NMI at the start of vblank
+const CTRL_NMI_ENABLE: u8 = 0b10000000;
+
+/// PPUMASK bit 0: display in greyscale
+const MASK_GREYSCALE: u8 = 0b00000001;
+/// PPUMASK bit 1: show the background in the leftmost 8 pixels of the screen
+const MASK_SHOW_BACKGROUND_LEFT: u8 = 0b00000010;
+/// PPUMASK bit 2: show sprites in the leftmost 8 pixels of the screen
+const MASK_SHOW_SPRITES_LEFT: u8 = 0b00000100;
+/// PPUMASK bit 3: show the background
+const MASK_SHOW_BACKGROUND: u8 = 0b00001000;
+/// PPUMASK bit 4: show sprites
+const MASK_SHOW_SPRITES: u8 = 0b00010000;
+/// PPUMASK bit 5: emphasize red
+const MASK_EMPHASIZE_RED: u8 = 0b00100000;
+/// PPUMASK bit 6: emphasize green
+const MASK_EMPHASIZE_GREEN: u8 = 0b01000000;
+/// PPUMASK bit 7: emphasize blue
+const MASK_EMPHASIZE_BLUE: u8 = 0b10000000;
+
+/// How much colour emphasis attenuates the channels that aren't emphasized
+const EMPHASIS_ATTENUATION: f32 = 0.816328;
+
+/// The most sprites the PPU can draw on one scanline
+const SPRITES_PER_SCANLINE: usize = 8;
+/// The size of the secondary OAM, which holds the entries of the sprites found for the next scanline
+const SECONDARY_OAM_SIZE: usize = SPRITES_PER_SCANLINE * 4;
+
+/// The dot of each rendered scanline at which the mapper's scanline counter is clocked
+const MAPPER_IRQ_DOT: u16 = 260;
+
+/// Sprite attribute bit 5: the sprite is drawn behind the background
+const SPRITE_BEHIND_BACKGROUND: u8 = 0b00100000;
+/// Sprite attribute bit 6: the sprite is flipped horizontally
+const SPRITE_FLIP_HORIZONTAL: u8 = 0b01000000;
+/// Sprite attribute bit 7: the sprite is flipped vertically
+const SPRITE_FLIP_VERTICAL: u8 = 0b10000000;
+
+/// PPUSTATUS bit 7: the PPU is in vertical blank
+pub const STATUS_VBLANK: u8 = 0b10000000;
+/// PPUSTATUS bit 6: an opaque pixel of sprite 0 overlapped an opaque background pixel
+pub const STATUS_SPRITE_0_HIT: u8 = 0b01000000;
+/// PPUSTATUS bit 5: more than eight sprites were found on a scanline
+pub const STATUS_SPRITE_OVERFLOW: u8 = 0b00100000;
+
+/// How a rendered picture differs from a reference picture, as found by `PPU::framebuffer_diff`
+#[derive(PartialEq, Eq)]
+#[derive(Debug, Copy, Clone)]
+pub struct DiffStats {
+    /// The number of pixels that differ
+    pub count: usize,
+    /// The smallest rectangle holding every differing pixel, as the inclusive `(left, top, right, bottom)` coordinates; `None` if the pictures match
+    pub bounds: Option<(usize, usize, usize, usize)>,
+}
+
+/// The Picture Processing Unit.
+/// The CPU talks to the PPU through eight registers, mirrored through `0x2000 - 0x3FFF`:
+/// * `0x2000`: PPUCTRL (write)
+/// * `0x2001`: PPUMASK (write)
+/// * `0x2002`: PPUSTATUS (read)
+/// * `0x2003`: OAMADDR (write)
+/// * `0x2004`: OAMDATA (read/write)
+/// * `0x2005`: PPUSCROLL (write twice)
+/// * `0x2006`: PPUADDR (write twice)
+/// * `0x2007`: PPUDATA (read/write)
+///
+/// The PPU has its own 14-bit address space: the pattern tables at `0x0000 - 0x1FFF` (on the cartridge), the nametables at `0x2000 - 0x2FFF` (mirrored to `0x3EFF`), and the palettes at `0x3F00 - 0x3FFF`.
+pub struct PPU {
+    ctrl: u8,
+    mask: u8,
+    status: u8,
+    oam_addr: u8,
+
+    oam: [u8; OAM_SIZE],
+    vram: [u8; VRAM_SIZE],
+    // four-screen cartridges supply another 2KB of VRAM for the third and fourth nametables; empty otherwise
+    cartridge_vram: Vec<u8>,
+    palette: [u8; PALETTE_SIZE],
+    mapper: Option<SharedMapper>,
+
+    // the internal registers that hold the scroll position and VRAM address (named as in loopy's "The skinny on NES scrolling")
+    // v and t are laid out as `yyy NN YYYYY XXXXX`: fine Y, nametable, coarse Y, and coarse X
+    // the current VRAM address; during rendering, this is the position of the tile being fetched
+    v: u16,
+    // the temporary VRAM address, which PPUCTRL, PPUSCROLL, and PPUADDR write into, and which is copied into v
+    t: u16,
+    // the fine X scroll, which selects the bit of the background shift registers to draw
+    x: u8,
+    // PPUSCROLL and PPUADDR share a latch selecting which of the two writes is next; false means the first
+    w: bool,
+    // the byte read by the previous PPUDATA access, which the next read returns
+    read_buffer: u8,
+    // the PPU's side of the data bus holds the last value read or written through any register, and the bits a register doesn't drive read back from it
+    io_latch: u8,
+
+    // the television system, which sets the number of scanlines and when vblank starts
+    region: Region,
+
+    // the position of the beam
+    dot: u16,
+    scanline: u16,
+    // on NTSC consoles, odd frames skip the last dot of the pre-render scanline when rendering is enabled
+    odd_frame: bool,
+    // the number of frames completed since power-on
+    frame: u64,
+    // set when PPUSTATUS is read just as vblank starts, which keeps the flag (and the NMI) from being raised that frame
+    suppress_vblank: bool,
+
+    // the latches the background tiles are fetched into, eight dots ahead of the pixels being drawn
+    nametable_byte: u8,
+    attribute_byte: u8,
+    pattern_low: u8,
+    pattern_high: u8,
+    // the background shift registers; the high byte is the tile being drawn and the low byte the next one
+    background_shift_low: u16,
+    background_shift_high: u16,
+    attribute_shift_low: u16,
+    attribute_shift_high: u16,
+
+    // the sprites found on the current scanline, evaluated at the end of the previous one
+    sprite_count: usize,
+    sprite_pattern_low: [u8; SPRITES_PER_SCANLINE],
+    sprite_pattern_high: [u8; SPRITES_PER_SCANLINE],
+    sprite_attributes: [u8; SPRITES_PER_SCANLINE],
+    sprite_x: [u8; SPRITES_PER_SCANLINE],
+    // whether the first sprite on the scanline is sprite 0, for sprite 0 hit
+    sprite_zero_on_line: bool,
+    // the OAM entries of the sprites found for the next scanline, with the unused slots left at 0xFF
+    secondary_oam: [u8; SECONDARY_OAM_SIZE],
+
+    // each pixel is an index into the palette RAM
+    framebuffer: Box<[u8; SCREEN_WIDTH * SCREEN_HEIGHT]>,
+    // the value of PPUMASK as each line was drawn, which sets its greyscale and colour emphasis
+    line_masks: [u8; SCREEN_HEIGHT],
+    frame_ready: bool,
+    // the colours used to convert the palette RAM's values to RGB
+    master_palette: [(u8, u8, u8); 64],
+    // the pixels cropped from the top, bottom, left, and right of the picture by `frame_rgba_cropped`
+    overscan: (u8, u8, u8, u8),
+}
+
+impl Default for PPU {
+    #[inline]
+    fn default() -> PPU {
+        PPU {
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_addr: 0,
+            oam: [0; OAM_SIZE],
+            vram: [0; VRAM_SIZE],
+            cartridge_vram: Vec::new(),
+            palette: [0; PALETTE_SIZE],
+            mapper: None,
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+            read_buffer: 0,
+            io_latch: 0,
+            region: Region::Ntsc,
+            dot: 0,
+            scanline: 0,
+            odd_frame: false,
+            frame: 0,
+            suppress_vblank: false,
+            nametable_byte: 0,
+            attribute_byte: 0,
+            pattern_low: 0,
+            pattern_high: 0,
+            background_shift_low: 0,
+            background_shift_high: 0,
+            attribute_shift_low: 0,
+            attribute_shift_high: 0,
+            sprite_count: 0,
+            sprite_pattern_low: [0; SPRITES_PER_SCANLINE],
+            sprite_pattern_high: [0; SPRITES_PER_SCANLINE],
+            sprite_attributes: [0; SPRITES_PER_SCANLINE],
+            sprite_x: [0; SPRITES_PER_SCANLINE],
+            sprite_zero_on_line: false,
+            secondary_oam: [0xff; SECONDARY_OAM_SIZE],
+            framebuffer: Box::new([0; SCREEN_WIDTH * SCREEN_HEIGHT]),
+            line_masks: [0; SCREEN_HEIGHT],
+            frame_ready: false,
+            master_palette: NTSC_PALETTE,
+            overscan: (0, 0, 0, 0),
+        }
+    }
+}
+
+impl PPU {
+    /// Sets the television system the PPU generates frames for; PPUs are NTSC by default
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    /// Returns the television system the PPU generates frames for
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Returns the number of scanlines in each frame, which depends on the region
+    pub fn scanlines_per_frame(&self) -> u16 {
+        self.region.scanlines_per_frame()
+    }
+
+    /// Returns the pre-render scanline, the last of the frame, which fetches the first tiles of the next frame
+    fn pre_render_scanline(&self) -> u16 {
+        self.region.scanlines_per_frame() - 1
+    }
+
+    /// Connects the PPU to the cartridge, which supplies the pattern tables
+    /// If the cartridge uses four-screen mirroring, the extra VRAM it supplies for the other two nametables is attached as well.
+    pub fn set_mapper(&mut self, mapper: SharedMapper) {
+        self.cartridge_vram = if mapper.borrow().mirroring() == Mirroring::FourScreen {
+            vec![0; VRAM_SIZE]
+        } else {
+            Vec::new()
+        };
+        self.mapper = Some(mapper);
+    }
+
+    /// Reads one of the PPU's registers; `address` is decoded modulo 8, so any mirror may be used
+    /// Some reads have side effects: reading PPUSTATUS clears the vblank flag and resets the write latch, and reading PPUDATA increments the VRAM address.
+    /// Bits that a register doesn't drive (and the whole of the write-only registers) hold the last value read or written through any register.
+    pub fn read_register(&mut self, address: u16) -> u8 {
+        let value = match address & 0x07 {
+            2 => {
+                // only the top three bits are status flags; the rest come from the latch
+                let status = (self.status & 0xe0) | (self.io_latch & 0x1f);
+                self.status &= !STATUS_VBLANK;
+                self.w = false;
+
+                // reading on the dot vblank is set races with it: the flag reads as clear and is never set, so no NMI occurs that frame
+                // reading just after it is set still returns it, but clearing it releases the NMI line before the CPU has seen it
+                if self.scanline == self.region.vblank_scanline() && self.dot == 1 {
+                    self.suppress_vblank = true;
+                }
+                status
+            },
+            4 => self.oam_data(),
+            7 => {
+                // reads are delayed by one through the internal buffer, except for the palettes, which are returned directly
+                // the buffer is still refilled on palette reads, from the nametable "underneath" the palettes
+                let address = self.v & 0x3fff;
+                let value = if address >= 0x3f00 {
+                    self.read_buffer = self.read_vram(address - 0x1000);
+                    // palette entries are six bits wide
+                    (self.read_vram(address) & 0x3f) | (self.io_latch & 0xc0)
+                } else {
+                    let buffered = self.read_buffer;
+                    self.read_buffer = self.read_vram(address);
+                    buffered
+                };
+                self.increment_address();
+                value
+            },
+            // the remaining registers are write-only, so reading them returns the latch
+            _ => self.io_latch,
+        };
+        self.io_latch = value;
+        value
+    }
+
+    /// Returns the value a read of one of the PPU's registers would return, without any of the side effects
+    pub fn peek_register(&self, address: u16) -> u8 {
+        match address & 0x07 {
+            2 => (self.status & 0xe0) | (self.io_latch & 0x1f),
+            4 => self.oam_data(),
+            7 => {
+                let address = self.v & 0x3fff;
+                if address >= 0x3f00 {
+                    (self.read_vram(address) & 0x3f) | (self.io_latch & 0xc0)
+                } else {
+                    self.read_buffer
+                }
+            },
+            _ => self.io_latch,
+        }
+    }
+
+    /// Writes one of the PPU's registers; `address` is decoded modulo 8, so any mirror may be used
+    pub fn write_register(&mut self, address: u16, value: u8) {
+        self.io_latch = value;
+        match address & 0x07 {
+            0 => {
+                // enabling NMIs during vblank asserts the NMI line, so the CPU takes one immediately
+                self.ctrl = value;
+                self.t = (self.t & !0x0c00) | (((value & 0x03) as u16) << 10);
+            },
+            1 => self.mask = value,
+            3 => self.oam_addr = value,
+            4 => {
+                self.oam[self.oam_addr as usize] = value;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            },
+            5 => {
+                // the first write is the X scroll, the second the Y scroll
+                if self.w {
+                    self.t = (self.t & !0x73e0) | (((value & 0x07) as u16) << 12) | (((value & 0xf8) as u16) << 2);
+                } else {
+                    self.t = (self.t & !0x001f) | (value >> 3) as u16;
+                    self.x = value & 0x07;
+                }
+                self.w = !self.w;
+            },
+            6 => {
+                // the high byte is written first, clearing bit 14; the address only takes effect once the low byte is written
+                if self.w {
+                    self.t = (self.t & 0xff00) | value as u16;
+                    self.v = self.t;
+                } else {
+                    self.t = (self.t & 0x00ff) | (((value & 0x3f) as u16) << 8);
+                }
+                self.w = !self.w;
+            },
+            7 => {
+                self.write_vram(self.v, value);
+                self.increment_address();
+            },
+            // PPUSTATUS is read-only
+            _ => {},
+        }
+    }
+
+    /// Returns the current VRAM address (`v`)
+    pub fn vram_address(&self) -> u16 {
+        self.v
+    }
+
+    /// Returns the temporary VRAM address (`t`), which holds the scroll position for the next frame or scanline
+    pub fn temp_vram_address(&self) -> u16 {
+        self.t
+    }
+
+    /// Returns the fine X scroll (`x`)
+    pub fn fine_x_scroll(&self) -> u8 {
+        self.x
+    }
+
+    /// Returns the shared PPUSCROLL/PPUADDR write latch (`w`); true if the next write is the second of the pair
+    pub fn write_latch(&self) -> bool {
+        self.w
+    }
+
+    /// Returns the value of PPUCTRL
+    pub fn ctrl(&self) -> u8 {
+        self.ctrl
+    }
+
+    /// Returns the value of PPUMASK
+    pub fn mask(&self) -> u8 {
+        self.mask
+    }
+
+    /// Returns the object attribute memory, which holds the sprites
+    pub fn oam(&self) -> &[u8; OAM_SIZE] {
+        &self.oam
+    }
+
+    /// Reads a byte of the PPU's address space (`address` is taken modulo `0x4000`), for debuggers and viewers
+    /// Unlike a read through PPUDATA, this doesn't use or refill the read buffer, and leaves the VRAM address alone. The nametables are mirrored as the cartridge has selected, so all four logical nametables can be read.
+    pub fn vram_peek(&self, address: u16) -> u8 {
+        self.read_vram(address)
+    }
+
+    /// Writes a byte of the PPU's address space (`address` is taken modulo `0x4000`), for debuggers and viewers
+    /// The write lands wherever a write through PPUDATA would, without disturbing the read buffer or the VRAM address; writes to CHR ROM are ignored.
+    pub fn vram_poke(&mut self, address: u16, value: u8) {
+        self.write_vram(address, value);
+    }
+
+    /// Renders all of nametable `which` (0 - 3, taken modulo 4) as palette indices, in the same form as the framebuffer, for viewers
+    /// The tiles come from the background pattern table selected in PPUCTRL and are coloured by the attribute table; scrolling, sprites, and PPUMASK are ignored.
+    pub fn render_nametable(&self, which: u8) -> [u8; SCREEN_WIDTH * SCREEN_HEIGHT] {
+        let base = 0x2000 + (which as u16 & 0x03) * 0x400;
+        let pattern_table = if (self.ctrl & CTRL_BACKGROUND_TABLE) != 0 { 0x1000 } else { 0x0000 };
+
+        let mut out = [0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        for tile_y in 0..(SCREEN_HEIGHT / 8) as u16 {
+            for tile_x in 0..(SCREEN_WIDTH / 8) as u16 {
+                let tile = self.read_vram(base + tile_y * 32 + tile_x);
+                // each attribute byte covers 4x4 tiles, with two bits for each 2x2 quadrant
+                let attribute = self.read_vram(base + 0x3c0 + (tile_y / 4) * 8 + tile_x / 4);
+                let shift = ((tile_y & 0x02) << 1) | (tile_x & 0x02);
+                let palette = (attribute >> shift) & 0x03;
+                let x = tile_x as usize * 8;
+                let y = tile_y as usize * 8;
+                self.draw_tile(&mut out, SCREEN_WIDTH, x, y, pattern_table + tile as u16 * 16, palette);
+            }
+        }
+        out
+    }
+
+    /// Renders the 256 tiles of pattern table `table` (0 or 1) as a 16x16 grid of palette indices, coloured with `palette` (0 - 3 for the background palettes, 4 - 7 for the sprite palettes), for viewers
+    pub fn render_pattern_table(&self, table: u8, palette: u8) -> [u8; 128 * 128] {
+        let base = (table as u16 & 0x01) * 0x1000;
+        let mut out = [0; 128 * 128];
+        for tile in 0..256u16 {
+            let x = (tile % 16) as usize * 8;
+            let y = (tile / 16) as usize * 8;
+            self.draw_tile(&mut out, 128, x, y, base + tile * 16, palette & 0x07);
+        }
+        out
+    }
+
+    /// Draws the 8x8 tile at `address` in the pattern tables into `out`, a picture `width` pixels wide, with its top left corner at (`x`, `y`)
+    /// Transparent pixels are drawn as the backdrop, as they are on screen.
+    fn draw_tile(&self, out: &mut [u8], width: usize, x: usize, y: usize, address: u16, palette: u8) {
+        for row in 0..8 {
+            let low = self.read_vram(address + row as u16);
+            let high = self.read_vram(address + row as u16 + 8);
+            for column in 0..8 {
+                let bit = 7 - column;
+                let pixel = ((high >> bit) & 1) << 1 | ((low >> bit) & 1);
+                out[(y + row) * width + x + column] = if pixel != 0 { palette << 2 | pixel } else { 0 };
+            }
+        }
+    }
+
+    /// Reads byte `index` of OAM, without disturbing OAMADDR
+    pub fn oam_peek(&self, index: u8) -> u8 {
+        self.oam[index as usize]
+    }
+
+    /// Writes byte `index` of OAM, without disturbing OAMADDR
+    pub fn oam_poke(&mut self, index: u8, value: u8) {
+        self.oam[index as usize] = value;
+    }
+
+    /// Returns the value of PPUSTATUS, without the side effects of reading it through the register
+    pub fn status(&self) -> u8 {
+        self.status
+    }
+
+    /// Copies `data` into the PPU's address space starting at `address`, for setting up tests
+    /// Addresses wrap around at `0x4000`, and each byte lands exactly where it would if it had been written through PPUDATA.
+    pub fn load_vram(&mut self, address: u16, data: &[u8]) {
+        for (offset, byte) in data.iter().enumerate() {
+            self.write_vram(address.wrapping_add(offset as u16), *byte);
+        }
+    }
+
+    /// Reads a byte of the PPU's address space without going through PPUDATA; the counterpart to `load_vram`
+    pub fn vram(&self, address: u16) -> u8 {
+        self.read_vram(address)
+    }
+
+    /// Returns the most recently rendered picture, as indices into the palette RAM
+    pub fn frame_buffer(&self) -> &[u8; SCREEN_WIDTH * SCREEN_HEIGHT] {
+        &self.framebuffer
+    }
+
+    /// Returns a hash of the most recently rendered picture, for checking a run's frames against a list recorded earlier
+    /// The hash covers the palette indices rather than the colours, so it doesn't change with the master palette (or with PPUMASK's greyscale and emphasis bits); it is stable across platforms and builds.
+    pub fn frame_hash(&self) -> u64 {
+        state::hash(&self.framebuffer[..])
+    }
+
+    /// Replaces the colours used to convert the picture to RGB; the default is `palette::NTSC_PALETTE`
+    pub fn set_palette(&mut self, palette: [(u8, u8, u8); 64]) {
+        self.master_palette = palette;
+    }
+
+    /// Returns the most recently rendered picture in RGBA format, four bytes per pixel
+    pub fn frame_rgba(&self) -> Vec<u8> {
+        let mut out = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 4];
+        self.fill_rgba(&mut out);
+        out
+    }
+
+    /// Writes the most recently rendered picture into `out` in RGBA format, four bytes per pixel
+    /// Each pixel is looked up in the palette RAM and then in the master palette, with PPUMASK's greyscale and colour emphasis applied as they were set when its line was drawn, so effects that change them between lines are kept.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `out` is shorter than `SCREEN_WIDTH * SCREEN_HEIGHT * 4` bytes.
+    pub fn fill_rgba(&self, out: &mut [u8]) {
+        let lines = self.framebuffer.chunks_exact(SCREEN_WIDTH).zip(out.chunks_exact_mut(SCREEN_WIDTH * 4));
+        for ((line, line_out), mask) in lines.zip(self.line_masks.iter()) {
+            for (pixel, rgba) in line.iter().zip(line_out.chunks_exact_mut(4)) {
+                let (r, g, b) = self.colour(*pixel, *mask);
+                rgba.copy_from_slice(&[r, g, b, 0xff]);
+            }
+        }
+    }
+
+    /// Sets how many pixels `frame_rgba_cropped` crops from each edge of the picture, hiding what televisions would have cut off
+    /// Many frontends crop 8 pixels from the top and bottom, leaving 256x224. By default nothing is cropped.
+    /// Crops larger than the picture are reduced so at least one row and column remain.
+    pub fn set_overscan(&mut self, top: u8, bottom: u8, left: u8, right: u8) {
+        let top = top.min(SCREEN_HEIGHT as u8 - 1);
+        let bottom = bottom.min(SCREEN_HEIGHT as u8 - 1 - top);
+        let right = right.min(u8::MAX - left);
+        self.overscan = (top, bottom, left, right);
+    }
+
+    /// Returns the width and height of the picture returned by `frame_rgba_cropped`
+    pub fn cropped_size(&self) -> (usize, usize) {
+        let (top, bottom, left, right) = self.overscan;
+        (SCREEN_WIDTH - left as usize - right as usize, SCREEN_HEIGHT - top as usize - bottom as usize)
+    }
+
+    /// Returns the most recently rendered picture in RGBA format, as `frame_rgba` does, with the overscan set by `set_overscan` cropped off
+    /// The picture's dimensions are given by `cropped_size`.
+    pub fn frame_rgba_cropped(&self) -> Vec<u8> {
+        let (top, _, left, _) = self.overscan;
+        let (width, height) = self.cropped_size();
+        let full = self.frame_rgba();
+        let mut out = Vec::with_capacity(width * height * 4);
+        for row in full.chunks_exact(SCREEN_WIDTH * 4).skip(top as usize).take(height) {
+            out.extend_from_slice(&row[left as usize * 4..(left as usize + width) * 4]);
+        }
+        out
+    }
+
+    /// Converts an index into the palette RAM to an RGB colour, under the given PPUMASK
+    fn colour(&self, index: u8, mask: u8) -> (u8, u8, u8) {
+        let mut colour = self.palette[palette_index(index as u16)] & 0x3f;
+        if (mask & MASK_GREYSCALE) != 0 {
+            // greyscale keeps only the luminance
+            colour &= 0x30;
+        }
+        let (mut r, mut g, mut b) = self.master_palette[colour as usize];
+
+        // emphasizing a channel darkens the other two
+        let attenuate = |channel: u8| (channel as f32 * EMPHASIS_ATTENUATION) as u8;
+        if (mask & MASK_EMPHASIZE_RED) != 0 {
+            g = attenuate(g);
+            b = attenuate(b);
+        }
+        if (mask & MASK_EMPHASIZE_GREEN) != 0 {
+            r = attenuate(r);
+            b = attenuate(b);
+        }
+        if (mask & MASK_EMPHASIZE_BLUE) != 0 {
+            r = attenuate(r);
+            g = attenuate(g);
+        }
+        (r, g, b)
+    }
+
+    /// Returns whether a complete picture is in the framebuffer
+    /// This is set when the beam leaves the visible region and cleared when the next frame starts rendering.
+    pub fn frame_ready(&self) -> bool {
+        self.frame_ready
+    }
+
+    /// Compares the most recently rendered picture with a reference picture of palette indices, such as an earlier `frame_buffer`
+    /// This is for rendering tests, which can report where a picture went wrong rather than just that it did.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `other` isn't exactly one picture (256x240 bytes).
+    pub fn framebuffer_diff(&self, other: &[u8]) -> DiffStats {
+        assert_eq!(other.len(), SCREEN_WIDTH * SCREEN_HEIGHT, "the reference isn't the size of a picture");
+
+        let mut stats = DiffStats {
+            count: 0,
+            bounds: None,
+        };
+        let differing = self.framebuffer.iter().zip(other.iter()).enumerate().filter(|(_, (a, b))| a != b);
+        for (index, _) in differing {
+            let (x, y) = (index % SCREEN_WIDTH, index / SCREEN_WIDTH);
+            stats.count += 1;
+            stats.bounds = Some(match stats.bounds {
+                None => (x, y, x, y),
+                Some((left, top, right, bottom)) => (left.min(x), top.min(y), right.max(x), bottom.max(y)),
+            });
+        }
+        stats
+    }
+
+    /// Returns the current scanline; 0 - 239 are visible and the last (261 on NTSC consoles) is the pre-render line
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    /// Returns the current dot within the scanline
+    pub fn dot(&self) -> u16 {
+        self.dot
+    }
+
+    /// Returns the number of frames completed since power-on
+    pub fn frame_count(&self) -> u64 {
+        self.frame
+    }
+
+    /// Advances the PPU by one dot.
+    /// Each dot of a visible scanline produces one pixel; meanwhile the background tiles are fetched into latches and fed through the shift registers, one tile (eight dots) ahead of the pixels being drawn.
+    pub fn clock(&mut self) {
+        if self.scanline == self.region.vblank_scanline() && self.dot == 1 {
+            if !self.suppress_vblank {
+                self.status |= STATUS_VBLANK;
+            }
+            self.suppress_vblank = false;
+        } else if self.scanline == self.pre_render_scanline() && self.dot == 1 {
+            self.status &= !(STATUS_VBLANK | STATUS_SPRITE_0_HIT | STATUS_SPRITE_OVERFLOW);
+            self.frame_ready = false;
+        }
+
+        let rendering_line = self.scanline < SCREEN_HEIGHT as u16 || self.scanline == self.pre_render_scanline();
+        if rendering_line && self.rendering_enabled() {
+            self.fetch_background();
+        }
+
+        if self.scanline < SCREEN_HEIGHT as u16 && self.dot >= 1 && self.dot <= SCREEN_WIDTH as u16 {
+            self.render_pixel();
+        }
+
+        // sprites for the next scanline are found once this one's pixels are done
+        if self.dot == 257 && rendering_line && self.rendering_enabled() {
+            self.evaluate_sprites();
+        }
+
+        // the sprite pattern fetches raise A12 once per rendered line, which clocks scanline counters such as the MMC3's
+        if self.dot == MAPPER_IRQ_DOT && rendering_line && self.rendering_enabled() {
+            if let Some(mapper) = &self.mapper {
+                mapper.borrow_mut().clock_irq();
+            }
+        }
+
+        self.dot += 1;
+        let skip = self.odd_frame && self.region.skips_odd_frame_dot() && self.scanline == self.pre_render_scanline() && self.dot == DOTS_PER_SCANLINE - 1 && self.rendering_enabled();
+        if self.dot == DOTS_PER_SCANLINE || skip {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline == SCREEN_HEIGHT as u16 {
+                self.frame_ready = true;
+            } else if self.scanline == self.scanlines_per_frame() {
+                self.scanline = 0;
+                self.odd_frame = !self.odd_frame;
+                self.frame += 1;
+            }
+        }
+    }
+
+    /// Advances the PPU by one dot, returning true if that finished the frame (the beam wrapped back to the top)
+    pub fn step_dot(&mut self) -> bool {
+        self.clock();
+        self.scanline == 0 && self.dot == 0
+    }
+
+    /// Runs the PPU until the beam reaches dot 0 of `scanline`
+    /// If the beam is already there, a whole frame is run. Scanlines past the end of the frame are never reached, so nothing is run for them.
+    pub fn run_to_scanline(&mut self, scanline: u16) {
+        if scanline >= self.scanlines_per_frame() {
+            return;
+        }
+
+        loop {
+            self.step_dot();
+            if self.scanline == scanline && self.dot == 0 {
+                break;
+            }
+        }
+    }
+
+    /// Returns whether the PPU is asserting the NMI line, which it does while the vblank flag is set and NMIs are enabled
+    /// The CPU only responds when the line is first asserted, so the flag must be cleared (or NMIs disabled) before another NMI can occur.
+    pub fn nmi_line(&self) -> bool {
+        (self.status & STATUS_VBLANK) != 0 && (self.ctrl & CTRL_NMI_ENABLE) != 0
+    }
+
+    /// Returns the value a read of OAMDATA returns
+    /// Outside rendering, this is the OAM byte at OAMADDR. While the PPU is rendering, OAM is busy with sprite evaluation, so the read sees whatever the evaluation is accessing:
+    /// * dots 1 - 64 clear the secondary OAM, and read as `0xFF`
+    /// * dots 65 - 256 check each sprite's Y coordinate in turn, two dots per sprite
+    /// * dots 257 - 320 fetch the patterns of the sprites in secondary OAM, eight dots per sprite
+    /// * the rest of the line reads the first byte of secondary OAM
+    ///
+    /// In no case does reading increment OAMADDR.
+    fn oam_data(&self) -> u8 {
+        let rendering_line = self.scanline < SCREEN_HEIGHT as u16 || self.scanline == self.pre_render_scanline();
+        if !(rendering_line && self.rendering_enabled()) {
+            return self.oam[self.oam_addr as usize];
+        }
+
+        match self.dot {
+            1..=64 => 0xff,
+            65..=256 => {
+                let sprite = ((self.dot - 65) / 2) as usize % (OAM_SIZE / 4);
+                self.oam[sprite * 4]
+            },
+            257..=320 => {
+                let offset = (self.dot - 257) as usize;
+                self.secondary_oam[(offset / 8) * 4 + (offset % 8).min(3)]
+            },
+            _ => self.secondary_oam[0],
+        }
+    }
+
+    /// Returns whether either the background or sprites are enabled; when neither is, the PPU leaves VRAM alone
+    fn rendering_enabled(&self) -> bool {
+        (self.mask & (MASK_SHOW_BACKGROUND | MASK_SHOW_SPRITES)) != 0
+    }
+
+    /// Performs this dot's step of the background fetch pipeline
+    /// Each tile takes eight dots: the nametable byte, the attribute byte, and the two pattern bitplanes are each fetched over two dots, and then the address moves to the next tile.
+    fn fetch_background(&mut self) {
+        let dot = self.dot;
+        if (2..=257).contains(&dot) || (321..=337).contains(&dot) {
+            self.shift_background();
+            match (dot - 1) % 8 {
+                0 => {
+                    self.load_background_shifters();
+                    self.nametable_byte = self.read_vram(0x2000 | (self.v & 0x0fff));
+                },
+                2 => {
+                    let v = self.v;
+                    let address = 0x23c0 | (v & 0x0c00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+                    let mut attribute = self.read_vram(address);
+                    // each attribute byte covers a 4x4 tile area; pick the 2x2 quadrant this tile is in
+                    if (v & 0x0040) != 0 {
+                        attribute >>= 4;
+                    }
+                    if (v & 0x0002) != 0 {
+                        attribute >>= 2;
+                    }
+                    self.attribute_byte = attribute & 0x03;
+                },
+                4 => self.pattern_low = self.read_vram(self.background_pattern_address()),
+                6 => self.pattern_high = self.read_vram(self.background_pattern_address() + 8),
+                7 => self.increment_x(),
+                _ => {},
+            }
+        }
+
+        if dot == 256 {
+            self.increment_y();
+        } else if dot == 257 {
+            self.load_background_shifters();
+            self.copy_x();
+        } else if self.scanline == self.pre_render_scanline() && (280..=304).contains(&dot) {
+            self.copy_y();
+        }
+    }
+
+    /// Returns the address of the low bitplane of the fetched tile's current row
+    fn background_pattern_address(&self) -> u16 {
+        let table = if (self.ctrl & CTRL_BACKGROUND_TABLE) == 0 { 0x0000 } else { 0x1000 };
+        let fine_y = (self.v >> 12) & 0x07;
+        table + (self.nametable_byte as u16) * 16 + fine_y
+    }
+
+    /// Moves the fetched tile into the low byte of the shift registers
+    fn load_background_shifters(&mut self) {
+        self.background_shift_low = (self.background_shift_low & 0xff00) | self.pattern_low as u16;
+        self.background_shift_high = (self.background_shift_high & 0xff00) | self.pattern_high as u16;
+
+        // the attribute applies to the whole tile, so it is spread across all eight bits
+        let low = if (self.attribute_byte & 0x01) != 0 { 0xff } else { 0x00 };
+        let high = if (self.attribute_byte & 0x02) != 0 { 0xff } else { 0x00 };
+        self.attribute_shift_low = (self.attribute_shift_low & 0xff00) | low;
+        self.attribute_shift_high = (self.attribute_shift_high & 0xff00) | high;
+    }
+
+    /// Shifts the background shift registers by one pixel
+    fn shift_background(&mut self) {
+        self.background_shift_low <<= 1;
+        self.background_shift_high <<= 1;
+        self.attribute_shift_low <<= 1;
+        self.attribute_shift_high <<= 1;
+    }
+
+    /// Finds the sprites on the next scanline and fetches their patterns
+    /// Only the first eight sprites (in OAM order) are drawn; if more are found, the sprite overflow flag is set.
+    fn evaluate_sprites(&mut self) {
+        self.sprite_count = 0;
+        self.sprite_zero_on_line = false;
+        self.secondary_oam = [0xff; SECONDARY_OAM_SIZE];
+
+        // nothing is drawn on the line after the pre-render line's evaluation
+        if self.scanline == self.pre_render_scanline() {
+            return;
+        }
+
+        let height: u16 = if (self.ctrl & CTRL_SPRITE_SIZE) == 0 { 8 } else { 16 };
+        for sprite in 0..(OAM_SIZE / 4) {
+            let entry = sprite * 4;
+            // a sprite's Y coordinate is one less than the first line it appears on
+            let row = self.scanline.wrapping_sub(self.oam[entry] as u16);
+            if row >= height {
+                continue;
+            }
+
+            if self.sprite_count == SPRITES_PER_SCANLINE {
+                self.status |= STATUS_SPRITE_OVERFLOW;
+                break;
+            }
+
+            let tile = self.oam[entry + 1];
+            let attributes = self.oam[entry + 2];
+            let address = self.sprite_pattern_address(tile, attributes, row, height);
+            let mut low = self.read_vram(address);
+            let mut high = self.read_vram(address + 8);
+            if (attributes & SPRITE_FLIP_HORIZONTAL) != 0 {
+                low = low.reverse_bits();
+                high = high.reverse_bits();
+            }
+
+            let slot = self.sprite_count;
+            self.secondary_oam[slot * 4..slot * 4 + 4].copy_from_slice(&self.oam[entry..entry + 4]);
+            self.sprite_pattern_low[slot] = low;
+            self.sprite_pattern_high[slot] = high;
+            self.sprite_attributes[slot] = attributes;
+            self.sprite_x[slot] = self.oam[entry + 3];
+            if sprite == 0 {
+                self.sprite_zero_on_line = true;
+            }
+            self.sprite_count += 1;
+        }
+    }
+
+    /// Returns the address of the low bitplane of the given row of a sprite
+    /// 8x16 sprites take their pattern table from bit 0 of the tile number, and are made of the even tile above the odd one.
+    fn sprite_pattern_address(&self, tile: u8, attributes: u8, row: u16, height: u16) -> u16 {
+        let row = if (attributes & SPRITE_FLIP_VERTICAL) != 0 { height - 1 - row } else { row };
+        if height == 8 {
+            let table = if (self.ctrl & CTRL_SPRITE_TABLE) == 0 { 0x0000 } else { 0x1000 };
+            table + (tile as u16) * 16 + row
+        } else {
+            let table = ((tile & 0x01) as u16) * 0x1000;
+            let tile = (tile & 0xfe) as u16 + (row >> 3);
+            table + tile * 16 + (row & 0x07)
+        }
+    }
+
+    /// Writes the pixel under the beam into the framebuffer
+    /// The sprite pixel is drawn over the background unless the background is opaque and the sprite has background priority.
+    /// PPUMASK can hide the background and sprites, either entirely or in the leftmost 8 pixels; hidden pixels are transparent, so they can't cause a sprite 0 hit.
+    fn render_pixel(&mut self) {
+        let x = (self.dot - 1) as usize;
+        let y = self.scanline as usize;
+        if x == 0 {
+            self.line_masks[y] = self.mask;
+        }
+
+        // with rendering disabled, the backdrop is shown, unless v points into the palettes, in which case that colour is shown instead
+        if !self.rendering_enabled() {
+            let address = self.v & 0x3fff;
+            self.framebuffer[y * SCREEN_WIDTH + x] = if address >= 0x3f00 { (address & 0x1f) as u8 } else { 0 };
+            return;
+        }
+
+        let (background_pixel, background_palette) = self.background_pixel(x as u8);
+        let (sprite_pixel, sprite_palette, behind, sprite_zero) = self.sprite_pixel(x as u8);
+
+        if sprite_zero && background_pixel != 0 && sprite_pixel != 0 && x != 255 {
+            self.status |= STATUS_SPRITE_0_HIT;
+        }
+
+        // every palette's colour 0 is the shared backdrop
+        let colour = if sprite_pixel != 0 && (background_pixel == 0 || !behind) {
+            sprite_palette << 2 | sprite_pixel
+        } else if background_pixel != 0 {
+            background_palette << 2 | background_pixel
+        } else {
+            0
+        };
+
+        self.framebuffer[y * SCREEN_WIDTH + x] = colour;
+    }
+
+    /// Returns the background pixel under the beam, at column `x`, and its palette
+    fn background_pixel(&self, x: u8) -> (u8, u8) {
+        if (self.mask & MASK_SHOW_BACKGROUND) == 0 || (x < 8 && (self.mask & MASK_SHOW_BACKGROUND_LEFT) == 0) {
+            return (0, 0);
+        }
+
+        let bit = 0x8000 >> self.x;
+        let pixel = (((self.background_shift_high & bit) != 0) as u8) << 1
+            | ((self.background_shift_low & bit) != 0) as u8;
+        let palette = (((self.attribute_shift_high & bit) != 0) as u8) << 1
+            | ((self.attribute_shift_low & bit) != 0) as u8;
+        (pixel, palette)
+    }
+
+    /// Returns the frontmost opaque sprite pixel at column `x`, as its pixel value, its palette (in the sprite half of the palette RAM), whether it is behind the background, and whether it belongs to sprite 0
+    fn sprite_pixel(&self, x: u8) -> (u8, u8, bool, bool) {
+        if (self.mask & MASK_SHOW_SPRITES) == 0 || (x < 8 && (self.mask & MASK_SHOW_SPRITES_LEFT) == 0) {
+            return (0, 0, false, false);
+        }
+
+        for slot in 0..self.sprite_count {
+            let offset = x.wrapping_sub(self.sprite_x[slot]);
+            if offset >= 8 {
+                continue;
+            }
+
+            let bit = 7 - offset;
+            let pixel = ((self.sprite_pattern_high[slot] >> bit) & 1) << 1
+                | ((self.sprite_pattern_low[slot] >> bit) & 1);
+            if pixel != 0 {
+                let attributes = self.sprite_attributes[slot];
+                let palette = 0x04 | (attributes & 0x03);
+                let behind = (attributes & SPRITE_BEHIND_BACKGROUND) != 0;
+                return (pixel, palette, behind, slot == 0 && self.sprite_zero_on_line);
+            }
+        }
+        (0, 0, false, false)
+    }
+
+    /// Moves the VRAM address to the next tile horizontally, wrapping into the neighbouring nametable
+    fn increment_x(&mut self) {
+        if (self.v & 0x001f) == 31 {
+            self.v &= !0x001f;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    /// Moves the VRAM address to the next row of pixels, wrapping into the neighbouring nametable after the 30th row of tiles
+    fn increment_y(&mut self) {
+        if (self.v & 0x7000) != 0x7000 {
+            self.v += 0x1000;
+        } else {
+            self.v &= !0x7000;
+            let mut coarse_y = (self.v & 0x03e0) >> 5;
+            if coarse_y == 29 {
+                coarse_y = 0;
+                self.v ^= 0x0800;
+            } else if coarse_y == 31 {
+                // rows 30 and 31 hold the attribute table, so the nametable doesn't switch
+                coarse_y = 0;
+            } else {
+                coarse_y += 1;
+            }
+            self.v = (self.v & !0x03e0) | (coarse_y << 5);
+        }
+    }
+
+    /// Copies the horizontal position (coarse X and the horizontal nametable bit) from `t` into `v`, at the end of each scanline
+    fn copy_x(&mut self) {
+        self.v = (self.v & !0x041f) | (self.t & 0x041f);
+    }
+
+    /// Copies the vertical position (fine Y, coarse Y, and the vertical nametable bit) from `t` into `v`, during the pre-render scanline
+    fn copy_y(&mut self) {
+        self.v = (self.v & !0x7be0) | (self.t & 0x7be0);
+    }
+
+    /// Advances the VRAM address after a PPUDATA access, by 1 or 32 depending on PPUCTRL
+    fn increment_address(&mut self) {
+        let step = if (self.ctrl & CTRL_INCREMENT) == 0 { 1 } else { 32 };
+        self.v = self.v.wrapping_add(step) & 0x3fff;
+    }
+
+    /// Reads a byte from the PPU's address space
+    fn read_vram(&self, address: u16) -> u8 {
+        let address = address & 0x3fff;
+        if address < 0x2000 {
+            match &self.mapper {
+                Some(mapper) => mapper.borrow().ppu_read(address),
+                None => 0,
+            }
+        } else if address < 0x3f00 {
+            let index = self.mirror_nametable(address) as usize;
+            match index.checked_sub(VRAM_SIZE) {
+                Some(index) => self.cartridge_vram.get(index).copied().unwrap_or(0),
+                None => self.vram[index],
+            }
+        } else {
+            self.palette[palette_index(address)]
+        }
+    }
+
+    /// Writes a byte to the PPU's address space
+    fn write_vram(&mut self, address: u16, value: u8) {
+        let address = address & 0x3fff;
+        if address < 0x2000 {
+            if let Some(mapper) = &self.mapper {
+                mapper.borrow_mut().ppu_write(address, value);
+            }
+        } else if address < 0x3f00 {
+            let index = self.mirror_nametable(address) as usize;
+            match index.checked_sub(VRAM_SIZE) {
+                Some(index) => {
+                    if let Some(byte) = self.cartridge_vram.get_mut(index) {
+                        *byte = value;
+                    }
+                },
+                None => self.vram[index] = value,
+            }
+        } else {
+            self.palette[palette_index(address)] = value;
+        }
+    }
+
+    /// Returns the nametable mirroring the cartridge has selected
+    fn mirroring(&self) -> Mirroring {
+        match &self.mapper {
+            Some(mapper) => mapper.borrow().mirroring(),
+            None => Mirroring::Horizontal,
+        }
+    }
+
+    /// Maps an address in the nametables (`0x2000 - 0x2FFF`, or its mirror at `0x3000 - 0x3EFF`) to an offset into the PPU's 2KB of VRAM
+    /// There are four logical nametables but only room for two physical ones, so the cartridge decides how the four are mapped onto the two.
+    /// Four-screen cartridges supply VRAM for the other two, which is addressed as offsets `0x800 - 0xFFF`.
+    pub fn mirror_nametable(&self, address: u16) -> u16 {
+        let address = address & 0x0fff;
+        let table = address / 0x400;
+        let offset = address & 0x3ff;
+        let physical = match self.mirroring() {
+            Mirroring::Horizontal => table / 2,
+            Mirroring::Vertical => table % 2,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => table,
+        };
+        physical * 0x400 + offset
+    }
+}
+
+/// Maps an address in the palettes (`0x3F00 - 0x3FFF`) to an index into the palette RAM
+/// The 32 bytes are mirrored throughout the region, and colour 0 of each sprite palette (`0x3F10`, `0x3F14`, `0x3F18`, `0x3F1C`) is a mirror of the corresponding background palette's.
+fn palette_index(address: u16) -> usize {
+    let index = (address as usize) & (PALETTE_SIZE - 1);
+    if (index & 0x13) == 0x10 {
+        index & !0x10
+    } else {
+        index
+    }
+}
+
+impl SaveState for PPU {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.ctrl);
+        writer.write_u8(self.mask);
+        writer.write_u8(self.status);
+        writer.write_u8(self.oam_addr);
+        writer.write_bytes(&self.oam);
+        writer.write_bytes(&self.vram);
+        writer.write_bytes(&self.cartridge_vram);
+        writer.write_bytes(&self.palette);
+        writer.write_u16(self.v);
+        writer.write_u16(self.t);
+        writer.write_u8(self.x);
+        writer.write_bool(self.w);
+        writer.write_u8(self.read_buffer);
+        writer.write_u8(self.io_latch);
+        writer.write_u16(self.dot);
+        writer.write_u16(self.scanline);
+        writer.write_bool(self.odd_frame);
+        writer.write_u64(self.frame);
+        writer.write_bool(self.suppress_vblank);
+        writer.write_u8(self.nametable_byte);
+        writer.write_u8(self.attribute_byte);
+        writer.write_u8(self.pattern_low);
+        writer.write_u8(self.pattern_high);
+        writer.write_u16(self.background_shift_low);
+        writer.write_u16(self.background_shift_high);
+        writer.write_u16(self.attribute_shift_low);
+        writer.write_u16(self.attribute_shift_high);
+        writer.write_u8(self.sprite_count as u8);
+        writer.write_bytes(&self.sprite_pattern_low);
+        writer.write_bytes(&self.sprite_pattern_high);
+        writer.write_bytes(&self.sprite_attributes);
+        writer.write_bytes(&self.sprite_x);
+        writer.write_bool(self.sprite_zero_on_line);
+        writer.write_bytes(&self.secondary_oam);
+        // the frame is saved too, so the screen is correct as soon as the state is loaded
+        writer.write_bytes(&self.framebuffer[..]);
+        writer.write_bytes(&self.line_masks);
+        writer.write_bool(self.frame_ready);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.ctrl = reader.read_u8()?;
+        self.mask = reader.read_u8()?;
+        self.status = reader.read_u8()?;
+        self.oam_addr = reader.read_u8()?;
+        reader.read_into(&mut self.oam)?;
+        reader.read_into(&mut self.vram)?;
+        reader.read_into(&mut self.cartridge_vram)?;
+        reader.read_into(&mut self.palette)?;
+        self.v = reader.read_u16()?;
+        self.t = reader.read_u16()?;
+        self.x = reader.read_u8()?;
+        self.w = reader.read_bool()?;
+        self.read_buffer = reader.read_u8()?;
+        self.io_latch = reader.read_u8()?;
+        self.dot = reader.read_u16()?;
+        if self.dot >= DOTS_PER_SCANLINE {
+            return Err(format!("Invalid PPU dot in save state: {}", self.dot));
+        }
+        self.scanline = reader.read_u16()?;
+        if self.scanline >= self.scanlines_per_frame() {
+            return Err(format!("Invalid PPU scanline in save state: {}", self.scanline));
+        }
+        self.odd_frame = reader.read_bool()?;
+        self.frame = reader.read_u64()?;
+        self.suppress_vblank = reader.read_bool()?;
+        self.nametable_byte = reader.read_u8()?;
+        self.attribute_byte = reader.read_u8()?;
+        self.pattern_low = reader.read_u8()?;
+        self.pattern_high = reader.read_u8()?;
+        self.background_shift_low = reader.read_u16()?;
+        self.background_shift_high = reader.read_u16()?;
+        self.attribute_shift_low = reader.read_u16()?;
+        self.attribute_shift_high = reader.read_u16()?;
+        self.sprite_count = (reader.read_u8()? as usize).min(SPRITES_PER_SCANLINE);
+        reader.read_into(&mut self.sprite_pattern_low)?;
+        reader.read_into(&mut self.sprite_pattern_high)?;
+        reader.read_into(&mut self.sprite_attributes)?;
+        reader.read_into(&mut self.sprite_x)?;
+        self.sprite_zero_on_line = reader.read_bool()?;
+        reader.read_into(&mut self.secondary_oam)?;
+        reader.read_into(&mut self.framebuffer[..])?;
+        reader.read_into(&mut self.line_masks)?;
+        self.frame_ready = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::mmc3::Mmc3;
+    use crate::mapper::nrom::Nrom;
+    use crate::mapper::{Chr, Mapper, Mirroring};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn status_read_clears_vblank_and_latch() {
+        let mut ppu = PPU { status: STATUS_VBLANK, ..PPU::default() };
+        ppu.write_register(0x2006, 0x21);
+
+        assert_eq!(ppu.read_register(0x2002) & STATUS_VBLANK, STATUS_VBLANK);
+        assert_eq!(ppu.read_register(0x2002) & STATUS_VBLANK, 0);
+        // the next PPUADDR write is the high byte again
+        ppu.write_register(0x2006, 0x3f);
+        ppu.write_register(0x2006, 0x00);
+        assert_eq!(ppu.vram_address(), 0x3f00);
+    }
+
+    #[test]
+    fn address_latch_toggles_between_bytes() {
+        let mut ppu = PPU::default();
+        ppu.write_register(0x2006, 0x21);
+        ppu.write_register(0x2006, 0x08);
+        assert_eq!(ppu.vram_address(), 0x2108);
+        // the high byte comes first again, and only goes into t until the low byte is written
+        ppu.write_register(0x2006, 0x3f);
+        assert_eq!(ppu.vram_address(), 0x2108);
+        ppu.write_register(0x2006, 0x10);
+        assert_eq!(ppu.vram_address(), 0x3f10);
+    }
+
+    #[test]
+    fn data_access_increments_address_by_1_or_32() {
+        let mut ppu = PPU::default();
+        ppu.write_register(0x2006, 0x20);
+        ppu.write_register(0x2006, 0x00);
+        ppu.write_register(0x2007, 0x01);
+        assert_eq!(ppu.vram_address(), 0x2001);
+
+        ppu.write_register(0x2000, CTRL_INCREMENT);
+        ppu.read_register(0x2007);
+        assert_eq!(ppu.vram_address(), 0x2021);
+    }
+
+    /// Clocks the PPU until the beam reaches `dot` of `scanline`
+    fn run_to(ppu: &mut PPU, scanline: u16, dot: u16) {
+        while ppu.scanline() != scanline || ppu.dot() != dot {
+            ppu.clock();
+        }
+    }
+
+    /// Creates a PPU attached to an NROM board with the given CHR ROM
+    fn ppu_with_chr(chr: Vec<u8>) -> PPU {
+        let mapper: Box<dyn Mapper> = Box::new(Nrom::new(vec![0; 0x8000], Chr::rom(chr), Mirroring::Vertical));
+        let mut ppu = PPU::default();
+        ppu.set_mapper(Rc::new(RefCell::new(mapper)));
+        ppu
+    }
+
+    /// Creates a PPU attached to an NROM board with CHR RAM and the given nametable mirroring
+    fn ppu_with_mirroring(mirroring: Mirroring) -> PPU {
+        let mapper: Box<dyn Mapper> = Box::new(Nrom::new(vec![0; 0x8000], Chr::ram(0x2000), mirroring));
+        let mut ppu = PPU::default();
+        ppu.set_mapper(Rc::new(RefCell::new(mapper)));
+        ppu
+    }
+
+    /// Runs the PPU to the end of the next whole frame; the scroll is copied into the VRAM address during the pre-render scanline, so the frame is drawn with everything set up beforehand
+    fn render_frame(ppu: &mut PPU) {
+        run_to(ppu, ppu.scanlines_per_frame() - 1, 2);
+        assert!(!ppu.frame_ready());
+        run_to(ppu, SCREEN_HEIGHT as u16, 0);
+        assert!(ppu.frame_ready());
+    }
+
+    #[test]
+    fn background_uses_attributes_and_fine_scroll() {
+        // tile 1 is solid colour 1, and tile 2 solid colour 3
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0xff);
+        chr[0x20..0x30].fill(0xff);
+        let mut ppu = ppu_with_chr(chr);
+        ppu.load_vram(0x2000, &[0x01, 0x02]);
+        // the top-left 16x16 pixels use palette 2
+        ppu.load_vram(0x23c0, &[0x02]);
+
+        ppu.write_register(0x2005, 3);
+        ppu.write_register(0x2005, 0);
+        ppu.write_register(0x2001, MASK_SHOW_BACKGROUND | MASK_SHOW_BACKGROUND_LEFT);
+        render_frame(&mut ppu);
+
+        // scrolled left by three pixels, so tile 1 shows for five pixels, then tile 2 for eight
+        let frame = ppu.frame_buffer();
+        assert_eq!(&frame[0..5], &[9; 5]);
+        assert_eq!(&frame[5..13], &[11; 8]);
+        assert_eq!(frame[13], 0);
+    }
+
+    #[test]
+    fn framebuffer_diff_reports_count_and_bounds() {
+        let mut ppu = PPU::default();
+        let reference = ppu.frame_buffer().to_vec();
+        assert_eq!(ppu.framebuffer_diff(&reference), DiffStats { count: 0, bounds: None });
+
+        // a 4x3 rectangle from (10, 20) to (13, 22)
+        for y in 20..23 {
+            for x in 10..14 {
+                ppu.framebuffer[y * SCREEN_WIDTH + x] = 0x21;
+            }
+        }
+        assert_eq!(ppu.framebuffer_diff(&reference), DiffStats { count: 12, bounds: Some((10, 20, 13, 22)) });
+    }
+
+    #[test]
+    fn load_vram_sets_up_a_render() {
+        // tile 1 is solid colour 1, and is placed at the top left of the first nametable
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0xff);
+        let mut ppu = ppu_with_chr(chr);
+        ppu.load_vram(0x2000, &[0x01]);
+        assert_eq!(ppu.vram(0x0013), 0xff);
+        assert_eq!(ppu.vram(0x2000), 0x01);
+        // with vertical mirroring, the nametable also appears at 0x2800
+        assert_eq!(ppu.vram(0x2800), 0x01);
+
+        ppu.write_register(0x2001, MASK_SHOW_BACKGROUND | MASK_SHOW_BACKGROUND_LEFT);
+        render_frame(&mut ppu);
+        let frame = ppu.frame_buffer();
+        assert_eq!(&frame[0..9], &[1, 1, 1, 1, 1, 1, 1, 1, 0]);
+        assert_eq!(frame[7 * SCREEN_WIDTH], 1);
+        assert_eq!(frame[8 * SCREEN_WIDTH], 0);
+    }
+
+    /// Sets up a PPU with tile 1 (solid colour 1) in the top-left background tile and the given OAM, with everything rendered, and runs it to the end of the visible part of a frame
+    fn render_sprites(oam_entries: &[u8]) -> PPU {
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0xff);
+        let mut ppu = ppu_with_chr(chr);
+        ppu.load_vram(0x2000, &[0x01]);
+        // the rest of OAM is off the bottom of the screen
+        let mut oam = [0xf8; OAM_SIZE];
+        oam[..oam_entries.len()].copy_from_slice(oam_entries);
+        write_oam(&mut ppu, &oam);
+        ppu.write_register(0x2001, MASK_SHOW_BACKGROUND | MASK_SHOW_BACKGROUND_LEFT | MASK_SHOW_SPRITES | MASK_SHOW_SPRITES_LEFT);
+        render_frame(&mut ppu);
+        ppu
+    }
+
+    #[test]
+    fn sprite_0_overlapping_background_hits() {
+        // sprite 0 uses tile 1 at (4, 1); sprites are drawn a line below their Y coordinate
+        let ppu = render_sprites(&[0x00, 0x01, 0x00, 0x04]);
+        assert_ne!(ppu.status() & STATUS_SPRITE_0_HIT, 0);
+        let frame = ppu.frame_buffer();
+        // in front of the background, and past its edge, the sprite's colour 1 of sprite palette 0 shows
+        assert_eq!(frame[SCREEN_WIDTH + 4], 0x11);
+        assert_eq!(frame[SCREEN_WIDTH + 10], 0x11);
+        assert_eq!(frame[SCREEN_WIDTH + 12], 0);
+        // the line above the sprite is only background
+        assert_eq!(frame[4], 0x01);
+    }
+
+    #[test]
+    fn sprite_behind_background_is_hidden_by_opaque_pixels() {
+        let ppu = render_sprites(&[0x00, 0x01, SPRITE_BEHIND_BACKGROUND, 0x04]);
+        let frame = ppu.frame_buffer();
+        assert_eq!(frame[SCREEN_WIDTH + 4], 0x01);
+        assert_eq!(frame[SCREEN_WIDTH + 10], 0x11);
+    }
+
+    #[test]
+    fn sprite_0_over_transparent_background_does_not_hit() {
+        let ppu = render_sprites(&[0x20, 0x01, 0x00, 0x40]);
+        assert_eq!(ppu.status() & STATUS_SPRITE_0_HIT, 0);
+        assert_eq!(ppu.frame_buffer()[0x21 * SCREEN_WIDTH + 0x40], 0x11);
+    }
+
+    #[test]
+    fn nine_sprites_on_a_line_overflow() {
+        let mut oam = Vec::new();
+        for i in 0..9 {
+            oam.extend([0x40, 0x01, 0x00, i * 10]);
+        }
+        let ppu = render_sprites(&oam);
+        assert_ne!(ppu.status() & STATUS_SPRITE_OVERFLOW, 0);
+        // only the first eight are drawn
+        let line = &ppu.frame_buffer()[0x41 * SCREEN_WIDTH..];
+        assert_eq!(line[70], 0x11);
+        assert_eq!(line[80], 0);
+
+        let ppu = render_sprites(&oam[..32]);
+        assert_eq!(ppu.status() & STATUS_SPRITE_OVERFLOW, 0);
+    }
+
+    /// Fills OAM through OAMADDR and OAMDATA, as a game would
+    fn write_oam(ppu: &mut PPU, bytes: &[u8]) {
+        ppu.write_register(0x2003, 0);
+        for byte in bytes.iter() {
+            ppu.write_register(0x2004, *byte);
+        }
+    }
+
+    #[test]
+    fn oam_data_read_outside_rendering_returns_primary_oam() {
+        let mut ppu = PPU::default();
+        write_oam(&mut ppu, &[0x10, 0x20, 0x30, 0x40]);
+        ppu.write_register(0x2003, 2);
+        ppu.write_register(0x2001, MASK_SHOW_BACKGROUND | MASK_SHOW_SPRITES);
+        run_to(&mut ppu, 241, 100);
+        assert_eq!(ppu.read_register(0x2004), 0x30);
+        // reads don't move OAMADDR
+        assert_eq!(ppu.read_register(0x2004), 0x30);
+    }
+
+    #[test]
+    fn oam_data_read_while_rendering_sees_sprite_evaluation() {
+        let mut ppu = PPU::default();
+        // sprite 0 covers lines 11 - 18; the rest are off the bottom of the screen
+        let mut oam = [0xf8; OAM_SIZE];
+        oam[..4].copy_from_slice(&[0x0a, 0x20, 0x30, 0x40]);
+        oam[4] = 0x55;
+        write_oam(&mut ppu, &oam);
+        ppu.write_register(0x2003, 2);
+        ppu.write_register(0x2001, MASK_SHOW_BACKGROUND | MASK_SHOW_SPRITES);
+
+        // while secondary OAM is cleared
+        run_to(&mut ppu, 10, 30);
+        assert_eq!(ppu.read_register(0x2004), 0xff);
+
+        // while each sprite's Y coordinate is checked
+        run_to(&mut ppu, 10, 65);
+        assert_eq!(ppu.peek_register(0x2004), 0x0a);
+        assert_eq!(ppu.read_register(0x2004), 0x0a);
+        run_to(&mut ppu, 10, 67);
+        assert_eq!(ppu.read_register(0x2004), 0x55);
+
+        // while the sprites found for the next line are fetched
+        run_to(&mut ppu, 10, 258);
+        assert_eq!(ppu.read_register(0x2004), 0x20);
+        run_to(&mut ppu, 10, 262);
+        assert_eq!(ppu.read_register(0x2004), 0x40);
+        run_to(&mut ppu, 10, 266);
+        assert_eq!(ppu.read_register(0x2004), 0xff);
+
+        // none of these reads moved OAMADDR
+        run_to(&mut ppu, 241, 100);
+        assert_eq!(ppu.read_register(0x2004), 0x30);
+    }
+
+    #[test]
+    fn mapper_scanline_counter_is_clocked_while_rendering() {
+        let mapper: Box<dyn Mapper> = Box::new(Mmc3::new(vec![0; 0x8000], Chr::ram(0x2000), Mirroring::Vertical));
+        let mapper = Rc::new(RefCell::new(mapper));
+        let mut ppu = PPU::default();
+        ppu.set_mapper(Rc::clone(&mapper));
+        {
+            let mut mapper = mapper.borrow_mut();
+            mapper.cpu_write(0xc000, 4);
+            mapper.cpu_write(0xc001, 0);
+            mapper.cpu_write(0xe001, 0);
+        }
+
+        // with rendering off, the counter isn't clocked
+        run_to(&mut ppu, 10, 0);
+        assert!(!mapper.borrow().irq_pending());
+
+        // the first line reloads the counter from the latch, and the next four count it down to zero
+        ppu.write_register(0x2001, MASK_SHOW_BACKGROUND);
+        run_to(&mut ppu, 14, MAPPER_IRQ_DOT);
+        assert!(!mapper.borrow().irq_pending());
+        ppu.clock();
+        assert!(mapper.borrow().irq_pending());
+    }
+
+    #[test]
+    fn scroll_and_address_writes_fill_loopy_registers() {
+        let mut ppu = PPU::default();
+        ppu.write_register(0x2000, 0x00);
+        ppu.read_register(0x2002);
+
+        // X scroll $7D: coarse X 15, fine X 5
+        ppu.write_register(0x2005, 0x7d);
+        assert_eq!(ppu.temp_vram_address(), 0x000f);
+        assert_eq!(ppu.fine_x_scroll(), 5);
+        // Y scroll $5E: coarse Y 11, fine Y 6
+        ppu.write_register(0x2005, 0x5e);
+        assert_eq!(ppu.temp_vram_address(), 0x616f);
+
+        // the address writes replace t, and the second copies it to v
+        ppu.write_register(0x2006, 0x3d);
+        assert_eq!(ppu.temp_vram_address(), 0x3d6f);
+        ppu.write_register(0x2006, 0xf0);
+        assert_eq!(ppu.temp_vram_address(), 0x3df0);
+        assert_eq!(ppu.vram_address(), 0x3df0);
+        assert_eq!(ppu.fine_x_scroll(), 5);
+    }
+
+    #[test]
+    fn ctrl_write_sets_nametable_bits_of_t() {
+        let mut ppu = PPU::default();
+        ppu.write_register(0x2000, 0x03);
+        assert_eq!(ppu.temp_vram_address(), 0x0c00);
+    }
+
+    #[test]
+    fn rendering_copies_t_into_v() {
+        let mut ppu = PPU::default();
+        ppu.write_register(0x2000, 0x01);
+        ppu.write_register(0x2005, 0x7d);
+        ppu.write_register(0x2005, 0x5e);
+        ppu.write_register(0x2001, MASK_SHOW_BACKGROUND);
+        let t = ppu.temp_vram_address();
+
+        // the vertical bits are copied during the pre-render line
+        run_to(&mut ppu, 261, 305);
+        assert_eq!(ppu.vram_address() & 0x7be0, t & 0x7be0);
+        // and the horizontal bits at dot 257 of each line
+        run_to(&mut ppu, 0, 258);
+        assert_eq!(ppu.vram_address() & 0x041f, t & 0x041f);
+    }
+
+    #[test]
+    fn rgba_maps_through_palette_ram_and_master_palette() {
+        let mut ppu = PPU::default();
+        // the blank framebuffer shows the backdrop colour
+        ppu.load_vram(0x3f00, &[0x0f]);
+        assert_eq!(&ppu.frame_rgba()[0..4], &[0x00, 0x00, 0x00, 0xff]);
+
+        // colour $21 is light blue
+        ppu.load_vram(0x3f00, &[0x21]);
+        assert_eq!(&ppu.frame_rgba()[0..4], &[0x53, 0xae, 0xff, 0xff]);
+
+        let mut palette = NTSC_PALETTE;
+        palette[0x21] = (0x12, 0x34, 0x56);
+        ppu.set_palette(palette);
+        let rgba = ppu.frame_rgba();
+        assert_eq!(rgba.len(), SCREEN_WIDTH * SCREEN_HEIGHT * 4);
+        assert_eq!(&rgba[rgba.len() - 4..], &[0x12, 0x34, 0x56, 0xff]);
+    }
+
+    #[test]
+    fn vblank_starts_at_scanline_241_dot_1() {
+        let mut ppu = PPU::default();
+        ppu.write_register(0x2000, CTRL_NMI_ENABLE);
+        run_to(&mut ppu, 241, 1);
+        assert_eq!(ppu.status() & STATUS_VBLANK, 0);
+        assert!(!ppu.nmi_line());
+        ppu.clock();
+        assert_ne!(ppu.status() & STATUS_VBLANK, 0);
+        assert!(ppu.nmi_line());
+
+        // the pre-render line clears it
+        run_to(&mut ppu, 261, 2);
+        assert_eq!(ppu.status() & STATUS_VBLANK, 0);
+        assert!(!ppu.nmi_line());
+    }
+
+    #[test]
+    fn nmi_line_needs_nmi_enable() {
+        let mut ppu = PPU::default();
+        run_to(&mut ppu, 241, 2);
+        assert_ne!(ppu.status() & STATUS_VBLANK, 0);
+        assert!(!ppu.nmi_line());
+        // enabling NMIs during vblank asserts the line straight away
+        ppu.write_register(0x2000, CTRL_NMI_ENABLE);
+        assert!(ppu.nmi_line());
+    }
+
+    #[test]
+    fn data_reads_are_delayed_by_the_read_buffer() {
+        let mut ppu = PPU::default();
+        ppu.load_vram(0x2100, &[0x11, 0x22, 0x33]);
+        ppu.write_register(0x2006, 0x21);
+        ppu.write_register(0x2006, 0x00);
+        // the first read returns the stale buffer; each later read returns the byte before it
+        ppu.read_register(0x2007);
+        assert_eq!(ppu.read_register(0x2007), 0x11);
+        assert_eq!(ppu.read_register(0x2007), 0x22);
+        assert_eq!(ppu.read_register(0x2007), 0x33);
+    }
+
+    #[test]
+    fn palette_reads_are_immediate_and_buffer_the_nametable_beneath() {
+        let mut ppu = PPU::default();
+        ppu.load_vram(0x2f05, &[0x44]);
+        ppu.load_vram(0x3f05, &[0x16]);
+        ppu.write_register(0x2006, 0x3f);
+        ppu.write_register(0x2006, 0x05);
+        assert_eq!(ppu.read_register(0x2007) & 0x3f, 0x16);
+
+        // moving out of the palettes, the buffered byte comes from $2F05
+        ppu.write_register(0x2006, 0x20);
+        ppu.write_register(0x2006, 0x00);
+        assert_eq!(ppu.read_register(0x2007), 0x44);
+    }
+
+    #[test]
+    fn horizontal_mirroring_pairs_the_nametables_side_by_side() {
+        let ppu = ppu_with_mirroring(Mirroring::Horizontal);
+        assert_eq!(ppu.mirror_nametable(0x2000), ppu.mirror_nametable(0x2400));
+        assert_eq!(ppu.mirror_nametable(0x2800), ppu.mirror_nametable(0x2c00));
+        assert_ne!(ppu.mirror_nametable(0x2000), ppu.mirror_nametable(0x2800));
+        assert_eq!(ppu.mirror_nametable(0x2c05), 0x405);
+    }
+
+    #[test]
+    fn vertical_mirroring_stacks_the_nametables() {
+        let ppu = ppu_with_mirroring(Mirroring::Vertical);
+        assert_eq!(ppu.mirror_nametable(0x2000), ppu.mirror_nametable(0x2800));
+        assert_eq!(ppu.mirror_nametable(0x2400), ppu.mirror_nametable(0x2c00));
+        assert_ne!(ppu.mirror_nametable(0x2000), ppu.mirror_nametable(0x2400));
+        // $3000 - $3EFF mirrors $2000 - $2EFF
+        assert_eq!(ppu.mirror_nametable(0x3405), 0x405);
+    }
+
+    #[test]
+    fn sprite_backdrop_mirrors_the_background_backdrop() {
+        let mut ppu = ppu_with_mirroring(Mirroring::Horizontal);
+        ppu.load_vram(0x3f10, &[0x21]);
+        assert_eq!(ppu.vram(0x3f00), 0x21);
+        ppu.load_vram(0x3f04, &[0x15]);
+        assert_eq!(ppu.vram(0x3f14), 0x15);
+        // the 32 bytes repeat through $3FFF
+        assert_eq!(ppu.vram(0x3fe4), 0x15);
+        // colours other than 0 aren't shared
+        ppu.load_vram(0x3f11, &[0x30]);
+        assert_ne!(ppu.vram(0x3f01), 0x30);
+    }
+
+    #[test]
+    fn run_to_scanline_stops_at_dot_0() {
+        let mut ppu = PPU::default();
+        ppu.run_to_scanline(100);
+        assert_eq!((ppu.scanline(), ppu.dot()), (100, 0));
+        // scanlines past the end of the frame are never reached
+        ppu.run_to_scanline(262);
+        assert_eq!((ppu.scanline(), ppu.dot()), (100, 0));
+    }
+
+    #[test]
+    fn frame_ready_is_set_once_per_frame() {
+        let mut ppu = PPU::default();
+        let mut ready = ppu.frame_ready();
+        let mut rises = 0;
+        let mut frame_ends = 0;
+        for _ in 0..(341 * 262) {
+            if ppu.step_dot() {
+                frame_ends += 1;
+            }
+            if ppu.frame_ready() && !ready {
+                rises += 1;
+            }
+            ready = ppu.frame_ready();
+        }
+        assert_eq!(rises, 1);
+        assert_eq!(frame_ends, 1);
+        assert_eq!(ppu.frame_count(), 1);
+    }
+
+    /// Saves `ppu`, then loads the state into a new PPU
+    fn reload(ppu: &PPU) -> Result<(), String> {
+        let mut writer = StateWriter::new();
+        ppu.save_state(&mut writer);
+        let bytes = writer.into_bytes();
+        PPU::default().load_state(&mut StateReader::new(&bytes))
+    }
+
+    #[test]
+    fn state_with_beam_outside_the_frame_is_rejected() {
+        let mut ppu = PPU { scanline: Region::Ntsc.scanlines_per_frame() - 1, dot: DOTS_PER_SCANLINE - 1, ..PPU::default() };
+        assert_eq!(reload(&ppu), Ok(()));
+
+        ppu.dot = DOTS_PER_SCANLINE;
+        assert!(reload(&ppu).is_err());
+        ppu.dot = 0;
+        ppu.scanline = Region::Ntsc.scanlines_per_frame();
+        assert!(reload(&ppu).is_err());
+    }
+
+    /// Renders a fixed background for four frames, scrolling it a pixel further each frame, and returns the hash of each
+    fn scrolling_background_hashes() -> Vec<u64> {
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].copy_from_slice(&[0xf0, 0x0f, 0xf0, 0x0f, 0xaa, 0x55, 0xaa, 0x55]);
+        chr[0x20..0x30].fill(0xff);
+        let mut ppu = ppu_with_chr(chr);
+        let tiles: Vec<u8> = (0..0x3c0).map(|i| (i % 3) as u8).collect();
+        ppu.load_vram(0x2000, &tiles);
+        ppu.load_vram(0x23c0, &[0xe4; 0x40]);
+        ppu.write_register(0x2001, MASK_SHOW_BACKGROUND | MASK_SHOW_BACKGROUND_LEFT);
+        render_frame(&mut ppu);
+
+        (0..4)
+            .map(|x| {
+                ppu.write_register(0x2005, x);
+                ppu.write_register(0x2005, 0);
+                render_frame(&mut ppu);
+                ppu.frame_hash()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn frame_hashes_are_stable_across_runs() {
+        let hashes = scrolling_background_hashes();
+        assert_eq!(hashes, scrolling_background_hashes());
+        // each frame is scrolled differently
+        for pair in hashes.windows(2) {
+            assert_ne!(pair[0], pair[1]);
+        }
+        // a golden list, as a CI run against a ROM would keep; if this changes, so has the rendered picture
+        assert_eq!(hashes, [0xbbf03c96aa8a41e5, 0x46c4c21e7d6e81e5, 0xd42e9f68e6e27fc5, 0xbde3628d866bc3a5]);
+    }
+
+    #[test]
+    fn frame_hash_ignores_the_master_palette() {
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0xff);
+        let mut ppu = ppu_with_chr(chr);
+        ppu.load_vram(0x2000, &[0x01; 0x20]);
+        ppu.write_register(0x2001, MASK_SHOW_BACKGROUND | MASK_SHOW_BACKGROUND_LEFT);
+        render_frame(&mut ppu);
+        let hash = ppu.frame_hash();
+
+        ppu.set_palette([(0xff, 0xff, 0xff); 64]);
+        assert_eq!(ppu.frame_hash(), hash);
+        // but not the picture
+        ppu.load_vram(0x2000, &[0x00]);
+        render_frame(&mut ppu);
+        assert_ne!(ppu.frame_hash(), hash);
+    }
+
+    /// Renders a frame with the top row of tiles solid background colour 1, and two sprites on line 20 at x 0 and 16, with PPUMASK set to `mask`
+    /// Returns the frame's pixels at x 3 and 100 of line 2 (background), and at x 3 and 20 of line 20 (sprites).
+    fn masked_pixels(mask: u8) -> [u8; 4] {
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0xff);
+        let mut ppu = ppu_with_chr(chr);
+        ppu.load_vram(0x2000, &[0x01; 32]);
+        let mut oam = [0xf8; OAM_SIZE];
+        oam[..8].copy_from_slice(&[19, 0x01, 0x00, 0, 19, 0x01, 0x00, 16]);
+        write_oam(&mut ppu, &oam);
+        ppu.write_register(0x2001, mask);
+        render_frame(&mut ppu);
+        let frame = ppu.frame_buffer();
+        [frame[2 * SCREEN_WIDTH + 3], frame[2 * SCREEN_WIDTH + 100], frame[20 * SCREEN_WIDTH + 3], frame[20 * SCREEN_WIDTH + 20]]
+    }
+
+    /// PPUMASK with everything shown
+    const MASK_SHOW_ALL: u8 = MASK_SHOW_BACKGROUND | MASK_SHOW_BACKGROUND_LEFT | MASK_SHOW_SPRITES | MASK_SHOW_SPRITES_LEFT;
+
+    #[test]
+    fn mask_bits_gate_background_and_sprites() {
+        assert_eq!(masked_pixels(MASK_SHOW_ALL), [0x01, 0x01, 0x11, 0x11]);
+        // the backdrop shows in place of whichever layer is disabled
+        assert_eq!(masked_pixels(MASK_SHOW_ALL & !MASK_SHOW_BACKGROUND), [0x00, 0x00, 0x11, 0x11]);
+        assert_eq!(masked_pixels(MASK_SHOW_ALL & !MASK_SHOW_SPRITES), [0x01, 0x01, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn mask_left_column_bits_clip_the_first_8_pixels() {
+        assert_eq!(masked_pixels(MASK_SHOW_ALL & !MASK_SHOW_BACKGROUND_LEFT), [0x00, 0x01, 0x11, 0x11]);
+        assert_eq!(masked_pixels(MASK_SHOW_ALL & !MASK_SHOW_SPRITES_LEFT), [0x01, 0x01, 0x00, 0x11]);
+    }
+
+    #[test]
+    fn rendering_off_shows_the_backdrop_or_the_palette_entry_at_v() {
+        assert_eq!(masked_pixels(0), [0x00; 4]);
+
+        // with v in palette space, that entry is shown instead
+        let mut ppu = ppu_with_mirroring(Mirroring::Vertical);
+        ppu.write_register(0x2006, 0x3f);
+        ppu.write_register(0x2006, 0x05);
+        render_frame(&mut ppu);
+        assert!(ppu.frame_buffer().iter().all(|pixel| *pixel == 0x05));
+    }
+
+    /// Renders a frame of the backdrop in `colour` with PPUMASK set to `mask` (so rendering is off), returning the RGBA of the first pixel
+    fn backdrop_rgba(colour: u8, mask: u8) -> [u8; 4] {
+        let mut ppu = ppu_with_mirroring(Mirroring::Vertical);
+        ppu.load_vram(0x3f00, &[colour]);
+        ppu.write_register(0x2001, mask);
+        render_frame(&mut ppu);
+        let rgba = ppu.frame_rgba();
+        [rgba[0], rgba[1], rgba[2], rgba[3]]
+    }
+
+    #[test]
+    fn greyscale_turns_a_colour_grey() {
+        let [r, g, b, _] = backdrop_rgba(0x16, 0);
+        assert!(r != g || g != b);
+        // $16 keeps only its luminance, becoming $10
+        let (r, g, b) = NTSC_PALETTE[0x10];
+        assert_eq!(backdrop_rgba(0x16, MASK_GREYSCALE), [r, g, b, 0xff]);
+        assert!(r == g && g == b);
+    }
+
+    #[test]
+    fn blue_emphasis_darkens_red_and_green() {
+        let [r, g, b, _] = backdrop_rgba(0x30, 0);
+        let attenuated = |channel: u8| (channel as f32 * EMPHASIS_ATTENUATION) as u8;
+        assert_eq!(backdrop_rgba(0x30, MASK_EMPHASIZE_BLUE), [attenuated(r), attenuated(g), b, 0xff]);
+        assert_eq!(backdrop_rgba(0x30, MASK_EMPHASIZE_RED), [r, attenuated(g), attenuated(b), 0xff]);
+        assert_eq!(backdrop_rgba(0x30, MASK_EMPHASIZE_GREEN), [attenuated(r), g, attenuated(b), 0xff]);
+    }
+
+    #[test]
+    fn emphasis_is_latched_per_line() {
+        let mut ppu = ppu_with_mirroring(Mirroring::Vertical);
+        ppu.load_vram(0x3f00, &[0x30]);
+        render_frame(&mut ppu);
+        run_to(&mut ppu, 100, 0);
+        ppu.write_register(0x2001, MASK_EMPHASIZE_BLUE);
+        run_to(&mut ppu, SCREEN_HEIGHT as u16, 0);
+
+        let rgba = ppu.frame_rgba();
+        let line = |y: usize| &rgba[y * SCREEN_WIDTH * 4..y * SCREEN_WIDTH * 4 + 4];
+        let (r, g, b) = NTSC_PALETTE[0x30];
+        assert_eq!(line(99), [r, g, b, 0xff]);
+        assert!(line(100)[0] < r && line(100)[2] == b);
+        assert_eq!(line(100), line(239));
+    }
+
+    #[test]
+    fn poked_pattern_table_byte_reads_back() {
+        let mut ppu = ppu_with_mirroring(Mirroring::Vertical);
+        ppu.vram_poke(0x1234, 0xa5);
+        assert_eq!(ppu.vram_peek(0x1234), 0xa5);
+        // addresses are taken modulo 0x4000
+        assert_eq!(ppu.vram_peek(0x5234), 0xa5);
+
+        // CHR ROM can't be poked
+        let mut ppu = ppu_with_chr(vec![0x11; 0x2000]);
+        ppu.vram_poke(0x1234, 0xa5);
+        assert_eq!(ppu.vram_peek(0x1234), 0x11);
+    }
+
+    #[test]
+    fn vram_peek_follows_mirroring() {
+        let mut ppu = ppu_with_mirroring(Mirroring::Horizontal);
+        ppu.vram_poke(0x2005, 0x42);
+        // the second nametable is a mirror of the first, and $3000 - $3EFF mirrors $2000 - $2EFF
+        assert_eq!(ppu.vram_peek(0x2405), 0x42);
+        assert_eq!(ppu.vram_peek(0x3005), 0x42);
+        assert_eq!(ppu.vram_peek(0x2805), 0x00);
+    }
+
+    #[test]
+    fn poke_leaves_the_read_buffer_and_address_alone() {
+        let mut ppu = ppu_with_mirroring(Mirroring::Vertical);
+        ppu.load_vram(0x2000, &[0x11, 0x22]);
+        ppu.write_register(0x2006, 0x20);
+        ppu.write_register(0x2006, 0x00);
+        // fill the read buffer with $2000
+        ppu.read_register(0x2007);
+
+        ppu.vram_poke(0x2000, 0x33);
+        ppu.vram_poke(0x2001, 0x44);
+        assert_eq!(ppu.vram_peek(0x2000), 0x33);
+        // the buffered value is still the old one, and the address has only moved for the read
+        assert_eq!(ppu.read_register(0x2007), 0x11);
+        assert_eq!(ppu.read_register(0x2007), 0x44);
+    }
+
+    #[test]
+    fn oam_peek_and_poke_leave_oamaddr_alone() {
+        let mut ppu = PPU::default();
+        ppu.write_register(0x2003, 0x10);
+        ppu.oam_poke(0x03, 0x99);
+        assert_eq!(ppu.oam_peek(0x03), 0x99);
+        assert_eq!(ppu.oam()[0x03], 0x99);
+
+        // OAMDATA still writes where OAMADDR pointed
+        ppu.write_register(0x2004, 0x55);
+        assert_eq!(ppu.oam_peek(0x10), 0x55);
+        assert_eq!(ppu.oam_peek(0x03), 0x99);
+    }
+
+    #[test]
+    fn nametable_viewer_uses_tiles_and_attributes() {
+        // tile 1 is solid colour 1; tile 2 has only its leftmost column in colour 3
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0xff);
+        chr[0x20..0x30].fill(0x80);
+        let mut ppu = ppu_with_chr(chr);
+        // nametable 1: tile 1 at (1, 1), and tile 2 at (4, 4) in the next attribute block
+        ppu.load_vram(0x2400 + 33, &[0x01]);
+        ppu.load_vram(0x2400 + 4 * 32 + 4, &[0x02]);
+        ppu.load_vram(0x2400 + 0x3c0, &[0b0000_0011]);
+        ppu.load_vram(0x2400 + 0x3c0 + 9, &[0b0000_0001]);
+
+        let nametable = ppu.render_nametable(1);
+        // palette 3, colour 1
+        assert_eq!(nametable[8 * SCREEN_WIDTH + 8], 3 << 2 | 1);
+        assert_eq!(nametable[15 * SCREEN_WIDTH + 15], 3 << 2 | 1);
+        assert_eq!(nametable[0], 0);
+        // palette 1, colour 3, in the first column of the tile only
+        assert_eq!(nametable[32 * SCREEN_WIDTH + 32], 1 << 2 | 3);
+        assert_eq!(nametable[32 * SCREEN_WIDTH + 33], 0);
+        // nametable 0 is empty
+        assert!(ppu.render_nametable(0).iter().all(|pixel| *pixel == 0));
+    }
+
+    #[test]
+    fn pattern_table_viewer_lays_tiles_out_in_a_grid() {
+        // tile $12 of the second table: its top row is colour 2 in the leftmost pixel
+        let mut chr = vec![0; 0x2000];
+        chr[0x1000 + 0x12 * 16 + 8] = 0x80;
+        let ppu = ppu_with_chr(chr);
+        let table = ppu.render_pattern_table(1, 5);
+        // tile $12 is in row 1, column 2 of the grid
+        assert_eq!(table[8 * 128 + 16], 5 << 2 | 2);
+        assert_eq!(table[8 * 128 + 17], 0);
+        assert!(ppu.render_pattern_table(0, 5).iter().all(|pixel| *pixel == 0));
+    }
+
+    #[test]
+    fn overscan_crop_starts_at_the_first_uncropped_row() {
+        // only lines 8 - 15 show tile 1 (solid colour 1), and only from x 16
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0xff);
+        let mut ppu = ppu_with_chr(chr);
+        ppu.load_vram(0x2000 + 32 + 2, &[0x01; 30]);
+        ppu.load_vram(0x3f00, &[0x0f, 0x30]);
+        ppu.write_register(0x2001, MASK_SHOW_BACKGROUND | MASK_SHOW_BACKGROUND_LEFT);
+        render_frame(&mut ppu);
+        let full = ppu.frame_rgba();
+
+        // nothing is cropped by default
+        assert_eq!(ppu.cropped_size(), (SCREEN_WIDTH, SCREEN_HEIGHT));
+        assert!(ppu.frame_rgba_cropped() == full);
+
+        ppu.set_overscan(8, 8, 0, 0);
+        assert_eq!(ppu.cropped_size(), (256, 224));
+        let cropped = ppu.frame_rgba_cropped();
+        assert_eq!(cropped.len(), 256 * 224 * 4);
+        let row = SCREEN_WIDTH * 4;
+        assert!(cropped[..row] == full[8 * row..9 * row]);
+        assert!(cropped[cropped.len() - row..] == full[231 * row..232 * row]);
+        let (r, g, b) = NTSC_PALETTE[0x30];
+        assert_eq!(cropped[16 * 4..17 * 4], [r, g, b, 0xff]);
+
+        ppu.set_overscan(8, 8, 16, 8);
+        assert_eq!(ppu.cropped_size(), (232, 224));
+        assert_eq!(ppu.frame_rgba_cropped()[..4], [r, g, b, 0xff]);
+
+        // crops can't remove the whole picture
+        ppu.set_overscan(255, 255, 200, 200);
+        assert_eq!(ppu.cropped_size(), (1, 1));
+        assert_eq!(ppu.frame_rgba_cropped().len(), 4);
+    }
+
+    #[test]
+    fn four_screen_nametables_do_not_alias() {
+        let mut ppu = ppu_with_mirroring(Mirroring::FourScreen);
+        for table in 0..4u16 {
+            let address = 0x2000 + table * 0x400 + 0x45;
+            ppu.write_register(0x2006, (address >> 8) as u8);
+            ppu.write_register(0x2006, address as u8);
+            ppu.write_register(0x2007, 0x10 + table as u8);
+        }
+        for table in 0..4u16 {
+            assert_eq!(ppu.vram_peek(0x2000 + table * 0x400 + 0x45), 0x10 + table as u8);
+            // $3000 - $3EFF still mirrors the nametables
+            assert_eq!(ppu.vram_peek(0x3000 + table * 0x400 + 0x45), 0x10 + table as u8);
+        }
+
+        // with ordinary mirroring, the third nametable is the first again
+        let mut ppu = ppu_with_mirroring(Mirroring::Vertical);
+        ppu.vram_poke(0x2045, 0x10);
+        ppu.vram_poke(0x2845, 0x12);
+        assert_eq!(ppu.vram_peek(0x2045), 0x12);
+    }
 }
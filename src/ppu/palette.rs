@@ -0,0 +1,27 @@
+// palette.rs
+// The colours the PPU can produce
+
+/// The 64 colours of the NTSC PPU, as RGB triples.
+/// The PPU generates a composite video signal directly rather than RGB, so these are an approximation of how a typical TV decodes each colour.
+/// Colours are indexed by `0bLLHHHH`: a 2-bit luminance and a 4-bit hue; hues `0xE` and `0xF` are black.
+pub const NTSC_PALETTE: [(u8, u8, u8); 64] = [
+    (0x62, 0x62, 0x62), (0x00, 0x1f, 0xb2), (0x24, 0x04, 0xc8), (0x52, 0x00, 0xb2),
+    (0x73, 0x00, 0x76), (0x80, 0x00, 0x24), (0x73, 0x0b, 0x00), (0x52, 0x28, 0x00),
+    (0x24, 0x44, 0x00), (0x00, 0x57, 0x00), (0x00, 0x5c, 0x00), (0x00, 0x53, 0x24),
+    (0x00, 0x3c, 0x76), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+
+    (0xab, 0xab, 0xab), (0x0d, 0x57, 0xff), (0x4b, 0x30, 0xff), (0x8a, 0x13, 0xff),
+    (0xbc, 0x08, 0xd6), (0xd2, 0x12, 0x69), (0xc7, 0x2e, 0x00), (0x9d, 0x54, 0x00),
+    (0x60, 0x7b, 0x00), (0x20, 0x98, 0x00), (0x00, 0xa3, 0x00), (0x00, 0x99, 0x42),
+    (0x00, 0x7d, 0xb4), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+
+    (0xff, 0xff, 0xff), (0x53, 0xae, 0xff), (0x90, 0x85, 0xff), (0xd3, 0x65, 0xff),
+    (0xff, 0x57, 0xff), (0xff, 0x5d, 0xcf), (0xff, 0x77, 0x57), (0xfa, 0x9e, 0x00),
+    (0xbd, 0xc7, 0x00), (0x7a, 0xe7, 0x00), (0x43, 0xf6, 0x11), (0x26, 0xef, 0x7e),
+    (0x2c, 0xd5, 0xf6), (0x4e, 0x4e, 0x4e), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+
+    (0xff, 0xff, 0xff), (0xb6, 0xe1, 0xff), (0xce, 0xd1, 0xff), (0xe9, 0xc3, 0xff),
+    (0xff, 0xbc, 0xff), (0xff, 0xbd, 0xf4), (0xff, 0xc6, 0xc3), (0xff, 0xd5, 0x9a),
+    (0xe9, 0xe6, 0x81), (0xce, 0xf4, 0x81), (0xb6, 0xfb, 0x9a), (0xa9, 0xfa, 0xc3),
+    (0xa9, 0xf0, 0xf4), (0xb8, 0xb8, 0xb8), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+];
@@ -0,0 +1,243 @@
+// state.rs
+// Encodes and decodes save states
+
+pub mod rewind;
+
+/// Identifies a save state produced by this emulator
+pub const STATE_MAGIC: &[u8; 4] = b"RNST";
+
+/// The version of the save state format, written after the magic number
+/// This must be incremented whenever any component changes what it saves, so old states are rejected rather than misread.
+pub const STATE_VERSION: u32 = 9;
+
+/// The FNV-1a parameters used by `hash`
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Hashes `bytes` with 64-bit FNV-1a
+/// Unlike the standard library's hasher, the result is guaranteed to be the same across platforms and Rust versions, so hashes can be recorded and compared later.
+pub fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, byte| (hash ^ *byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+/// A component of the machine whose state can be saved and restored.
+/// Components write their fields in a fixed order, and must read them back in the same order.
+/// Configuration that isn't part of the emulated hardware (such as the sample rate or the colour palette) isn't saved, so loading a state keeps the frontend's settings.
+pub trait SaveState {
+    /// Writes the component's state
+    fn save_state(&self, writer: &mut StateWriter);
+
+    /// Restores the component's state from one written by `save_state`
+    /// Returns an error if the state is truncated or malformed; the component may then be left partially restored.
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String>;
+}
+
+/// Builds a save state.
+/// Values are written in little-endian order with no padding or field names, which keeps states compact.
+#[derive(Default)]
+pub struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    /// Creates an empty state
+    pub fn new() -> StateWriter {
+        StateWriter::default()
+    }
+
+    /// Returns the encoded state
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_i64(&mut self, value: i64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_f64(&mut self, value: f64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Writes a block of bytes, preceded by its length
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.bytes.extend_from_slice(bytes);
+    }
+}
+
+/// Reads back a save state written by a `StateWriter`
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    /// Creates a reader at the start of `bytes`
+    pub fn new(bytes: &'a [u8]) -> StateReader<'a> {
+        StateReader {
+            bytes,
+            position: 0,
+        }
+    }
+
+    /// Returns whether all of the state has been read
+    pub fn is_finished(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    /// Takes the next `length` bytes of the state
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.position < length {
+            return Err(format!("Save state is truncated: expected {} more bytes at offset {}", length, self.position));
+        }
+        let bytes = &self.bytes[self.position..self.position + length];
+        self.position += length;
+        Ok(bytes)
+    }
+
+    /// Takes the next `N` bytes of the state as an array
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, String> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(format!("Invalid boolean in save state: {}", value)),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take_array()?))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take_array()?))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take_array()?))
+    }
+
+    pub fn read_i64(&mut self) -> Result<i64, String> {
+        Ok(i64::from_le_bytes(self.take_array()?))
+    }
+
+    pub fn read_f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_le_bytes(self.take_array()?))
+    }
+
+    pub fn read_f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_le_bytes(self.take_array()?))
+    }
+
+    /// Reads a block of bytes written by `write_bytes`
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], String> {
+        let length = self.read_u32()? as usize;
+        self.take(length)
+    }
+
+    /// Reads a block of bytes written by `write_bytes` into `buffer`, which must be exactly the size of the block
+    pub fn read_into(&mut self, buffer: &mut [u8]) -> Result<(), String> {
+        let bytes = self.read_bytes()?;
+        if bytes.len() != buffer.len() {
+            return Err(format!("Save state has a block of {} bytes where {} were expected", bytes.len(), buffer.len()));
+        }
+        buffer.copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_round_trip_in_order() {
+        let mut writer = StateWriter::new();
+        writer.write_u8(0xa5);
+        writer.write_bool(true);
+        writer.write_u16(0x1234);
+        writer.write_u32(0xdeadbeef);
+        writer.write_u64(u64::MAX - 1);
+        writer.write_i64(-42);
+        writer.write_f32(0.25);
+        writer.write_f64(-1.5);
+        writer.write_bytes(&[1, 2, 3]);
+        let bytes = writer.into_bytes();
+
+        let mut reader = StateReader::new(&bytes);
+        assert_eq!(reader.read_u8(), Ok(0xa5));
+        assert_eq!(reader.read_bool(), Ok(true));
+        assert_eq!(reader.read_u16(), Ok(0x1234));
+        assert_eq!(reader.read_u32(), Ok(0xdeadbeef));
+        assert_eq!(reader.read_u64(), Ok(u64::MAX - 1));
+        assert_eq!(reader.read_i64(), Ok(-42));
+        assert_eq!(reader.read_f32(), Ok(0.25));
+        assert_eq!(reader.read_f64(), Ok(-1.5));
+        let mut block = [0; 3];
+        assert_eq!(reader.read_into(&mut block), Ok(()));
+        assert_eq!(block, [1, 2, 3]);
+        assert!(reader.is_finished());
+    }
+
+    #[test]
+    fn values_are_little_endian_without_padding() {
+        let mut writer = StateWriter::new();
+        writer.write_u16(0x1234);
+        writer.write_bytes(&[0xff]);
+        assert_eq!(writer.into_bytes(), vec![0x34, 0x12, 0x01, 0x00, 0x00, 0x00, 0xff]);
+    }
+
+    #[test]
+    fn truncated_state_is_an_error() {
+        let mut reader = StateReader::new(&[0x01, 0x02, 0x03]);
+        assert!(reader.read_u32().is_err());
+        // a block whose length runs past the end
+        let mut reader = StateReader::new(&[0x08, 0x00, 0x00, 0x00, 0x01]);
+        assert!(reader.read_bytes().is_err());
+    }
+
+    #[test]
+    fn malformed_values_are_errors() {
+        let mut reader = StateReader::new(&[0x02]);
+        assert!(reader.read_bool().is_err());
+
+        let mut writer = StateWriter::new();
+        writer.write_bytes(&[1, 2]);
+        let bytes = writer.into_bytes();
+        let mut block = [0; 3];
+        assert!(StateReader::new(&bytes).read_into(&mut block).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+// rewind.rs
+// Keeps a history of recent save states so play can be rewound
+
+use std::collections::VecDeque;
+
+/// A ring buffer of save states, captured every few frames.
+/// To keep memory down, only the newest snapshot is kept in full; each older one is stored as a delta that rebuilds it from the snapshot after it.
+/// When the buffer is full, the oldest snapshot is dropped.
+pub struct Rewind {
+    capacity: usize,
+    interval: u32,
+    // frames until the next snapshot is due
+    countdown: u32,
+    newest: Option<Vec<u8>>,
+    // the deltas from each snapshot to the one before it, oldest first
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+    /// Creates an empty buffer holding up to `capacity` snapshots (at least one), taken every `interval` frames
+    pub fn new(capacity: usize, interval: u32) -> Rewind {
+        let interval = interval.max(1);
+        Rewind {
+            capacity: capacity.max(1),
+            interval,
+            countdown: interval,
+            newest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    /// Returns the number of snapshots held
+    pub fn len(&self) -> usize {
+        match self.newest {
+            Some(_) => self.deltas.len() + 1,
+            None => 0,
+        }
+    }
+
+    /// Returns whether there are no snapshots to rewind to
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    /// Counts a finished frame, returning whether a snapshot is due
+    pub fn end_frame(&mut self) -> bool {
+        self.countdown -= 1;
+        if self.countdown == 0 {
+            self.countdown = self.interval;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Adds a snapshot, dropping the oldest if the buffer is full
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(previous) = self.newest.take() {
+            self.deltas.push_back(diff(&state, &previous));
+            if self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.newest = Some(state);
+    }
+
+    /// Removes and returns the newest snapshot
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let newest = self.newest.take()?;
+        self.newest = self.deltas.pop_back().map(|delta| patch(&newest, &delta));
+        // the next snapshot is due a full interval after the one being restored
+        self.countdown = self.interval;
+        Some(newest)
+    }
+}
+
+/// Encodes the differences between `from` and `to` as a delta, which `patch` applies to `from` to rebuild `to`
+/// The delta is the length of `to`, followed by each run of differing bytes as its offset, its length, and the bytes from `to`.
+fn diff(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    delta.extend_from_slice(&(to.len() as u32).to_le_bytes());
+
+    let mut offset = 0;
+    while offset < to.len() {
+        if offset < from.len() && from[offset] == to[offset] {
+            offset += 1;
+            continue;
+        }
+
+        let start = offset;
+        while offset < to.len() && (offset >= from.len() || from[offset] != to[offset]) {
+            offset += 1;
+        }
+        delta.extend_from_slice(&(start as u32).to_le_bytes());
+        delta.extend_from_slice(&((offset - start) as u32).to_le_bytes());
+        delta.extend_from_slice(&to[start..offset]);
+    }
+    delta
+}
+
+/// Applies a delta produced by `diff` to `from`
+fn patch(from: &[u8], delta: &[u8]) -> Vec<u8> {
+    let word = |position: usize| {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&delta[position..position + 4]);
+        u32::from_le_bytes(bytes) as usize
+    };
+
+    let mut to = from.to_vec();
+    to.resize(word(0), 0);
+
+    let mut position = 4;
+    while position < delta.len() {
+        let start = word(position);
+        let length = word(position + 4);
+        position += 8;
+        to[start..start + length].copy_from_slice(&delta[position..position + length]);
+        position += length;
+    }
+    to
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patch_rebuilds_the_target_of_a_diff() {
+        let from = vec![1, 2, 3, 4, 5, 6];
+        for to in [vec![1, 9, 3, 4, 8, 8], vec![1, 2, 3], vec![0, 2, 3, 4, 5, 6, 7, 8], vec![1, 2, 3, 4, 5, 6]] {
+            assert_eq!(patch(&from, &diff(&from, &to)), to);
+        }
+    }
+
+    #[test]
+    fn delta_only_holds_the_changed_bytes() {
+        let from = vec![0; 1000];
+        let mut to = from.clone();
+        to[500] = 1;
+        // the length, then one run: its offset, its length, and the byte
+        assert_eq!(diff(&from, &to).len(), 4 + 8 + 1);
+    }
+
+    #[test]
+    fn snapshots_are_popped_newest_first() {
+        let mut rewind = Rewind::new(4, 1);
+        for value in 1..=3 {
+            rewind.push(vec![value; 16]);
+        }
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(rewind.pop(), Some(vec![3; 16]));
+        assert_eq!(rewind.pop(), Some(vec![2; 16]));
+        assert_eq!(rewind.pop(), Some(vec![1; 16]));
+        assert_eq!(rewind.pop(), None);
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn full_buffer_drops_the_oldest_snapshot() {
+        let mut rewind = Rewind::new(2, 1);
+        for value in 1..=3 {
+            rewind.push(vec![value; 16]);
+        }
+        assert_eq!(rewind.len(), 2);
+        assert_eq!(rewind.pop(), Some(vec![3; 16]));
+        assert_eq!(rewind.pop(), Some(vec![2; 16]));
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn snapshot_is_due_every_interval() {
+        let mut rewind = Rewind::new(4, 3);
+        let due: Vec<bool> = (0..6).map(|_| rewind.end_frame()).collect();
+        assert_eq!(due, vec![false, false, true, false, false, true]);
+    }
+}
@@ -0,0 +1,181 @@
+// rom_loading.rs
+// Loads an iNES file the way the frontend does, through the public API
+
+use std::fs;
+
+use rust_nes::cpu::CPU;
+use rust_nes::mem::Mem;
+use rust_nes::nes::{Region, NES};
+use rust_nes::{ines, mapper};
+
+/// Builds a minimal NROM file: 16KB of PRG ROM whose reset routine at `0x8010` is `routine`, and 8KB of CHR ROM
+fn nrom_with_reset_routine(routine: &[u8]) -> Vec<u8> {
+    let mut prg = vec![0xea; 0x4000];
+    prg[0x10..0x10 + routine.len()].copy_from_slice(routine);
+    prg[0x3ffc] = 0x10;
+    prg[0x3ffd] = 0x80;
+
+    let mut file = vec![0x4e, 0x45, 0x53, 0x1a, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+    file.extend(prg);
+    file.extend(vec![0; 0x2000]);
+    file
+}
+
+/// Builds a minimal NROM file whose reset routine is `LDA #$42; JMP $8012`
+fn minimal_nrom() -> Vec<u8> {
+    nrom_with_reset_routine(&[0xa9, 0x42, 0x4c, 0x12, 0x80])
+}
+
+#[test]
+fn runs_reset_routine_of_nrom_file() {
+    let rom = ines::load_rom(&minimal_nrom()).unwrap();
+    let mut cpu = CPU::default();
+    cpu.bus_mut().set_mapper(mapper::create_mapper(&rom.header, rom.prg_rom, rom.chr_rom).unwrap());
+    cpu.power_on();
+    assert_eq!(cpu.pc(), 0x8010);
+
+    cpu.step();
+    assert_eq!(cpu.a(), 0x42);
+    assert_eq!(cpu.pc(), 0x8012);
+}
+
+#[test]
+fn from_rom_bytes_starts_at_the_reset_vector() {
+    let nes = NES::from_rom_bytes(&minimal_nrom(), None).unwrap();
+    assert_eq!(nes.cpu.pc(), 0x8010);
+    assert!(nes.cpu.is_running());
+    // the header doesn't ask for PAL timing
+    assert_eq!(nes.region(), Region::Ntsc);
+}
+
+#[test]
+fn from_rom_bytes_disables_decimal_mode() {
+    // SED; CLC; LDA #$09; ADC #$01; JMP $8017
+    let rom = nrom_with_reset_routine(&[0xf8, 0x18, 0xa9, 0x09, 0x69, 0x01, 0x4c, 0x17, 0x80]);
+    let mut nes = NES::from_rom_bytes(&rom, None).unwrap();
+    for _ in 0..4 {
+        nes.cpu.step();
+    }
+    // decimal mode would give 0x10
+    assert_eq!(nes.cpu.a(), 0x0a);
+}
+
+#[test]
+fn from_rom_file_loads_the_file() {
+    let path = std::env::temp_dir().join(format!("rust_nes_rom_loading_{}.nes", std::process::id()));
+    fs::write(&path, minimal_nrom()).unwrap();
+    let loaded = NES::from_rom_file(&path, Some(Region::Pal));
+    fs::remove_file(&path).unwrap();
+
+    let nes = loaded.unwrap();
+    assert_eq!(nes.cpu.pc(), 0x8010);
+    assert_eq!(nes.region(), Region::Pal);
+}
+
+#[test]
+fn rom_errors_are_reported() {
+    assert!(NES::from_rom_bytes(&minimal_nrom()[..100], None).is_err());
+    let missing = std::env::temp_dir().join("rust_nes_no_such_rom.nes");
+    let error = NES::from_rom_file(&missing, None).err().unwrap();
+    assert!(error.contains("Could not read"), "{}", error);
+}
+
+#[test]
+fn battery_ram_survives_into_a_fresh_machine() {
+    let mut rom = minimal_nrom();
+    // flags 6, bit 1: battery-backed PRG RAM
+    rom[6] |= 0x02;
+    let mut nes = NES::from_rom_bytes(&rom, None).unwrap();
+    nes.cpu.bus_mut().write(0x6000, 0x77);
+    nes.cpu.bus_mut().write(0x7fff, 0x88);
+    let sram = nes.save_sram().unwrap();
+    assert_eq!(sram.len(), 0x2000);
+
+    let mut fresh = NES::from_rom_bytes(&rom, None).unwrap();
+    assert_eq!(fresh.cpu.bus_mut().read(0x6000), 0x00);
+    fresh.load_sram(&sram).unwrap();
+    assert_eq!(fresh.cpu.bus_mut().read(0x6000), 0x77);
+    assert_eq!(fresh.cpu.bus_mut().read(0x7fff), 0x88);
+
+    // the saved RAM has to be the size of the cartridge's
+    assert!(fresh.load_sram(&sram[..0x1000]).is_err());
+}
+
+#[test]
+fn cartridges_without_a_battery_have_no_sram() {
+    let mut nes = NES::from_rom_bytes(&minimal_nrom(), None).unwrap();
+    assert!(nes.save_sram().is_none());
+    assert!(nes.load_sram(&[0; 0x2000]).is_err());
+}
+
+#[test]
+fn trainer_is_loaded_at_0x7000() {
+    let rom = minimal_nrom();
+    let (header, sections) = rom.split_at(16);
+    let mut file = header.to_vec();
+    // flags 6, bit 2: a 512-byte trainer follows the header
+    file[6] |= 0x04;
+    file.extend((0..512).map(|i| i as u8));
+    file.extend_from_slice(sections);
+    let mut nes = NES::from_rom_bytes(&file, None).unwrap();
+
+    assert_eq!(nes.cpu.bus_mut().read(0x7000), 0x00);
+    assert_eq!(nes.cpu.bus_mut().read(0x7005), 0x05);
+    assert_eq!(nes.cpu.bus_mut().read(0x71ff), 0xff);
+    // the PRG ROM starts after the trainer, so the reset vector and routine are where they were without one
+    assert_eq!(nes.cpu.pc(), 0x8010);
+    assert_eq!(nes.cpu.bus_mut().read(0x8010), 0xa9);
+    assert_eq!(nes.cpu.bus_mut().read(0x8000), 0xea);
+}
+
+#[test]
+fn four_screen_cartridge_keeps_all_four_nametables() {
+    let mut rom = minimal_nrom();
+    // flags 6, bit 3: the cartridge supplies VRAM for four nametables
+    rom[6] |= 0x08;
+    let mut nes = NES::from_rom_bytes(&rom, None).unwrap();
+    for table in 0..4u16 {
+        nes.ppu_mut().vram_poke(0x2000 + table * 0x400, 0x10 + table as u8);
+    }
+    let state = nes.save_state();
+
+    // the extra nametables are part of the save state
+    let mut fresh = NES::from_rom_bytes(&rom, None).unwrap();
+    fresh.load_state(&state).unwrap();
+    for table in 0..4u16 {
+        assert_eq!(nes.ppu().vram_peek(0x2000 + table * 0x400), 0x10 + table as u8);
+        assert_eq!(fresh.ppu().vram_peek(0x2000 + table * 0x400), 0x10 + table as u8);
+    }
+}
+
+/// Writes `tile` to the start of the pattern tables through PPUADDR and PPUDATA, then reads it back the same way
+fn write_and_read_tile(nes: &mut NES, tile: &[u8]) -> Vec<u8> {
+    let bus = nes.cpu.bus_mut();
+    bus.write(0x2006, 0x00);
+    bus.write(0x2006, 0x00);
+    for byte in tile.iter() {
+        bus.write(0x2007, *byte);
+    }
+    bus.write(0x2006, 0x00);
+    bus.write(0x2006, 0x00);
+    // the first read only fills the read buffer
+    bus.read(0x2007);
+    tile.iter().map(|_| bus.read(0x2007)).collect()
+}
+
+#[test]
+fn chr_ram_cartridge_keeps_tiles_written_through_the_ppu() {
+    let mut rom = minimal_nrom();
+    // no CHR ROM, so the board has CHR RAM instead
+    rom[5] = 0;
+    rom.truncate(16 + 0x4000);
+    let mut nes = NES::from_rom_bytes(&rom, None).unwrap();
+    let tile = [0x3c, 0x42, 0x81, 0x81, 0x81, 0x81, 0x42, 0x3c];
+    assert_eq!(write_and_read_tile(&mut nes, &tile), tile);
+}
+
+#[test]
+fn chr_rom_cartridge_ignores_tile_writes() {
+    let mut nes = NES::from_rom_bytes(&minimal_nrom(), None).unwrap();
+    assert_eq!(write_and_read_tile(&mut nes, &[0xff; 8]), [0x00; 8]);
+}
@@ -0,0 +1,225 @@
+// json.rs
+// A minimal JSON reader, enough for the single-step test files
+
+/// A parsed JSON value
+#[derive(PartialEq)]
+#[derive(Debug, Clone)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Parses a complete JSON document
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser {
+            bytes: text.as_bytes(),
+            position: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.position != parser.bytes.len() {
+            return Err(format!("Unexpected data after JSON value at offset {}", parser.position));
+        }
+        Ok(value)
+    }
+
+    /// Returns the member of an object with the given key
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as an array
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as a string
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as an unsigned integer, if it is a whole number in range
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Json::Number(value) if *value >= 0.0 && value.fract() == 0.0 && *value <= u64::MAX as f64 => Some(*value as u64),
+            _ => None,
+        }
+    }
+}
+
+/// A recursive descent parser over the document's bytes
+struct Parser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.position < self.bytes.len() && self.bytes[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).copied()
+    }
+
+    /// Consumes `expected`, which must be next
+    fn expect(&mut self, expected: u8) -> Result<(), String> {
+        if self.peek() == Some(expected) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(format!("Expected '{}' at offset {}", expected as char, self.position))
+        }
+    }
+
+    /// Consumes `literal`, which must be next, returning `value`
+    fn literal(&mut self, literal: &str, value: Json) -> Result<Json, String> {
+        if self.bytes[self.position..].starts_with(literal.as_bytes()) {
+            self.position += literal.len();
+            Ok(value)
+        } else {
+            Err(format!("Invalid literal at offset {}", self.position))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(_) => self.number(),
+            None => Err(String::from("Unexpected end of JSON")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(b':')?;
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(Json::Object(members));
+                },
+                _ => return Err(format!("Expected ',' or '}}' at offset {}", self.position)),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect(b'[')?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(Json::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(Json::Array(values));
+                },
+                _ => return Err(format!("Expected ',' or ']' at offset {}", self.position)),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut text = String::new();
+        loop {
+            let start = self.position;
+            while let Some(byte) = self.peek() {
+                if byte == b'"' || byte == b'\\' {
+                    break;
+                }
+                self.position += 1;
+            }
+            text.push_str(std::str::from_utf8(&self.bytes[start..self.position]).map_err(|e| e.to_string())?);
+
+            match self.peek() {
+                Some(b'"') => {
+                    self.position += 1;
+                    return Ok(text);
+                },
+                Some(b'\\') => {
+                    self.position += 1;
+                    let escaped = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            let digits = self.bytes.get(self.position + 1..self.position + 5)
+                                .and_then(|digits| std::str::from_utf8(digits).ok())
+                                .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+                                .ok_or_else(|| format!("Invalid unicode escape at offset {}", self.position))?;
+                            self.position += 4;
+                            char::from_u32(digits).unwrap_or('\u{fffd}')
+                        },
+                        _ => return Err(format!("Invalid escape at offset {}", self.position)),
+                    };
+                    text.push(escaped);
+                    self.position += 1;
+                },
+                _ => return Err(String::from("Unterminated string in JSON")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+        while let Some(byte) = self.peek() {
+            if byte.is_ascii_digit() || matches!(byte, b'-' | b'+' | b'.' | b'e' | b'E') {
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+        std::str::from_utf8(&self.bytes[start..self.position])
+            .ok()
+            .and_then(|number| number.parse().ok())
+            .map(Json::Number)
+            .ok_or_else(|| format!("Invalid number at offset {}", start))
+    }
+}
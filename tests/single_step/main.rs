@@ -0,0 +1,232 @@
+// single_step/main.rs
+// Runs the CPU against single-step tests in the format of the ProcessorTests suite
+// Each file in cases/ holds tests for one opcode, named by its hex value; more files from the suite can be dropped in and added to `CASE_FILES`.
+
+mod json;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use json::Json;
+use rust_nes::cpu::{BusOp, CPU};
+use rust_nes::mem::{FlatMemory, Mem};
+
+/// Bits 4 and 5 of the status register don't exist in the processor, so they aren't compared
+const STATUS_COMPARE_MASK: u8 = 0xcf;
+
+/// How single-step tests are run
+struct Options {
+    /// Whether the D flag enables decimal mode; the suite's 6502 tests expect it, while its NES tests don't
+    decimal_enabled: bool,
+    /// Whether to compare the bus accesses the instruction made against the cycle list in each test
+    check_bus: bool,
+}
+
+impl Default for Options {
+    #[inline]
+    fn default() -> Options {
+        Options {
+            decimal_enabled: true,
+            check_bus: false,
+        }
+    }
+}
+
+/// The registers and memory of a test's initial or final state
+struct State {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+/// Reads a number from a member of a JSON object, checking that it is in range
+fn field(json: &Json, key: &str, max: u64) -> Result<u64, String> {
+    json.get(key)
+        .and_then(Json::as_u64)
+        .filter(|value| *value <= max)
+        .ok_or_else(|| format!("Missing or invalid field '{}'", key))
+}
+
+/// Reads an `[address, value]` pair
+fn address_and_value(json: &Json) -> Option<(u16, u8)> {
+    match json.as_array()? {
+        [address, value, ..] => Some((address.as_u64()? as u16, value.as_u64()? as u8)),
+        _ => None,
+    }
+}
+
+impl State {
+    fn parse(json: &Json) -> Result<State, String> {
+        let ram = json.get("ram")
+            .and_then(Json::as_array)
+            .ok_or_else(|| String::from("Missing or invalid field 'ram'"))?
+            .iter()
+            .map(|entry| address_and_value(entry).ok_or_else(|| String::from("Invalid RAM entry")))
+            .collect::<Result<Vec<(u16, u8)>, String>>()?;
+
+        Ok(State {
+            pc: field(json, "pc", 0xffff)? as u16,
+            s: field(json, "s", 0xff)? as u8,
+            a: field(json, "a", 0xff)? as u8,
+            x: field(json, "x", 0xff)? as u8,
+            y: field(json, "y", 0xff)? as u8,
+            p: field(json, "p", 0xff)? as u8,
+            ram,
+        })
+    }
+}
+
+/// Runs every case in a test file, which is a JSON array of cases, returning a description of each failure
+/// Returns an error if the file can't be parsed.
+fn run_file(text: &str, options: &Options) -> Result<Vec<String>, String> {
+    let json = Json::parse(text)?;
+    let cases = json.as_array().ok_or_else(|| String::from("Expected an array of test cases"))?;
+    Ok(cases.iter().filter_map(|case| run_parsed_case(case, options).err()).collect())
+}
+
+/// Runs a single case, given as a JSON object: sets up the initial state, executes one instruction, and compares the result with the final state
+/// Returns a description of the first difference found.
+fn run_case(text: &str, options: &Options) -> Result<(), String> {
+    run_parsed_case(&Json::parse(text)?, options)
+}
+
+fn run_parsed_case(case: &Json, options: &Options) -> Result<(), String> {
+    let name = case.get("name").and_then(Json::as_str).unwrap_or("unnamed");
+    let fail = |message: String| format!("{}: {}", name, message);
+
+    let initial = State::parse(case.get("initial").ok_or_else(|| fail(String::from("Missing initial state")))?).map_err(fail)?;
+    let expected = State::parse(case.get("final").ok_or_else(|| fail(String::from("Missing final state")))?).map_err(fail)?;
+
+    let mut memory = FlatMemory::default();
+    for (address, value) in initial.ram.iter() {
+        memory.write(*address, *value);
+    }
+    let mut cpu = CPU::with_memory(memory);
+    cpu.set_decimal_enabled(options.decimal_enabled);
+    cpu.set_pc(initial.pc);
+    cpu.set_sp(initial.s);
+    cpu.set_a(initial.a);
+    cpu.set_x(initial.x);
+    cpu.set_y(initial.y);
+    cpu.set_status(initial.p);
+
+    let accesses = Rc::new(RefCell::new(Vec::new()));
+    let log = Rc::clone(&accesses);
+    cpu.set_bus_trace(move |op| log.borrow_mut().push(op));
+    cpu.step();
+
+    // each register is compared along with the number of hex digits to show it with
+    let registers = [
+        ("PC", cpu.pc(), expected.pc, 4),
+        ("S", cpu.sp() as u16, expected.s as u16, 2),
+        ("A", cpu.a() as u16, expected.a as u16, 2),
+        ("X", cpu.x() as u16, expected.x as u16, 2),
+        ("Y", cpu.y() as u16, expected.y as u16, 2),
+    ];
+    for (register, actual, wanted, digits) in registers.iter() {
+        if actual != wanted {
+            return Err(fail(format!("{} is ${:0width$X}, expected ${:0width$X}", register, actual, wanted, width = *digits)));
+        }
+    }
+    if (cpu.status() ^ expected.p) & STATUS_COMPARE_MASK != 0 {
+        return Err(fail(format!("P is ${:02X}, expected ${:02X}", cpu.status(), expected.p)));
+    }
+
+    for (address, value) in expected.ram.iter() {
+        let actual = cpu.bus().peek(*address);
+        if actual != *value {
+            return Err(fail(format!("${:04X} is ${:02X}, expected ${:02X}", address, actual, value)));
+        }
+    }
+
+    if options.check_bus {
+        let cycles = case.get("cycles").and_then(Json::as_array).ok_or_else(|| fail(String::from("Missing cycle list")))?;
+        let expected_ops = cycles
+            .iter()
+            .map(|cycle| {
+                let (address, value) = address_and_value(cycle)?;
+                match cycle.as_array()?.get(2)?.as_str()? {
+                    "read" => Some(BusOp::Read(address)),
+                    "write" => Some(BusOp::Write(address, value)),
+                    _ => None,
+                }
+            })
+            .collect::<Option<Vec<BusOp>>>()
+            .ok_or_else(|| fail(String::from("Invalid cycle list")))?;
+        let actual_ops = accesses.borrow();
+        if *actual_ops != expected_ops {
+            return Err(fail(format!("bus accesses were {:?}, expected {:?}", actual_ops, expected_ops)));
+        }
+    }
+
+    Ok(())
+}
+
+/// The vendored test files, by opcode
+const CASE_FILES: [(&str, &str); 19] = [
+    ("00", include_str!("cases/00.json")),
+    ("08", include_str!("cases/08.json")),
+    ("0a", include_str!("cases/0a.json")),
+    ("20", include_str!("cases/20.json")),
+    ("28", include_str!("cases/28.json")),
+    ("40", include_str!("cases/40.json")),
+    ("48", include_str!("cases/48.json")),
+    ("60", include_str!("cases/60.json")),
+    ("68", include_str!("cases/68.json")),
+    ("69", include_str!("cases/69.json")),
+    ("6c", include_str!("cases/6c.json")),
+    ("91", include_str!("cases/91.json")),
+    ("a1", include_str!("cases/a1.json")),
+    ("a9", include_str!("cases/a9.json")),
+    ("bd", include_str!("cases/bd.json")),
+    ("d0", include_str!("cases/d0.json")),
+    ("e8", include_str!("cases/e8.json")),
+    ("ea", include_str!("cases/ea.json")),
+    ("f6", include_str!("cases/f6.json")),
+];
+
+#[test]
+fn vendored_cases_pass_with_bus_activity() {
+    let options = Options {
+        check_bus: true,
+        ..Options::default()
+    };
+    let mut failures = Vec::new();
+    for (opcode, text) in CASE_FILES.iter() {
+        let file_failures = run_file(text, &options).unwrap_or_else(|e| panic!("{}.json: {}", opcode, e));
+        failures.extend(file_failures.into_iter().map(|failure| format!("{}.json: {}", opcode, failure)));
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn mismatches_are_reported() {
+    // LDA #$3C, with the final state expecting the wrong A and one access too few
+    let case = r#"{
+        "name": "a9 3c 00",
+        "initial": {"pc": 32768, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36, "ram": [[32768, 169], [32769, 60]]},
+        "final": {"pc": 32770, "s": 253, "a": 61, "x": 0, "y": 0, "p": 36, "ram": [[32768, 169], [32769, 60]]},
+        "cycles": [[32768, 169, "read"]]
+    }"#;
+    let error = run_case(case, &Options::default()).unwrap_err();
+    assert_eq!(error, "a9 3c 00: A is $3C, expected $3D");
+
+    let fixed = case.replace("\"a\": 61", "\"a\": 60");
+    assert_eq!(run_case(&fixed, &Options::default()), Ok(()));
+    let options = Options {
+        check_bus: true,
+        ..Options::default()
+    };
+    assert!(run_case(&fixed, &options).unwrap_err().contains("bus accesses"));
+}
+
+#[test]
+fn malformed_files_are_errors() {
+    assert!(run_file("[{\"name\": ", &Options::default()).is_err());
+    assert!(run_file("{}", &Options::default()).is_err());
+    assert!(run_case("{\"name\": \"x\", \"initial\": {}}", &Options::default()).unwrap_err().starts_with("x: "));
+}